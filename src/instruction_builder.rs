@@ -0,0 +1,354 @@
+// Ready-made instructions for off-chain Rust clients, with every account in the order the
+// processor reads it and the PDAs worked out, so clients don't have to copy account lists out of
+// the `SwapInstruction` docs. Token accounts are the parties' associated token accounts under the
+// legacy token program.
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    sysvar,
+};
+use spl_associated_token_account_client::address::get_associated_token_address;
+use spl_token::id as spl_token_program_id;
+
+use crate::{Offer, OfferType, SwapInstruction, accept_accounts, is_sol_side, nonce_seed};
+
+// What a new offer (or counter-offer) trades: `offer_token_amount` of the offered mint for
+// `receive_token_amount` of the other one. SOL amounts are in lamports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Amounts {
+    pub offer_token_amount: u64,
+    pub receive_token_amount: u64,
+}
+
+// The offer PDA a maker gets for a mint pair and nonce, and its canonical bump (the one the
+// program stores on the offer).
+pub fn offer_address(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    offer_mint: &Pubkey,
+    receive_mint: &Pubkey,
+    nonce: u64,
+) -> (Pubkey, u8) {
+    let nonce_bytes = nonce.to_le_bytes();
+    Pubkey::find_program_address(
+        &[
+            b"offer",
+            maker.as_ref(),
+            offer_mint.as_ref(),
+            receive_mint.as_ref(),
+            nonce_seed(&nonce_bytes),
+        ],
+        program_id,
+    )
+}
+
+fn pda(program_id: &Pubkey, seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, program_id).0
+}
+
+fn recent_cancel_address(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    offer_mint: &Pubkey,
+    receive_mint: &Pubkey,
+) -> Pubkey {
+    pda(
+        program_id,
+        &[
+            b"recent",
+            maker.as_ref(),
+            offer_mint.as_ref(),
+            receive_mint.as_ref(),
+        ],
+    )
+}
+
+// `CreateOffer` for a public offer anyone can take, as the maker's first offer on the pair
+// (nonce 0): a `PublicBuy` (escrowing SOL) when the maker
+// offers SOL, a `PublicSell` otherwise. The create fee, if the config charges one, is paid to the
+// maker's own wallet here; set account 12 to the config's fee collector in that case.
+pub fn create_offer(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    offer_mint: &Pubkey,
+    receive_mint: &Pubkey,
+    amounts: Amounts,
+    expiration: Option<i64>,
+) -> Instruction {
+    let offer_type = if is_sol_side(offer_mint) {
+        OfferType::PublicBuy
+    } else {
+        OfferType::PublicSell
+    };
+    build_create_offer(
+        program_id,
+        maker,
+        None,
+        offer_mint,
+        receive_mint,
+        offer_type,
+        amounts,
+        expiration,
+    )
+}
+
+// `CreateOffer` for a direct offer only `taker` can take.
+pub fn create_direct_offer(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    taker: &Pubkey,
+    offer_mint: &Pubkey,
+    receive_mint: &Pubkey,
+    amounts: Amounts,
+    expiration: Option<i64>,
+) -> Instruction {
+    build_create_offer(
+        program_id,
+        maker,
+        Some(taker),
+        offer_mint,
+        receive_mint,
+        OfferType::Direct,
+        amounts,
+        expiration,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_create_offer(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    taker: Option<&Pubkey>,
+    offer_mint: &Pubkey,
+    receive_mint: &Pubkey,
+    offer_type: OfferType,
+    amounts: Amounts,
+    expiration: Option<i64>,
+) -> Instruction {
+    let (offer_key, _) = offer_address(program_id, maker, offer_mint, receive_mint, 0);
+    let mut accounts = vec![
+        AccountMeta::new(*maker, true),
+        AccountMeta::new(offer_key, false),
+        // Unused when the maker offers SOL, but the slot still has to be filled.
+        AccountMeta::new(get_associated_token_address(maker, offer_mint), false),
+        AccountMeta::new_readonly(*offer_mint, false),
+        AccountMeta::new_readonly(*receive_mint, false),
+        AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+        AccountMeta::new_readonly(spl_token_program_id(), false),
+        AccountMeta::new_readonly(sysvar::rent::ID, false),
+        AccountMeta::new_readonly(pda(program_id, &[b"mintpause", offer_mint.as_ref()]), false),
+        AccountMeta::new_readonly(
+            pda(program_id, &[b"mintpause", receive_mint.as_ref()]),
+            false,
+        ),
+        AccountMeta::new_readonly(pda(program_id, &[b"config"]), false),
+        AccountMeta::new(pda(program_id, &[b"maker", maker.as_ref()]), false),
+        AccountMeta::new(*maker, false), // fee_collector
+        AccountMeta::new(
+            pda(
+                program_id,
+                &[b"book", offer_mint.as_ref(), receive_mint.as_ref()],
+            ),
+            false,
+        ),
+        AccountMeta::new(
+            recent_cancel_address(program_id, maker, offer_mint, receive_mint),
+            false,
+        ),
+        AccountMeta::new(*maker, false), // maker_sol_account
+    ];
+    if let Some(taker) = taker {
+        accounts.push(AccountMeta::new_readonly(*taker, false));
+    }
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: borsh::to_vec(&SwapInstruction::CreateOffer {
+            offer_type,
+            offer_token_amount: amounts.offer_token_amount,
+            receive_token_amount: amounts.receive_token_amount,
+            expiration,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        })
+        .expect("writing to a Vec can't fail"),
+    }
+}
+
+// `AcceptOffer` for the whole offer, or `AcceptOfferPartial` for `fill_amount` of the offered
+// tokens. The token accounts are both parties' associated accounts for the offer's non-SOL mint,
+// or the offered mint in a token-for-token swap (a sealed offer's vault stands in for the maker's;
+// see `accept_accounts`).
+pub fn accept_offer(
+    program_id: &Pubkey,
+    offer: &Offer,
+    taker: &Pubkey,
+    fill_amount: Option<u64>,
+) -> Instruction {
+    let (offer_key, _) = offer_address(
+        program_id,
+        &offer.maker,
+        &offer.offer_token_mint,
+        &offer.receive_token_mint,
+        offer.nonce,
+    );
+    // The taker receives a buy offer's SOL for their tokens, and anything else's offered tokens.
+    let token_mint = if is_sol_side(&offer.offer_token_mint) {
+        &offer.receive_token_mint
+    } else {
+        &offer.offer_token_mint
+    };
+    // A buy offer's tokens go to whoever gets the maker's proceeds.
+    let maker_token_owner = if offer.escrow_sol_amount > 0 {
+        offer.proceeds_owner()
+    } else {
+        offer.maker
+    };
+    let data = match fill_amount {
+        Some(fill_amount) => SwapInstruction::AcceptOfferPartial { fill_amount },
+        None => SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    };
+    Instruction {
+        program_id: *program_id,
+        accounts: accept_accounts(
+            offer,
+            program_id,
+            &offer_key,
+            taker,
+            &get_associated_token_address(taker, token_mint),
+            &get_associated_token_address(&maker_token_owner, token_mint),
+        ),
+        data: borsh::to_vec(&data).expect("writing to a Vec can't fail"),
+    }
+}
+
+// `CounterOffer` against `original`: the counter-maker offers what the original asked for, in
+// return for what it offered, at their own `amounts`.
+pub fn counter_offer(
+    program_id: &Pubkey,
+    counter_maker: &Pubkey,
+    original: &Offer,
+    amounts: Amounts,
+    expiration: Option<i64>,
+) -> Instruction {
+    let (offer_mint, receive_mint) = (&original.receive_token_mint, &original.offer_token_mint);
+    let (original_key, _) = offer_address(
+        program_id,
+        &original.maker,
+        &original.offer_token_mint,
+        &original.receive_token_mint,
+        original.nonce,
+    );
+    let (counter_key, _) = offer_address(program_id, counter_maker, offer_mint, receive_mint, 0);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*counter_maker, true),
+            AccountMeta::new(original_key, false),
+            AccountMeta::new(counter_key, false),
+            AccountMeta::new(
+                get_associated_token_address(counter_maker, offer_mint),
+                false,
+            ),
+            AccountMeta::new_readonly(*offer_mint, false),
+            AccountMeta::new_readonly(*receive_mint, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(spl_token_program_id(), false),
+            AccountMeta::new_readonly(sysvar::rent::ID, false),
+            AccountMeta::new_readonly(pda(program_id, &[b"config"]), false),
+            AccountMeta::new(pda(program_id, &[b"maker", original.maker.as_ref()]), false),
+            AccountMeta::new(pda(program_id, &[b"maker", counter_maker.as_ref()]), false),
+            AccountMeta::new(
+                pda(
+                    program_id,
+                    &[
+                        b"book",
+                        original.offer_token_mint.as_ref(),
+                        original.receive_token_mint.as_ref(),
+                    ],
+                ),
+                false,
+            ),
+            AccountMeta::new(
+                pda(
+                    program_id,
+                    &[b"book", offer_mint.as_ref(), receive_mint.as_ref()],
+                ),
+                false,
+            ),
+            AccountMeta::new(*counter_maker, false), // counter_maker_sol_account
+            AccountMeta::new(original.maker, false), // original_maker_sol_account
+        ],
+        data: borsh::to_vec(&SwapInstruction::CounterOffer {
+            offer_token_amount: amounts.offer_token_amount,
+            receive_token_amount: amounts.receive_token_amount,
+            expiration,
+            start_time: None,
+        })
+        .expect("writing to a Vec can't fail"),
+    }
+}
+
+// `CancelOffer` for the maker, refunding any escrowed SOL to their wallet and, for a sealed offer,
+// the vault's tokens to their associated token account.
+pub fn cancel_offer(program_id: &Pubkey, offer: &Offer, close_account: bool) -> Instruction {
+    let (offer_key, _) = offer_address(
+        program_id,
+        &offer.maker,
+        &offer.offer_token_mint,
+        &offer.receive_token_mint,
+        offer.nonce,
+    );
+    let mut accounts = vec![
+        AccountMeta::new(offer.maker, true),
+        AccountMeta::new(offer_key, false),
+        AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+        AccountMeta::new(pda(program_id, &[b"maker", offer.maker.as_ref()]), false),
+        AccountMeta::new(
+            pda(
+                program_id,
+                &[
+                    b"book",
+                    offer.offer_token_mint.as_ref(),
+                    offer.receive_token_mint.as_ref(),
+                ],
+            ),
+            false,
+        ),
+        AccountMeta::new(
+            recent_cancel_address(
+                program_id,
+                &offer.maker,
+                &offer.offer_token_mint,
+                &offer.receive_token_mint,
+            ),
+            false,
+        ),
+        AccountMeta::new(offer.maker, false), // maker_sol_account
+    ];
+    if offer.escrow_token_amount > 0 {
+        let mint = &offer.offer_token_mint;
+        accounts.extend([
+            AccountMeta::new(get_associated_token_address(&offer_key, mint), false),
+            AccountMeta::new(get_associated_token_address(&offer.maker, mint), false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(spl_token_program_id(), false),
+        ]);
+    }
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: borsh::to_vec(&SwapInstruction::CancelOffer { close_account })
+            .expect("writing to a Vec can't fail"),
+    }
+}
@@ -2,47 +2,93 @@
 // `solana_program` gives us the basic building blocks for Solana programs.
 // `spl_token` gives us tools specifically for handling those shiny SPL tokens.
 use borsh::{BorshDeserialize, BorshSerialize};
+
+pub mod instruction_builder; // Ready-made instructions for off-chain clients
+#[cfg(feature = "test-sdk")]
+pub mod test_sdk; // BanksClient helpers for integration tests (not for on-chain builds)
 use solana_program::{
     account_info::{AccountInfo, next_account_info},
+    ed25519_program,
     entrypoint,
     entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
     msg,
-    program::{invoke, invoke_signed},
+    program::{invoke, invoke_signed, set_return_data},
     program_error::ProgramError,
     program_pack::Pack,
     pubkey::Pubkey,
-    system_instruction,
     sysvar::{Sysvar, rent::Rent}, // To make sure accounts pay their "rent" on the blockchain
 };
+use solana_system_interface::instruction as system_instruction;
+use spl_associated_token_account_client::address::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+}; // To create accounts and move SOL
 
 use spl_token::{
+    error::TokenError,
     id as spl_token_program_id,
-    instruction::transfer_checked,          // To transfer tokens
     state::{Account as TokenAccount, Mint}, // To understand token accounts and token types
 };
-use std::convert::TryInto; // For converting numbers safely // Our new super-smart librarian for data!
+use spl_token_2022::{
+    extension::{
+        BaseStateWithExtensions, StateWithExtensions, non_transferable::NonTransferable,
+        pausable::PausableConfig,
+    },
+    instruction::transfer_checked, // To transfer tokens (works for either token program)
+};
 
 // --- Error Handling ---
 // This is like our list of "oops!" messages if something goes wrong.
-// Each number is a unique "oops!" code.
+// Each number is a unique "oops!" code, and clients match on it, so a code never changes:
+// new errors get the next number, and a retired one's number isn't handed out again.
 #[derive(Debug, PartialEq)]
+#[repr(u32)]
 pub enum SwapError {
-    InvalidInstruction,    // "Oops! I don't understand that button you pressed!"
-    NotRentExempt,         // "Oops! This account doesn't have enough rent paid!"
-    InvalidAccountData,    // "Oops! The data in this locker looks weird!"
-    IncorrectOwner,        // "Oops! You're trying to use someone else's locker!"
-    InsufficientFunds,     // "Oops! Not enough tokens/SOL for this trade!"
-    InvalidOfferStatus,    // "Oops! This offer isn't active anymore!"
-    OfferExpired,          // "Oops! This offer is too old!"
-    Unauthorized,          // "Oops! You're not allowed to do that!"
-    OfferMismatch,         // "Oops! The offer details don't match!"
-    TokenMismatch,         // "Oops! You're trying to trade the wrong type of token!"
-    AccountNotInitialized, // "Oops! This locker hasn't been set up yet!"
-    InvalidProgramAddress, // "Oops! This account's special address isn't right!"
-    MissingRequiredAccount,
-    InvalidAccountInput, // "Oops! One of the accounts you gave me is not what I expected (e.g., wrong type or not writable)!"
-    InvalidSystemProgram, // "Oops! The System Program address is wrong!"
-    InvalidTokenProgram, // "Oops! The SPL Token Program address is wrong!" // "Oops! You forgot to give me an important locker!"
+    InvalidInstruction = 0, // "Oops! I don't understand that button you pressed!"
+    NotRentExempt = 1,      // "Oops! This account doesn't have enough rent paid!"
+    InvalidAccountData = 2, // "Oops! The data in this locker looks weird!"
+    IncorrectOwner = 3,     // "Oops! You're trying to use someone else's locker!"
+    InsufficientFunds = 4,  // "Oops! Not enough tokens/SOL for this trade!"
+    InvalidOfferStatus = 5, // "Oops! This offer isn't active anymore!"
+    OfferExpired = 6,       // "Oops! This offer is too old!"
+    Unauthorized = 7,       // "Oops! You're not allowed to do that!"
+    OfferMismatch = 8,      // "Oops! The offer details don't match!"
+    TokenMismatch = 9,      // "Oops! You're trying to trade the wrong type of token!"
+    AccountNotInitialized = 10, // "Oops! This locker hasn't been set up yet!"
+    InvalidProgramAddress = 11, // "Oops! This account's special address isn't right!"
+    MissingRequiredAccount = 12,
+    InvalidAccountInput = 13, // "Oops! One of the accounts you gave me is not what I expected (e.g., wrong type or not writable)!"
+    InvalidSystemProgram = 14, // "Oops! The System Program address is wrong!"
+    InvalidTokenProgram = 15, // "Oops! That's not the SPL Token or Token-2022 program!" // "Oops! You forgot to give me an important locker!"
+    InvalidFillAmount = 16,   // "Oops! You can't fill zero, or more than what's left!"
+    FillTooSmall = 17,        // "Oops! That fill is smaller than the maker's minimum!"
+    OfferCollision = 18, // "Oops! The counter-offer would land in the very same locker as the original!"
+    ProgramPaused = 19,  // "Oops! Trading is paused (for this token, or everywhere) right now!"
+    TooManyOffers = 20,  // "Oops! You already have as many open offers as you're allowed!"
+    SealedOffer = 21,    // "Oops! This offer's tokens are locked in escrow; it can't be countered!"
+    InvalidBumpSeed = 22, // "Oops! That bump seed doesn't make a valid program address!"
+    AmountExceedsSupply = 23, // "Oops! That's more tokens than exist! (Wrong decimals?)"
+    SettlementPending = 24, // "Oops! Someone's big trade on this offer is still waiting to settle!"
+    SettlementNotReady = 25, // "Oops! This trade has to wait a bit longer before it can settle!"
+    InvalidMetadata = 26, // "Oops! That's not the right (or a real) metadata account for this NFT!"
+    InvalidAmount = 27, // "Oops! You can't trade nothing for something (or something for nothing)!"
+    AmountTooSmall = 28, // "Oops! That's too little SOL to bother escrowing!"
+    MalformedInstructionData = 29, // "Oops! There's extra stuff after that button's details!"
+    FeeTooHigh = 30,    // "Oops! That fee is more than the program is allowed to take!"
+    MissingMakerSolAccount = 31, // "Oops! I need the original maker's wallet to give their escrowed SOL back!"
+    SelfTrade = 32,              // "Oops! You can't accept your own offer!"
+    MathOverflow = 33,           // "Oops! Those amounts are too big (or too small) to add up!"
+    EscrowInYield = 34, // "Oops! That offer's SOL is out earning yield; withdraw it first!"
+    YieldShortfall = 35, // "Oops! The yield strategy didn't give all the escrowed SOL back!"
+    OfferNotStarted = 36, // "Oops! This offer can't be taken yet; come back at its start time!"
+    SlippageExceeded = 37, // "Oops! That trade's worse than the taker said they'd accept!"
+    InvalidApproval = 38, // "Oops! The maker didn't sign off on those terms!"
+    AmountTooLarge = 39, // "Oops! That's more SOL than one offer is allowed to escrow!"
+    AccountFrozen = 40, // "Oops! A token account (or its mint) is frozen, so nothing can move!"
+    UnsupportedVersion = 41, // "Oops! This offer's layout is one we can't read (or has to be migrated)!"
+    MintNotAllowed = 42,     // "Oops! That token isn't one this marketplace trades!"
+    InvalidMint = 43,        // "Oops! That's not a real (set-up) token mint!"
+    CooldownActive = 44, // "Oops! You just cancelled that very offer; wait a bit before posting it again!"
 }
 
 // We need to tell Solana how to turn our `SwapError` into a `ProgramError`.
@@ -56,11 +102,12 @@ impl From<SwapError> for ProgramError {
 // This is the blueprint for our "offer locker."
 // It tells us what information each offer will hold.
 // We add `#[derive(BorshSerialize, BorshDeserialize)]` so `borsh` can handle packing/unpacking!
-#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone)]
 pub struct Offer {
+    pub version: u8, // Layout version, always first so it can be read before the rest
     pub offer_type: OfferType, // Is it a direct offer, public buy, or public sell?
-    pub status: OfferStatus,   // Is it active, accepted, countered, etc.?
-    pub maker: Pubkey,         // The person who created the offer
+    pub status: OfferStatus, // Is it active, accepted, countered, etc.?
+    pub maker: Pubkey, // The person who created the offer
     pub taker: Option<Pubkey>, // The person the direct offer is for (if any)
     pub offer_token_mint: Pubkey, // The type of token being offered (e.g., "ShinyCoin")
     pub offer_token_amount: u64, // How many tokens are being offered
@@ -71,12 +118,46 @@ pub struct Offer {
     pub is_counter_offer: bool,     // Is this a counter-offer?
     pub original_offer_id: Option<Pubkey>, // If it's a counter, what was the original offer?
     pub bump_seed: u8,              // This is a special number for our PDA
+    pub min_fill: u64, // Smallest partial fill allowed (0 = any size), except for the last bit
+    pub rent_reserved: u64, // Lamports put in at creation to keep the locker rent-exempt (not escrow!)
+    pub zeroize_on_terminal: bool, // Wipe the trade details once the offer is finished?
+    pub escrow_token_amount: u64, // Tokens locked in the offer's vault (sealed offers only)
+    pub rate_num: u64,      // Ratio orders: a fill of N tokens costs N * rate_num / rate_den
+    pub rate_den: u64,      // (both 0 for every other kind of offer)
+    pub settled_offer_amount: u64, // What actually went out from the maker's side, over all fills
+    pub settled_receive_amount: u64, // What the maker actually got for it, over all fills
+    pub cancelled_at: i64,  // When the maker cancelled it (0 if they never did)
+    pub receive_recipient: Option<Pubkey>, // Who gets the maker's proceeds, if not the maker
+    pub premium_bps: u16, // Premium orders: how far over the last trade's price to sell (0 otherwise)
+    pub yield_strategy: Option<Pubkey>, // Program the escrowed SOL earns yield with, if any
+    pub yield_principal: u64, // Escrowed SOL currently deposited with the strategy
+    pub nonce: u64,       // Part of the PDA seeds, so a maker can have several offers on one pair
+    pub created_at: i64,  // When the offer was made
+    pub last_updated_at: i64, // When its status or terms last changed
+    pub start_time: Option<i64>, // It can't be taken before this (None = right away)
+    pub end_price: u64,   // Dutch auctions: where the price ends up at expiration (0 otherwise)
+    pub client_id: [u8; 8], // Tag for the front-end that made it, for their analytics (zeros = none)
+    pub auto_cancel_on_fail: bool, // One-shot: the first accept it turns away cancels it
+    pub offer_decimals: Option<u8>, // The offered mint's decimals, read when it was made (None for SOL)
+    pub min_acceptable_receive: u64, // A Dutch auction's floor: it expires rather than go below (0 = none)
 }
 
 impl Offer {
+    // The layout this program writes. Version 1 offers came before the version byte: the same
+    // fields as version 2, but no `version` in front, in lockers one byte shorter. Version 2
+    // offers came before `auto_cancel_on_fail`, version 3 ones before `offer_decimals`, and
+    // version 4 ones before `min_acceptable_receive`. We can still read them all, and
+    // `MigrateOffer` rewrites them; anything that changes them has to wait for that.
+    pub const CURRENT_VERSION: u8 = 5;
+    pub const V4_LEN: usize = Self::MAX_LEN - 8;
+    pub const V3_LEN: usize = Self::V4_LEN - 2;
+    pub const V2_LEN: usize = Self::V3_LEN - 1;
+    pub const V1_LEN: usize = Self::V2_LEN - 1;
+
     // We'll calculate a reasonable max size for the offer account.
     // Borsh adds 1 byte for each Option<T> field.
-    pub const MAX_LEN: usize = 1 // offer_type
+    pub const MAX_LEN: usize = 1 // version
+        + 1 // offer_type
         + 1 // status
         + 32 // maker
         + 1 + 32 // taker (Option<Pubkey>)
@@ -88,17 +169,115 @@ impl Offer {
         + 1 + 8 // expiration (Option<i64>)
         + 1 // is_counter_offer
         + 1 + 32 // original_offer_id (Option<Pubkey>)
-        + 1; // bump_seed
+        + 1 // bump_seed
+        + 8 // min_fill
+        + 8 // rent_reserved
+        + 1 // zeroize_on_terminal
+        + 8 // escrow_token_amount
+        + 8 // rate_num
+        + 8 // rate_den
+        + 8 // settled_offer_amount
+        + 8 // settled_receive_amount
+        + 8 // cancelled_at
+        + 1 + 32 // receive_recipient (Option<Pubkey>)
+        + 2 // premium_bps
+        + 1 + 32 // yield_strategy (Option<Pubkey>)
+        + 8 // yield_principal
+        + 8 // nonce
+        + 8 // created_at
+        + 8 // last_updated_at
+        + 1 + 8 // start_time (Option<i64>)
+        + 8 // end_price
+        + 8 // client_id
+        + 1 // auto_cancel_on_fail
+        + 1 + 1 // offer_decimals (Option<u8>)
+        + 8; // min_acceptable_receive
+
+    // Read an offer out of its locker. The locker is sized for `MAX_LEN`, but empty
+    // `Option`s take less room, so there can be leftover zero bytes at the end.
+    // Older offers come back upgraded to the current layout (in memory only), with the fields
+    // they didn't have yet zeroed.
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        let mut upgraded = Vec::with_capacity(Self::MAX_LEN);
+        if data.len() == Self::V1_LEN {
+            upgraded.push(Self::CURRENT_VERSION);
+            upgraded.extend_from_slice(data);
+        } else if (data.len() == Self::V2_LEN && data.first() == Some(&2))
+            || (data.len() == Self::V3_LEN && data.first() == Some(&3))
+            || (data.len() == Self::V4_LEN && data.first() == Some(&4))
+        {
+            upgraded.extend_from_slice(data);
+            upgraded[0] = Self::CURRENT_VERSION;
+        } else if data.first() != Some(&Self::CURRENT_VERSION) {
+            return Err(SwapError::UnsupportedVersion.into());
+        } else {
+            return Ok(Self::deserialize(&mut &data[..])?);
+        }
+        upgraded.resize(Self::MAX_LEN, 0);
+        Ok(Self::deserialize(&mut &upgraded[..])?)
+    }
+
+    // Forget who was trading what with whom. We keep the PDA seeds (maker, mints, nonce, bump) and
+    // `rent_reserved` so the locker can still be found, checked, and closed later.
+    pub fn zeroize_details(&mut self) {
+        self.taker = None;
+        self.offer_token_amount = 0;
+        self.receive_token_amount = 0;
+        self.escrow_sol_amount = 0;
+        self.escrow_token_amount = 0;
+        self.expiration = None;
+        self.is_counter_offer = false;
+        self.original_offer_id = None;
+        self.min_fill = 0;
+        self.rate_num = 0;
+        self.rate_den = 0;
+        self.settled_offer_amount = 0;
+        self.settled_receive_amount = 0;
+        self.receive_recipient = None;
+        self.premium_bps = 0;
+        self.yield_strategy = None;
+        self.yield_principal = 0;
+        self.start_time = None;
+        self.end_price = 0;
+        self.min_acceptable_receive = 0;
+    }
+
+    // Whoever the maker's side of a trade (tokens or SOL) gets paid to.
+    pub fn proceeds_owner(&self) -> Pubkey {
+        self.receive_recipient.unwrap_or(self.maker)
+    }
+
+    // Whether its start time (if it has one) has come.
+    pub fn has_started(&self, now: i64) -> bool {
+        self.start_time.is_none_or(|start| now >= start)
+    }
 }
 
 // Types of offers
-#[repr(u8)] // This tells Rust to store these as simple numbers (0, 1, 2)
+#[repr(u8)] // This tells Rust to store these as simple numbers (0, 1, 2, 3)
 #[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone, Copy)] // Add Borsh and Clone/Copy
 #[borsh(use_discriminant = true)]
 pub enum OfferType {
-    Direct = 0,     // An offer sent to a specific person
-    PublicBuy = 1,  // "I want to buy X tokens for Y SOL" - anyone can accept
-    PublicSell = 2, // "I want to sell X tokens for Y SOL" - anyone can accept
+    Direct = 0,       // An offer sent to a specific person
+    PublicBuy = 1,    // "I want to buy X tokens for Y SOL" - anyone can accept
+    PublicSell = 2,   // "I want to sell X tokens for Y SOL" - anyone can accept
+    RatioOrder = 3,   // "I'll sell X tokens at a fixed rate" - anyone can fill any chunk of it
+    PremiumOrder = 4, // "I'll sell X tokens for a bit more than the last trade" - anyone can accept
+    DutchAuction = 5, // "I'll sell X tokens for a price that drops until someone bites" - anyone can accept
+}
+
+impl OfferType {
+    // What a counter to an offer of this type is. Counters name their own fixed price, so an
+    // order priced some other way comes back as a plain public offer (a buy if it offers SOL).
+    pub fn for_counter(self, offers_sol: bool) -> Self {
+        match self {
+            Self::RatioOrder | Self::PremiumOrder | Self::DutchAuction if offers_sol => {
+                Self::PublicBuy
+            }
+            Self::RatioOrder | Self::PremiumOrder | Self::DutchAuction => Self::PublicSell,
+            other => other,
+        }
+    }
 }
 
 // Status of an offer
@@ -106,11 +285,919 @@ pub enum OfferType {
 #[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone, Copy)] // Add Borsh and Clone/Copy
 #[borsh(use_discriminant = true)]
 pub enum OfferStatus {
-    Active = 0,    // The offer is waiting to be accepted
-    Accepted = 1,  // The offer has been completed
-    Declined = 2,  // The offer was rejected
-    Countered = 3, // A counter-offer was made
-    Expired = 4,   // The offer timed out
+    Active = 0,          // The offer is waiting to be accepted
+    Accepted = 1,        // The offer has been completed
+    Declined = 2,        // The offer was rejected
+    Countered = 3,       // A counter-offer was made
+    Expired = 4,         // The offer timed out
+    Pending = 5,         // A direct offer waiting for its taker to confirm interest
+    PartiallyFilled = 6, // Some of the offer has been taken; the rest is still up for grabs
+}
+
+impl OfferStatus {
+    // Whether the offer can still be taken: untouched, or with some of it left.
+    pub fn is_live(&self) -> bool {
+        matches!(self, OfferStatus::Active | OfferStatus::PartiallyFilled)
+    }
+}
+
+// --- Program Config ---
+// One locker (seeds `[b"config"]`) that remembers who runs the vending machine.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct ProgramConfig {
+    pub admin: Pubkey, // The operator allowed to pause mints and change settings
+    pub bump_seed: u8, // The bump for the config PDA
+    pub max_active_offers_per_maker: u32, // How many active offers one maker may have (0 = no limit)
+    pub create_fee: u64,                  // Lamports charged to list an offer (0 = free)
+    pub fee_collector: Pubkey,            // Who gets the create fee
+    pub zeroize_terminal_offers: bool,    // Wipe offer details when they finish (for privacy)
+    pub reject_amounts_over_supply: bool, // Refuse (not just warn about) offers bigger than the supply
+    pub large_trade_threshold: u64, // Accepts of more offered tokens than this have to wait (0 = off)
+    pub settlement_delay: i64,      // How many seconds a large trade waits before it can settle
+    pub pending_authority: Option<Pubkey>, // Who the admin offered to hand over to (not yet accepted)
+    pub min_escrow_sol: u64, // Fewest lamports a buy offer may escrow (0 = no minimum)
+    pub reopen_grace: i64,   // How many seconds after cancelling a maker may reopen (0 = never)
+    pub fee_bps: u16, // Cut of the maker's proceeds on every accept, in basis points (0 = free)
+    pub treasury: Pubkey, // Who gets the accept fee
+    pub max_escrow_per_offer: u64, // Most lamports one offer may escrow (0 = no cap)
+    pub paused: bool, // Emergency stop: no new offers, counters or accepts (cancels still work)
+    pub recreate_cooldown: i64, // Seconds before a maker may post a cancelled offer again (0 = off)
+    pub allowed_mints: Vec<Pubkey>, // The only mints offers may trade (empty = any mint)
+}
+
+impl ProgramConfig {
+    pub const MAX_LEN: usize = 32 // admin
+        + 1 // bump_seed
+        + 4 // max_active_offers_per_maker
+        + 8 // create_fee
+        + 32 // fee_collector
+        + 1 // zeroize_terminal_offers
+        + 1 // reject_amounts_over_supply
+        + 8 // large_trade_threshold
+        + 8 // settlement_delay
+        + 1 + 32 // pending_authority (Option<Pubkey>)
+        + 8 // min_escrow_sol
+        + 8 // reopen_grace
+        + 2 // fee_bps
+        + 32 // treasury
+        + 8 // max_escrow_per_offer
+        + 1 // paused
+        + 8 // recreate_cooldown
+        + 4 + Self::MAX_ALLOWED_MINTS * 32; // allowed_mints (Vec<Pubkey>)
+
+    // The most `fee_bps` can be set to (10%).
+    pub const MAX_FEE_BPS: u16 = 1_000;
+
+    // How many mints the allowlist can hold.
+    pub const MAX_ALLOWED_MINTS: usize = 64;
+
+    // Whether offers may trade `mint`. With nothing on the allowlist, every mint is fine.
+    pub fn is_mint_allowed(&self, mint: &Pubkey) -> bool {
+        self.allowed_mints.is_empty() || self.allowed_mints.contains(mint)
+    }
+
+    // Read the config, ignoring any spare room at the end of the locker.
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        Ok(Self::deserialize(&mut &data[..])?)
+    }
+}
+
+// A single setting the admin can change with `UpdateConfig`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone, Copy)]
+pub enum ConfigField {
+    MaxActiveOffersPerMaker(u32),
+    CreateFee(u64),
+    FeeCollector(Pubkey),
+    ZeroizeTerminalOffers(bool),
+    RejectAmountsOverSupply(bool),
+    LargeTradeThreshold(u64),
+    SettlementDelay(i64),
+    MinEscrowSol(u64),
+    ReopenGrace(i64),
+    FeeBps(u16),
+    Treasury(Pubkey),
+    MaxEscrowPerOffer(u64),
+    RecreateCooldown(i64),
+}
+
+// --- Maker Index ---
+// Each maker gets a little tally (seeds `[b"maker", maker]`) of how many offers they have open,
+// so we can cap it. Offers that are accepted, cancelled, or countered don't count anymore.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct MakerIndex {
+    pub maker: Pubkey,      // Whose tally this is
+    pub active_offers: u32, // How many of their offers are still active
+    pub bump_seed: u8,      // The bump for the maker index PDA
+}
+
+impl MakerIndex {
+    pub const MAX_LEN: usize = 32 // maker
+        + 4 // active_offers
+        + 1; // bump_seed
+}
+
+// --- Recent Cancels ---
+// The last offer a maker cancelled on a pair (seeds `[b"recent", maker, offer_mint,
+// receive_mint]`), so they can't put the very same offer straight back up while the config's
+// `recreate_cooldown` runs (and make the pair look busier than it is). Creates make it, the maker
+// paying, while there's a cool-down; cancels fill it in.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct RecentCancel {
+    pub maker: Pubkey,             // Whose cancels these are
+    pub offer_token_amount: u64,   // What the cancelled offer gave...
+    pub receive_token_amount: u64, // ...and wanted for it
+    pub cancelled_at: i64,         // When it was cancelled (0 = nothing cancelled yet)
+    pub bump_seed: u8,             // The bump for the recent cancel PDA
+}
+
+impl RecentCancel {
+    pub const MAX_LEN: usize = 32 // maker
+        + 8 // offer_token_amount
+        + 8 // receive_token_amount
+        + 8 // cancelled_at
+        + 1; // bump_seed
+}
+
+// --- Order Books ---
+// An optional list of the open offers on one mint pair (seeds `[b"book", offer_mint,
+// receive_mint]`), made by the admin with room for `capacity` of them. While a pair has a book,
+// new offers on it take a slot and are refused once it's full; cancels, accepts, matches and
+// expiries give the slot back, and `PruneOrderBook` clears out anything still listed that ended
+// without doing so. Pairs without a book have no limit.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct OrderBook {
+    pub offer_token_mint: Pubkey,   // The mint the listed offers give
+    pub receive_token_mint: Pubkey, // The mint they want in return
+    pub capacity: u16,              // How many offers fit
+    pub bump_seed: u8,              // The bump for the order book PDA
+    pub offers: Vec<Pubkey>,        // The open offers, oldest first (never more than `capacity`)
+}
+
+impl OrderBook {
+    // The most offers one book can hold, so it can still be made in a single CPI (10KB).
+    pub const MAX_CAPACITY: u16 = 256;
+
+    // How big the locker for a book of `capacity` offers is.
+    pub const fn space(capacity: u16) -> usize {
+        32 // offer_token_mint
+            + 32 // receive_token_mint
+            + 2 // capacity
+            + 1 // bump_seed
+            + 4 + capacity as usize * 32 // offers (Vec<Pubkey>)
+    }
+
+    // Read the book, ignoring the spare room its unused slots leave at the end of the locker.
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        Ok(Self::deserialize(&mut &data[..])?)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.offers.len() >= self.capacity as usize
+    }
+}
+
+// --- Mint Pauses ---
+// While a locker with seeds `[b"mintpause", mint]` exists, nobody can create or accept
+// offers that involve that mint. Cancels still work so people can get their stuff back.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct MintPause {
+    pub mint: Pubkey,  // The paused mint
+    pub bump_seed: u8, // The bump for the mint pause PDA
+}
+
+impl MintPause {
+    pub const MAX_LEN: usize = 32 // mint
+        + 1; // bump_seed
+}
+
+// --- Negotiations ---
+// A taker's "I'd do it for this much" note on an offer (seeds `[b"nego", offer, taker]`).
+// It doesn't bind anyone or hold anything; the maker can read it and change their offer if
+// they like. There's only one per offer and taker, so asking again replaces the old note.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct Negotiation {
+    pub offer: Pubkey,         // The offer being haggled over
+    pub taker: Pubkey,         // Who is asking
+    pub proposed_receive: u64, // What the taker would pay instead of `receive_token_amount`
+    pub bump_seed: u8,         // The bump for the negotiation PDA
+}
+
+impl Negotiation {
+    pub const MAX_LEN: usize = 32 // offer
+        + 32 // taker
+        + 8 // proposed_receive
+        + 1; // bump_seed
+}
+
+// --- Settlements ---
+// A big accept doesn't trade right away. It books the trade here (seeds `[b"settle", offer]`),
+// and `FinalizeSettlement` can carry it out once `ready_at` comes around. Until then either side
+// can back out with `AbortSettlement`. Only one trade per offer can be waiting at a time.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct Settlement {
+    pub offer: Pubkey,            // The offer being taken
+    pub taker: Pubkey,            // Who is taking it (and paid for this locker)
+    pub fill_amount: Option<u64>, // How much of the offer (None = all of it)
+    pub ready_at: i64,            // When the trade can be finalized
+    pub bump_seed: u8,            // The bump for the settlement PDA
+}
+
+impl Settlement {
+    pub const MAX_LEN: usize = 32 // offer
+        + 32 // taker
+        + 1 + 8 // fill_amount (Option<u64>)
+        + 8 // ready_at
+        + 1; // bump_seed
+
+    // Read a settlement, ignoring the spare byte(s) a `None` fill leaves at the end.
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        Ok(Self::deserialize(&mut &data[..])?)
+    }
+}
+
+// --- Approvals ---
+// Accept terms a maker signs off-chain (the borsh bytes of this, signed with ed25519), so a
+// taker can settle with `AcceptWithApproval` while the maker is offline. It's pinned to the
+// offer as it stood when signed: any fill or update since bumps `last_updated_at` (and changes
+// the amounts), so an approval can't be played twice or carried over to new terms.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone, Copy)]
+pub struct AcceptApproval {
+    pub offer: Pubkey,              // The offer it's for
+    pub taker: Pubkey,              // The only one who can use it
+    pub fill_amount: Option<u64>,   // How much of the offer (None = all of it)
+    pub offer_token_amount: u64,    // The offer's amounts when the maker signed
+    pub receive_token_amount: u64,  // (what's left, after any partial fills)
+    pub offer_last_updated_at: i64, // The offer's `last_updated_at` then
+    pub expires_at: i64,            // No good after this
+}
+
+// --- Program Version ---
+// Tells clients which version of the program (and of the offer layout) they're talking to,
+// so a front-end can notice an upgrade and adjust instead of sending buttons we don't have.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone, Copy)]
+pub struct ProgramVersion {
+    pub major: u16,        // Crate major version
+    pub minor: u16,        // Crate minor version
+    pub patch: u16,        // Crate patch version
+    pub state_version: u8, // Layout version of the `Offer` data we store
+}
+
+// Turns a version piece like "12" into 12 at compile time.
+const fn parse_version_part(part: &str) -> u16 {
+    let bytes = part.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = value * 10 + (bytes[i] - b'0') as u16;
+        i += 1;
+    }
+    value
+}
+
+pub const PROGRAM_VERSION: ProgramVersion = ProgramVersion {
+    major: parse_version_part(env!("CARGO_PKG_VERSION_MAJOR")),
+    minor: parse_version_part(env!("CARGO_PKG_VERSION_MINOR")),
+    patch: parse_version_part(env!("CARGO_PKG_VERSION_PATCH")),
+    state_version: Offer::CURRENT_VERSION,
+};
+
+// --- Status Batches ---
+// Return data can be at most 1KB. Each `(Pubkey, OfferStatus)` entry takes 33 bytes and the
+// `Vec` length takes 4, so this is as many offers as we can report on in one go.
+pub const MAX_STATUS_BATCH: usize = (1024 - 4) / (32 + 1);
+
+// --- Sweeps ---
+// How many offers one `SweepExpired` may look at. Each takes three accounts and maybe a refund,
+// so this keeps a full sweep well inside one transaction (and its events inside the 1KB of
+// return data).
+pub const MAX_SWEEP_BATCH: usize = 8;
+
+// How many offers one `CancelAllForPair` may cancel, for the same reasons.
+pub const MAX_CANCEL_BATCH: usize = 8;
+
+// How many offers one `UpdateOfferBatch` may reprice, for the same reasons again.
+pub const MAX_UPDATE_BATCH: usize = 8;
+
+// How many offers one `AcceptOffers` may take. Each one is a whole accept, with its CPIs and
+// `ACCEPT_BATCH_GROUP_LEN` accounts, so this is about what fits in a transaction's account
+// list and compute budget.
+pub const MAX_ACCEPT_BATCH: usize = 8;
+
+// How many child offers one `SplitOffer` may make. Each is a new account (and its rent), so the
+// same limit again.
+pub const MAX_SPLIT_PARTS: usize = 8;
+
+// The accounts each offer in an `AcceptOffers` takes: `AcceptOffer`'s, up to its history account.
+pub const ACCEPT_BATCH_GROUP_LEN: usize = 19;
+
+// New amounts for one offer in an `UpdateOfferBatch` (`None` = keep it, as for `UpdateOffer`).
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone, Copy)]
+pub struct OfferUpdate {
+    pub new_offer_amount: Option<u64>,
+    pub new_receive_amount: Option<u64>,
+}
+
+// --- Events ---
+// Logged with `sol_log_data`, so they show up as `Program data: <base64>` lines. The first field
+// is the event's name and the second is the Borsh-encoded event.
+pub const OFFER_EXPIRED_EVENT: &[u8] = b"OfferExpired";
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct OfferExpiredEvent {
+    pub offer: Pubkey,          // The offer that ran out of time
+    pub maker: Pubkey,          // Whose it was
+    pub refunded_lamports: u64, // Escrowed SOL sent back to the maker (0 for sell offers)
+    pub expiration: i64,        // When it expired
+}
+
+// Everything else that happens to an offer, logged as `SWAP_EVENT` then the Borsh-encoded event.
+// Borsh writes the variant first (one byte, in the order below) and then its fields in order:
+// `Pubkey`s as their 32 bytes, integers little-endian, and an `OfferStatus` as its one-byte value.
+pub const SWAP_EVENT: &[u8] = b"SwapEvent";
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub enum SwapEvent {
+    /// 0: A new offer, by `CreateOffer` or any of the other create instructions.
+    OfferCreated {
+        offer: Pubkey,
+        maker: Pubkey,
+        offer_token_mint: Pubkey,
+        offer_token_amount: u64,
+        receive_token_mint: Pubkey,
+        receive_token_amount: u64,
+        client_id: [u8; 8],
+    },
+    /// 1: An accept (or one fill of it). The amounts are what actually changed hands this time;
+    /// `filled` says whether the offer is now `Accepted`.
+    OfferAccepted {
+        offer: Pubkey,
+        maker: Pubkey,
+        taker: Pubkey,
+        offer_amount: u64,
+        receive_amount: u64,
+        filled: bool,
+    },
+    /// 2: The maker cancelled an offer. `status` is `Declined`, or `Expired` if it had already run
+    /// out of time; `refunded_lamports` is the escrowed SOL they got back.
+    OfferCancelled {
+        offer: Pubkey,
+        maker: Pubkey,
+        status: OfferStatus,
+        refunded_lamports: u64,
+    },
+    /// 3: `original_offer` was countered with the new `counter_offer`, at its amounts.
+    OfferCountered {
+        original_offer: Pubkey,
+        counter_offer: Pubkey,
+        counter_maker: Pubkey,
+        offer_token_amount: u64,
+        receive_token_amount: u64,
+    },
+}
+
+// --- Fee Buffer ---
+// We can't see who pays a transaction's fees, but it has to be a signer. So when a signer's
+// wallet funds an escrow, we make sure it keeps this much (two signatures' worth) for fees.
+pub const FEE_BUFFER_LAMPORTS: u64 = 10_000;
+
+// --- NFT Royalties ---
+// NFTs keep their royalty in a Metaplex metadata account (seeds `[b"metadata", METADATA_PROGRAM_ID,
+// mint]`, owned by the Metaplex program). We only read the start of it, up to the creators list.
+pub const METADATA_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+pub const METADATA_KEY_V1: u8 = 4; // The first byte of a Metaplex `MetadataV1` account
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone)]
+pub struct MetadataCreator {
+    pub address: Pubkey, // Who gets a cut
+    pub verified: bool,  // Whether they signed off on being listed (we pay either way)
+    pub share: u8,       // Their percentage of the royalty (all shares add up to 100)
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone)]
+pub struct TokenMetadata {
+    pub key: u8, // Should be `METADATA_KEY_V1`
+    pub update_authority: Pubkey,
+    pub mint: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub seller_fee_basis_points: u16, // The royalty, in hundredths of a percent
+    pub creators: Option<Vec<MetadataCreator>>,
+}
+
+impl TokenMetadata {
+    // Read the metadata, ignoring everything Metaplex stores after the creators.
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| SwapError::InvalidMetadata.into())
+    }
+}
+
+// --- Quotes ---
+// The money side of an accept, worked out ahead of time by `QuoteAccept`. All SOL amounts are
+// in lamports. When the maker sells tokens, `sol_amount == seller_proceeds + royalty + protocol_fee`;
+// when the maker buys with escrowed SOL, their fee comes out of the tokens they get instead.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct Quote {
+    pub token_amount: u64,    // Tokens that change hands
+    pub sol_amount: u64,      // What the buyer pays for them, all in
+    pub seller_proceeds: u64, // What the token seller ends up with
+    pub royalty: u64,         // What the NFT's creators get, all together
+    pub protocol_fee: u64,    // What the treasury takes from the maker's side (lamports or tokens)
+}
+
+// --- Trade History ---
+// A little notebook that remembers the most recent trades for a token pair.
+// When the notebook is full, the oldest page gets overwritten (a "ring buffer").
+// On chain it lives in a PDA (seeds `[b"history", base_mint, quote_mint]`), which premium
+// orders read their price from.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone, Copy, Default)]
+pub struct Trade {
+    pub base_amount: u64,  // How many tokens changed hands
+    pub quote_amount: u64, // How much was paid for those tokens
+    pub timestamp: i64,    // When the trade happened
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Default)]
+pub struct TradeHistory {
+    pub head: u8,           // Where the next trade will be written once the notebook is full
+    pub trades: Vec<Trade>, // The remembered trades (never more than CAPACITY)
+}
+
+impl TradeHistory {
+    pub const CAPACITY: usize = 16;
+    pub const MAX_LEN: usize = 1 // head
+        + 4 + Self::CAPACITY * (8 + 8 + 8); // trades (Vec<Trade>)
+
+    // Write a new trade into the notebook, overwriting the oldest one if we're out of pages.
+    pub fn record(&mut self, trade: Trade) {
+        if self.trades.len() < Self::CAPACITY {
+            self.trades.push(trade);
+        } else {
+            self.trades[self.head as usize] = trade;
+        }
+        self.head = ((self.head as usize + 1) % Self::CAPACITY) as u8;
+    }
+
+    // Read the notebook, ignoring any spare room at the end of the locker.
+    pub fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        Ok(Self::deserialize(&mut &data[..])?)
+    }
+
+    // The most recently written trade, if there is one.
+    pub fn latest(&self) -> Option<&Trade> {
+        let len = self.trades.len();
+        if len == 0 {
+            return None;
+        }
+        self.trades.get((self.head as usize + len - 1) % len)
+    }
+}
+
+// Prices are "quote per one base token", multiplied by this so we don't lose the decimals.
+pub const PRICE_SCALE: u64 = 1_000_000_000;
+
+// Volume-weighted average price over everything in the notebook, scaled by `PRICE_SCALE`.
+// Big trades count more than small ones. Returns `None` when there's nothing to average
+// or the answer doesn't fit in a `u64`.
+pub fn vwap(history: &TradeHistory) -> Option<u64> {
+    let mut total_base: u128 = 0;
+    let mut total_quote: u128 = 0;
+    for trade in &history.trades {
+        total_base = total_base.checked_add(trade.base_amount as u128)?;
+        total_quote = total_quote.checked_add(trade.quote_amount as u128)?;
+    }
+    if total_base == 0 {
+        return None;
+    }
+    let price = total_quote
+        .checked_mul(PRICE_SCALE as u128)?
+        .checked_div(total_base)?;
+    u64::try_from(price).ok()
+}
+
+// Pick the best offer to pair with `target` out of a pile of candidates (for off-chain matchers).
+// A candidate counts if it's active, not expired at `now`, trades the same two tokens the other
+// way round, is allowed to trade with the target's maker (direct offers), and gives the target
+// at least the price it asked for. The best one gives the most tokens per token it wants back.
+// Ties go to whichever candidate comes first in `candidates`, so pass them oldest first.
+pub fn select_match<'a>(target: &Offer, candidates: &'a [Offer], now: i64) -> Option<&'a Offer> {
+    let mut best: Option<&'a Offer> = None;
+    for candidate in candidates {
+        let is_live =
+            candidate.status.is_live() && candidate.expiration.is_none_or(|exp| now <= exp);
+        let is_mirror = candidate.offer_token_mint == target.receive_token_mint
+            && candidate.receive_token_mint == target.offer_token_mint;
+        let is_allowed = (candidate.offer_type != OfferType::Direct
+            || candidate.taker == Some(target.maker))
+            && (target.offer_type != OfferType::Direct || target.taker == Some(candidate.maker));
+        if !is_live || !is_mirror || !is_allowed || candidate.receive_token_amount == 0 {
+            continue;
+        }
+
+        // The target wants `receive / offer` per token it gives; the candidate hands over
+        // `offer / receive`. Cross-multiply in u128 so nothing overflows or rounds.
+        let gives = candidate.offer_token_amount as u128 * target.offer_token_amount as u128;
+        let asked = candidate.receive_token_amount as u128 * target.receive_token_amount as u128;
+        if gives < asked {
+            continue;
+        }
+
+        let is_better = best.is_none_or(|best| {
+            candidate.offer_token_amount as u128 * best.receive_token_amount as u128
+                > best.offer_token_amount as u128 * candidate.receive_token_amount as u128
+        });
+        if is_better {
+            best = Some(candidate);
+        }
+    }
+    best
+}
+
+// The rate you get by chaining two offers: `a` gives X for Y, and `b` gives Y for Z, so together
+// they give X for Z. Returns `(x, z)`, meaning `x` of a's offered token for `z` of b's wanted
+// token, in lowest terms. `None` if a's wanted token isn't b's offered token, an amount is zero,
+// or the answer doesn't fit in a `u64`. (Only prices are compared, not how much is left.)
+pub fn cross_rate(a: &Offer, b: &Offer) -> Option<(u64, u64)> {
+    if a.receive_token_mint != b.offer_token_mint {
+        return None;
+    }
+    let amounts = [
+        a.offer_token_amount,
+        a.receive_token_amount,
+        b.offer_token_amount,
+        b.receive_token_amount,
+    ];
+    if amounts.contains(&0) {
+        return None;
+    }
+
+    // X per Y (from `a`) times Y per Z (from `b`), kept as a fraction so nothing rounds.
+    let x = a.offer_token_amount as u128 * b.offer_token_amount as u128;
+    let z = a.receive_token_amount as u128 * b.receive_token_amount as u128;
+    let divisor = gcd(x, z);
+    Some((
+        u64::try_from(x / divisor).ok()?,
+        u64::try_from(z / divisor).ok()?,
+    ))
+}
+
+// A side of an offer that's plain SOL is marked with the native mint's address, so clients see
+// a real mint on both sides. The lamports themselves move as SOL (never as wrapped tokens).
+pub fn is_sol_side(mint: &Pubkey) -> bool {
+    *mint == spl_token::native_mint::ID
+}
+
+// The offer PDA's nonce seed. Nonce 0 adds nothing, so a maker's first offer on a pair keeps the
+// address it had before offers took a nonce; any other nonce is folded in as its LE bytes.
+pub fn nonce_seed(nonce_bytes: &[u8; 8]) -> &[u8] {
+    if *nonce_bytes == [0; 8] {
+        &[]
+    } else {
+        nonce_bytes
+    }
+}
+
+// Price math treats a SOL side as lamports: 9 decimals, whatever is passed for it.
+pub const SOL_DECIMALS: u8 = 9;
+
+// What one whole offered token costs in whole received tokens, scaled by `PRICE_SCALE`.
+// Raw amounts are in each mint's smallest unit, so both legs are brought to whole tokens
+// (by their decimals) before dividing; a SOL leg uses `SOL_DECIMALS` whatever
+// decimals are passed for it. `None` for an empty offer or if the answer doesn't fit in a `u64`.
+pub fn price_per_token(offer: &Offer, offer_decimals: u8, receive_decimals: u8) -> Option<u64> {
+    let leg_decimals = |mint: &Pubkey, decimals: u8| {
+        if is_sol_side(mint) {
+            SOL_DECIMALS
+        } else {
+            decimals
+        }
+    };
+    let offer_decimals = leg_decimals(&offer.offer_token_mint, offer_decimals) as u32;
+    let receive_decimals = leg_decimals(&offer.receive_token_mint, receive_decimals) as u32;
+    if offer.offer_token_amount == 0 {
+        return None;
+    }
+
+    // (receive / 10^rd) / (offer / 10^od), kept whole until the one division at the end.
+    let numerator = (offer.receive_token_amount as u128)
+        .checked_mul(PRICE_SCALE as u128)?
+        .checked_mul(10u128.checked_pow(offer_decimals)?)?;
+    let denominator =
+        (offer.offer_token_amount as u128).checked_mul(10u128.checked_pow(receive_decimals)?)?;
+    u64::try_from(numerator / denominator).ok()
+}
+
+// Why a taker can't accept an offer right now (see `accept_blocker` and `CanAccept`).
+#[repr(u8)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Clone, Copy)]
+#[borsh(use_discriminant = true)]
+pub enum AcceptBlocker {
+    NotOpen = 0,              // Finished, cancelled, or countered
+    AwaitingConfirmation = 1, // A direct offer its taker hasn't confirmed yet
+    Expired = 2,              // Past its expiration
+    SelfTrade = 3,            // The taker is the maker
+    NotTheTaker = 4,          // A direct offer for someone else
+    MintPaused = 5,           // One of its mints is paused
+    NotStarted = 6,           // Before its start time
+}
+
+impl AcceptBlocker {
+    // Whether the offer is turning this taker away (rather than not being open to anyone),
+    // which is what cancels a one-shot offer.
+    pub fn rejects_taker(self) -> bool {
+        matches!(self, AcceptBlocker::NotTheTaker | AcceptBlocker::SelfTrade)
+    }
+
+    // The error an accept fails with for this.
+    pub fn error(self) -> SwapError {
+        match self {
+            AcceptBlocker::NotOpen | AcceptBlocker::AwaitingConfirmation => {
+                SwapError::InvalidOfferStatus
+            }
+            AcceptBlocker::Expired => SwapError::OfferExpired,
+            AcceptBlocker::SelfTrade => SwapError::SelfTrade,
+            AcceptBlocker::NotTheTaker => SwapError::Unauthorized,
+            AcceptBlocker::MintPaused => SwapError::ProgramPaused,
+            AcceptBlocker::NotStarted => SwapError::OfferNotStarted,
+        }
+    }
+}
+
+// What `CanAccept` writes as return data: `allowed` is true exactly when there's no `reason`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct AcceptEligibility {
+    pub allowed: bool,
+    pub reason: Option<AcceptBlocker>,
+}
+
+// The first thing stopping `taker` from accepting `offer` at `now`, if anything, leaving out
+// paused mints (those take accounts to look up). The accept itself checks the same things.
+pub fn accept_blocker(offer: &Offer, taker: &Pubkey, now: i64) -> Option<AcceptBlocker> {
+    if offer.status == OfferStatus::Pending {
+        return Some(AcceptBlocker::AwaitingConfirmation);
+    }
+    if !offer.status.is_live() {
+        return Some(AcceptBlocker::NotOpen);
+    }
+    if offer.expiration.is_some_and(|exp| now > exp) || below_auction_floor(offer, now) {
+        return Some(AcceptBlocker::Expired);
+    }
+    if !offer.has_started(now) {
+        return Some(AcceptBlocker::NotStarted);
+    }
+    // A maker taking their own offer is a wash trade that only fakes volume.
+    if *taker == offer.maker {
+        return Some(AcceptBlocker::SelfTrade);
+    }
+    if offer.offer_type == OfferType::Direct && offer.taker != Some(*taker) {
+        return Some(AcceptBlocker::NotTheTaker);
+    }
+    None
+}
+
+// The cluster's current unix time, which is all the program ever asks the clock. Everything
+// time-based (expirations, grace periods, settlement delays) reads it through here. Tests move
+// time by starting with `ProgramTest::start_with_context()` and then calling
+// `context.set_sysvar(&Clock { unix_timestamp, ..clock })`; later transactions see the new time.
+fn current_timestamp() -> Result<i64, ProgramError> {
+    Ok(solana_program::clock::Clock::get()?.unix_timestamp)
+}
+
+// How many seconds an offer has left, for countdowns in UIs. `None` if it never expires or
+// has already expired. An offer is still good at the exact second it expires, so that's `Some(0)`.
+pub fn time_remaining(offer: &Offer, now: i64) -> Option<i64> {
+    let expiration = offer.expiration?;
+    if now > expiration {
+        return None;
+    }
+    expiration.checked_sub(now)
+}
+
+// What a Dutch auction costs at `now`, all in: its start price (`receive_token_amount`) until it
+// starts, falling in a straight line to `end_price` at its expiration, and `end_price` from then
+// on. It starts at its `start_time`, or when it was made if it doesn't have one. Rounds up, in
+// the maker's favor.
+pub fn dutch_auction_price(offer: &Offer, now: i64) -> Result<u64, SwapError> {
+    let start = offer.start_time.unwrap_or(offer.created_at);
+    let end = offer.expiration.unwrap_or(start);
+    if now <= start {
+        return Ok(offer.receive_token_amount);
+    }
+    if now >= end {
+        return Ok(offer.end_price);
+    }
+    let drop = offer
+        .receive_token_amount
+        .checked_sub(offer.end_price)
+        .ok_or(SwapError::MathOverflow)?;
+    let elapsed = now.checked_sub(start).ok_or(SwapError::MathOverflow)? as u64;
+    let window = end.checked_sub(start).ok_or(SwapError::MathOverflow)? as u64;
+    offer
+        .receive_token_amount
+        .checked_sub(mul_div(drop, elapsed, window)?)
+        .ok_or(SwapError::MathOverflow)
+}
+
+// Whether a Dutch auction has come down below its maker's floor (`min_acceptable_receive`), which
+// ends it like running out of time does.
+fn below_auction_floor(offer: &Offer, now: i64) -> bool {
+    offer.offer_type == OfferType::DutchAuction
+        && dutch_auction_price(offer, now).is_ok_and(|price| price < offer.min_acceptable_receive)
+}
+
+// The status an offer really has at `now`, for showing in UIs: a live offer past its
+// expiration, or an auction below its floor, is `Expired` (as the next accept would mark it)
+// even before anyone has written that down. Everything else is just its stored status.
+pub fn effective_status(offer: &Offer, now: i64) -> OfferStatus {
+    if offer.status.is_live()
+        && (offer.expiration.is_some_and(|exp| now > exp) || below_auction_floor(offer, now))
+    {
+        OfferStatus::Expired
+    } else {
+        offer.status
+    }
+}
+
+// `amount * numerator / denominator`, rounded down, worked out in 128 bits so the product
+// can't overflow. Fails with `MathOverflow` if the answer doesn't fit back in a `u64` (or
+// `denominator` is 0).
+pub fn mul_div(amount: u64, numerator: u64, denominator: u64) -> Result<u64, SwapError> {
+    (amount as u128)
+        .checked_mul(numerator as u128)
+        .and_then(|product| product.checked_div(denominator as u128))
+        .and_then(|result| u64::try_from(result).ok())
+        .ok_or(SwapError::MathOverflow)
+}
+
+// How many lamports a maker spends to create an offer: the offer locker's rent, any SOL they
+// escrow (a buy offer's `offer_token_amount`), and the config's create fee. Clients can check a
+// wallet against this before sending. (A maker's very first offer also pays for their index.)
+pub fn creation_cost(rent: &Rent, escrow_sol: u64, create_fee: u64) -> u64 {
+    rent.minimum_balance(Offer::MAX_LEN)
+        .saturating_add(escrow_sol)
+        .saturating_add(create_fee)
+}
+
+// --- Compact Offers ---
+// Just the terms another program needs to settle against an offer, for passing in instruction
+// data instead of handing over the offer account. It's Borsh, so the layout (little-endian) is:
+//   offer_type          1 byte (0 = Direct, 1 = PublicBuy, 2 = PublicSell, 3 = RatioOrder,
+//                       4 = PremiumOrder, 5 = DutchAuction)
+//   maker               32 bytes
+//   taker               1 byte tag (0 = anyone), then 32 bytes if it's 1
+//   offer_token_mint    32 bytes
+//   offer_token_amount  8 bytes (u64)
+//   receive_token_mint  32 bytes
+//   receive_token_amount 8 bytes (u64)
+//   expiration          1 byte tag (0 = never), then 8 bytes (i64) if it's 1
+//   min_fill            8 bytes (u64)
+//   start_time          1 byte tag (0 = right away), then 8 bytes (i64) if it's 1
+//   rate_num, rate_den  8 bytes (u64) each
+//   premium_bps         2 bytes (u16)
+//   end_price           8 bytes (u64)
+//   min_acceptable_receive 8 bytes (u64)
+// The pricing fields mean what they do on `Offer` (0 for types that don't use them), so a
+// ratio order, premium order or Dutch auction can be priced from this alone. An auction made
+// without a start time gets the time it was made, which is when its price started falling.
+// That's 166 bytes at most, against `Offer::MAX_LEN` for the whole offer.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct CompactOffer {
+    pub offer_type: OfferType,
+    pub maker: Pubkey,
+    pub taker: Option<Pubkey>,
+    pub offer_token_mint: Pubkey,
+    pub offer_token_amount: u64,
+    pub receive_token_mint: Pubkey,
+    pub receive_token_amount: u64,
+    pub expiration: Option<i64>,
+    pub min_fill: u64,
+    pub start_time: Option<i64>,
+    pub rate_num: u64,
+    pub rate_den: u64,
+    pub premium_bps: u16,
+    pub end_price: u64,
+    pub min_acceptable_receive: u64,
+}
+
+impl CompactOffer {
+    pub const MAX_LEN: usize =
+        1 + 32 + (1 + 32) + 32 + 8 + 32 + 8 + (1 + 8) + 8 + (1 + 8) + 8 + 8 + 2 + 8 + 8;
+}
+
+pub fn pack_compact(offer: &Offer) -> Vec<u8> {
+    let compact = CompactOffer {
+        offer_type: offer.offer_type,
+        maker: offer.maker,
+        taker: offer.taker,
+        offer_token_mint: offer.offer_token_mint,
+        offer_token_amount: offer.offer_token_amount,
+        receive_token_mint: offer.receive_token_mint,
+        receive_token_amount: offer.receive_token_amount,
+        expiration: offer.expiration,
+        min_fill: offer.min_fill,
+        start_time: if offer.offer_type == OfferType::DutchAuction {
+            Some(offer.start_time.unwrap_or(offer.created_at))
+        } else {
+            offer.start_time
+        },
+        rate_num: offer.rate_num,
+        rate_den: offer.rate_den,
+        premium_bps: offer.premium_bps,
+        end_price: offer.end_price,
+        min_acceptable_receive: offer.min_acceptable_receive,
+    };
+    borsh::to_vec(&compact).expect("writing to a Vec can't fail")
+}
+
+// Reads back what `pack_compact` wrote. Anything short, too long, or otherwise off is
+// `InvalidInstructionData`, since that's where these bytes come from.
+pub fn unpack_compact(data: &[u8]) -> Result<CompactOffer, ProgramError> {
+    CompactOffer::try_from_slice(data).map_err(|_| ProgramError::InvalidInstructionData)
+}
+
+// --- Accept Accounts ---
+// The accounts an `AcceptOffer` (or `AcceptOfferPartial`) for this offer needs, in order, so
+// front-ends don't have to work out the PDAs and which SOL accounts to add. The token accounts
+// are the ones holding the non-SOL token (the maker's and the taker's); the treasury, trade
+// history and royalty accounts aren't included, add them to the end when there's a fee, a
+// premium order or an NFT. It assumes the legacy token program; for a Token-2022 mint, swap in
+// `spl_token_2022::id()` (and a sealed offer's Token-2022 vault).
+pub fn accept_accounts(
+    offer: &Offer,
+    program_id: &Pubkey,
+    offer_key: &Pubkey,
+    taker: &Pubkey,
+    taker_token_account: &Pubkey,
+    maker_token_account: &Pubkey,
+) -> Vec<AccountMeta> {
+    let pda = |seeds: &[&[u8]]| Pubkey::find_program_address(seeds, program_id).0;
+    let (offer_mint, receive_mint) = (&offer.offer_token_mint, &offer.receive_token_mint);
+    // The maker only has to sign when their own tokens are sent; escrowed SOL and a sealed
+    // offer's vault are the offer's, and the vault stands in for the maker's token account.
+    let sealed = offer.escrow_token_amount > 0;
+    let maker_signs = offer.escrow_sol_amount == 0 && !sealed;
+    let maker_token_account = if sealed {
+        &get_associated_token_address(offer_key, offer_mint)
+    } else {
+        maker_token_account
+    };
+    let mut accounts = vec![
+        AccountMeta::new(*taker, true),
+        AccountMeta::new(*offer_key, false),
+        AccountMeta::new(offer.maker, maker_signs),
+        AccountMeta::new(*maker_token_account, false),
+        AccountMeta::new(*taker_token_account, false),
+        AccountMeta::new_readonly(*offer_mint, false),
+        AccountMeta::new_readonly(*receive_mint, false),
+        AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+        AccountMeta::new_readonly(spl_token_program_id(), false),
+        AccountMeta::new_readonly(pda(&[b"mintpause", offer_mint.as_ref()]), false),
+        AccountMeta::new_readonly(pda(&[b"mintpause", receive_mint.as_ref()]), false),
+        AccountMeta::new(pda(&[b"maker", offer.maker.as_ref()]), false),
+        AccountMeta::new_readonly(pda(&[b"config"]), false),
+        AccountMeta::new(pda(&[b"settle", offer_key.as_ref()]), false),
+        AccountMeta::new(
+            pda(&[b"book", offer_mint.as_ref(), receive_mint.as_ref()]),
+            false,
+        ),
+    ];
+    // SOL goes to and from the wallets themselves; in a token-for-token swap the received mint
+    // moves between the taker's and the maker's associated accounts for it instead.
+    if is_sol_side(offer_mint) || is_sol_side(receive_mint) {
+        accounts.push(AccountMeta::new(offer.proceeds_owner(), false));
+        accounts.push(AccountMeta::new(*taker, false));
+    } else {
+        accounts.push(AccountMeta::new(
+            get_associated_token_address(&offer.proceeds_owner(), receive_mint),
+            false,
+        ));
+        accounts.push(AccountMeta::new(
+            get_associated_token_address(taker, receive_mint),
+            false,
+        ));
+    }
+    accounts
+}
+
+// Greatest common divisor, for putting ratios in lowest terms.
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+// --- Yield Strategies ---
+// What we ask a yield strategy program to do, as its instruction data. The offer PDA comes first
+// and signs, followed by the strategy's own accounts, the first of which is its vault.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub enum YieldInstruction {
+    /// `amount` lamports of escrow are on their way. They land in the vault by hand right
+    /// after this returns, since only we can take lamports out of the offer PDA.
+    Deposit { amount: u64 },
+    /// Pay everything deposited, plus whatever it earned, back into the offer PDA.
+    Withdraw,
 }
 
 // --- Instructions ---
@@ -125,32 +1212,122 @@ pub enum SwapInstruction {
     /// 3. `[]` offer_token_mint: The mint account of the token being offered.
     /// 4. `[]` receive_token_mint: The mint account of the token/SOL expected in return.
     /// 5. `[]` system_program: Solana's System Program.
-    /// 6. `[]` token_program: SPL Token Program.
+    /// 6. `[]` token_program: SPL Token Program (or Token-2022).
     /// 7. `[]` rent_sysvar: Rent Sysvar.
-    /// 8. `[writable]` (optional) maker_sol_account: Maker's SOL account (if offering SOL or receiving SOL).
-    /// 9. `[]` (optional) taker_account: The specific person for a direct offer.
+    /// 8. `[]` offer_mint_pause: Mint pause PDA for the offered mint (may not exist).
+    /// 9. `[]` receive_mint_pause: Mint pause PDA for the received mint (may not exist).
+    /// 10. `[]` config_account: The config PDA (may not exist).
+    /// 11. `[writable]` maker_index: Maker index PDA. Created by the program if needed.
+    /// 12. `[writable]` fee_collector: Gets the create fee. Must be the config's fee collector when a fee is set.
+    /// 13. `[writable]` order_book: The pair's order book PDA (may not exist). If it does, the offer
+    ///     takes one of its slots, and fails with `TooManyOffers` when there are none left.
+    /// 14. `[writable]` recent_cancel: The maker's recent cancel PDA for the pair (may not exist).
+    ///     Created by the program while the config has a `recreate_cooldown`; the same amounts as
+    ///     the offer cancelled there fail with `CooldownActive` until it runs out.
+    /// 15. `[writable]` (optional) maker_sol_account: Maker's SOL account (if offering SOL or receiving SOL).
+    /// 16. `[]` (optional) taker_account: The specific person for a direct offer (ignored otherwise).
+    /// 17. `[writable]` (optional) offer_token_vault: Makes the offer "sealed": the offered tokens are
+    ///     moved into this vault (the offer PDA's associated token account for the offered mint)
+    ///     right away, so they're sure to be there when the offer is accepted or matched.
+    ///
+    /// With `auto_cancel_on_fail`, the offer is a one-shot: an accept it turns away for being
+    /// from the wrong taker or the maker cancels it on the spot and refunds the escrow to the
+    /// maker (that accept succeeds, without trading anything). Accepts that fail for anything
+    /// else, like a bad token account or coming before its start time, leave it alone.
     CreateOffer {
         offer_type: OfferType,
         offer_token_amount: u64,
         receive_token_amount: u64,
         expiration: Option<i64>,
-        bump_seed: u8, // The bump seed for the offer_account PDA
+        min_fill: u64, // Smallest partial fill takers may make (0 = no minimum)
+        // Expire this many seconds after creation instead (can't be used with `expiration`)
+        duration_secs: Option<u64>,
+        // Send the maker's proceeds here instead of to the maker (e.g. a treasury)
+        receive_recipient: Option<Pubkey>,
+        // Direct offers only: start `Pending` until the taker calls `ConfirmInterest`
+        confirm_first: bool,
+        // Picks which of the maker's offers on this mint pair this is (folded into the PDA seeds)
+        nonce: u64,
+        // Nobody can take it before this time (it has to come before any expiration)
+        start_time: Option<i64>,
+        // Tags the offer with the front-end that made it; stored and logged, never checked
+        client_id: [u8; 8],
+        // One-shot: the first accept it turns away cancels it instead (not for sealed offers)
+        auto_cancel_on_fail: bool,
     },
     /// Accept an existing swap offer.
     /// Accounts:
     /// 0. `[signer]` taker_account: The person accepting the offer.
     /// 1. `[writable]` offer_account: The PDA for the offer data.
     /// 2. `[writable]` maker_account: The original offer maker's account.
-    /// 3. `[writable]` maker_token_account: Maker's token account for the token they are giving/receiving.
+    /// 3. `[writable]` maker_token_account: Maker's token account for the token they are giving/receiving
+    ///    (the offer's vault, for a sealed offer).
     /// 4. `[writable]` taker_token_account: Taker's token account for the token they are giving/receiving.
     /// 5. `[]` offer_token_mint: The mint account of the token offered by the maker.
     /// 6. `[]` receive_token_mint: The mint account of the token expected by the maker (given by taker).
     /// 7. `[]` system_program: Solana's System Program.
-    /// 8. `[]` token_program: SPL Token Program.
-    /// 9. `[writable]` (optional) maker_sol_account: Maker's SOL account (if involved in SOL transfer).
-    /// 10. `[writable]` (optional) taker_sol_account: Taker's SOL account (if involved in SOL transfer).
-    AcceptOffer,
+    /// 8. `[]` token_program: SPL Token Program (or Token-2022).
+    /// 9. `[]` offer_mint_pause: Mint pause PDA for the offered mint (may not exist).
+    /// 10. `[]` receive_mint_pause: Mint pause PDA for the received mint (may not exist).
+    /// 11. `[writable]` maker_index: The offer maker's index PDA.
+    /// 12. `[]` config_account: The config PDA (may not exist).
+    /// 13. `[writable]` settlement_account: The offer's settlement PDA. Created by the program for large trades.
+    /// 14. `[writable]` order_book: The pair's order book PDA (may not exist). An accept that
+    ///     finishes the offer takes it off the book.
+    /// 15. `[writable]` (optional) maker_sol_account: Maker's SOL account (if involved in SOL transfer).
+    /// 16. `[writable]` (optional) taker_sol_account: Taker's SOL account (if involved in SOL transfer).
+    /// 17. `[writable]` (optional) treasury_account: Where the accept fee goes. Needed when the config
+    ///     sets a `fee_bps`; any account will do as a placeholder otherwise.
+    /// 18. `[]` (optional) history_account: The pair's trade history PDA (may not exist). Needed
+    ///     for premium orders; any account will do as a placeholder otherwise.
+    /// 19. `[]` (optional) metadata_account: Metaplex metadata for the token being sold for SOL.
+    /// 20. `[writable]` (optional) creator_accounts (20 onwards): One per creator in the metadata, in the same order.
+    ///
+    /// The maker can't accept their own offer (`SelfTrade`), in full or in part.
+    ///
+    /// SOL always goes straight to the person's own wallet (the SOL account must be the same address
+    /// as the maker/taker). If that wallet is empty, it must receive at least the rent-exempt minimum,
+    /// otherwise the accept fails with `NotRentExempt`.
+    ///
+    /// A sealed offer pays the taker out of its vault, so the maker doesn't have to sign (or still
+    /// hold the tokens); partial fills draw the vault down.
+    ///
+    /// If the offer has a `receive_recipient`, the maker's proceeds go there instead: its wallet is
+    /// the maker SOL account, and when the maker is buying, the maker token account is its.
+    ///
+    /// When neither side is SOL, 15 and 16 are token accounts for the received mint instead: the
+    /// maker's (or `receive_recipient`'s), which gets paid, and the taker's, which pays. Leaving the
+    /// maker's out fails with `MissingRequiredAccount`, and one for another mint with `TokenMismatch`.
+    ///
+    /// With a metadata account, the NFT's royalty (`seller_fee_basis_points`) comes out of the SOL
+    /// the seller gets and is split between the creators by their shares. A metadata account that
+    /// isn't the mint's real Metaplex metadata fails with `InvalidMetadata`.
+    ///
+    /// If the config sets a `fee_bps`, that cut of what the maker receives goes to the treasury
+    /// instead: the treasury wallet itself when the maker is paid in SOL, or a token account the
+    /// treasury owns when the maker is paid in tokens.
+    ///
+    /// If the config sets a `large_trade_threshold` and this accept takes more offered tokens than
+    /// that, nothing is traded yet: the taker pays for a `Settlement` and the trade waits
+    /// `settlement_delay` seconds for `FinalizeSettlement`. While it waits, nobody else can accept.
+    ///
+    /// With `close_account`, an offer this accept finishes is closed once it's marked `Accepted`:
+    /// its rent goes to the maker account (which must then be writable) and the record is gone.
+    ///
+    /// `min_receive_amount` and `max_send_amount` bound what the taker gets (after any royalty) and
+    /// what they pay (royalties and fees included), so an offer that changed under them fails with
+    /// `SlippageExceeded` instead of filling at the new terms. A large trade is checked when it's
+    /// booked, not again at `FinalizeSettlement`.
+    AcceptOffer {
+        close_account: bool, // Close the offer (rent back to the maker) instead of keeping the record
+        min_receive_amount: Option<u64>, // Least the taker will take for it (None = anything)
+        max_send_amount: Option<u64>, // Most the taker will pay (None = whatever it costs)
+    },
     /// Create a counter-offer to an existing offer.
+    /// A direct offer can only be countered by its maker or its named taker; a public offer
+    /// (including one made public with `MakePublic`) can be countered by anyone.
+    /// The counter takes the original's place in the order books: the original comes out of its
+    /// pair's book and the counter goes into its own, failing with `TooManyOffers` if that's full.
     /// Accounts:
     /// 0. `[signer]` counter_maker_account: The person making the counter-offer.
     /// 1. `[writable]` original_offer_account: The PDA for the original offer data.
@@ -159,50 +1336,597 @@ pub enum SwapInstruction {
     /// 4. `[]` offer_token_mint: The mint account of the token being offered in the counter.
     /// 5. `[]` receive_token_mint: The mint account of the token/SOL expected in return in the counter.
     /// 6. `[]` system_program: Solana's System Program.
-    /// 7. `[]` token_program: SPL Token Program.
+    /// 7. `[]` token_program: SPL Token Program (or Token-2022).
     /// 8. `[]` rent_sysvar: Rent Sysvar.
-    /// 9. `[writable]` (optional) counter_maker_sol_account: Counter-maker's SOL account (if offering SOL or receiving SOL).
-    /// 10. `[writable]` (optional) original_maker_sol_account: Original maker's SOL account (for refund of escrowed SOL).
+    /// 9. `[]` config_account: The config PDA (may not exist).
+    /// 10. `[writable]` original_maker_index: The original maker's index PDA.
+    /// 11. `[writable]` counter_maker_index: The counter-maker's index PDA. Created by the program if needed.
+    /// 12. `[writable]` original_order_book: The original's pair's order book PDA (may not exist).
+    /// 13. `[writable]` counter_order_book: The counter's pair's order book PDA (may not exist).
+    /// 14. `[writable]` (optional) counter_maker_sol_account: Counter-maker's SOL account (if offering SOL or receiving SOL).
+    /// 15. `[writable]` (optional) original_maker_sol_account: Original maker's SOL account (for refund of escrowed SOL).
+    ///     Required, and must be the original maker's own wallet, when the original offer escrows SOL;
+    ///     leaving it out then fails with `MissingMakerSolAccount`.
     CounterOffer {
         offer_token_amount: u64,
         receive_token_amount: u64,
         expiration: Option<i64>,
-        bump_seed: u8, // The bump seed for the new_offer_account PDA
+        // When the counter can first be taken; `None` keeps the original's start time
+        start_time: Option<i64>,
     },
-    /// Cancel an existing offer.
+    /// Cancel an existing offer. Offers already past their expiration end up `Expired`
+    /// rather than `Declined`; any escrow is refunded either way.
+    /// The maker can be another program's PDA, signing through CPI with `invoke_signed`. Refunds
+    /// are paid out of our own offer PDA, so they don't need the maker's seeds.
     /// Accounts:
     /// 0. `[signer]` offer_maker_account: The person who made the offer.
     /// 1. `[writable]` offer_account: The PDA for the offer data.
     /// 2. `[]` system_program: Solana's System Program.
-    /// 3. `[writable]` (optional) maker_sol_account: Maker's SOL account (to refund escrowed SOL).
-    CancelOffer,
-}
-
-// --- Processor (The Brain of Our Vending Machine) ---
-// This is where all the magic happens! It takes an instruction and figures out what to do.
-pub struct Processor;
-
-impl Processor {
-    // This is the main function that Solana calls when someone interacts with our program.
-    pub fn process(
-        program_id: &Pubkey,      // Our program's address
-        accounts: &[AccountInfo], // All the lockers involved in this transaction
-        instruction_data: &[u8],  // The "button" pressed and any extra info
-    ) -> ProgramResult {
-        // First, let's figure out which "button" was pressed.
-        let instruction = SwapInstruction::try_from_slice(instruction_data) // Use borsh to unpack!
-            .map_err(|_| SwapError::InvalidInstruction)?;
-
-        // Now, based on the button, we call the right function.
-        match instruction {
-            SwapInstruction::CreateOffer {
-                offer_type,
-                offer_token_amount,
+    /// 3. `[writable]` maker_index: The maker's index PDA.
+    /// 4. `[writable]` order_book: The pair's order book PDA (may not exist). The offer comes off it.
+    /// 5. `[writable]` recent_cancel: The maker's recent cancel PDA for the pair (may not exist).
+    ///    If it does, it remembers this cancel for the config's `recreate_cooldown`.
+    /// 6. `[writable]` (optional) maker_sol_account: Maker's SOL account (to refund escrowed SOL).
+    /// 7. `[writable]` (optional) offer_token_vault: The sealed offer's vault (to refund escrowed tokens).
+    /// 8. `[writable]` (optional) maker_token_account: Maker's token account for the offered token.
+    /// 9. `[]` (optional) offer_token_mint: The mint account of the offered token.
+    /// 10. `[]` (optional) token_program: SPL Token Program (or Token-2022).
+    ///
+    /// A yield offer (see `CreateYieldOffer`) takes its strategy program, vault and any other
+    /// strategy accounts from 7 on instead, and withdraws the escrow before refunding it.
+    ///
+    /// With `close_account`, the offer is closed after it's marked: its rent goes to the maker SOL
+    /// account (required then) and the record is gone. Leave it off to keep the record for indexing.
+    CancelOffer {
+        close_account: bool, // Close the offer (rent back to the maker) instead of keeping the record
+    },
+    /// Accept only part of an offer.
+    /// Where the maker sells tokens, `fill_amount` is how many the taker buys. The taker pays the
+    /// matching share of `receive_token_amount` (rounded up in the maker's favor).
+    /// Fills smaller than the offer's `min_fill` are rejected, unless they take everything that's left.
+    /// Where the maker escrowed SOL to buy tokens, `fill_amount` is how many tokens the taker
+    /// delivers, and the taker gets the matching share of the escrow (rounded down in its favor).
+    /// Either way the offer is `PartiallyFilled` until nothing is left, then `Accepted`. Its
+    /// `offer_token_amount` and `receive_token_amount` always hold what's still up for grabs.
+    /// Accounts: same as `AcceptOffer`.
+    AcceptOfferPartial {
+        fill_amount: u64, // How many of the offered tokens the taker wants
+    },
+    /// Report which version of the program is deployed.
+    /// Writes a borsh `ProgramVersion` as return data and touches nothing else.
+    /// Accounts: none.
+    GetVersion,
+    /// Report the status of many offers at once.
+    /// Writes a borsh `Vec<(Pubkey, OfferStatus)>` as return data, in account order.
+    /// Accounts that aren't offers are skipped. At most `MAX_STATUS_BATCH` accounts.
+    /// Accounts:
+    /// 0..N. `[]` offer_account: The PDAs for the offers to look up.
+    GetStatuses,
+    /// Set up the program config, making the signer its admin. Can only be done once.
+    /// Accounts:
+    /// 0. `[signer, writable]` admin_account: The new admin (pays for the config account).
+    /// 1. `[writable]` config_account: The config PDA. Created by the program.
+    /// 2. `[]` system_program: Solana's System Program.
+    InitializeConfig,
+    /// Stop all new offers and accepts involving one mint.
+    /// Accounts:
+    /// 0. `[signer, writable]` admin_account: The admin (pays for the mint pause account).
+    /// 1. `[]` config_account: The config PDA.
+    /// 2. `[writable]` mint_pause_account: The mint pause PDA. Created by the program.
+    /// 3. `[]` mint: The mint to pause.
+    /// 4. `[]` system_program: Solana's System Program.
+    PauseMint,
+    /// Let a paused mint trade again.
+    /// Accounts:
+    /// 0. `[signer, writable]` admin_account: The admin (gets the mint pause rent back).
+    /// 1. `[]` config_account: The config PDA.
+    /// 2. `[writable]` mint_pause_account: The mint pause PDA. Closed by the program.
+    UnpauseMint,
+    /// Change one program setting.
+    /// Accounts:
+    /// 0. `[signer]` admin_account: The admin.
+    /// 1. `[writable]` config_account: The config PDA.
+    UpdateConfig { field: ConfigField },
+    /// Settle two mirror-image token offers against each other (A offers X for Y, B offers Y for X,
+    /// with matching amounts). Both legs happen together and both offers end up `Accepted`.
+    /// A maker whose tokens aren't locked up has to sign to release them. Sealed offers pay out
+    /// of their vault instead, so their maker doesn't need to be around.
+    /// Accounts:
+    /// 0. `[signer]` matcher_account: Whoever puts the two offers together.
+    /// 1. `[writable]` offer_a_account: The PDA for offer A.
+    /// 2. `[writable]` offer_b_account: The PDA for offer B.
+    /// 3. `[signer]` maker_a_account: The maker of offer A (only signs if A isn't sealed).
+    /// 4. `[signer]` maker_b_account: The maker of offer B (only signs if B isn't sealed).
+    /// 5. `[writable]` maker_a_offer_token_account: Maker A's token account for X, or A's vault if sealed (sends).
+    /// 6. `[writable]` maker_a_receive_token_account: Maker A's token account for Y (receives).
+    /// 7. `[writable]` maker_b_offer_token_account: Maker B's token account for Y, or B's vault if sealed (sends).
+    /// 8. `[writable]` maker_b_receive_token_account: Maker B's token account for X (receives).
+    /// 9. `[]` mint_x: The mint offer A gives.
+    /// 10. `[]` mint_y: The mint offer A wants.
+    /// 11. `[]` token_program: SPL Token Program (or Token-2022).
+    /// 12. `[]` mint_x_pause: Mint pause PDA for X (may not exist).
+    /// 13. `[]` mint_y_pause: Mint pause PDA for Y (may not exist).
+    /// 14. `[writable]` maker_a_index: Maker A's index PDA.
+    /// 15. `[writable]` maker_b_index: Maker B's index PDA.
+    /// 16. `[]` config_account: The config PDA (may not exist).
+    /// 17. `[writable]` order_book_a: The order book PDA for offer A's pair (X for Y, may not exist).
+    /// 18. `[writable]` order_book_b: The order book PDA for offer B's pair (Y for X, may not exist).
+    MatchOffers,
+    /// Open a direct offer up to everyone. The named taker is forgotten, and the offer becomes
+    /// `PublicBuy` if the maker escrowed SOL, or `PublicSell` otherwise.
+    /// Accounts:
+    /// 0. `[signer]` offer_maker_account: The person who made the offer.
+    /// 1. `[writable]` offer_account: The PDA for the offer data.
+    MakePublic,
+    /// Suggest a different price for an offer without making a counter-offer. Records a
+    /// non-binding `Negotiation` the maker can read; asking again replaces the old proposal.
+    /// Accounts:
+    /// 0. `[signer, writable]` taker_account: The person asking (pays for the negotiation account).
+    /// 1. `[]` offer_account: The PDA for the offer data.
+    /// 2. `[writable]` negotiation_account: The negotiation PDA. Created by the program if needed.
+    /// 3. `[]` system_program: Solana's System Program.
+    RequestTerms {
+        proposed_receive: u64, // What the taker would pay instead
+    },
+    /// Carry out a large trade booked by `AcceptOffer`/`AcceptOfferPartial` once its delay is up.
+    /// The taker in the settlement signs, and the trade then goes exactly like an accept.
+    /// Accounts: same as `AcceptOffer`. The settlement is closed and its rent goes to the taker.
+    FinalizeSettlement,
+    /// Call off a large trade that's still waiting. Either the maker or the taker can do it.
+    /// Accounts:
+    /// 0. `[signer]` authority_account: The offer's maker or the settlement's taker.
+    /// 1. `[]` offer_account: The PDA for the offer data.
+    /// 2. `[writable]` settlement_account: The offer's settlement PDA. Closed by the program.
+    /// 3. `[writable]` taker_account: The settlement's taker (gets the rent back).
+    AbortSettlement,
+    /// Offer to hand the admin role over to someone else. Nothing changes until they accept,
+    /// so a typo'd key can't take over. Proposing again replaces the earlier offer.
+    /// Accounts:
+    /// 0. `[signer]` admin_account: The current admin.
+    /// 1. `[writable]` config_account: The config PDA.
+    SetPendingAuthority { new_authority: Pubkey },
+    /// Take over as admin after the current admin proposed you with `SetPendingAuthority`.
+    /// Accounts:
+    /// 0. `[signer]` new_authority_account: The pending authority.
+    /// 1. `[writable]` config_account: The config PDA.
+    AcceptAuthority,
+    /// Create a ratio order: tokens for sale at a fixed rate, so every chunk a taker fills costs
+    /// exactly `fill * rate_num / rate_den` lamports (rounded down), however it's split up.
+    /// The offer's `receive_token_amount` is what the whole lot costs at that rate.
+    /// Accounts: same as `CreateOffer`.
+    CreateRatioOrder {
+        offer_token_amount: u64,
+        rate_num: u64,
+        rate_den: u64,
+        expiration: Option<i64>,
+        min_fill: u64,
+    },
+    /// Last resort for a sealed offer whose data can't be read anymore: the admin moves
+    /// everything in the offer's vault to a recovery token account. The offer PDA signs with
+    /// seeds rebuilt from the arguments, since they can't be read from the broken offer.
+    /// Offers that still read fine are refused; they can be cancelled normally.
+    /// Accounts:
+    /// 0. `[signer]` admin_account: The admin.
+    /// 1. `[]` config_account: The config PDA.
+    /// 2. `[]` offer_account: The broken offer PDA.
+    /// 3. `[writable]` offer_token_vault: The offer PDA's associated token account for the mint.
+    /// 4. `[writable]` recovery_token_account: Where the tokens go.
+    /// 5. `[]` offer_token_mint: The mint the offer was selling.
+    /// 6. `[]` token_program: SPL Token Program (or Token-2022).
+    ForceRecoverTokens {
+        maker: Pubkey,              // The rest of the offer's seeds...
+        receive_token_mint: Pubkey, // ...
+        bump_seed: u8,              // ...its bump...
+        nonce: u64,                 // ...and its nonce
+    },
+    /// Work out what an accept would pay everyone, without moving anything.
+    /// Writes a borsh `Quote` as return data. `fill_amount` is what `AcceptOfferPartial` would
+    /// take (`None` = `AcceptOffer`). Fails the same way the accept would for a bad fill.
+    /// Accounts:
+    /// 0. `[]` offer_account: The PDA for the offer data.
+    /// 1. `[]` config_account: The config PDA (may not exist).
+    /// 2. `[]` (optional) history_account: The pair's trade history PDA, for premium orders (as for
+    ///    `AcceptOffer`).
+    /// 3. `[]` (optional) metadata_account: Metaplex metadata for the token being sold for SOL.
+    QuoteAccept { fill_amount: Option<u64> },
+    /// Close an offer PDA that our program owns but that never got its offer written (all zero
+    /// bytes), giving its lamports to the maker. The seeds come in as arguments, since there's no
+    /// offer to read them from.
+    /// Accounts:
+    /// 0. `[signer, writable]` maker_account: The maker the PDA was derived for (gets the lamports).
+    /// 1. `[writable]` offer_account: The empty offer PDA. Closed by the program.
+    ReclaimEmptyOffer {
+        offer_token_mint: Pubkey,
+        receive_token_mint: Pubkey,
+        bump_seed: u8,
+        nonce: u64,
+    },
+    /// Settle three token offers that form a ring: A's tokens go to B, B's to C, and C's to A.
+    /// Each offer must give exactly the mint and amount the next one wants, so everybody ends up
+    /// with what they asked for. All three legs happen together and all three offers end up
+    /// `Accepted`. Like `MatchOffers`, it's tokens only, and a sealed offer pays out of its vault.
+    /// Accounts (three of each, in A, B, C order):
+    /// 0. `[signer]` settler_account: Whoever puts the ring together.
+    /// 1-3. `[writable]` offer_accounts: The PDAs for offers A, B and C.
+    /// 4-6. `[signer]` maker_accounts: The makers (each only signs if their offer isn't sealed).
+    /// 7-9. `[writable]` offer_token_accounts: Each maker's account for the mint they give,
+    ///      or their offer's vault if sealed (sends).
+    /// 10-12. `[writable]` receive_token_accounts: Each maker's account for the mint they want (receives).
+    /// 13-15. `[]` mints: The mint each offer gives.
+    /// 16. `[]` token_program: SPL Token Program (or Token-2022).
+    /// 17-19. `[]` mint_pauses: Mint pause PDAs for those mints (may not exist).
+    /// 20-22. `[writable]` maker_indexes: The makers' index PDAs.
+    /// 23. `[]` config_account: The config PDA (may not exist).
+    /// 24-26. `[writable]` order_books: The order book PDAs for each offer's pair (may not exist).
+    RingSettle,
+    /// Expire a batch of stale offers in one go (anyone can run it, it's for keepers).
+    /// Each active offer past its expiration ends up `Expired`, escrowed SOL goes back to its
+    /// maker, and an `OfferExpiredEvent` is logged for it. Offers that can't be expired here
+    /// (not ours, not open, not expired yet, sealed, or with the wrong maker or book accounts) are
+    /// logged and skipped, so one bad entry doesn't spoil the rest. At most `MAX_SWEEP_BATCH` offers.
+    /// Sealed offers, and yield offers with their escrow out, are skipped because they need
+    /// more accounts; cancel those instead.
+    /// The events also come back together as return data (a Borsh `Vec<OfferExpiredEvent>`).
+    /// Accounts:
+    /// 0. `[signer]` keeper_account: Whoever runs the sweep.
+    /// Then four for each offer:
+    /// 1. `[writable]` offer_account: The PDA for the offer data.
+    /// 2. `[writable]` maker_account: The offer's maker (gets any escrowed SOL back).
+    /// 3. `[writable]` maker_index: The maker's index PDA.
+    /// 4. `[writable]` order_book: The order book PDA for the offer's pair (may not exist).
+    SweepExpired,
+    /// Undo a cancel: a `Declined` offer goes back to `Active`, as long as it's within the
+    /// config's `reopen_grace` seconds of being cancelled (otherwise `InvalidOfferStatus`).
+    /// A buy offer's SOL was refunded on cancel, so the maker escrows it again. Offers that were
+    /// wiped when they closed, or have expired since, can't be reopened. It goes back in its
+    /// pair's order book, so that fails with `TooManyOffers` if the book has filled up since.
+    /// Accounts:
+    /// 0. `[signer, writable]` offer_maker_account: The maker (pays the escrow back in).
+    /// 1. `[writable]` offer_account: The PDA for the offer data.
+    /// 2. `[]` system_program: Solana's System Program.
+    /// 3. `[]` config_account: The config PDA (may not exist, then nothing can be reopened).
+    /// 4. `[writable]` maker_index: The maker's index PDA.
+    /// 5. `[writable]` order_book: The pair's order book PDA (may not exist).
+    ReopenOffer,
+    /// The named taker of a `Pending` direct offer says they're interested, making it `Active`
+    /// (and acceptable). Until then the offer can only be cancelled, so nobody can be pushed
+    /// into direct offers they never wanted.
+    /// Accounts:
+    /// 0. `[signer]` taker_account: The offer's named taker.
+    /// 1. `[writable]` offer_account: The PDA for the offer data.
+    ConfirmInterest,
+    /// Expire one open offer that's past its expiration and close it. Anyone can call this (a
+    /// keeper bot, say): escrowed SOL and a sealed offer's tokens go back to the maker, the offer
+    /// is marked `Expired`, and the maker gets the offer account's rent back as it's closed.
+    /// Offers that haven't expired (or never do) fail with `InvalidOfferStatus`.
+    /// Accounts:
+    /// 0. `[writable]` offer_account: The PDA for the offer data.
+    /// 1. `[writable]` maker_account: The offer's maker (gets the rent back).
+    /// 2. `[writable]` maker_sol_account: Maker's SOL account (gets any escrowed SOL back).
+    /// 3. `[]` system_program: Solana's System Program.
+    /// 4. `[writable]` maker_index: The maker's index PDA.
+    /// 5. `[writable]` order_book: The pair's order book PDA (may not exist).
+    /// 6. `[writable]` (optional) offer_token_vault: The sealed offer's vault (to refund escrowed tokens).
+    /// 7. `[writable]` (optional) maker_token_account: Maker's token account for the offered token.
+    /// 8. `[]` (optional) offer_token_mint: The mint account of the offered token.
+    /// 9. `[]` (optional) token_program: SPL Token Program (or Token-2022).
+    ///
+    /// Yield offers take their strategy accounts from 6 on instead, like `CancelOffer`.
+    ExpireOffer,
+    /// Cancel every open offer the maker has for one mint pair, refunding escrowed SOL, so a
+    /// maker winding down a market doesn't need a cancel per offer. Offers that aren't the
+    /// signer's, are for another pair, aren't open, or are sealed or earning yield (they need
+    /// more accounts; cancel those one by one) are logged and skipped. At most `MAX_CANCEL_BATCH` offers.
+    /// Each cancel is remembered for the recreate cool-down, like `CancelOffer`'s.
+    /// Accounts:
+    /// 0. `[signer, writable]` offer_maker_account: The maker (gets the escrow back).
+    /// 1. `[writable]` maker_index: The maker's index PDA.
+    /// 2. `[writable]` recent_cancel: The maker's recent-cancel PDA for the pair (may not exist).
+    /// 3. `[writable]` order_book: The pair's order book PDA (may not exist).
+    /// 4. `[writable]` offer_accounts (4 onwards): The offers to cancel.
+    CancelAllForPair {
+        offer_token_mint: Pubkey,
+        receive_token_mint: Pubkey,
+    },
+    /// Create a premium order: tokens for sale at `premium_bps` over the price of the pair's
+    /// latest recorded trade (in its `TradeHistory`), worked out when it's accepted. With no
+    /// trade history to go on, it sells for `floor_receive_amount` like a plain offer would.
+    /// Partial fills are priced the same way, and the floor comes down in step.
+    /// Accounts: same as `CreateOffer`.
+    CreatePremiumOrder {
+        offer_token_amount: u64,
+        premium_bps: u16,
+        floor_receive_amount: u64, // What the whole lot sells for without any trade history
+        expiration: Option<i64>,
+        min_fill: u64,
+    },
+    /// Change an active offer's terms in place, instead of cancelling and recreating it. Only
+    /// the maker can, and not for counter-offers or offers that aren't `Active`
+    /// (`InvalidOfferStatus`), or while a large trade on it waits to settle.
+    /// Escrow follows the offered amount: more escrowed SOL is pulled in from the maker and
+    /// less is refunded, and a sealed offer's vault is topped up or drawn down the same way.
+    /// A ratio order's price comes from its rate, so only its amount and expiration can change.
+    /// A Dutch auction's comes from its schedule, which runs to its expiration, so only its
+    /// amount can. A new expiration before the offer's start time fails with `InvalidInstruction`.
+    /// Accounts:
+    /// 0. `[signer, writable]` offer_maker_account: The maker (pays in or gets back escrowed SOL).
+    /// 1. `[writable]` offer_account: The PDA for the offer data.
+    /// 2. `[]` system_program: Solana's System Program.
+    /// 3. `[]` config_account: The config PDA (may not exist).
+    /// 4. `[]` settlement_account: The offer's settlement PDA (must not exist).
+    /// 5. `[writable]` (optional) offer_token_vault: The sealed offer's vault (if the amount changes).
+    /// 6. `[writable]` (optional) maker_token_account: Maker's token account for the offered token.
+    /// 7. `[]` (optional) offer_token_mint: The mint account of the offered token.
+    /// 8. `[]` (optional) token_program: SPL Token Program (or Token-2022).
+    UpdateOffer {
+        new_offer_amount: Option<u64>,       // `None` = keep it
+        new_receive_amount: Option<u64>,     // `None` = keep it
+        new_expiration: Option<Option<i64>>, // `Some(None)` = never expire
+    },
+    /// Reprice many of the maker's offers at once, each like `UpdateOffer` would. `updates[i]`
+    /// goes with the i-th offer. Offers that aren't the signer's, aren't `Active`, or can't take
+    /// their update (including sealed offers whose amount changes, since there's no room for
+    /// their vault accounts) are logged and skipped. At most `MAX_UPDATE_BATCH` offers.
+    /// Accounts:
+    /// 0. `[signer, writable]` offer_maker_account: The maker (pays in or gets back escrowed SOL).
+    /// 1. `[]` system_program: Solana's System Program.
+    /// 2. `[]` config_account: The config PDA (may not exist).
+    /// 3. `[writable]` offer_accounts (3 onwards): Each offer followed by its `[]` settlement PDA, in update order.
+    UpdateOfferBatch { updates: Vec<OfferUpdate> },
+    /// Check whether `taker` could accept an offer right now, so front-ends can grey out the
+    /// button. Writes a borsh `AcceptEligibility` as return data; nothing is changed, and the
+    /// taker doesn't sign. Covers the offer's status, expiration, the direct-offer taker, self
+    /// trades and paused mints, the same checks the accept makes before looking at any money.
+    /// Accounts:
+    /// 0. `[]` offer_account: The PDA for the offer data.
+    /// 1. `[]` offer_mint_pause: Mint pause PDA for the offered mint (may not exist).
+    /// 2. `[]` receive_mint_pause: Mint pause PDA for the received mint (may not exist).
+    CanAccept { taker: Pubkey },
+    /// Create a public buy offer whose escrowed SOL earns yield while it waits: once escrowed,
+    /// it's deposited with `yield_strategy` (see `YieldInstruction`). Cancelling or expiring the
+    /// offer withdraws it again, and fails with `YieldShortfall` unless at least the whole
+    /// escrow comes back; anything earned goes straight to the maker. While deposited,
+    /// nothing else can touch the escrow (`EscrowInYield`), so takers put a `WithdrawYield`
+    /// ahead of their accept.
+    /// Accounts: same as `CreateOffer` up to the taker (16, ignored), then:
+    /// 17. `[]` yield_strategy: The strategy program.
+    /// 18. `[writable]` yield_vault: Where the strategy keeps the escrow. Gets the deposit.
+    /// 19. (optional) Any other accounts the strategy needs, passed on as given.
+    CreateYieldOffer {
+        offer_token_amount: u64, // Lamports to escrow
+        receive_token_amount: u64,
+        expiration: Option<i64>,
+        min_fill: u64,
+        yield_strategy: Pubkey,
+    },
+    /// Bring a yield offer's escrow back from its strategy so it can be accepted, paying what it
+    /// earned to the maker. Anyone can; it fails with `YieldShortfall` if the strategy comes up
+    /// short.
+    /// Accounts:
+    /// 0. `[writable]` offer_account: The PDA for the offer data.
+    /// 1. `[writable]` maker_account: The offer's maker (gets the earnings).
+    /// 2. `[]` yield_strategy: The offer's strategy program.
+    /// 3. `[writable]` yield_vault: The strategy's vault, then any other accounts it needs.
+    WithdrawYield,
+    /// Cancel an offer and create its replacement in one go, for repricing that changes the
+    /// mints (which `UpdateOffer` can't, since they're part of the seeds). The new offer is made
+    /// as by `CreateOffer`, then the old one is cancelled as by `CancelOffer` with
+    /// `close_account`, its escrow and rent going back to the maker. Either both happen or
+    /// neither does. Only the old offer's maker can do this, and they need room for one more
+    /// open offer while the new one is made. Sealed and yield offers have to be cancelled on
+    /// their own.
+    /// Accounts:
+    /// 0. `[writable]` old_offer_account: The PDA of the offer being replaced. Closed by the program.
+    /// 1. `[writable]` old_order_book: The old offer's pair order book PDA (may not exist).
+    /// 2. `[writable]` old_recent_cancel: The maker's recent cancel PDA for the old pair (may not exist).
+    /// 3. Then the accounts for `CreateOffer`, from the maker (signer) on. The maker SOL account
+    ///    (18 here) is required and must be the maker's wallet.
+    ReplaceOffer {
+        offer_type: OfferType,
+        offer_token_amount: u64,
+        receive_token_amount: u64,
+        expiration: Option<i64>,
+        min_fill: u64,
+        receive_recipient: Option<Pubkey>,
+        nonce: u64,
+    },
+    /// Accept an offer on terms the maker signed off-chain, so the maker doesn't have to sign
+    /// (or be online). The transaction carries an ed25519 program instruction, right before this
+    /// one, verifying the maker's signature over the borsh bytes of `approval`; the approval must
+    /// name this offer and taker, match the offer's current amounts and `last_updated_at`, and
+    /// not have passed `expires_at`, or it fails with `InvalidApproval`.
+    /// When the maker sells tokens without a sealed vault, they must have made the offer PDA a
+    /// delegate of their token account (SPL `Approve`) for at least the amount sold; the offer
+    /// signs the transfer as that delegate. A large trade booked this way still needs the maker
+    /// at `FinalizeSettlement`.
+    /// Accounts:
+    /// 0. `[]` instructions_sysvar: The instructions sysvar.
+    /// 1. Then the accounts for `AcceptOffer`, from the taker (signer) on. The maker needn't sign.
+    AcceptWithApproval { approval: AcceptApproval },
+    /// Create a Dutch auction: tokens for sale at `start_price`, falling in a straight line to
+    /// `end_price` between `start_time` (when it's made, if `None`) and `expiration`. A taker
+    /// pays whatever it's come down to when they accept (see `dutch_auction_price`), and takes
+    /// the whole lot; partial fills fail with `InvalidFillAmount`. The end price can't be above
+    /// the start price, and after `expiration` the auction is over like any other offer.
+    /// With a `min_acceptable_receive` floor (0 for none, at most the start price), the auction
+    /// is over as soon as its price comes down below that instead: an accept from then on marks
+    /// it `Expired` and fails with `OfferExpired`, so it never sells for less.
+    /// Accounts: same as `CreateOffer`.
+    CreateDutchAuction {
+        offer_token_amount: u64,
+        start_price: u64,
+        end_price: u64,
+        start_time: Option<i64>,
+        expiration: i64,
+        min_acceptable_receive: u64,
+    },
+    /// Add `amount` lamports to a live offer's escrowed SOL, offering that much more for the same
+    /// tokens. Only the maker can, and only for offers escrowing SOL (not out earning yield).
+    /// The escrow can't go over the config's `max_escrow_per_offer` (`AmountTooLarge`).
+    /// Accounts:
+    /// 0. `[signer, writable]` offer_maker_account: The maker (pays the top-up).
+    /// 1. `[writable]` offer_account: The PDA for the offer data.
+    /// 2. `[]` system_program: Solana's System Program.
+    /// 3. `[]` config_account: The config PDA (may not exist).
+    TopUpEscrow { amount: u64 },
+    /// Rewrite an offer stored in an older layout (see `Offer::CURRENT_VERSION`) in the current
+    /// one, with defaults for any fields it didn't have. Anyone can call it; the payer covers the
+    /// extra rent, which stays with the offer (and goes to the maker when it's closed). Offers that
+    /// are already current are left alone; ones we can't read fail with `UnsupportedVersion`.
+    /// Accounts:
+    /// 0. `[signer, writable]` payer: Pays for the bigger locker.
+    /// 1. `[writable]` offer_account: The PDA for the offer data.
+    /// 2. `[]` system_program: Solana's System Program.
+    MigrateOffer,
+    /// Give a mint pair an order book with room for `capacity` open offers (at most
+    /// `OrderBook::MAX_CAPACITY`). From then on, offers made on the pair are listed in it, and
+    /// once it's full new ones fail with `TooManyOffers` until a slot frees up. Offers already
+    /// open on the pair aren't listed, and don't count.
+    /// Accounts:
+    /// 0. `[signer, writable]` admin_account: The admin (pays for the order book account).
+    /// 1. `[]` config_account: The config PDA.
+    /// 2. `[writable]` order_book: The pair's order book PDA. Created by the program.
+    /// 3. `[]` offer_token_mint: The mint the pair's offers give.
+    /// 4. `[]` receive_token_mint: The mint they want in return.
+    /// 5. `[]` system_program: Solana's System Program.
+    InitOrderBook { capacity: u16 },
+    /// Take offers that are no longer open (or closed altogether) off a pair's order book,
+    /// freeing their slots. Anyone can call it. Offers that are still open, or
+    /// aren't on the book, are left alone.
+    /// Accounts:
+    /// 0. `[writable]` order_book: The pair's order book PDA.
+    /// 1. `[]` offer_accounts (1 onwards): The offers to check.
+    PruneOrderBook,
+    /// Put a mint on the config's allowlist (at most `ProgramConfig::MAX_ALLOWED_MINTS`). Once
+    /// the list has anything on it, `CreateOffer` (and its variants) and `CounterOffer` fail with
+    /// `MintNotAllowed` unless both mints are listed, so SOL has to be listed (as the native mint)
+    /// for SOL offers to go on. Offers already open aren't affected. A mint that's already listed
+    /// fails with `InvalidInstruction`.
+    /// Accounts:
+    /// 0. `[signer]` admin_account: The admin.
+    /// 1. `[writable]` config_account: The config PDA.
+    AddAllowedMint { mint: Pubkey },
+    /// Take a mint off the config's allowlist. Taking the last one off lets every mint trade
+    /// again. A mint that isn't listed fails with `InvalidInstruction`.
+    /// Accounts:
+    /// 0. `[signer]` admin_account: The admin.
+    /// 1. `[writable]` config_account: The config PDA.
+    RemoveAllowedMint { mint: Pubkey },
+    /// Stop (or restart) trading everywhere at once, for emergencies. While paused,
+    /// `CreateOffer`, `AcceptOffer`, `CounterOffer` and `ReopenOffer` fail with
+    /// `ProgramPaused`. Cancels and expiries still go through, so everyone can get their
+    /// escrow back.
+    /// Accounts:
+    /// 0. `[signer]` admin_account: The admin.
+    /// 1. `[writable]` config_account: The config PDA.
+    SetPaused { paused: bool },
+    /// Take several offers in one go, one after another, each like a whole `AcceptOffer` (no
+    /// slippage bounds, and the records kept). If one fails the whole thing does, after logging
+    /// which; more than `MAX_ACCEPT_BATCH` fails with `TooManyOffers`.
+    /// Accounts:
+    /// `count` groups of `ACCEPT_BATCH_GROUP_LEN`, one per offer: `AcceptOffer`'s accounts 0 to
+    /// 18 (taker through history account; the treasury and history accounts can be placeholders
+    /// when they're not needed). There's no room for royalty accounts, so nothing bought this way
+    /// pays a royalty; accept NFTs one at a time for that.
+    AcceptOffers { count: u8 },
+    /// Accept a whole offer like `AcceptOffer`, but deliver the taker's side into a custodian's
+    /// vault instead of to the taker. The vault is the PDA of `custody_program` for
+    /// `vault_seeds` (which must include its bump); anything else fails with
+    /// `InvalidProgramAddress`. The taker still signs and pays. Large trades (see
+    /// `AcceptOffer`) can't be accepted this way.
+    /// Accounts:
+    /// The accounts for `AcceptOffer`, except that when the taker gets tokens the taker token
+    /// account is one the vault owns, and when they get SOL the taker SOL account is the vault.
+    AcceptToVault {
+        custody_program: Pubkey,
+        vault_seeds: Vec<Vec<u8>>,
+    },
+    /// Break one of the maker's open offers into smaller ones, each `(offer_token_amount,
+    /// receive_token_amount)` in `parts`, so the pieces can sit at different prices. The offered
+    /// amounts must add up to what's left of the original (`InvalidAmount` otherwise); the
+    /// received amounts are each piece's own price. The pieces are otherwise copies of the
+    /// original, at the maker's next nonces on the pair (the original's plus 1, plus 2, ...),
+    /// and its escrowed SOL is shared out between them. The original is closed, its rent going
+    /// back to the maker. At most `MAX_SPLIT_PARTS` pieces. Sealed offers (`SealedOffer`), yield
+    /// offers with their escrow out, and offers priced by rate, premium or auction can't be split,
+    /// nor can one while a large trade on it waits to settle (`SettlementPending`).
+    /// Accounts:
+    /// 0. `[signer, writable]` maker_account: The offer's maker. Pays the new offers' rent.
+    /// 1. `[writable]` offer_account: The offer to split.
+    /// 2. `[]` system_program: Solana's System Program.
+    /// 3. `[]` config_account: The config PDA (may not exist).
+    /// 4. `[writable]` maker_index: The maker's index PDA.
+    /// 5. `[writable]` order_book: The pair's order book PDA (may not exist).
+    /// 6. `[]` settlement_account: The offer's settlement PDA (must not exist).
+    /// 7. `[writable]` child_offer_accounts (7 onwards): The new offers' PDAs, one per part, in order.
+    SplitOffer { parts: Vec<(u64, u64)> },
+    /// Turn down a direct offer: only its named taker can, so they don't have to wait for it to
+    /// expire. The offer ends up `Declined` like a cancelled one, and the maker gets back any
+    /// escrowed SOL and, for a sealed offer, the vault's tokens. Anyone else fails with
+    /// `Unauthorized`; public offers and offers that aren't open fail with `InvalidOfferStatus`.
+    /// A rejected offer can't be reopened.
+    /// Accounts:
+    /// 0. `[signer]` taker_account: The taker the direct offer names.
+    /// 1. `[writable]` offer_account: The PDA for the offer data.
+    /// 2. `[writable]` maker_account: The maker's wallet. Gets the escrowed SOL (and the rent, with
+    ///    `close_account`).
+    /// 3. `[writable]` maker_index: The maker's index PDA.
+    /// 4. `[writable]` order_book: The pair's order book PDA (may not exist). The offer comes off it.
+    /// 5. `[writable]` (optional) offer_token_vault: The sealed offer's vault (to refund escrowed tokens).
+    /// 6. `[writable]` (optional) maker_token_account: Maker's token account for the offered token.
+    /// 7. `[]` (optional) offer_token_mint: The mint account of the offered token.
+    /// 8. `[]` (optional) token_program: SPL Token Program (or Token-2022).
+    ///
+    /// An offer whose escrow is out with a yield strategy takes the strategy program, vault and
+    /// any other strategy accounts from 5 on instead, and withdraws the escrow before refunding it.
+    ///
+    /// With `close_account`, the offer is closed after it's marked, like `CancelOffer` does.
+    RejectOffer { close_account: bool },
+}
+
+// --- Processor (The Brain of Our Vending Machine) ---
+// This is where all the magic happens! It takes an instruction and figures out what to do.
+pub struct Processor;
+
+impl Processor {
+    // This is the main function that Solana calls when someone interacts with our program.
+    pub fn process(
+        program_id: &Pubkey,      // Our program's address
+        accounts: &[AccountInfo], // All the lockers involved in this transaction
+        instruction_data: &[u8],  // The "button" pressed and any extra info
+    ) -> ProgramResult {
+        // First, let's figure out which "button" was pressed.
+        let data = &mut &instruction_data[..];
+        let instruction = SwapInstruction::deserialize(data) // Use borsh to unpack!
+            .map_err(|_| SwapError::InvalidInstruction)?;
+        // A real button with junk after it means the client built the data wrong.
+        if !data.is_empty() {
+            msg!("{} unexpected bytes after the instruction", data.len());
+            return Err(SwapError::MalformedInstructionData.into());
+        }
+
+        // Now, based on the button, we call the right function.
+        match instruction {
+            SwapInstruction::CreateOffer {
+                offer_type,
+                offer_token_amount,
                 receive_token_amount,
                 expiration,
-                bump_seed,
+                min_fill,
+                duration_secs,
+                receive_recipient,
+                confirm_first,
+                nonce,
+                start_time,
+                client_id,
+                auto_cancel_on_fail,
             } => {
                 msg!("Instruction: CreateOffer");
+                let expiration = Self::resolve_expiration(expiration, duration_secs)?;
+                // Only a direct offer has someone to confirm it.
+                if confirm_first && offer_type != OfferType::Direct {
+                    return Err(SwapError::InvalidInstruction.into());
+                }
                 Self::process_create_offer(
                     program_id,
                     accounts,
@@ -210,18 +1934,54 @@ impl Processor {
                     offer_token_amount,
                     receive_token_amount,
                     expiration,
-                    bump_seed,
+                    min_fill,
+                    None,
+                    None,
+                    receive_recipient,
+                    confirm_first,
+                    None,
+                    nonce,
+                    start_time,
+                    None,
+                    client_id,
+                    auto_cancel_on_fail,
                 )
             }
-            SwapInstruction::AcceptOffer => {
+            SwapInstruction::AcceptOffer {
+                close_account,
+                min_receive_amount,
+                max_send_amount,
+            } => {
                 msg!("Instruction: AcceptOffer");
-                Self::process_accept_offer(program_id, accounts)
+                Self::process_accept_offer(
+                    program_id,
+                    accounts,
+                    None,
+                    false,
+                    close_account,
+                    (min_receive_amount, max_send_amount),
+                    false,
+                    None,
+                )
+            }
+            SwapInstruction::AcceptOfferPartial { fill_amount } => {
+                msg!("Instruction: AcceptOfferPartial");
+                Self::process_accept_offer(
+                    program_id,
+                    accounts,
+                    Some(fill_amount),
+                    false,
+                    false,
+                    (None, None),
+                    false,
+                    None,
+                )
             }
             SwapInstruction::CounterOffer {
                 offer_token_amount,
                 receive_token_amount,
                 expiration,
-                bump_seed,
+                start_time,
             } => {
                 msg!("Instruction: CounterOffer");
                 Self::process_counter_offer(
@@ -230,17 +1990,403 @@ impl Processor {
                     offer_token_amount,
                     receive_token_amount,
                     expiration,
-                    bump_seed,
+                    start_time,
                 )
             }
-            SwapInstruction::CancelOffer => {
+            SwapInstruction::CancelOffer { close_account } => {
                 msg!("Instruction: CancelOffer");
-                Self::process_cancel_offer(program_id, accounts)
+                Self::process_cancel_offer(program_id, accounts, close_account)
+            }
+            SwapInstruction::GetVersion => {
+                msg!("Instruction: GetVersion");
+                set_return_data(&borsh::to_vec(&PROGRAM_VERSION)?);
+                Ok(())
+            }
+            SwapInstruction::GetStatuses => {
+                msg!("Instruction: GetStatuses");
+                Self::process_get_statuses(program_id, accounts)
+            }
+            SwapInstruction::InitializeConfig => {
+                msg!("Instruction: InitializeConfig");
+                Self::process_initialize_config(program_id, accounts)
+            }
+            SwapInstruction::PauseMint => {
+                msg!("Instruction: PauseMint");
+                Self::process_pause_mint(program_id, accounts)
+            }
+            SwapInstruction::UnpauseMint => {
+                msg!("Instruction: UnpauseMint");
+                Self::process_unpause_mint(program_id, accounts)
+            }
+            SwapInstruction::UpdateConfig { field } => {
+                msg!("Instruction: UpdateConfig");
+                Self::process_update_config(program_id, accounts, field)
+            }
+            SwapInstruction::MatchOffers => {
+                msg!("Instruction: MatchOffers");
+                Self::process_match_offers(program_id, accounts)
+            }
+            SwapInstruction::MakePublic => {
+                msg!("Instruction: MakePublic");
+                Self::process_make_public(program_id, accounts)
+            }
+            SwapInstruction::RequestTerms { proposed_receive } => {
+                msg!("Instruction: RequestTerms");
+                Self::process_request_terms(program_id, accounts, proposed_receive)
+            }
+            SwapInstruction::FinalizeSettlement => {
+                msg!("Instruction: FinalizeSettlement");
+                Self::process_accept_offer(
+                    program_id,
+                    accounts,
+                    None,
+                    true,
+                    false,
+                    (None, None),
+                    false,
+                    None,
+                )
+            }
+            SwapInstruction::AbortSettlement => {
+                msg!("Instruction: AbortSettlement");
+                Self::process_abort_settlement(program_id, accounts)
+            }
+            SwapInstruction::SetPendingAuthority { new_authority } => {
+                msg!("Instruction: SetPendingAuthority");
+                Self::process_set_pending_authority(program_id, accounts, new_authority)
+            }
+            SwapInstruction::AcceptAuthority => {
+                msg!("Instruction: AcceptAuthority");
+                Self::process_accept_authority(program_id, accounts)
+            }
+            SwapInstruction::CreateRatioOrder {
+                offer_token_amount,
+                rate_num,
+                rate_den,
+                expiration,
+                min_fill,
+            } => {
+                msg!("Instruction: CreateRatioOrder");
+                // The whole lot at the order's rate, like any other offer's total.
+                let receive_token_amount =
+                    Self::ratio_amount(rate_num, rate_den, offer_token_amount)?;
+                Self::process_create_offer(
+                    program_id,
+                    accounts,
+                    OfferType::RatioOrder,
+                    offer_token_amount,
+                    receive_token_amount,
+                    expiration,
+                    min_fill,
+                    Some((rate_num, rate_den)),
+                    None,
+                    None,
+                    false,
+                    None,
+                    0,
+                    None,
+                    None,
+                    [0; 8],
+                    false,
+                )
+            }
+            SwapInstruction::QuoteAccept { fill_amount } => {
+                msg!("Instruction: QuoteAccept");
+                Self::process_quote_accept(program_id, accounts, fill_amount)
+            }
+            SwapInstruction::ReclaimEmptyOffer {
+                offer_token_mint,
+                receive_token_mint,
+                bump_seed,
+                nonce,
+            } => {
+                msg!("Instruction: ReclaimEmptyOffer");
+                Self::process_reclaim_empty_offer(
+                    program_id,
+                    accounts,
+                    offer_token_mint,
+                    receive_token_mint,
+                    bump_seed,
+                    nonce,
+                )
+            }
+            SwapInstruction::ForceRecoverTokens {
+                maker,
+                receive_token_mint,
+                bump_seed,
+                nonce,
+            } => {
+                msg!("Instruction: ForceRecoverTokens");
+                Self::process_force_recover_tokens(
+                    program_id,
+                    accounts,
+                    maker,
+                    receive_token_mint,
+                    bump_seed,
+                    nonce,
+                )
+            }
+            SwapInstruction::RingSettle => {
+                msg!("Instruction: RingSettle");
+                Self::process_ring_settle(program_id, accounts)
+            }
+            SwapInstruction::SweepExpired => {
+                msg!("Instruction: SweepExpired");
+                Self::process_sweep_expired(program_id, accounts)
+            }
+            SwapInstruction::ReopenOffer => {
+                msg!("Instruction: ReopenOffer");
+                Self::process_reopen_offer(program_id, accounts)
+            }
+            SwapInstruction::ConfirmInterest => {
+                msg!("Instruction: ConfirmInterest");
+                Self::process_confirm_interest(program_id, accounts)
+            }
+            SwapInstruction::ExpireOffer => {
+                msg!("Instruction: ExpireOffer");
+                Self::process_expire_offer(program_id, accounts)
+            }
+            SwapInstruction::CancelAllForPair {
+                offer_token_mint,
+                receive_token_mint,
+            } => {
+                msg!("Instruction: CancelAllForPair");
+                Self::process_cancel_all_for_pair(
+                    program_id,
+                    accounts,
+                    offer_token_mint,
+                    receive_token_mint,
+                )
+            }
+            SwapInstruction::CreatePremiumOrder {
+                offer_token_amount,
+                premium_bps,
+                floor_receive_amount,
+                expiration,
+                min_fill,
+            } => {
+                msg!("Instruction: CreatePremiumOrder");
+                Self::process_create_offer(
+                    program_id,
+                    accounts,
+                    OfferType::PremiumOrder,
+                    offer_token_amount,
+                    floor_receive_amount,
+                    expiration,
+                    min_fill,
+                    None,
+                    Some(premium_bps),
+                    None,
+                    false,
+                    None,
+                    0,
+                    None,
+                    None,
+                    [0; 8],
+                    false,
+                )
+            }
+            SwapInstruction::UpdateOffer {
+                new_offer_amount,
+                new_receive_amount,
+                new_expiration,
+            } => {
+                msg!("Instruction: UpdateOffer");
+                Self::process_update_offer(
+                    program_id,
+                    accounts,
+                    new_offer_amount,
+                    new_receive_amount,
+                    new_expiration,
+                )
+            }
+            SwapInstruction::UpdateOfferBatch { updates } => {
+                msg!("Instruction: UpdateOfferBatch");
+                Self::process_update_offer_batch(program_id, accounts, &updates)
+            }
+            SwapInstruction::CanAccept { taker } => {
+                msg!("Instruction: CanAccept");
+                Self::process_can_accept(program_id, accounts, &taker)
+            }
+            SwapInstruction::CreateYieldOffer {
+                offer_token_amount,
+                receive_token_amount,
+                expiration,
+                min_fill,
+                yield_strategy,
+            } => {
+                msg!("Instruction: CreateYieldOffer");
+                Self::process_create_offer(
+                    program_id,
+                    accounts,
+                    OfferType::PublicBuy,
+                    offer_token_amount,
+                    receive_token_amount,
+                    expiration,
+                    min_fill,
+                    None,
+                    None,
+                    None,
+                    false,
+                    Some(yield_strategy),
+                    0,
+                    None,
+                    None,
+                    [0; 8],
+                    false,
+                )
+            }
+            SwapInstruction::WithdrawYield => {
+                msg!("Instruction: WithdrawYield");
+                Self::process_withdraw_yield(program_id, accounts)
+            }
+            SwapInstruction::ReplaceOffer {
+                offer_type,
+                offer_token_amount,
+                receive_token_amount,
+                expiration,
+                min_fill,
+                receive_recipient,
+                nonce,
+            } => {
+                msg!("Instruction: ReplaceOffer");
+                let [
+                    old_offer_account,
+                    old_order_book,
+                    old_recent_cancel,
+                    create_accounts @ ..,
+                ] = accounts
+                else {
+                    return Err(ProgramError::NotEnoughAccountKeys);
+                };
+                // Create first: the cancel pays out of the old PDA directly, and that has to
+                // come after the create's CPIs.
+                Self::process_create_offer(
+                    program_id,
+                    create_accounts,
+                    offer_type,
+                    offer_token_amount,
+                    receive_token_amount,
+                    expiration,
+                    min_fill,
+                    None,
+                    None,
+                    receive_recipient,
+                    false,
+                    None,
+                    nonce,
+                    None,
+                    None,
+                    [0; 8],
+                    false,
+                )?;
+                Self::process_replace_offer(
+                    program_id,
+                    old_offer_account,
+                    old_order_book,
+                    old_recent_cancel,
+                    create_accounts,
+                )
+            }
+            SwapInstruction::AcceptWithApproval { approval } => {
+                msg!("Instruction: AcceptWithApproval");
+                Self::process_accept_with_approval(program_id, accounts, approval)
+            }
+            SwapInstruction::TopUpEscrow { amount } => {
+                msg!("Instruction: TopUpEscrow");
+                Self::process_top_up_escrow(program_id, accounts, amount)
+            }
+            SwapInstruction::CreateDutchAuction {
+                offer_token_amount,
+                start_price,
+                end_price,
+                start_time,
+                expiration,
+                min_acceptable_receive,
+            } => {
+                msg!("Instruction: CreateDutchAuction");
+                Self::process_create_offer(
+                    program_id,
+                    accounts,
+                    OfferType::DutchAuction,
+                    offer_token_amount,
+                    start_price,
+                    Some(expiration),
+                    0,
+                    None,
+                    None,
+                    None,
+                    false,
+                    None,
+                    0,
+                    start_time,
+                    Some((end_price, min_acceptable_receive)),
+                    [0; 8],
+                    false,
+                )
+            }
+            SwapInstruction::MigrateOffer => {
+                msg!("Instruction: MigrateOffer");
+                Self::process_migrate_offer(program_id, accounts)
+            }
+            SwapInstruction::InitOrderBook { capacity } => {
+                msg!("Instruction: InitOrderBook");
+                Self::process_init_order_book(program_id, accounts, capacity)
+            }
+            SwapInstruction::PruneOrderBook => {
+                msg!("Instruction: PruneOrderBook");
+                Self::process_prune_order_book(program_id, accounts)
+            }
+            SwapInstruction::AddAllowedMint { mint } => {
+                msg!("Instruction: AddAllowedMint");
+                Self::process_set_mint_allowed(program_id, accounts, mint, true)
+            }
+            SwapInstruction::RemoveAllowedMint { mint } => {
+                msg!("Instruction: RemoveAllowedMint");
+                Self::process_set_mint_allowed(program_id, accounts, mint, false)
+            }
+            SwapInstruction::SetPaused { paused } => {
+                msg!("Instruction: SetPaused");
+                Self::process_set_paused(program_id, accounts, paused)
+            }
+            SwapInstruction::AcceptOffers { count } => {
+                msg!("Instruction: AcceptOffers");
+                Self::process_accept_offers(program_id, accounts, count)
+            }
+            SwapInstruction::AcceptToVault {
+                custody_program,
+                vault_seeds,
+            } => {
+                msg!("Instruction: AcceptToVault");
+                let seeds: Vec<&[u8]> = vault_seeds.iter().map(Vec::as_slice).collect();
+                let vault = Pubkey::create_program_address(&seeds, &custody_program)
+                    .map_err(|_| SwapError::InvalidProgramAddress)?;
+                msg!("Delivering to vault {} of {}", vault, custody_program);
+                Self::process_accept_offer(
+                    program_id,
+                    accounts,
+                    None,
+                    false,
+                    false,
+                    (None, None),
+                    false,
+                    Some(&vault),
+                )
+            }
+            SwapInstruction::SplitOffer { parts } => {
+                msg!("Instruction: SplitOffer");
+                Self::process_split_offer(program_id, accounts, &parts)
+            }
+            SwapInstruction::RejectOffer { close_account } => {
+                msg!("Instruction: RejectOffer");
+                Self::process_reject_offer(program_id, accounts, close_account)
             }
         }
     }
 
     // --- Helper function to transfer SOL (money) ---
+    // Only for wallets the System Program owns; escrow in our own PDAs goes through
+    // `transfer_sol_from_pda`.
     fn transfer_sol(
         account_infos: &[AccountInfo], // [from_account, to_account, system_program]
         amount: u64,
@@ -262,6 +2408,7 @@ impl Processor {
         if from_account.lamports() < amount {
             return Err(SwapError::InsufficientFunds.into());
         }
+        Self::check_sol_destination(to_account, amount)?;
         // Create an instruction to transfer SOL.
         let transfer_instruction = system_instruction::transfer(
             from_account.key, // From whom
@@ -277,656 +2424,5241 @@ impl Processor {
         Ok(())
     }
 
-    // --- Helper function to transfer SPL Tokens (shiny cards) ---
-    // This function helps us move tokens between accounts.
-    fn transfer_spl_token(
-        account_infos: &[AccountInfo], // [from_token_account, mint_account, to_token_account, from_authority, token_program]
+    // --- Helper to make sure a brand-new SOL account ends up rent-exempt ---
+    // Any wallet can receive SOL, but an empty one that gets less than the rent-exempt minimum
+    // would be rejected by Solana at the end of the transaction. We catch it up front instead,
+    // so the error says what actually went wrong.
+    fn check_sol_destination(to_account: &AccountInfo, amount: u64) -> ProgramResult {
+        if to_account.lamports() == 0 && amount > 0 {
+            let minimum = Rent::get()?.minimum_balance(to_account.data_len());
+            if amount < minimum {
+                msg!(
+                    "{} is empty and would only get {} lamports, but needs {} to be rent-exempt",
+                    to_account.key,
+                    amount,
+                    minimum
+                );
+                return Err(SwapError::NotRentExempt.into());
+            }
+        }
+        Ok(())
+    }
+
+    // --- Helper function to pay SOL out of one of our offer lockers ---
+    // The System Program only moves SOL out of plain wallets. Our program owns the offer PDA,
+    // so it can move the lamports by hand instead.
+    fn transfer_sol_from_pda(
+        from_account: &AccountInfo, // Program-owned offer PDA holding the escrow
+        to_account: &AccountInfo,
         amount: u64,
-        mint_decimals: u8,
-        signer_seeds: Option<&[&[u8]]>,
     ) -> ProgramResult {
-        let from_token_account = &account_infos[0];
-        let mint_account = &account_infos[1];
-        let to_token_account = &account_infos[2];
-        let from_authority = &account_infos[3];
-        let token_program = &account_infos[4];
-        if !from_token_account.is_writable {
-            return Err(SwapError::InvalidAccountInput.into());
-        }
-        if !to_token_account.is_writable {
+        if !to_account.is_writable {
             return Err(SwapError::InvalidAccountInput.into());
         }
-        if token_program.key != &spl_token_program_id() {
-            return Err(SwapError::InvalidTokenProgram.into());
-        }
-        // Authority should be a signer if not program-signed
+        let from_lamports = from_account
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(SwapError::InsufficientFunds)?;
+        Self::check_sol_destination(to_account, amount)?;
+        let to_lamports = to_account
+            .lamports()
+            .checked_add(amount)
+            .ok_or(SwapError::MathOverflow)?;
+        **from_account.try_borrow_mut_lamports()? = from_lamports;
+        **to_account.try_borrow_mut_lamports()? = to_lamports;
+        Ok(())
+    }
+
+    // --- Helper to check a token account has room for a transfer ---
+    // Make sure `incoming` tokens still fit in `recipient`. A mint with a rogue authority can leave
+    // an account near `u64::MAX`; we'd rather fail with our own error than the token program's.
+    fn check_token_room(recipient: &TokenAccount, incoming: u64) -> ProgramResult {
+        recipient
+            .amount
+            .checked_add(incoming)
+            .ok_or(SwapError::MathOverflow)?;
+        Ok(())
+    }
+
+    // --- Helper to close an offer for good ---
+    // Hands all its lamports to `destination`, wipes it, and gives it back to the System Program.
+    // The lamports move by hand, so this goes after any CPIs.
+    fn close_offer_account(
+        offer_account: &AccountInfo,
+        destination: &AccountInfo,
+    ) -> ProgramResult {
+        Self::transfer_sol_from_pda(offer_account, destination, offer_account.lamports())?;
+        offer_account.data.borrow_mut().fill(0);
+        offer_account.resize(0)?;
+        offer_account.assign(&solana_system_interface::program::ID);
+        Ok(())
+    }
+
+    // --- Helpers for NFT royalties ---
+    // Works out what each creator is owed when `sale_amount` lamports are paid for `mint`.
+    // No metadata account means no royalties. Each creator's cut is rounded down; the seller
+    // keeps the dust.
+    fn royalty_payouts(
+        metadata_account: Option<&AccountInfo>,
+        mint: &Pubkey,
+        sale_amount: u64,
+    ) -> Result<Vec<(Pubkey, u64)>, ProgramError> {
+        let Some(metadata_account) = metadata_account else {
+            return Ok(Vec::new());
+        };
+        let (expected_key, _) = Pubkey::find_program_address(
+            &[b"metadata", METADATA_PROGRAM_ID.as_ref(), mint.as_ref()],
+            &METADATA_PROGRAM_ID,
+        );
+        if *metadata_account.key != expected_key || *metadata_account.owner != METADATA_PROGRAM_ID {
+            return Err(SwapError::InvalidMetadata.into());
+        }
+        let metadata = TokenMetadata::load(&metadata_account.data.borrow())?;
+        if metadata.key != METADATA_KEY_V1
+            || metadata.mint != *mint
+            || metadata.seller_fee_basis_points > 10_000
+        {
+            return Err(SwapError::InvalidMetadata.into());
+        }
+
+        let creators = metadata.creators.unwrap_or_default();
+        let royalty = mul_div(sale_amount, metadata.seller_fee_basis_points as u64, 10_000)?;
+        creators
+            .iter()
+            .map(|creator| {
+                if creator.share > 100 {
+                    return Err(SwapError::InvalidMetadata.into());
+                }
+                Ok((
+                    creator.address,
+                    mul_div(royalty, creator.share as u64, 100)?,
+                ))
+            })
+            .collect()
+    }
+
+    // All the royalties together. Shares that add up to more than 100 could make this more than
+    // the sale, which the checked subtractions after it catch.
+    fn royalty_total(royalties: &[(Pubkey, u64)]) -> Result<u64, ProgramError> {
+        royalties
+            .iter()
+            .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+            .ok_or_else(|| SwapError::MathOverflow.into())
+    }
+
+    // Pays each creator their cut. `creator_accounts` must list the creators in the metadata's
+    // order. With a system program the payer signs a transfer; without one it's a program PDA.
+    fn pay_royalties<'a>(
+        royalties: &[(Pubkey, u64)],
+        creator_accounts: &[AccountInfo<'a>],
+        payer: &AccountInfo<'a>,
+        system_program: Option<&AccountInfo<'a>>,
+    ) -> ProgramResult {
+        let mut creator_accounts = creator_accounts.iter();
+        for (creator, amount) in royalties {
+            let creator_account = creator_accounts
+                .next()
+                .ok_or(SwapError::MissingRequiredAccount)?;
+            if creator_account.key != creator {
+                return Err(SwapError::InvalidMetadata.into());
+            }
+            if *amount == 0 {
+                continue;
+            }
+            match system_program {
+                Some(system_program) => Self::transfer_sol(
+                    &[
+                        payer.clone(),
+                        creator_account.clone(),
+                        system_program.clone(),
+                    ],
+                    *amount,
+                    None,
+                )?,
+                None => Self::transfer_sol_from_pda(payer, creator_account, *amount)?,
+            }
+        }
+        Ok(())
+    }
+
+    // --- Helper to check a mint isn't paused ---
+    // The mint pause PDA must always be passed in, so nobody can skip the check by leaving it out.
+    // It only counts as "paused" if our program actually created it.
+    fn check_mint_not_paused(
+        program_id: &Pubkey,
+        mint_pause_account: &AccountInfo,
+        mint: &Pubkey,
+    ) -> ProgramResult {
+        let (expected_key, _) =
+            Pubkey::find_program_address(&[b"mintpause", mint.as_ref()], program_id);
+        if expected_key != *mint_pause_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if mint_pause_account.owner == program_id && mint_pause_account.lamports() > 0 {
+            msg!("Mint {} is paused", mint);
+            return Err(SwapError::ProgramPaused.into());
+        }
+        Ok(())
+    }
+
+    // --- Helper to check the admin hasn't stopped trading altogether ---
+    // Only new business is stopped; cancels and refunds never look at this.
+    fn check_not_paused(config: Option<&ProgramConfig>) -> ProgramResult {
+        if config.is_some_and(|config| config.paused) {
+            msg!("The program is paused");
+            return Err(SwapError::ProgramPaused.into());
+        }
+        Ok(())
+    }
+
+    // --- Helper to check both sides of an offer are on the config's allowlist ---
+    fn check_mints_allowed(
+        config: Option<&ProgramConfig>,
+        offer_token_mint: &Pubkey,
+        receive_token_mint: &Pubkey,
+    ) -> ProgramResult {
+        let Some(config) = config else {
+            return Ok(());
+        };
+        for mint in [offer_token_mint, receive_token_mint] {
+            if !config.is_mint_allowed(mint) {
+                msg!("Mint {} isn't on the allowlist", mint);
+                return Err(SwapError::MintNotAllowed.into());
+            }
+        }
+        Ok(())
+    }
+
+    // --- Helper to check the admin is who the config says it is ---
+    fn check_admin(
+        program_id: &Pubkey,
+        admin_account: &AccountInfo,
+        config_account: &AccountInfo,
+    ) -> Result<ProgramConfig, ProgramError> {
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let config = Self::load_verified_config(program_id, config_account)?;
+        if config.admin != *admin_account.key {
+            return Err(SwapError::Unauthorized.into());
+        }
+        Ok(config)
+    }
+
+    // --- Helper to load the config and make sure its locker is really ours ---
+    fn load_verified_config(
+        program_id: &Pubkey,
+        config_account: &AccountInfo,
+    ) -> Result<ProgramConfig, ProgramError> {
+        if config_account.owner != program_id {
+            return Err(SwapError::AccountNotInitialized.into());
+        }
+        let config = ProgramConfig::load(&config_account.data.borrow())?;
+        let expected_config_key =
+            Pubkey::create_program_address(&[b"config", &[config.bump_seed]], program_id)
+                .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_config_key != *config_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        Ok(config)
+    }
+
+    // --- Helper to load an offer we're going to change ---
+    // An old layout's locker is too small to write the current one back into, so it has to be
+    // migrated first.
+    fn load_offer(offer_account: &AccountInfo) -> Result<Offer, ProgramError> {
+        if offer_account.data_len() < Offer::MAX_LEN {
+            msg!(
+                "Offer {} uses an old layout; call MigrateOffer first",
+                offer_account.key
+            );
+            return Err(SwapError::UnsupportedVersion.into());
+        }
+        Offer::load(&offer_account.data.borrow())
+    }
+
+    // --- Helper to load an offer and make sure its locker is really ours ---
+    fn load_verified_offer(
+        program_id: &Pubkey,
+        offer_account: &AccountInfo,
+    ) -> Result<Offer, ProgramError> {
+        if offer_account.owner != program_id {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+        let offer_data = Self::load_offer(offer_account)?;
+        Self::check_offer_address(program_id, offer_account, &offer_data)?;
+        Ok(offer_data)
+    }
+
+    // --- Helper to check an offer's locker is the PDA its own seeds make ---
+    fn check_offer_address(
+        program_id: &Pubkey,
+        offer_account: &AccountInfo,
+        offer_data: &Offer,
+    ) -> ProgramResult {
+        let nonce_bytes = offer_data.nonce.to_le_bytes();
+        let expected_offer_key = Pubkey::create_program_address(
+            &[
+                b"offer",
+                offer_data.maker.as_ref(),
+                offer_data.offer_token_mint.as_ref(),
+                offer_data.receive_token_mint.as_ref(),
+                nonce_seed(&nonce_bytes),
+                &[offer_data.bump_seed],
+            ],
+            program_id,
+        )
+        .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_offer_key != *offer_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        Ok(())
+    }
+
+    // --- Helpers for the two token programs ---
+    // Tokens can live under the legacy SPL Token program or Token-2022; either is fine.
+    fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+        if *token_program.key != spl_token_program_id()
+            && *token_program.key != spl_token_2022::id()
+        {
+            return Err(SwapError::InvalidTokenProgram.into());
+        }
+        Ok(())
+    }
+
+    // Token-2022 accounts and mints start with the legacy layout and put any extensions after
+    // it, so once Token-2022's crate has checked the whole thing we read the base like any other.
+    fn unpack_token_account(token_account: &AccountInfo) -> Result<TokenAccount, ProgramError> {
+        let data = token_account.data.borrow();
+        if *token_account.owner == spl_token_2022::id() {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data)?;
+            TokenAccount::unpack(&data[..TokenAccount::LEN])
+        } else {
+            TokenAccount::unpack(&data)
+        }
+    }
+
+    fn unpack_mint(mint: &AccountInfo) -> Result<Mint, ProgramError> {
+        let data = mint.data.borrow();
+        if *mint.owner == spl_token_2022::id() {
+            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+            Mint::unpack(&data[..Mint::LEN])
+        } else {
+            Mint::unpack(&data)
+        }
+    }
+
+    // --- Helper to read a mint an offer is going to rely on ---
+    // Anything that isn't an initialized mint of one of the token programs is `InvalidMint`.
+    fn unpack_valid_mint(mint: &AccountInfo) -> Result<Mint, ProgramError> {
+        if *mint.owner != spl_token::id() && *mint.owner != spl_token_2022::id() {
+            msg!("{} isn't owned by a token program", mint.key);
+            return Err(SwapError::InvalidMint.into());
+        }
+        Self::unpack_mint(mint).map_err(|err| {
+            msg!("{} isn't a mint ({:?})", mint.key, err);
+            SwapError::InvalidMint.into()
+        })
+    }
+
+    // --- Helpers to check tokens can move at all ---
+    // The token program would refuse these transfers anyway, but with an error that doesn't say
+    // which account is to blame.
+    fn check_not_frozen(token_account: &TokenAccount) -> ProgramResult {
+        if token_account.is_frozen() {
+            msg!("Token account is frozen");
+            return Err(SwapError::AccountFrozen.into());
+        }
+        Ok(())
+    }
+
+    // Legacy mints can't stop transfers as a whole; Token-2022 ones can be paused or made
+    // non-transferable. SOL has no mint account to check.
+    fn check_mint_transferable(mint: &AccountInfo) -> ProgramResult {
+        if is_sol_side(mint.key) || *mint.owner != spl_token_2022::id() {
+            return Ok(());
+        }
+        let data = mint.data.borrow();
+        let mint_data = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+        let paused = mint_data
+            .get_extension::<PausableConfig>()
+            .is_ok_and(|pausable| bool::from(pausable.paused));
+        if paused || mint_data.get_extension::<NonTransferable>().is_ok() {
+            msg!("Mint {} doesn't allow transfers", mint.key);
+            return Err(SwapError::AccountFrozen.into());
+        }
+        Ok(())
+    }
+
+    // --- Helper to check a token account belongs to `owner` and holds `mint` ---
+    fn check_token_account(
+        token_account: &AccountInfo,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> ProgramResult {
+        let token_account_data = Self::unpack_token_account(token_account)?;
+        if token_account_data.owner != *owner {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+        if token_account_data.mint != *mint {
+            return Err(SwapError::TokenMismatch.into());
+        }
+        Ok(())
+    }
+
+    // --- Helper to check a sealed offer's vault ---
+    // The vault is the offer PDA's associated token account for the offered mint, under
+    // whichever token program the vault belongs to.
+    fn check_offer_vault(
+        offer_account: &AccountInfo,
+        offer_token_vault: &AccountInfo,
+        mint: &Pubkey,
+    ) -> ProgramResult {
+        if *offer_token_vault.key
+            != get_associated_token_address_with_program_id(
+                offer_account.key,
+                mint,
+                offer_token_vault.owner,
+            )
+        {
+            return Err(SwapError::InvalidAccountInput.into());
+        }
+        Self::check_token_account(offer_token_vault, offer_account.key, mint)
+    }
+
+    // --- Helpers for yield offers ---
+    // `strategy_accounts` must start with the offer's strategy program, then its vault and any
+    // other accounts it wants. The offer PDA signs.
+    fn invoke_yield_strategy<'a>(
+        offer_data: &Offer,
+        offer_account: &AccountInfo<'a>,
+        strategy_accounts: &[AccountInfo<'a>],
+        instruction: &YieldInstruction,
+    ) -> ProgramResult {
+        let [strategy_program, own_accounts @ ..] = strategy_accounts else {
+            return Err(SwapError::MissingRequiredAccount.into());
+        };
+        if offer_data.yield_strategy != Some(*strategy_program.key) {
+            return Err(SwapError::InvalidAccountInput.into());
+        }
+        let mut account_metas = vec![AccountMeta::new(*offer_account.key, true)];
+        account_metas.extend(own_accounts.iter().map(|account| AccountMeta {
+            pubkey: *account.key,
+            is_signer: account.is_signer,
+            is_writable: account.is_writable,
+        }));
+        let mut account_infos = vec![offer_account.clone()];
+        account_infos.extend_from_slice(own_accounts);
+        account_infos.push(strategy_program.clone());
+        let nonce_bytes = offer_data.nonce.to_le_bytes();
+        let offer_seeds: &[&[u8]] = &[
+            b"offer",
+            offer_data.maker.as_ref(),
+            offer_data.offer_token_mint.as_ref(),
+            offer_data.receive_token_mint.as_ref(),
+            nonce_seed(&nonce_bytes),
+            &[offer_data.bump_seed],
+        ];
+        invoke_signed(
+            &Instruction {
+                program_id: *strategy_program.key,
+                accounts: account_metas,
+                data: borsh::to_vec(instruction)?,
+            },
+            &account_infos,
+            &[offer_seeds],
+        )
+    }
+
+    // Hands a new yield offer's escrow to its strategy. Moves lamports by hand, so it goes after
+    // any other CPIs.
+    fn deposit_yield<'a>(
+        offer_data: &mut Offer,
+        offer_account: &AccountInfo<'a>,
+        strategy_accounts: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let amount = offer_data.escrow_sol_amount;
+        Self::invoke_yield_strategy(
+            offer_data,
+            offer_account,
+            strategy_accounts,
+            &YieldInstruction::Deposit { amount },
+        )?;
+        let yield_vault = strategy_accounts
+            .get(1)
+            .ok_or(SwapError::MissingRequiredAccount)?;
+        msg!("Depositing {} lamports of escrow for yield...", amount);
+        Self::transfer_sol_from_pda(offer_account, yield_vault, amount)?;
+        offer_data.yield_principal = amount;
+        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    // Brings a yield offer's escrow home; does nothing if it's already there. The strategy can
+    // add to the escrow but never eat into it: anything short of the principal fails the whole
+    // transaction. What it earned goes straight on to the maker's wallet, so the offer PDA only
+    // ever holds its rent and its escrow. That's a lamport move by hand, so this goes after any
+    // other CPIs.
+    fn withdraw_yield<'a>(
+        offer_data: &mut Offer,
+        offer_account: &AccountInfo<'a>,
+        maker_account: &AccountInfo<'a>,
+        strategy_accounts: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let principal = offer_data.yield_principal;
+        if principal == 0 {
+            return Ok(());
+        }
+        if *maker_account.key != offer_data.maker {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+        let lamports_before = offer_account.lamports();
+        Self::invoke_yield_strategy(
+            offer_data,
+            offer_account,
+            strategy_accounts,
+            &YieldInstruction::Withdraw,
+        )?;
+        let returned = offer_account.lamports().saturating_sub(lamports_before);
+        if returned < principal {
+            msg!(
+                "Yield strategy returned {} of the {} lamports deposited",
+                returned,
+                principal
+            );
+            return Err(SwapError::YieldShortfall.into());
+        }
+        let earned = returned - principal;
+        msg!(
+            "Yield strategy returned {} lamports ({} earned)",
+            returned,
+            earned
+        );
+        offer_data.yield_principal = 0;
+        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+        if earned > 0 {
+            Self::transfer_sol_from_pda(offer_account, maker_account, earned)?;
+        }
+        Ok(())
+    }
+
+    // Anything that pays out or changes an offer's escrow needs it back from the strategy first.
+    fn check_escrow_home(offer_data: &Offer) -> ProgramResult {
+        if offer_data.yield_principal > 0 {
+            msg!("The escrow is deposited with its yield strategy; withdraw it first");
+            return Err(SwapError::EscrowInYield.into());
+        }
+        Ok(())
+    }
+
+    // --- Helper to log a `SwapEvent` for indexers ---
+    fn log_event(event: &SwapEvent) -> ProgramResult {
+        solana_program::log::sol_log_data(&[SWAP_EVENT, &borsh::to_vec(event)?]);
+        Ok(())
+    }
+
+    // --- Helper to keep one offer's escrow within bounds ---
+    fn check_escrow_cap(config: Option<&ProgramConfig>, escrow_sol: u64) -> ProgramResult {
+        if let Some(config) = config
+            && config.max_escrow_per_offer > 0
+            && escrow_sol > config.max_escrow_per_offer
+        {
+            msg!(
+                "Escrow of {} lamports is over the cap of {}",
+                escrow_sol,
+                config.max_escrow_per_offer
+            );
+            return Err(SwapError::AmountTooLarge.into());
+        }
+        Ok(())
+    }
+
+    // --- Helper to hand a sealed offer's tokens back to its maker ---
+    // Does nothing for unsealed offers. Otherwise `vault_accounts` must start with the vault, the
+    // maker's token account, the offered mint and the token program. The vault is recorded as
+    // empty before the CPI, so nothing it calls sees tokens to refund.
+    fn refund_sealed_tokens<'a>(
+        offer_data: &mut Offer,
+        offer_account: &AccountInfo<'a>,
+        vault_accounts: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        if offer_data.escrow_token_amount == 0 {
+            return Ok(());
+        }
+        let [
+            offer_token_vault,
+            maker_token_account,
+            offer_token_mint,
+            token_program,
+            ..,
+        ] = vault_accounts
+        else {
+            return Err(SwapError::MissingRequiredAccount.into());
+        };
+        if *offer_token_mint.key != offer_data.offer_token_mint {
+            return Err(SwapError::TokenMismatch.into());
+        }
+        Self::check_offer_vault(offer_account, offer_token_vault, offer_token_mint.key)?;
+        Self::check_token_account(maker_token_account, &offer_data.maker, offer_token_mint.key)?;
+
+        let mint_info = Self::unpack_mint(offer_token_mint)?;
+        let refund = offer_data.escrow_token_amount;
+        offer_data.escrow_token_amount = 0;
+        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+        msg!("Refunding {} tokens from the vault to maker...", refund);
+        let nonce_bytes = offer_data.nonce.to_le_bytes();
+        let offer_seeds: &[&[u8]] = &[
+            b"offer",
+            offer_data.maker.as_ref(),
+            offer_data.offer_token_mint.as_ref(),
+            offer_data.receive_token_mint.as_ref(),
+            nonce_seed(&nonce_bytes),
+            &[offer_data.bump_seed],
+        ];
+        Self::transfer_spl_token(
+            &[
+                offer_token_vault.clone(),
+                offer_token_mint.clone(),
+                maker_token_account.clone(),
+                offer_account.clone(),
+                token_program.clone(),
+            ],
+            refund,
+            mint_info.decimals,
+            Some(offer_seeds), // Program is signing for the vault
+        )
+    }
+
+    // --- Helper to work out the treasury's cut of an amount ---
+    // Rounds down, so tiny trades may pay nothing.
+    fn protocol_fee(amount: u64, fee_bps: u16) -> Result<u64, ProgramError> {
+        Ok(mul_div(amount, fee_bps as u64, 10_000)?)
+    }
+
+    // --- Helper to read the config, if the admin has set one up ---
+    // The config PDA must always be passed in; if it doesn't exist yet, every setting is off.
+    fn load_config(
+        program_id: &Pubkey,
+        config_account: &AccountInfo,
+    ) -> Result<Option<ProgramConfig>, ProgramError> {
+        let (expected_config_key, _) = Pubkey::find_program_address(&[b"config"], program_id);
+        if expected_config_key != *config_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if config_account.owner != program_id {
+            return Ok(None);
+        }
+        Ok(Some(ProgramConfig::load(&config_account.data.borrow())?))
+    }
+
+    // --- Helper to count a new active offer against its maker ---
+    // Creates the maker's tally the first time (the maker pays), then refuses to go over the cap.
+    fn open_maker_slot<'a>(
+        program_id: &Pubkey,
+        maker_account: &AccountInfo<'a>,
+        maker_index_account: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        config: Option<&ProgramConfig>,
+    ) -> ProgramResult {
+        let (expected_key, bump_seed) =
+            Pubkey::find_program_address(&[b"maker", maker_account.key.as_ref()], program_id);
+        if expected_key != *maker_index_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+
+        let mut maker_index = if maker_index_account.data_len() == 0 {
+            let space = MakerIndex::MAX_LEN;
+            let rent_lamports = Rent::get()?.minimum_balance(space);
+            invoke_signed(
+                &system_instruction::create_account(
+                    maker_account.key,
+                    maker_index_account.key,
+                    rent_lamports,
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    maker_account.clone(),
+                    maker_index_account.clone(),
+                    system_program.clone(),
+                ],
+                &[&[b"maker", maker_account.key.as_ref(), &[bump_seed]]],
+            )?;
+            MakerIndex {
+                maker: *maker_account.key,
+                active_offers: 0,
+                bump_seed,
+            }
+        } else {
+            if maker_index_account.owner != program_id {
+                return Err(SwapError::IncorrectOwner.into());
+            }
+            MakerIndex::try_from_slice(&maker_index_account.data.borrow())?
+        };
+
+        let max_active_offers = config.map_or(0, |config| config.max_active_offers_per_maker);
+        if max_active_offers > 0 && maker_index.active_offers >= max_active_offers {
+            msg!(
+                "Maker {} already has {} active offers",
+                maker_account.key,
+                maker_index.active_offers
+            );
+            return Err(SwapError::TooManyOffers.into());
+        }
+        maker_index.active_offers = maker_index
+            .active_offers
+            .checked_add(1)
+            .ok_or(SwapError::TooManyOffers)?;
+        maker_index.serialize(&mut &mut maker_index_account.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    // --- Helper to keep a maker from re-posting an offer they just cancelled ---
+    // With a cool-down set, the maker's recent cancel record for the pair is made the first time
+    // (the maker pays), and an offer with the same amounts as the last one cancelled there is
+    // refused until the cool-down has passed. Without one, there's nothing to check.
+    fn check_recreate_cooldown<'a>(
+        program_id: &Pubkey,
+        maker_account: &AccountInfo<'a>,
+        recent_cancel_account: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        config: Option<&ProgramConfig>,
+        (offer_token_mint, receive_token_mint): (&Pubkey, &Pubkey),
+        (offer_token_amount, receive_token_amount): (u64, u64),
+    ) -> ProgramResult {
+        let seeds: &[&[u8]] = &[
+            b"recent",
+            maker_account.key.as_ref(),
+            offer_token_mint.as_ref(),
+            receive_token_mint.as_ref(),
+        ];
+        let (expected_key, bump_seed) = Pubkey::find_program_address(seeds, program_id);
+        if expected_key != *recent_cancel_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        let cooldown = config.map_or(0, |config| config.recreate_cooldown);
+        if cooldown <= 0 {
+            return Ok(());
+        }
+
+        if recent_cancel_account.data_len() == 0 {
+            let space = RecentCancel::MAX_LEN;
+            let rent_lamports = Rent::get()?.minimum_balance(space);
+            invoke_signed(
+                &system_instruction::create_account(
+                    maker_account.key,
+                    recent_cancel_account.key,
+                    rent_lamports,
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    maker_account.clone(),
+                    recent_cancel_account.clone(),
+                    system_program.clone(),
+                ],
+                &[&[
+                    b"recent",
+                    maker_account.key.as_ref(),
+                    offer_token_mint.as_ref(),
+                    receive_token_mint.as_ref(),
+                    &[bump_seed],
+                ]],
+            )?;
+            RecentCancel {
+                maker: *maker_account.key,
+                offer_token_amount: 0,
+                receive_token_amount: 0,
+                cancelled_at: 0,
+                bump_seed,
+            }
+            .serialize(&mut &mut recent_cancel_account.data.borrow_mut()[..])?;
+            return Ok(());
+        }
+        if recent_cancel_account.owner != program_id {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+
+        let recent = RecentCancel::try_from_slice(&recent_cancel_account.data.borrow())?;
+        let same_offer = recent.offer_token_amount == offer_token_amount
+            && recent.receive_token_amount == receive_token_amount;
+        let ready_at = recent.cancelled_at.saturating_add(cooldown);
+        if recent.cancelled_at > 0 && same_offer && current_timestamp()? < ready_at {
+            msg!(
+                "The same offer was cancelled here; it can be posted again at {}",
+                ready_at
+            );
+            return Err(SwapError::CooldownActive.into());
+        }
+        Ok(())
+    }
+
+    // --- Helper to remember a cancel for the recreate cool-down ---
+    // Only makers who already have a record on the pair (made by a create while there was a
+    // cool-down) get it filled in; everyone else's cancels cost nothing extra.
+    fn record_cancel(
+        program_id: &Pubkey,
+        recent_cancel_account: &AccountInfo,
+        offer: &Offer,
+    ) -> ProgramResult {
+        if recent_cancel_account.owner != program_id || recent_cancel_account.lamports() == 0 {
+            let (expected_key, _) = Pubkey::find_program_address(
+                &[
+                    b"recent",
+                    offer.maker.as_ref(),
+                    offer.offer_token_mint.as_ref(),
+                    offer.receive_token_mint.as_ref(),
+                ],
+                program_id,
+            );
+            if expected_key != *recent_cancel_account.key {
+                return Err(SwapError::InvalidProgramAddress.into());
+            }
+            return Ok(());
+        }
+
+        let mut recent = RecentCancel::try_from_slice(&recent_cancel_account.data.borrow())?;
+        let expected_key = Pubkey::create_program_address(
+            &[
+                b"recent",
+                offer.maker.as_ref(),
+                offer.offer_token_mint.as_ref(),
+                offer.receive_token_mint.as_ref(),
+                &[recent.bump_seed],
+            ],
+            program_id,
+        )
+        .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_key != *recent_cancel_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        recent.offer_token_amount = offer.offer_token_amount;
+        recent.receive_token_amount = offer.receive_token_amount;
+        recent.cancelled_at = offer.cancelled_at;
+        recent.serialize(&mut &mut recent_cancel_account.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    // --- Helper to save an offer that's done for good ---
+    // If the offer asked to be wiped, we clear the whole locker first so none of the old
+    // (longer) data is left hanging around past the end of the new (shorter) one.
+    fn close_out_offer(
+        offer: &mut Offer,
+        status: OfferStatus,
+        offer_account: &AccountInfo,
+    ) -> ProgramResult {
+        offer.status = status;
+        offer.last_updated_at = current_timestamp()?;
+        let mut data = offer_account.data.borrow_mut();
+        if offer.zeroize_on_terminal {
+            offer.zeroize_details();
+            data.fill(0);
+        }
+        offer.serialize(&mut &mut data[..])?;
+        Ok(())
+    }
+
+    // --- Helper to stop counting an offer that's no longer active ---
+    // Makers whose offers predate the tally have no index yet, so there's nothing to take off.
+    fn release_maker_slot(
+        program_id: &Pubkey,
+        maker: &Pubkey,
+        maker_index_account: &AccountInfo,
+    ) -> ProgramResult {
+        if maker_index_account.owner != program_id {
+            let (expected_key, _) =
+                Pubkey::find_program_address(&[b"maker", maker.as_ref()], program_id);
+            if expected_key != *maker_index_account.key {
+                return Err(SwapError::InvalidProgramAddress.into());
+            }
+            return Ok(());
+        }
+
+        let mut maker_index = MakerIndex::try_from_slice(&maker_index_account.data.borrow())?;
+        let expected_key = Pubkey::create_program_address(
+            &[b"maker", maker.as_ref(), &[maker_index.bump_seed]],
+            program_id,
+        )
+        .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_key != *maker_index_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        maker_index.active_offers = maker_index.active_offers.saturating_sub(1);
+        maker_index.serialize(&mut &mut maker_index_account.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    // --- Helper to load a pair's order book, if it has one ---
+    // Like the mint pause, the book PDA must always be passed in, so nobody can dodge the cap by
+    // leaving it out. It only counts if our program actually created it.
+    fn load_order_book(
+        program_id: &Pubkey,
+        order_book_account: &AccountInfo,
+        offer_token_mint: &Pubkey,
+        receive_token_mint: &Pubkey,
+    ) -> Result<Option<OrderBook>, ProgramError> {
+        let (expected_key, _) = Pubkey::find_program_address(
+            &[
+                b"book",
+                offer_token_mint.as_ref(),
+                receive_token_mint.as_ref(),
+            ],
+            program_id,
+        );
+        if expected_key != *order_book_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if order_book_account.owner != program_id || order_book_account.lamports() == 0 {
+            return Ok(None);
+        }
+        Ok(Some(OrderBook::load(&order_book_account.data.borrow())?))
+    }
+
+    // --- Helper to list a new offer in its pair's book (if there is one) ---
+    fn list_in_order_book(
+        program_id: &Pubkey,
+        order_book_account: &AccountInfo,
+        offer: &Offer,
+        offer_key: &Pubkey,
+    ) -> ProgramResult {
+        let Some(mut book) = Self::load_order_book(
+            program_id,
+            order_book_account,
+            &offer.offer_token_mint,
+            &offer.receive_token_mint,
+        )?
+        else {
+            return Ok(());
+        };
+        if book.is_full() {
+            msg!("The order book is full ({} offers)", book.capacity);
+            return Err(SwapError::TooManyOffers.into());
+        }
+        book.offers.push(*offer_key);
+        book.serialize(&mut &mut order_book_account.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    // --- Helper to free an offer's slot in its pair's book ---
+    // Offers made before the book (or without one) aren't listed, so there's nothing to take off.
+    fn delist_from_order_book(
+        program_id: &Pubkey,
+        order_book_account: &AccountInfo,
+        offer: &Offer,
+        offer_key: &Pubkey,
+    ) -> ProgramResult {
+        let Some(mut book) = Self::load_order_book(
+            program_id,
+            order_book_account,
+            &offer.offer_token_mint,
+            &offer.receive_token_mint,
+        )?
+        else {
+            return Ok(());
+        };
+        let Some(position) = book.offers.iter().position(|key| key == offer_key) else {
+            return Ok(());
+        };
+        book.offers.remove(position);
+        // The list got shorter, so clear the slot it used to end in.
+        let mut data = order_book_account.data.borrow_mut();
+        data.fill(0);
+        book.serialize(&mut &mut data[..])?;
+        Ok(())
+    }
+
+    // --- Helper function to transfer SPL Tokens (shiny cards) ---
+    // This function helps us move tokens between accounts.
+    fn transfer_spl_token(
+        account_infos: &[AccountInfo], // [from_token_account, mint_account, to_token_account, from_authority, token_program]
+        amount: u64,
+        mint_decimals: u8,
+        signer_seeds: Option<&[&[u8]]>,
+    ) -> ProgramResult {
+        let from_token_account = &account_infos[0];
+        let mint_account = &account_infos[1];
+        let to_token_account = &account_infos[2];
+        let from_authority = &account_infos[3];
+        let token_program = &account_infos[4];
+        if !from_token_account.is_writable {
+            return Err(SwapError::InvalidAccountInput.into());
+        }
+        if !to_token_account.is_writable {
+            return Err(SwapError::InvalidAccountInput.into());
+        }
+        Self::check_token_program(token_program)?;
+        // Authority should be a signer if not program-signed
         if signer_seeds.is_none() && !from_authority.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
-        // Create an instruction to transfer tokens.
-        let transfer_instruction = transfer_checked(
-            token_program.key,      // The SPL Token program's address
-            from_token_account.key, // From this token account
-            mint_account.key,       // The token mint (type of token)
-            to_token_account.key,   // To this token account
-            from_authority.key,
-            &[],
+        // Create an instruction to transfer tokens.
+        let transfer_instruction = transfer_checked(
+            token_program.key,      // The SPL Token program's address
+            from_token_account.key, // From this token account
+            mint_account.key,       // The token mint (type of token)
+            to_token_account.key,   // To this token account
+            from_authority.key,
+            &[],
+            amount,
+            mint_decimals,
+        )?;
+        // Call the SPL Token program to actually do the transfer.
+        let result = if let Some(seeds) = signer_seeds {
+            invoke_signed(&transfer_instruction, account_infos, &[seeds])
+        } else {
+            invoke(&transfer_instruction, account_infos)
+        };
+        result.map_err(|err| Self::map_token_error(err, from_token_account, to_token_account))
+    }
+
+    // --- Helper to explain SPL Token failures ---
+    // A raw token program error doesn't say which of our transfers went wrong, so we log the
+    // accounts and the original error, then turn the common ones into our own "oops!" codes.
+    // Anything we don't recognize is passed along untouched.
+    fn map_token_error(
+        err: ProgramError,
+        from_token_account: &AccountInfo,
+        to_token_account: &AccountInfo,
+    ) -> ProgramError {
+        msg!(
+            "Token transfer from {} to {} failed: {:?}",
+            from_token_account.key,
+            to_token_account.key,
+            err
+        );
+        match err {
+            ProgramError::Custom(code) if code == TokenError::InsufficientFunds as u32 => {
+                SwapError::InsufficientFunds.into()
+            }
+            ProgramError::Custom(code) if code == TokenError::OwnerMismatch as u32 => {
+                SwapError::IncorrectOwner.into()
+            }
+            ProgramError::Custom(code) if code == TokenError::AccountFrozen as u32 => {
+                SwapError::AccountFrozen.into()
+            }
+            other => other,
+        }
+    }
+
+    // --- Helper to price a partial fill ---
+    // The taker pays the same share of `receive_token_amount` as the share of the offer they take.
+    // We round up so the maker is never short-changed by a tiny fill.
+    fn proportional_amount(offer: &Offer, fill_amount: u64) -> Result<u64, ProgramError> {
+        let numerator = (offer.receive_token_amount as u128) * (fill_amount as u128);
+        let denominator = offer.offer_token_amount as u128;
+        let amount = numerator.div_ceil(denominator);
+        u64::try_from(amount).map_err(|_| SwapError::MathOverflow.into())
+    }
+
+    // What a ratio order charges for `fill_amount` tokens: `fill_amount * rate_num / rate_den`,
+    // rounded down. A rate with a zero in it, or a fill too small to cost anything, won't do.
+    fn ratio_amount(rate_num: u64, rate_den: u64, fill_amount: u64) -> Result<u64, ProgramError> {
+        if rate_num == 0 || rate_den == 0 {
+            return Err(SwapError::InvalidAmount.into());
+        }
+        match mul_div(fill_amount, rate_num, rate_den)? {
+            0 => Err(SwapError::InvalidFillAmount.into()),
+            amount => Ok(amount),
+        }
+    }
+
+    // What a premium order charges for `fill_amount` tokens: the reference trade's price plus
+    // `premium_bps`, rounded up like any other partial fill.
+    fn premium_amount(
+        reference: &Trade,
+        premium_bps: u16,
+        fill_amount: u64,
+    ) -> Result<u64, ProgramError> {
+        let amount = (fill_amount as u128)
+            .checked_mul(reference.quote_amount as u128)
+            .and_then(|amount| amount.checked_mul(10_000 + premium_bps as u128))
+            .ok_or(SwapError::MathOverflow)?
+            .div_ceil(reference.base_amount as u128 * 10_000);
+        match u64::try_from(amount) {
+            Ok(0) => Err(SwapError::InvalidFillAmount.into()),
+            Ok(amount) => Ok(amount),
+            Err(_) => Err(SwapError::MathOverflow.into()),
+        }
+    }
+
+    // --- Helper to find what a premium order prices off ---
+    // The latest trade in the pair's history, or `None` (use the floor) if nothing's been
+    // recorded. Other offers don't need a history, so whatever was passed is ignored for them.
+    fn reference_trade(
+        program_id: &Pubkey,
+        offer: &Offer,
+        history_account_opt: Option<&AccountInfo>,
+    ) -> Result<Option<Trade>, ProgramError> {
+        if offer.offer_type != OfferType::PremiumOrder {
+            return Ok(None);
+        }
+        // Has to be the real PDA, or a taker could skip a pricier history by passing another.
+        let history_account = history_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+        let (expected_key, _) = Pubkey::find_program_address(
+            &[
+                b"history",
+                offer.offer_token_mint.as_ref(),
+                offer.receive_token_mint.as_ref(),
+            ],
+            program_id,
+        );
+        if expected_key != *history_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if history_account.owner != program_id {
+            return Ok(None);
+        }
+        let history = TradeHistory::load(&history_account.data.borrow())?;
+        Ok(history
+            .latest()
+            .copied()
+            .filter(|trade| trade.base_amount > 0))
+    }
+
+    // A partial fill of a token sale has to take something, no more than is left, and at least
+    // the maker's minimum (unless it's the last of it).
+    fn check_fill(offer: &Offer, fill: u64) -> ProgramResult {
+        if fill == 0 || fill > offer.offer_token_amount {
+            return Err(SwapError::InvalidFillAmount.into());
+        }
+        if fill < offer.min_fill && fill != offer.offer_token_amount {
+            return Err(SwapError::FillTooSmall.into());
+        }
+        Ok(())
+    }
+
+    // --- Helper to size up a token sale ---
+    // For an offer selling tokens for SOL: `(tokens, lamports)` for taking `fill_amount` of it
+    // (`None` = all). Ratio orders always charge their fixed rate, rather than a share of what's
+    // left, and premium orders with a `reference` trade charge its price plus their premium.
+    // Dutch auctions charge what they've come down to by `now`, and only sell all at once.
+    fn token_sale_amounts(
+        offer: &Offer,
+        fill_amount: Option<u64>,
+        reference: Option<&Trade>,
+        now: i64,
+    ) -> Result<(u64, u64), ProgramError> {
+        let is_ratio_order = offer.offer_type == OfferType::RatioOrder;
+        if offer.offer_type == OfferType::DutchAuction {
+            if fill_amount.is_some_and(|fill| fill != offer.offer_token_amount) {
+                msg!("A Dutch auction sells everything at once");
+                return Err(SwapError::InvalidFillAmount.into());
+            }
+            return Ok((offer.offer_token_amount, dutch_auction_price(offer, now)?));
+        }
+        if let Some(reference) = reference {
+            let fill = fill_amount.unwrap_or(offer.offer_token_amount);
+            if fill_amount.is_some() {
+                Self::check_fill(offer, fill)?;
+            }
+            return Ok((
+                fill,
+                Self::premium_amount(reference, offer.premium_bps, fill)?,
+            ));
+        }
+        match fill_amount {
+            None if is_ratio_order => Ok((
+                offer.offer_token_amount,
+                Self::ratio_amount(offer.rate_num, offer.rate_den, offer.offer_token_amount)?,
+            )),
+            None => Ok((offer.offer_token_amount, offer.receive_token_amount)),
+            Some(fill) => {
+                Self::check_fill(offer, fill)?;
+                let sol_amount = if is_ratio_order {
+                    Self::ratio_amount(offer.rate_num, offer.rate_den, fill)?
+                } else {
+                    Self::proportional_amount(offer, fill)?
+                };
+                Ok((fill, sol_amount))
+            }
+        }
+    }
+
+    // --- Helper to size up an escrow release ---
+    // For an offer buying tokens with escrowed SOL: `(tokens, lamports)` for a taker delivering
+    // `fill_amount` of the tokens still wanted (`None` = all). A partial fill gets the same share
+    // of the escrow, rounded down so the escrow never pays out more than its share.
+    fn escrow_release_amounts(
+        offer: &Offer,
+        fill_amount: Option<u64>,
+    ) -> Result<(u64, u64), ProgramError> {
+        match fill_amount {
+            None => Ok((offer.receive_token_amount, offer.escrow_sol_amount)),
+            Some(fill) if fill == 0 || fill > offer.receive_token_amount => {
+                Err(SwapError::InvalidFillAmount.into())
+            }
+            // The last of the tokens gets the last of the escrow, rounding dust included.
+            Some(fill) if fill == offer.receive_token_amount => Ok((fill, offer.escrow_sol_amount)),
+            Some(fill) => {
+                match mul_div(offer.escrow_sol_amount, fill, offer.receive_token_amount)? {
+                    0 => Err(SwapError::InvalidFillAmount.into()),
+                    lamports => Ok((fill, lamports)),
+                }
+            }
+        }
+    }
+
+    // --- Helper to hold the taker to their bounds ---
+    // What the taker would get and pay for this accept, measured against the
+    // `(min_receive_amount, max_send_amount)` they signed. Royalties come out of the escrow a
+    // seller gets, and out of (not on top of) what a buyer pays.
+    fn check_slippage(
+        program_id: &Pubkey,
+        offer: &Offer,
+        fill_amount: Option<u64>,
+        history_account_opt: Option<&AccountInfo>,
+        metadata_account_opt: Option<&AccountInfo>,
+        (min_receive_amount, max_send_amount): (Option<u64>, Option<u64>),
+        now: i64,
+    ) -> ProgramResult {
+        if min_receive_amount.is_none() && max_send_amount.is_none() {
+            return Ok(());
+        }
+        let (receive, send) = if offer.escrow_sol_amount > 0 {
+            let (token_amount, sol_amount) = Self::escrow_release_amounts(offer, fill_amount)?;
+            let royalty = Self::royalty_total(&Self::royalty_payouts(
+                metadata_account_opt,
+                &offer.receive_token_mint,
+                sol_amount,
+            )?)?;
+            let receive = sol_amount
+                .checked_sub(royalty)
+                .ok_or(SwapError::MathOverflow)?;
+            (receive, token_amount)
+        } else {
+            let reference = Self::reference_trade(program_id, offer, history_account_opt)?;
+            Self::token_sale_amounts(offer, fill_amount, reference.as_ref(), now)?
+        };
+        if min_receive_amount.is_some_and(|min| receive < min)
+            || max_send_amount.is_some_and(|max| send > max)
+        {
+            msg!(
+                "Taker would get {} and pay {} (bounds: at least {:?}, at most {:?})",
+                receive,
+                send,
+                min_receive_amount,
+                max_send_amount
+            );
+            return Err(SwapError::SlippageExceeded.into());
+        }
+        Ok(())
+    }
+
+    // --- Helper to find a signature the runtime already checked ---
+    // The ed25519 program verifies signatures before any other instruction runs, so all that's
+    // left is making sure the instruction right before ours verified `signer` signing exactly
+    // `message`, with everything read from its own data (not pointed at some other instruction).
+    fn check_ed25519_signature(
+        instructions_sysvar: &AccountInfo,
+        signer: &Pubkey,
+        message: &[u8],
+    ) -> ProgramResult {
+        if *instructions_sysvar.key != solana_instructions_sysvar::ID {
+            return Err(SwapError::InvalidAccountInput.into());
+        }
+        let current = solana_instructions_sysvar::load_current_index_checked(instructions_sysvar)?;
+        let verify_ix = current
+            .checked_sub(1)
+            .map(|index| {
+                solana_instructions_sysvar::load_instruction_at_checked(
+                    index as usize,
+                    instructions_sysvar,
+                )
+            })
+            .transpose()?
+            .filter(|ix| ix.program_id == ed25519_program::ID)
+            .ok_or(SwapError::InvalidApproval)?;
+
+        // One signature: [count, padding], then its offsets (seven little-endian u16s).
+        let data = &verify_ix.data;
+        if data.len() < 16 || data[0] != 1 {
+            return Err(SwapError::InvalidApproval.into());
+        }
+        let field = |i: usize| u16::from_le_bytes([data[2 + 2 * i], data[3 + 2 * i]]) as usize;
+        let (public_key_offset, message_offset, message_size) = (field(2), field(4), field(5));
+        if [field(1), field(3), field(6)]
+            .iter()
+            .any(|&index| index != u16::MAX as usize)
+        {
+            msg!("The signature has to be checked against its own instruction's data");
+            return Err(SwapError::InvalidApproval.into());
+        }
+        let signed_key = data.get(public_key_offset..public_key_offset + 32);
+        let signed_message = data.get(message_offset..message_offset + message_size);
+        if signed_key != Some(signer.as_ref()) || signed_message != Some(message) {
+            msg!("The maker's signature is over something else");
+            return Err(SwapError::InvalidApproval.into());
+        }
+        Ok(())
+    }
+
+    // Turn a "this long from now" into the absolute time we store. Only one of the two, please!
+    // An offer that would expire before it starts could never be taken.
+    fn check_start_time(start_time: Option<i64>, expiration: Option<i64>) -> ProgramResult {
+        if let (Some(start), Some(exp)) = (start_time, expiration)
+            && start > exp
+        {
+            msg!("The offer would expire before it starts");
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        Ok(())
+    }
+
+    fn resolve_expiration(
+        expiration: Option<i64>,
+        duration_secs: Option<u64>,
+    ) -> Result<Option<i64>, ProgramError> {
+        match (expiration, duration_secs) {
+            (Some(_), Some(_)) => {
+                msg!("Give an expiration or a duration, not both");
+                Err(SwapError::InvalidInstruction.into())
+            }
+            (_, Some(duration_secs)) => {
+                let now = current_timestamp()?;
+                i64::try_from(duration_secs)
+                    .ok()
+                    .and_then(|duration| now.checked_add(duration))
+                    .map(Some)
+                    .ok_or_else(|| SwapError::InvalidInstruction.into())
+            }
+            (expiration, None) => Ok(expiration),
+        }
+    }
+
+    // --- Process CreateOffer Instruction ---
+    #[allow(clippy::too_many_arguments)]
+    fn process_create_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_type: OfferType,
+        offer_token_amount: u64,
+        receive_token_amount: u64,
+        expiration: Option<i64>,
+        min_fill: u64,
+        rate: Option<(u64, u64)>, // (rate_num, rate_den) for ratio orders
+        premium_bps: Option<u16>, // For premium orders
+        receive_recipient: Option<Pubkey>,
+        confirm_first: bool,
+        yield_strategy: Option<Pubkey>, // For yield offers
+        nonce: u64,
+        start_time: Option<i64>,
+        auction: Option<(u64, u64)>, // (end_price, min_acceptable_receive) for Dutch auctions
+        client_id: [u8; 8],
+        auto_cancel_on_fail: bool,
+    ) -> ProgramResult {
+        msg!("Processing CreateOffer...");
+        let account_info_iter = &mut accounts.iter();
+
+        // Get all the lockers we need from the list.
+        let maker_account = next_account_info(account_info_iter)?; // The person making the offer
+        let offer_account = next_account_info(account_info_iter)?; // The new locker for this offer (PDA)
+        let maker_token_account = next_account_info(account_info_iter)?; // Maker's token account (for what they offer)
+        let offer_token_mint = next_account_info(account_info_iter)?; // The type of token being offered
+        let receive_token_mint = next_account_info(account_info_iter)?; // The type of token/SOL expected
+        let system_program = next_account_info(account_info_iter)?; // Solana's basic program
+        let token_program = next_account_info(account_info_iter)?; // SPL Token program
+        let rent_sysvar = next_account_info(account_info_iter)?; // Rent checker
+        let offer_mint_pause = next_account_info(account_info_iter)?; // Is the offered mint paused?
+        let receive_mint_pause = next_account_info(account_info_iter)?; // Is the received mint paused?
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+        let maker_index_account = next_account_info(account_info_iter)?; // Maker's open-offer tally
+        let fee_collector = next_account_info(account_info_iter)?; // Where the create fee goes
+        let order_book_account = next_account_info(account_info_iter)?; // The pair's book (may not exist)
+        let recent_cancel_account = next_account_info(account_info_iter)?; // Maker's last cancel on the pair (may not exist)
+
+        // Optional accounts
+        let maker_sol_account_opt = next_account_info(account_info_iter).ok();
+        let taker_account_opt = next_account_info(account_info_iter).ok();
+        // Sealed offers end with their vault; yield offers with their strategy's accounts.
+        let offer_token_vault_opt = if yield_strategy.is_none() {
+            next_account_info(account_info_iter).ok()
+        } else {
+            None
+        };
+        let strategy_accounts = account_info_iter.as_slice();
+
+        // --- Basic Checks ---
+        // 1. Is the maker signing this?
+        if !maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Self::check_token_program(token_program)?;
+        Self::check_mint_not_paused(program_id, offer_mint_pause, offer_token_mint.key)?;
+        Self::check_mint_not_paused(program_id, receive_mint_pause, receive_token_mint.key)?;
+        let config = Self::load_config(program_id, config_account)?;
+        Self::check_not_paused(config.as_ref())?;
+        Self::check_mints_allowed(
+            config.as_ref(),
+            offer_token_mint.key,
+            receive_token_mint.key,
+        )?;
+
+        // 2. Verify the offer_account is a PDA derived from our program. We always use the
+        // canonical bump, so nobody gets to pick which address the offer lands on.
+        let nonce_bytes = nonce.to_le_bytes();
+        let (expected_offer_key, bump_seed) = Pubkey::find_program_address(
+            &[
+                b"offer",
+                maker_account.key.as_ref(),
+                offer_token_mint.key.as_ref(),
+                receive_token_mint.key.as_ref(),
+                nonce_seed(&nonce_bytes),
+            ],
+            program_id,
+        );
+        if expected_offer_key != *offer_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        Self::check_recreate_cooldown(
+            program_id,
+            maker_account,
+            recent_cancel_account,
+            system_program,
+            config.as_ref(),
+            (offer_token_mint.key, receive_token_mint.key),
+            (offer_token_amount, receive_token_amount),
+        )?;
+        let offer_seeds = &[
+            b"offer",                        // A constant string seed
+            maker_account.key.as_ref(),      // Maker's public key as a seed
+            offer_token_mint.key.as_ref(),   // Offered token mint as a seed
+            receive_token_mint.key.as_ref(), // Received token mint as a seed
+            nonce_seed(&nonce_bytes),        // Which of the maker's offers on this pair
+            &[bump_seed],                    // The canonical bump
+        ];
+
+        // 3. Create the offer account if it doesn't exist and is not rent-exempt.
+        // The offer_account must be writable and owned by the system program for creation.
+        let space = Offer::MAX_LEN; // Max size for our offer data
+        let rent = &Rent::from_account_info(rent_sysvar)?;
+        let rent_lamports = rent.minimum_balance(space); // Remembered on the offer as `rent_reserved`
+        if offer_account.data_len() == 0 {
+            invoke_signed(
+                &system_instruction::create_account(
+                    maker_account.key, // Payer
+                    offer_account.key, // New account address (PDA)
+                    rent_lamports,     // Rent
+                    space as u64,      // Size
+                    program_id,        // Owner
+                ),
+                &[
+                    maker_account.clone(),
+                    offer_account.clone(),
+                    system_program.clone(),
+                ],
+                &[offer_seeds], // Sign with the PDA seeds
+            )?;
+        } else {
+            // If account already exists, it must be owned by our program and empty
+            if offer_account.owner != program_id || !offer_account.data_is_empty() {
+                return Err(SwapError::InvalidAccountData.into());
+            }
+        }
+
+        // 4. A minimum fill bigger than the whole offer could never be met.
+        if min_fill > offer_token_amount {
+            return Err(SwapError::InvalidFillAmount.into());
+        }
+
+        // 5. Both sides of the trade have to be worth something.
+        if offer_token_amount == 0 || receive_token_amount == 0 {
+            return Err(SwapError::InvalidAmount.into());
+        }
+        Self::check_start_time(start_time, expiration)?;
+
+        // 6. Ratio orders come from `CreateRatioOrder` (which has the rate), and sell tokens.
+        if (offer_type == OfferType::RatioOrder) != rate.is_some()
+            || (rate.is_some() && is_sol_side(offer_token_mint.key))
+        {
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        let (rate_num, rate_den) = rate.unwrap_or_default();
+
+        // 7. Premium orders come from `CreatePremiumOrder`, and sell tokens too.
+        if (offer_type == OfferType::PremiumOrder) != premium_bps.is_some()
+            || (premium_bps.is_some() && is_sol_side(offer_token_mint.key))
+        {
+            return Err(SwapError::InvalidInstruction.into());
+        }
+
+        // 8. Dutch auctions come from `CreateDutchAuction`, sell tokens, and only come down in price.
+        if (offer_type == OfferType::DutchAuction) != auction.is_some()
+            || (auction.is_some() && is_sol_side(offer_token_mint.key))
+        {
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        if let Some((end_price, min_acceptable_receive)) = auction {
+            if end_price == 0 {
+                return Err(SwapError::InvalidAmount.into());
+            }
+            if end_price > receive_token_amount {
+                msg!("A Dutch auction's end price can't be above its start price");
+                return Err(SwapError::InvalidInstruction.into());
+            }
+            if min_acceptable_receive > receive_token_amount {
+                msg!("A Dutch auction's floor can't be above its start price");
+                return Err(SwapError::InvalidInstruction.into());
+            }
+        }
+        let (end_price, min_acceptable_receive) = auction.unwrap_or_default();
+
+        // 9. Only a public buy offer has escrowed SOL to earn yield on.
+        if yield_strategy.is_some() && offer_type != OfferType::PublicBuy {
+            return Err(SwapError::InvalidInstruction.into());
+        }
+
+        // --- Handle Direct Offers ---
+        let taker_pubkey = if offer_type == OfferType::Direct {
+            let taker_account = taker_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+            Some(*taker_account.key)
+        } else {
+            None
+        };
+
+        // --- Escrow SOL if it's a "Buy" offer (maker offers SOL for tokens) ---
+        let mut escrow_sol = 0;
+        let mut escrow_tokens = 0;
+        let mut offer_decimals = None;
+        // A SOL side is marked by the native mint (see `is_sol_side`).
+        if offer_type == OfferType::PublicBuy
+            || (offer_type == OfferType::Direct && is_sol_side(offer_token_mint.key))
+        {
+            // If the maker is offering SOL, they need to send it to our program's escrow.
+            escrow_sol = offer_token_amount; // The amount of SOL they are offering
+            // Dust buy offers just clutter the market.
+            if let Some(config) = &config
+                && escrow_sol < config.min_escrow_sol
+            {
+                msg!(
+                    "Escrow of {} lamports is below the minimum of {}",
+                    escrow_sol,
+                    config.min_escrow_sol
+                );
+                return Err(SwapError::AmountTooSmall.into());
+            }
+            Self::check_escrow_cap(config.as_ref(), escrow_sol)?;
+
+            let maker_sol_account =
+                maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+            if *maker_sol_account.key != *maker_account.key {
+                return Err(SwapError::IncorrectOwner.into()); // Ensure it's the maker's SOL account
+            }
+            // The maker's wallet may be the fee payer too, so don't let escrow drain it.
+            if maker_sol_account.is_signer
+                && maker_sol_account.lamports() < escrow_sol.saturating_add(FEE_BUFFER_LAMPORTS)
+            {
+                msg!(
+                    "Escrowing {} lamports would leave less than {} for fees",
+                    escrow_sol,
+                    FEE_BUFFER_LAMPORTS
+                );
+                return Err(SwapError::InsufficientFunds.into());
+            }
+
+            msg!("Transferring {} SOL to escrow...", escrow_sol);
+            Self::transfer_sol(
+                &[
+                    maker_sol_account.clone(),
+                    offer_account.clone(),
+                    system_program.clone(),
+                ],
+                escrow_sol,
+                None, // Not signed by program
+            )?;
+            msg!("SOL transferred to escrow.");
+        } else {
+            // If maker is offering tokens, the token account must be theirs, hold that token,
+            // and have enough of it. (SOL offers don't need a real token account here.)
+            let maker_token_account_data = Self::unpack_token_account(maker_token_account)?;
+            if maker_token_account_data.owner != *maker_account.key {
+                return Err(SwapError::IncorrectOwner.into());
+            }
+            if maker_token_account_data.mint != *offer_token_mint.key {
+                return Err(SwapError::TokenMismatch.into());
+            }
+
+            // Offering more tokens than exist usually means the client got the decimals wrong.
+            let offer_mint_info = Self::unpack_valid_mint(offer_token_mint)?;
+            offer_decimals = Some(offer_mint_info.decimals);
+            if offer_token_amount > offer_mint_info.supply {
+                msg!(
+                    "Offer of {} is more than the mint's supply of {}",
+                    offer_token_amount,
+                    offer_mint_info.supply
+                );
+                if config
+                    .as_ref()
+                    .is_some_and(|config| config.reject_amounts_over_supply)
+                {
+                    return Err(SwapError::AmountExceedsSupply.into());
+                }
+            }
+
+            // An empty account can't back a sell offer, whatever the amount.
+            if maker_token_account_data.amount == 0
+                || offer_token_amount > maker_token_account_data.amount
+            {
+                return Err(SwapError::InsufficientFunds.into());
+            }
+
+            // Sealed offers lock the tokens up now, so they're sure to be there when matched.
+            if let Some(offer_token_vault) = offer_token_vault_opt {
+                Self::check_offer_vault(offer_account, offer_token_vault, offer_token_mint.key)?;
+                msg!(
+                    "Sealing {} tokens in the offer vault...",
+                    offer_token_amount
+                );
+                Self::transfer_spl_token(
+                    &[
+                        maker_token_account.clone(),
+                        offer_token_mint.clone(),
+                        offer_token_vault.clone(),
+                        maker_account.clone(),
+                        token_program.clone(),
+                    ],
+                    offer_token_amount,
+                    offer_mint_info.decimals,
+                    None, // Maker is signing directly
+                )?;
+                escrow_tokens = offer_token_amount;
+            }
+        }
+        // SOL stays in the offer locker itself; there's nothing to seal in a vault.
+        if escrow_sol > 0 && offer_token_vault_opt.is_some() {
+            return Err(SwapError::InvalidAccountInput.into());
+        }
+        // A one-shot offer is cancelled from inside an accept, which has nowhere to send a
+        // sealed offer's tokens back to, and no strategy accounts to bring a yield offer's
+        // escrow home with.
+        if auto_cancel_on_fail && offer_token_vault_opt.is_some() {
+            msg!("A sealed offer can't cancel itself on a failed accept");
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        if auto_cancel_on_fail && yield_strategy.is_some() {
+            msg!("A yield offer can't cancel itself on a failed accept");
+            return Err(SwapError::InvalidInstruction.into());
+        }
+
+        // --- Create and Save the Offer Data ---
+        let now = current_timestamp()?;
+        let mut offer = Offer {
+            version: Offer::CURRENT_VERSION,
+            offer_type,
+            // New offers are active, unless their taker has to confirm first
+            status: if confirm_first {
+                OfferStatus::Pending
+            } else {
+                OfferStatus::Active
+            },
+            maker: *maker_account.key,
+            taker: taker_pubkey,
+            offer_token_mint: *offer_token_mint.key,
+            offer_token_amount,
+            receive_token_mint: *receive_token_mint.key,
+            receive_token_amount,
+            escrow_sol_amount: escrow_sol,
+            expiration,
+            is_counter_offer: false,
+            original_offer_id: None,
+            bump_seed, // Store the bump seed in the offer data
+            min_fill,
+            rent_reserved: rent_lamports,
+            zeroize_on_terminal: config
+                .as_ref()
+                .is_some_and(|config| config.zeroize_terminal_offers),
+            escrow_token_amount: escrow_tokens,
+            rate_num,
+            rate_den,
+            settled_offer_amount: 0,
+            settled_receive_amount: 0,
+            cancelled_at: 0,
+            receive_recipient,
+            premium_bps: premium_bps.unwrap_or(0),
+            yield_strategy,
+            yield_principal: 0,
+            nonce,
+            created_at: now,
+            last_updated_at: now,
+            start_time,
+            end_price,
+            client_id,
+            auto_cancel_on_fail,
+            offer_decimals,
+            min_acceptable_receive,
+        };
+
+        // Save the offer data into the `offer_account` locker using borsh.
+        offer.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+
+        // Count it against the maker (and stop them if they have too many open already).
+        Self::open_maker_slot(
+            program_id,
+            maker_account,
+            maker_index_account,
+            system_program,
+            config.as_ref(),
+        )?;
+        // And take a slot in the pair's book, if the market is capped.
+        Self::list_in_order_book(program_id, order_book_account, &offer, offer_account.key)?;
+
+        // Listing costs a flat fee if the admin set one, whether or not the offer ever trades.
+        if let Some(config) = config.as_ref()
+            && config.create_fee > 0
+        {
+            if *fee_collector.key != config.fee_collector {
+                return Err(SwapError::InvalidAccountInput.into());
+            }
+            msg!("Charging create fee of {} lamports", config.create_fee);
+            Self::transfer_sol(
+                &[
+                    maker_account.clone(),
+                    fee_collector.clone(),
+                    system_program.clone(),
+                ],
+                config.create_fee,
+                None, // Maker is signing directly
+            )?;
+        }
+
+        // Last, since the deposit moves lamports by hand.
+        if yield_strategy.is_some() {
+            Self::deposit_yield(&mut offer, offer_account, strategy_accounts)?;
+        }
+
+        Self::log_event(&SwapEvent::OfferCreated {
+            offer: *offer_account.key,
+            maker: offer.maker,
+            offer_token_mint: offer.offer_token_mint,
+            offer_token_amount: offer.offer_token_amount,
+            receive_token_mint: offer.receive_token_mint,
+            receive_token_amount: offer.receive_token_amount,
+            client_id: offer.client_id,
+        })?;
+        msg!("Offer created successfully!");
+        Ok(())
+    }
+
+    // --- Process AcceptOffer Instruction ---
+    // `fill_amount` is `None` for a full accept, or how many offered tokens a partial accept takes.
+    // Also finalizes large trades (`finalizing`), which take their fill from the settlement.
+    // `bounds` is the taker's `(min_receive_amount, max_send_amount)`. With `approved`, the
+    // maker's signed approval stands in for their signature, and the offer signs as their delegate.
+    // With a `vault`, the taker's side goes there instead of to the taker.
+    #[allow(clippy::too_many_arguments)]
+    fn process_accept_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        fill_amount: Option<u64>,
+        finalizing: bool,
+        close_account: bool,
+        bounds: (Option<u64>, Option<u64>),
+        approved: bool,
+        vault: Option<&Pubkey>,
+    ) -> ProgramResult {
+        msg!("Processing AcceptOffer...");
+        let account_info_iter = &mut accounts.iter();
+
+        // Get all the lockers we need.
+        let taker_account = next_account_info(account_info_iter)?; // The person accepting
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+        let maker_account = next_account_info(account_info_iter)?; // The original maker
+        let maker_token_account = next_account_info(account_info_iter)?; // Maker's token account
+        let taker_token_account = next_account_info(account_info_iter)?; // Taker's token account
+        let offer_token_mint = next_account_info(account_info_iter)?; // Offered token type (mint)
+        let receive_token_mint = next_account_info(account_info_iter)?; // Received token type (mint)
+        let system_program = next_account_info(account_info_iter)?; // System program
+        let token_program = next_account_info(account_info_iter)?; // Token program
+        let offer_mint_pause = next_account_info(account_info_iter)?; // Is the offered mint paused?
+        let receive_mint_pause = next_account_info(account_info_iter)?; // Is the received mint paused?
+        let maker_index_account = next_account_info(account_info_iter)?; // Maker's open-offer tally
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+        let settlement_account = next_account_info(account_info_iter)?; // Where big trades wait
+        let order_book_account = next_account_info(account_info_iter)?; // The pair's book (may not exist)
+
+        // Optional accounts for SOL transfers
+        let maker_sol_account_opt = next_account_info(account_info_iter).ok();
+        let taker_sol_account_opt = next_account_info(account_info_iter).ok();
+        let treasury_account_opt = next_account_info(account_info_iter).ok(); // Gets the accept fee
+        let history_account_opt = next_account_info(account_info_iter).ok(); // Prices premium orders
+
+        // Optional NFT royalty accounts
+        let metadata_account_opt = next_account_info(account_info_iter).ok();
+        let creator_accounts = account_info_iter.as_slice();
+
+        // --- Basic Checks ---
+        if !taker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if offer_account.owner != program_id {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+
+        // Load the offer data from its locker using borsh.
+        let mut offer_data = Self::load_offer(offer_account)?;
+
+        // Verify the offer_account is a PDA derived from our program and the stored bump seed.
+        let nonce_bytes = offer_data.nonce.to_le_bytes();
+        let offer_seeds = &[
+            b"offer",
+            offer_data.maker.as_ref(),
+            offer_data.offer_token_mint.as_ref(),
+            offer_data.receive_token_mint.as_ref(),
+            nonce_seed(&nonce_bytes),
+            &[offer_data.bump_seed],
+        ];
+        let expected_offer_key = Pubkey::create_program_address(offer_seeds, program_id)
+            .map_err(|_| SwapError::InvalidProgramAddress)?;
+
+        if expected_offer_key != *offer_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+
+        // Can this taker take it at all? (The same checks `CanAccept` reports on.)
+        let now = current_timestamp()?;
+        if let Some(blocker) = accept_blocker(&offer_data, taker_account.key, now) {
+            if blocker == AcceptBlocker::Expired {
+                offer_data.status = OfferStatus::Expired;
+                offer_data.last_updated_at = now;
+                offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+            }
+            if offer_data.auto_cancel_on_fail && blocker.rejects_taker() {
+                return Self::cancel_one_shot(
+                    program_id,
+                    &mut offer_data,
+                    offer_account,
+                    maker_account,
+                    maker_index_account,
+                    order_book_account,
+                    blocker,
+                );
+            }
+            return Err(blocker.error().into());
+        }
+        Self::check_escrow_home(&offer_data)?;
+
+        // Verify maker_account is the actual maker.
+        if offer_data.maker != *maker_account.key {
+            return Err(SwapError::OfferMismatch.into());
+        }
+
+        // A sealed offer's tokens sit in its vault, and the offer signs them out itself.
+        let sealed = offer_data.escrow_token_amount > 0;
+
+        // The mint accounts passed in must be the exact mints this offer was made for,
+        // otherwise someone could swap in a different (but self-consistent) token pair.
+        if *offer_token_mint.key != offer_data.offer_token_mint {
+            return Err(SwapError::TokenMismatch.into());
+        }
+        if *receive_token_mint.key != offer_data.receive_token_mint {
+            return Err(SwapError::TokenMismatch.into());
+        }
+        Self::check_mint_not_paused(program_id, offer_mint_pause, offer_token_mint.key)?;
+        Self::check_mint_not_paused(program_id, receive_mint_pause, receive_token_mint.key)?;
+
+        // --- Large Trades ---
+        let (settlement_key, settlement_bump) =
+            Pubkey::find_program_address(&[b"settle", offer_account.key.as_ref()], program_id);
+        if settlement_key != *settlement_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        let pending_settlement = if settlement_account.owner == program_id {
+            Some(Settlement::load(&settlement_account.data.borrow())?)
+        } else {
+            None
+        };
+        let config = Self::load_config(program_id, config_account)?;
+        Self::check_not_paused(config.as_ref())?;
+        let fill_amount = if finalizing {
+            let settlement = pending_settlement.ok_or(SwapError::AccountNotInitialized)?;
+            if settlement.taker != *taker_account.key {
+                return Err(SwapError::Unauthorized.into());
+            }
+            if now < settlement.ready_at {
+                msg!(
+                    "Settlement is ready at {}, it's {}",
+                    settlement.ready_at,
+                    now
+                );
+                return Err(SwapError::SettlementNotReady.into());
+            }
+            settlement.fill_amount
+        } else {
+            if pending_settlement.is_some() {
+                return Err(SwapError::SettlementPending.into());
+            }
+            Self::check_slippage(
+                program_id,
+                &offer_data,
+                fill_amount,
+                history_account_opt,
+                metadata_account_opt,
+                bounds,
+                now,
+            )?;
+            // Big trades wait a while first, so either side can back out.
+            if let Some(config) = config.as_ref()
+                && config.large_trade_threshold > 0
+                && fill_amount.unwrap_or(offer_data.offer_token_amount)
+                    > config.large_trade_threshold
+            {
+                // `FinalizeSettlement` pays the taker, so it would skip the vault.
+                if vault.is_some() {
+                    msg!("Large trades can't be accepted into a vault");
+                    return Err(SwapError::InvalidInstruction.into());
+                }
+                let settlement = Settlement {
+                    offer: *offer_account.key,
+                    taker: *taker_account.key,
+                    fill_amount,
+                    ready_at: now.saturating_add(config.settlement_delay),
+                    bump_seed: settlement_bump,
+                };
+                let space = Settlement::MAX_LEN;
+                invoke_signed(
+                    &system_instruction::create_account(
+                        taker_account.key,
+                        settlement_account.key,
+                        Rent::get()?.minimum_balance(space),
+                        space as u64,
+                        program_id,
+                    ),
+                    &[
+                        taker_account.clone(),
+                        settlement_account.clone(),
+                        system_program.clone(),
+                    ],
+                    &[&[b"settle", offer_account.key.as_ref(), &[settlement_bump]]],
+                )?;
+                settlement.serialize(&mut &mut settlement_account.data.borrow_mut()[..])?;
+                msg!(
+                    "Large trade booked, it can settle at {}",
+                    settlement.ready_at
+                );
+                return Ok(());
+            }
+            fill_amount
+        };
+
+        // Check token account ownership and mints
+        let maker_token_account_data = Self::unpack_token_account(maker_token_account)?;
+        let taker_token_account_data = Self::unpack_token_account(taker_token_account)?;
+
+        // The only account of the offer's an accept may touch is a sealed offer's vault, passed
+        // as the maker's token account. Any other one is someone trying to muddle the escrow.
+        if (!sealed && maker_token_account_data.owner == *offer_account.key)
+            || taker_token_account_data.owner == *offer_account.key
+        {
+            msg!("Token accounts owned by the offer can't be used to accept it");
+            return Err(SwapError::InvalidAccountInput.into());
+        }
+
+        // The maker's token account is where their tokens come from when they sell, and where
+        // the tokens go when they buy (which may be a `receive_recipient`'s account instead).
+        if sealed {
+            Self::check_offer_vault(offer_account, maker_token_account, offer_token_mint.key)?;
+        } else {
+            let maker_token_owner = if offer_data.escrow_sol_amount > 0 {
+                offer_data.proceeds_owner()
+            } else {
+                offer_data.maker
+            };
+            if maker_token_account_data.owner != maker_token_owner {
+                return Err(SwapError::IncorrectOwner.into());
+            }
+        }
+        // Likewise the taker's is where their tokens come from when the maker buys, and where
+        // the tokens go otherwise (which may be a custody vault's account instead).
+        let taker_payee = vault.unwrap_or(taker_account.key);
+        let taker_token_owner = if offer_data.escrow_sol_amount > 0 {
+            taker_account.key
+        } else {
+            taker_payee
+        };
+        if taker_token_account_data.owner != *taker_token_owner {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+        Self::check_not_frozen(&maker_token_account_data)?;
+        Self::check_not_frozen(&taker_token_account_data)?;
+        Self::check_mint_transferable(offer_token_mint)?;
+        Self::check_mint_transferable(receive_token_mint)?;
+
+        // The treasury's cut of whatever the maker receives.
+        let fee_bps = config.as_ref().map_or(0, |config| config.fee_bps);
+
+        // --- Perform the Swap! ---
+        // What really changes hands, which isn't always the listed price (ratio orders round).
+        let (settled_offer, settled_receive);
+        // Case 1: Maker offered SOL (escrow_sol_amount > 0), Taker offers Tokens
+        if offer_data.escrow_sol_amount > 0 {
+            msg!("Executing SOL for Token swap...");
+
+            // How many tokens the taker delivers, and how much of the escrow that releases.
+            let (token_amount, sol_amount) =
+                Self::escrow_release_amounts(&offer_data, fill_amount)?;
+
+            // Ensure correct mints for token accounts
+            if maker_token_account_data.mint != *receive_token_mint.key {
+                return Err(SwapError::TokenMismatch.into());
+            }
+            if taker_token_account_data.mint != *receive_token_mint.key {
+                // Taker gives tokens (receive_token_mint)
+                return Err(SwapError::TokenMismatch.into());
+            }
+
+            let taker_sol_account =
+                taker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+            if taker_sol_account.key != taker_payee {
+                return Err(SwapError::IncorrectOwner.into());
+            }
+
+            // Transfer tokens from taker to maker, less the fee, which goes to the treasury.
+            let fee = Self::protocol_fee(token_amount, fee_bps)?;
+            let maker_token_amount = token_amount
+                .checked_sub(fee)
+                .ok_or(SwapError::MathOverflow)?;
+            Self::check_token_room(&maker_token_account_data, maker_token_amount)?;
+            let mint_info = Self::unpack_mint(receive_token_mint)?; // Get decimals for the token taker is giving
+            Self::transfer_spl_token(
+                &[
+                    taker_token_account.clone(),
+                    receive_token_mint.clone(),
+                    maker_token_account.clone(),
+                    taker_account.clone(),
+                    token_program.clone(),
+                ],
+                maker_token_amount,
+                mint_info.decimals,
+                None, // Taker is signing directly
+            )?;
+            if let Some(config) = config.as_ref()
+                && fee > 0
+            {
+                let treasury_account =
+                    treasury_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+                Self::check_token_account(
+                    treasury_account,
+                    &config.treasury,
+                    receive_token_mint.key,
+                )?;
+                msg!("Charging accept fee of {} tokens", fee);
+                Self::transfer_spl_token(
+                    &[
+                        taker_token_account.clone(),
+                        receive_token_mint.clone(),
+                        treasury_account.clone(),
+                        taker_account.clone(),
+                        token_program.clone(),
+                    ],
+                    fee,
+                    mint_info.decimals,
+                    None, // Taker is signing directly
+                )?;
+            }
+
+            // Transfer SOL from escrow (offer_account) to the taker, who is buying it with tokens.
+            // We move lamports by hand, so this goes after the token CPI.
+            let royalties =
+                Self::royalty_payouts(metadata_account_opt, receive_token_mint.key, sol_amount)?;
+            let royalty_total = Self::royalty_total(&royalties)?;
+            Self::transfer_sol_from_pda(
+                offer_account,
+                taker_sol_account,
+                sol_amount
+                    .checked_sub(royalty_total)
+                    .ok_or(SwapError::MathOverflow)?,
+            )?;
+            Self::pay_royalties(&royalties, creator_accounts, offer_account, None)?;
+            // What was paid out comes off the escrow (all of it, unless this was a partial fill).
+            offer_data.escrow_sol_amount = offer_data
+                .escrow_sol_amount
+                .checked_sub(sol_amount)
+                .ok_or(SwapError::MathOverflow)?;
+            offer_data.offer_token_amount = offer_data
+                .offer_token_amount
+                .checked_sub(sol_amount)
+                .ok_or(SwapError::MathOverflow)?;
+            offer_data.receive_token_amount = offer_data
+                .receive_token_amount
+                .checked_sub(token_amount)
+                .ok_or(SwapError::MathOverflow)?;
+            (settled_offer, settled_receive) = (sol_amount, token_amount);
+            msg!("SOL for Token swap completed.");
+        } else {
+            // Case 2: Maker offered Tokens (escrow_sol_amount == 0), Taker pays SOL, or the
+            // other token when neither side is SOL
+            let token_for_token = !is_sol_side(receive_token_mint.key);
+            if token_for_token {
+                msg!("Executing Token for Token swap...");
+            } else {
+                msg!("Executing Token for SOL swap...");
+            }
+
+            // Work out how much of the offer this accept takes, and what the taker pays for it.
+            let is_ratio_order = offer_data.offer_type == OfferType::RatioOrder;
+            let reference = Self::reference_trade(program_id, &offer_data, history_account_opt)?;
+            let (token_amount, sol_amount) =
+                Self::token_sale_amounts(&offer_data, fill_amount, reference.as_ref(), now)?;
+
+            // Ensure correct mints for token accounts
+            if maker_token_account_data.mint != *offer_token_mint.key {
+                // Maker gives tokens (offer_token_mint)
+                return Err(SwapError::TokenMismatch.into());
+            }
+            if taker_token_account_data.mint != *offer_token_mint.key {
+                // Taker gets the same tokens the maker gives
+                return Err(SwapError::TokenMismatch.into());
+            }
+
+            // Transfer tokens from maker (or the sealed offer's vault) to taker
+            Self::check_token_room(&taker_token_account_data, token_amount)?;
+            // The decimals the offer was made with, unless it's older than that.
+            let offer_decimals = match offer_data.offer_decimals {
+                Some(decimals) => decimals,
+                None => Self::unpack_mint(offer_token_mint)?.decimals,
+            };
+            Self::transfer_spl_token(
+                &[
+                    maker_token_account.clone(),
+                    offer_token_mint.clone(),
+                    taker_token_account.clone(),
+                    if sealed || approved {
+                        offer_account.clone()
+                    } else {
+                        maker_account.clone()
+                    },
+                    token_program.clone(),
+                ],
+                token_amount,
+                offer_decimals,
+                // The offer signs for its vault, or as the maker's delegate
+                (sealed || approved).then_some(&offer_seeds[..]),
+            )?;
+            if sealed {
+                offer_data.escrow_token_amount = offer_data
+                    .escrow_token_amount
+                    .checked_sub(token_amount)
+                    .ok_or(SwapError::MathOverflow)?;
+            }
+
+            if !token_for_token {
+                // Transfer SOL from taker to maker
+                let taker_sol_account =
+                    taker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+                let maker_sol_account =
+                    maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+                if *taker_sol_account.key != *taker_account.key
+                    || *maker_sol_account.key != offer_data.proceeds_owner()
+                {
+                    return Err(SwapError::IncorrectOwner.into());
+                }
+                let royalties =
+                    Self::royalty_payouts(metadata_account_opt, offer_token_mint.key, sol_amount)?;
+                let royalty_total = Self::royalty_total(&royalties)?;
+                let fee = Self::protocol_fee(sol_amount, fee_bps)?;
+                Self::transfer_sol(
+                    &[
+                        taker_sol_account.clone(),
+                        maker_sol_account.clone(),
+                        system_program.clone(),
+                    ],
+                    sol_amount
+                        .checked_sub(royalty_total)
+                        .and_then(|proceeds| proceeds.checked_sub(fee))
+                        .ok_or(SwapError::MathOverflow)?,
+                    None, // Not signed by program
+                )?;
+                if let Some(config) = config.as_ref()
+                    && fee > 0
+                {
+                    let treasury_account =
+                        treasury_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+                    if *treasury_account.key != config.treasury {
+                        return Err(SwapError::InvalidAccountInput.into());
+                    }
+                    msg!("Charging accept fee of {} lamports", fee);
+                    Self::transfer_sol(
+                        &[
+                            taker_sol_account.clone(),
+                            treasury_account.clone(),
+                            system_program.clone(),
+                        ],
+                        fee,
+                        None, // Taker is signing directly
+                    )?;
+                }
+                Self::pay_royalties(
+                    &royalties,
+                    creator_accounts,
+                    taker_sol_account,
+                    Some(system_program),
+                )?;
+            } else {
+                // Token for token: the "price" is in the received mint, paid from the taker's
+                // account for it into the maker's (slots 14 and 15).
+                let maker_receive_token_account =
+                    maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+                let taker_receive_token_account =
+                    taker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+                Self::check_token_account(
+                    maker_receive_token_account,
+                    &offer_data.proceeds_owner(),
+                    receive_token_mint.key,
+                )?;
+                Self::check_token_account(
+                    taker_receive_token_account,
+                    taker_account.key,
+                    receive_token_mint.key,
+                )?;
+                let maker_receive_data = Self::unpack_token_account(maker_receive_token_account)?;
+                Self::check_not_frozen(&maker_receive_data)?;
+                Self::check_not_frozen(&Self::unpack_token_account(taker_receive_token_account)?)?;
+                let fee = Self::protocol_fee(sol_amount, fee_bps)?;
+                let maker_proceeds = sol_amount.checked_sub(fee).ok_or(SwapError::MathOverflow)?;
+                Self::check_token_room(&maker_receive_data, maker_proceeds)?;
+                let receive_mint_info = Self::unpack_mint(receive_token_mint)?;
+                Self::transfer_spl_token(
+                    &[
+                        taker_receive_token_account.clone(),
+                        receive_token_mint.clone(),
+                        maker_receive_token_account.clone(),
+                        taker_account.clone(),
+                        token_program.clone(),
+                    ],
+                    maker_proceeds,
+                    receive_mint_info.decimals,
+                    None, // Taker is signing directly
+                )?;
+                if let Some(config) = config.as_ref()
+                    && fee > 0
+                {
+                    let treasury_account =
+                        treasury_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+                    Self::check_token_account(
+                        treasury_account,
+                        &config.treasury,
+                        receive_token_mint.key,
+                    )?;
+                    msg!("Charging accept fee of {} tokens", fee);
+                    Self::transfer_spl_token(
+                        &[
+                            taker_receive_token_account.clone(),
+                            receive_token_mint.clone(),
+                            treasury_account.clone(),
+                            taker_account.clone(),
+                            token_program.clone(),
+                        ],
+                        fee,
+                        receive_mint_info.decimals,
+                        None, // Taker is signing directly
+                    )?;
+                }
+            }
+            (settled_offer, settled_receive) = (token_amount, sol_amount);
+            if token_for_token {
+                msg!("Token for Token swap completed.");
+            } else {
+                msg!("Token for SOL swap completed.");
+            }
+
+            // A partial fill comes off the offer; the rest stays up for grabs.
+            if fill_amount.is_some() {
+                // A premium order's floor comes down by the fill's share, whatever it sold for.
+                let floor_share = if reference.is_some() {
+                    Self::proportional_amount(&offer_data, token_amount)?
+                } else {
+                    sol_amount
+                };
+                offer_data.offer_token_amount = offer_data
+                    .offer_token_amount
+                    .checked_sub(token_amount)
+                    .ok_or(SwapError::MathOverflow)?;
+                offer_data.receive_token_amount = if is_ratio_order {
+                    // What the rest costs at the rate (0 once it's too little to cost anything).
+                    Self::ratio_amount(
+                        offer_data.rate_num,
+                        offer_data.rate_den,
+                        offer_data.offer_token_amount,
+                    )
+                    .unwrap_or(0)
+                } else {
+                    offer_data
+                        .receive_token_amount
+                        .checked_sub(floor_share)
+                        .ok_or(SwapError::MathOverflow)?
+                };
+            }
+        }
+
+        offer_data.settled_offer_amount = offer_data
+            .settled_offer_amount
+            .checked_add(settled_offer)
+            .ok_or(SwapError::MathOverflow)?;
+        offer_data.settled_receive_amount = offer_data
+            .settled_receive_amount
+            .checked_add(settled_receive)
+            .ok_or(SwapError::MathOverflow)?;
+        msg!(
+            "OfferAccepted: {} offered for {} received",
+            settled_offer,
+            settled_receive
+        );
+
+        // Update offer status to Accepted once nothing is left to fill.
+        let filled = fill_amount.is_none() || offer_data.offer_token_amount == 0;
+        if filled {
+            Self::delist_from_order_book(
+                program_id,
+                order_book_account,
+                &offer_data,
+                offer_account.key,
+            )?;
+            Self::close_out_offer(&mut offer_data, OfferStatus::Accepted, offer_account)?;
+            Self::release_maker_slot(program_id, &offer_data.maker, maker_index_account)?;
+            if close_account {
+                Self::close_offer_account(offer_account, maker_account)?;
+            }
+        } else {
+            msg!(
+                "Partially filled, {} tokens remain.",
+                offer_data.offer_token_amount
+            );
+            offer_data.status = OfferStatus::PartiallyFilled;
+            offer_data.last_updated_at = now;
+            offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?; // Use borsh to pack!
+        }
+
+        // A finished settlement is closed, and the taker gets its rent back.
+        if finalizing {
+            Self::transfer_sol_from_pda(
+                settlement_account,
+                taker_account,
+                settlement_account.lamports(),
+            )?;
+            settlement_account.data.borrow_mut().fill(0);
+        }
+
+        Self::log_event(&SwapEvent::OfferAccepted {
+            offer: *offer_account.key,
+            maker: *maker_account.key,
+            taker: *taker_account.key,
+            offer_amount: settled_offer,
+            receive_amount: settled_receive,
+            filled,
+        })?;
+        msg!("Offer accepted successfully!");
+        Ok(())
+    }
+
+    // --- Process AcceptOffers Instruction ---
+    fn process_accept_offers(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        count: u8,
+    ) -> ProgramResult {
+        let count = count as usize;
+        if count > MAX_ACCEPT_BATCH {
+            msg!("Too many offers: {} (max {})", count, MAX_ACCEPT_BATCH);
+            return Err(SwapError::TooManyOffers.into());
+        }
+        if accounts.len() != count * ACCEPT_BATCH_GROUP_LEN {
+            msg!(
+                "Expected {} accounts for {} offers, got {}",
+                count * ACCEPT_BATCH_GROUP_LEN,
+                count,
+                accounts.len()
+            );
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        for (index, group) in accounts.chunks_exact(ACCEPT_BATCH_GROUP_LEN).enumerate() {
+            Self::process_accept_offer(
+                program_id,
+                group,
+                None,
+                false,
+                false,
+                (None, None),
+                false,
+                None,
+            )
+            .inspect_err(|err| msg!("Accept {} of {} failed: {:?}", index, count, err))?;
+        }
+        msg!("Accepted {} offers", count);
+        Ok(())
+    }
+
+    // --- Process CounterOffer Instruction ---
+    fn process_counter_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_token_amount: u64,
+        receive_token_amount: u64,
+        expiration: Option<i64>,
+        start_time: Option<i64>,
+    ) -> ProgramResult {
+        msg!("Processing CounterOffer...");
+        let account_info_iter = &mut accounts.iter();
+
+        let counter_maker_account = next_account_info(account_info_iter)?; // The person making the counter
+        let original_offer_account = next_account_info(account_info_iter)?; // The original offer's locker (PDA)
+        let new_offer_account = next_account_info(account_info_iter)?; // New locker for the counter-offer (PDA)
+        let counter_maker_token_account = next_account_info(account_info_iter)?; // Counter-maker's token account
+        let offer_token_mint = next_account_info(account_info_iter)?; // Token offered in counter
+        let receive_token_mint = next_account_info(account_info_iter)?; // Token received in counter
+        let system_program = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let rent_sysvar = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+        let original_maker_index = next_account_info(account_info_iter)?; // Original maker's tally
+        let counter_maker_index = next_account_info(account_info_iter)?; // Counter-maker's tally
+        let original_order_book = next_account_info(account_info_iter)?; // The original's book (may not exist)
+        let counter_order_book = next_account_info(account_info_iter)?; // The counter's book (may not exist)
+
+        // Optional accounts
+        let counter_maker_sol_account_opt = next_account_info(account_info_iter).ok();
+        let original_maker_sol_account_opt = next_account_info(account_info_iter).ok();
+
+        // --- Basic Checks ---
+        if !counter_maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Self::check_token_program(token_program)?;
+        let config = Self::load_config(program_id, config_account)?;
+        Self::check_not_paused(config.as_ref())?;
+        Self::check_mints_allowed(
+            config.as_ref(),
+            offer_token_mint.key,
+            receive_token_mint.key,
+        )?;
+        if original_offer_account.owner != program_id {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+
+        let mut original_offer_data = Self::load_offer(original_offer_account)?;
+
+        // Verify original_offer_account PDA
+        let nonce_bytes = original_offer_data.nonce.to_le_bytes();
+        let original_offer_seeds = &[
+            b"offer",
+            original_offer_data.maker.as_ref(),
+            original_offer_data.offer_token_mint.as_ref(),
+            original_offer_data.receive_token_mint.as_ref(),
+            nonce_seed(&nonce_bytes),
+            &[original_offer_data.bump_seed],
+        ];
+        let expected_original_offer_key =
+            Pubkey::create_program_address(original_offer_seeds, program_id)
+                .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_original_offer_key != *original_offer_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+
+        // If the counter uses the same maker and mints as the original, both PDAs are the same
+        // locker and we'd write the counter right over the original.
+        if new_offer_account.key == original_offer_account.key {
+            return Err(SwapError::OfferCollision.into());
+        }
+
+        // Who may counter depends on what the offer is right now:
+        // - Direct: only the two parties, i.e. the maker or the named taker.
+        // - Public (including direct offers the maker has since made public): anyone.
+        let is_party = *counter_maker_account.key == original_offer_data.maker
+            || original_offer_data.taker == Some(*counter_maker_account.key);
+        if original_offer_data.offer_type == OfferType::Direct && !is_party {
+            return Err(SwapError::Unauthorized.into());
+        }
+
+        // Check if the original offer is active.
+        if !original_offer_data.status.is_live() {
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+        Self::check_escrow_home(&original_offer_data)?;
+        // Countering would leave a sealed offer's tokens stuck in its vault.
+        if original_offer_data.escrow_token_amount > 0 {
+            return Err(SwapError::SealedOffer.into());
+        }
+
+        // If the original offer had SOL in escrow, it goes back to the original maker (paid below,
+        // since we move those lamports by hand and that has to come after the CPIs). Their wallet
+        // is optional only when there's nothing to refund.
+        let original_maker_sol_account = if original_offer_data.escrow_sol_amount > 0 {
+            let original_maker_sol_account =
+                original_maker_sol_account_opt.ok_or(SwapError::MissingMakerSolAccount)?;
+            if *original_maker_sol_account.key != original_offer_data.maker {
+                return Err(SwapError::IncorrectOwner.into());
+            }
+            Some(original_maker_sol_account)
+        } else {
+            None
+        };
+
+        // --- Create New Counter-Offer Account (PDA), at its canonical bump ---
+        let (expected_new_offer_key, bump_seed) = Pubkey::find_program_address(
+            &[
+                b"offer",
+                counter_maker_account.key.as_ref(),
+                offer_token_mint.key.as_ref(),
+                receive_token_mint.key.as_ref(),
+            ],
+            program_id,
+        );
+        if expected_new_offer_key != *new_offer_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        let new_offer_seeds = &[
+            b"offer",
+            counter_maker_account.key.as_ref(),
+            offer_token_mint.key.as_ref(),
+            receive_token_mint.key.as_ref(),
+            &[bump_seed],
+        ];
+
+        let space = Offer::MAX_LEN;
+        let rent = &Rent::from_account_info(rent_sysvar)?;
+        let rent_lamports = rent.minimum_balance(space);
+        if new_offer_account.data_len() == 0 {
+            invoke_signed(
+                &system_instruction::create_account(
+                    counter_maker_account.key, // Payer
+                    new_offer_account.key,     // New account address (PDA)
+                    rent_lamports,             // Rent
+                    space as u64,              // Size
+                    program_id,                // Owner
+                ),
+                &[
+                    counter_maker_account.clone(),
+                    new_offer_account.clone(),
+                    system_program.clone(),
+                ],
+                &[new_offer_seeds], // Sign with the PDA seeds
+            )?;
+        } else if new_offer_account.owner != program_id || !new_offer_account.data_is_empty() {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+
+        // --- Escrow SOL for the New Counter-Offer if applicable ---
+        let mut new_escrow_sol = 0;
+        let mut offer_decimals = None;
+        if is_sol_side(offer_token_mint.key) {
+            // If counter-maker offers SOL
+            new_escrow_sol = offer_token_amount;
+            let counter_maker_sol_account =
+                counter_maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+            if *counter_maker_sol_account.key != *counter_maker_account.key {
+                return Err(SwapError::IncorrectOwner.into());
+            }
+
+            msg!(
+                "Transferring {} SOL to new counter-offer escrow...",
+                new_escrow_sol
+            );
+            Self::transfer_sol(
+                &[
+                    counter_maker_sol_account.clone(),
+                    new_offer_account.clone(),
+                    system_program.clone(),
+                ],
+                new_escrow_sol,
+                None, // Not signed by program
+            )?;
+            msg!("SOL transferred to new escrow.");
+        } else {
+            // Check if counter-maker has enough tokens if they are offering tokens.
+            let counter_maker_token_account_data =
+                Self::unpack_token_account(counter_maker_token_account)?;
+            if offer_token_amount > counter_maker_token_account_data.amount {
+                return Err(SwapError::InsufficientFunds.into());
+            }
+            if counter_maker_token_account_data.mint != *offer_token_mint.key {
+                return Err(SwapError::TokenMismatch.into());
+            }
+            offer_decimals = Some(Self::unpack_valid_mint(offer_token_mint)?.decimals);
+        }
+
+        // --- Handle Escrowed SOL from Original Offer ---
+        if let Some(original_maker_sol_account) = original_maker_sol_account {
+            msg!(
+                "Refunding {} SOL from original offer escrow to original maker.",
+                original_offer_data.escrow_sol_amount
+            );
+            Self::transfer_sol_from_pda(
+                original_offer_account,
+                original_maker_sol_account,
+                original_offer_data.escrow_sol_amount,
+            )?;
+            original_offer_data.escrow_sol_amount = 0; // Clear escrow amount
+        }
+
+        // --- Create and Save the New Counter Offer Data ---
+        let now = current_timestamp()?;
+        let start_time = start_time.or(original_offer_data.start_time);
+        Self::check_start_time(start_time, expiration)?;
+        let counter_offer = Offer {
+            version: Offer::CURRENT_VERSION,
+            // Keep the same type (direct/public), but always at a fixed price
+            offer_type: original_offer_data
+                .offer_type
+                .for_counter(is_sol_side(offer_token_mint.key)),
+            status: OfferStatus::Active,
+            maker: *counter_maker_account.key,
+            taker: if original_offer_data.maker == *counter_maker_account.key {
+                original_offer_data.taker // If maker countered, taker is still the same
+            } else {
+                Some(original_offer_data.maker) // If taker countered, maker is now the taker
+            },
+            offer_token_mint: *offer_token_mint.key,
+            offer_token_amount,
+            receive_token_mint: *receive_token_mint.key,
+            receive_token_amount,
+            escrow_sol_amount: new_escrow_sol,
+            expiration,
+            is_counter_offer: true,
+            original_offer_id: Some(*original_offer_account.key),
+            bump_seed,
+            min_fill: original_offer_data.min_fill.min(offer_token_amount), // Keep the maker's minimum
+            rent_reserved: rent_lamports,
+            zeroize_on_terminal: config
+                .as_ref()
+                .is_some_and(|config| config.zeroize_terminal_offers),
+            escrow_token_amount: 0,
+            rate_num: 0, // Counters name their own price
+            rate_den: 0,
+            settled_offer_amount: 0,
+            settled_receive_amount: 0,
+            cancelled_at: 0,
+            receive_recipient: None, // Counters pay their own maker
+            premium_bps: 0,
+            yield_strategy: None,
+            yield_principal: 0,
+            nonce: 0, // Counters always take the first slot on their pair
+            created_at: now,
+            last_updated_at: now,
+            start_time,
+            end_price: 0, // Counters sell at a fixed price
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+            offer_decimals,
+            min_acceptable_receive: 0,
+        };
+
+        counter_offer.serialize(&mut &mut new_offer_account.data.borrow_mut()[..])?;
+
+        // The counter is listed in the original's stead (out first, in case it's the same book).
+        Self::delist_from_order_book(
+            program_id,
+            original_order_book,
+            &original_offer_data,
+            original_offer_account.key,
+        )?;
+        Self::list_in_order_book(
+            program_id,
+            counter_order_book,
+            &counter_offer,
+            new_offer_account.key,
+        )?;
+
+        // Update the original offer's status to Countered.
+        Self::close_out_offer(
+            &mut original_offer_data,
+            OfferStatus::Countered,
+            original_offer_account,
+        )?;
+
+        // The original no longer counts as active; the counter does.
+        Self::release_maker_slot(program_id, &original_offer_data.maker, original_maker_index)?;
+        Self::open_maker_slot(
+            program_id,
+            counter_maker_account,
+            counter_maker_index,
+            system_program,
+            config.as_ref(),
+        )?;
+
+        Self::log_event(&SwapEvent::OfferCountered {
+            original_offer: *original_offer_account.key,
+            counter_offer: *new_offer_account.key,
+            counter_maker: *counter_maker_account.key,
+            offer_token_amount: counter_offer.offer_token_amount,
+            receive_token_amount: counter_offer.receive_token_amount,
+        })?;
+        msg!("Counter-offer created successfully!");
+        Ok(())
+    }
+
+    // --- Process WithdrawYield Instruction ---
+    fn process_withdraw_yield(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let [offer_account, maker_account, strategy_accounts @ ..] = accounts else {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        };
+
+        let mut offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if offer_data.yield_principal == 0 {
+            msg!("Nothing is deposited with a yield strategy");
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+        Self::withdraw_yield(
+            &mut offer_data,
+            offer_account,
+            maker_account,
+            strategy_accounts,
+        )?;
+
+        msg!("Escrow is back from its yield strategy");
+        Ok(())
+    }
+
+    // --- Process AcceptWithApproval Instruction ---
+    // Check the maker's signed approval against the offer as it stands, then accept as usual.
+    fn process_accept_with_approval(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        approval: AcceptApproval,
+    ) -> ProgramResult {
+        let (instructions_sysvar, accept_accounts) = accounts
+            .split_first()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let taker_account = accept_accounts
+            .first()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let offer_account = accept_accounts
+            .get(1)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+        let offer = Self::load_verified_offer(program_id, offer_account)?;
+        if approval.offer != *offer_account.key
+            || approval.taker != *taker_account.key
+            || approval.offer_token_amount != offer.offer_token_amount
+            || approval.receive_token_amount != offer.receive_token_amount
+            || approval.offer_last_updated_at != offer.last_updated_at
+        {
+            msg!("The approval is for other terms than the offer's");
+            return Err(SwapError::InvalidApproval.into());
+        }
+        let now = current_timestamp()?;
+        if now > approval.expires_at {
+            msg!(
+                "The approval ran out at {}, it's {}",
+                approval.expires_at,
+                now
+            );
+            return Err(SwapError::InvalidApproval.into());
+        }
+        Self::check_ed25519_signature(
+            instructions_sysvar,
+            &offer.maker,
+            &borsh::to_vec(&approval)?,
+        )?;
+
+        Self::process_accept_offer(
+            program_id,
+            accept_accounts,
+            approval.fill_amount,
+            false,
+            false,
+            (None, None),
+            true,
+            None,
+        )
+    }
+
+    // --- Process SplitOffer Instruction ---
+    fn process_split_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        parts: &[(u64, u64)],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let maker_account = next_account_info(account_info_iter)?; // The person splitting
+        let offer_account = next_account_info(account_info_iter)?; // The offer being split (PDA)
+        let system_program = next_account_info(account_info_iter)?; // To make the new lockers
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+        let maker_index_account = next_account_info(account_info_iter)?; // Maker's open-offer tally
+        let order_book_account = next_account_info(account_info_iter)?; // The pair's book (may not exist)
+        let settlement_account = next_account_info(account_info_iter)?; // Must be empty
+        let child_accounts = account_info_iter.as_slice();
+
+        if !maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if system_program.key != &solana_system_interface::program::ID {
+            return Err(SwapError::InvalidSystemProgram.into());
+        }
+        if parts.is_empty() || parts.len() > MAX_SPLIT_PARTS {
+            msg!(
+                "Can't split into {} parts (max {})",
+                parts.len(),
+                MAX_SPLIT_PARTS
+            );
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        if child_accounts.len() != parts.len() {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let config = Self::load_config(program_id, config_account)?;
+        Self::check_not_paused(config.as_ref())?;
+
+        let offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if offer_data.maker != *maker_account.key {
+            return Err(SwapError::Unauthorized.into());
+        }
+        let now = current_timestamp()?;
+        if !offer_data.status.is_live() {
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+        if offer_data.expiration.is_some_and(|exp| now > exp) {
+            return Err(SwapError::OfferExpired.into());
+        }
+        if offer_data.escrow_token_amount > 0 {
+            return Err(SwapError::SealedOffer.into());
+        }
+        Self::check_escrow_home(&offer_data)?;
+        // A taker who booked a large trade is owed the whole of the original.
+        let (settlement_key, _) =
+            Pubkey::find_program_address(&[b"settle", offer_account.key.as_ref()], program_id);
+        if settlement_key != *settlement_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if settlement_account.owner == program_id {
+            return Err(SwapError::SettlementPending.into());
+        }
+        // These are priced by something other than their amounts.
+        if matches!(
+            offer_data.offer_type,
+            OfferType::RatioOrder | OfferType::PremiumOrder | OfferType::DutchAuction
+        ) {
+            msg!("Only offers priced by their amounts can be split");
+            return Err(SwapError::InvalidInstruction.into());
+        }
+
+        // The pieces have to add up to exactly what's on offer, and each be worth something.
+        let mut total: u64 = 0;
+        for &(offer_amount, receive_amount) in parts {
+            if offer_amount == 0 || receive_amount == 0 {
+                return Err(SwapError::InvalidAmount.into());
+            }
+            total = total
+                .checked_add(offer_amount)
+                .ok_or(SwapError::MathOverflow)?;
+        }
+        if total != offer_data.offer_token_amount {
+            msg!(
+                "Parts offer {} in all, but the offer has {}",
+                total,
+                offer_data.offer_token_amount
+            );
+            return Err(SwapError::InvalidAmount.into());
+        }
+
+        // The original gives up its slots first, so the pieces can have them.
+        Self::delist_from_order_book(
+            program_id,
+            order_book_account,
+            &offer_data,
+            offer_account.key,
+        )?;
+        Self::release_maker_slot(program_id, &offer_data.maker, maker_index_account)?;
+
+        // Make every piece (all CPIs) before any escrow moves by hand.
+        let space = Offer::MAX_LEN;
+        let rent_lamports = Rent::get()?.minimum_balance(space);
+        let escrowed = offer_data.escrow_sol_amount > 0;
+        let mut children = Vec::with_capacity(parts.len());
+        for (i, (&(offer_amount, receive_amount), child_account)) in
+            parts.iter().zip(child_accounts).enumerate()
+        {
+            let nonce = offer_data
+                .nonce
+                .checked_add(i as u64 + 1)
+                .ok_or(SwapError::MathOverflow)?;
+            let nonce_bytes = nonce.to_le_bytes();
+            let seeds: &[&[u8]] = &[
+                b"offer",
+                offer_data.maker.as_ref(),
+                offer_data.offer_token_mint.as_ref(),
+                offer_data.receive_token_mint.as_ref(),
+                nonce_seed(&nonce_bytes),
+            ];
+            let (child_key, bump_seed) = Pubkey::find_program_address(seeds, program_id);
+            if child_key != *child_account.key {
+                return Err(SwapError::InvalidProgramAddress.into());
+            }
+            if child_account.data_len() != 0 {
+                msg!("Offer {} is already in use", child_key);
+                return Err(SwapError::InvalidAccountData.into());
+            }
+            invoke_signed(
+                &system_instruction::create_account(
+                    maker_account.key,
+                    child_account.key,
+                    rent_lamports,
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    maker_account.clone(),
+                    child_account.clone(),
+                    system_program.clone(),
+                ],
+                &[&[
+                    b"offer",
+                    offer_data.maker.as_ref(),
+                    offer_data.offer_token_mint.as_ref(),
+                    offer_data.receive_token_mint.as_ref(),
+                    nonce_seed(&nonce_bytes),
+                    &[bump_seed],
+                ]],
+            )?;
+
+            let child = Offer {
+                version: Offer::CURRENT_VERSION,
+                status: OfferStatus::Active,
+                offer_token_amount: offer_amount,
+                receive_token_amount: receive_amount,
+                escrow_sol_amount: if escrowed { offer_amount } else { 0 },
+                bump_seed,
+                min_fill: offer_data.min_fill.min(offer_amount),
+                rent_reserved: rent_lamports,
+                settled_offer_amount: 0,
+                settled_receive_amount: 0,
+                cancelled_at: 0,
+                nonce,
+                created_at: now,
+                last_updated_at: now,
+                ..offer_data.clone()
+            };
+            child.serialize(&mut &mut child_account.data.borrow_mut()[..])?;
+            Self::open_maker_slot(
+                program_id,
+                maker_account,
+                maker_index_account,
+                system_program,
+                config.as_ref(),
+            )?;
+            Self::list_in_order_book(program_id, order_book_account, &child, child_account.key)?;
+            children.push(child);
+        }
+
+        // Now share out the escrow, and close the original (its rent goes back to the maker).
+        for (child, child_account) in children.iter().zip(child_accounts) {
+            if child.escrow_sol_amount > 0 {
+                Self::transfer_sol_from_pda(offer_account, child_account, child.escrow_sol_amount)?;
+            }
+            Self::log_event(&SwapEvent::OfferCreated {
+                offer: *child_account.key,
+                maker: child.maker,
+                offer_token_mint: child.offer_token_mint,
+                offer_token_amount: child.offer_token_amount,
+                receive_token_mint: child.receive_token_mint,
+                receive_token_amount: child.receive_token_amount,
+                client_id: child.client_id,
+            })?;
+        }
+        Self::close_offer_account(offer_account, maker_account)?;
+        Self::log_event(&SwapEvent::OfferCancelled {
+            offer: *offer_account.key,
+            maker: *maker_account.key,
+            status: OfferStatus::Declined,
+            refunded_lamports: 0,
+        })?;
+
+        msg!("Offer split into {} offers", parts.len());
+        Ok(())
+    }
+
+    // --- Process ReplaceOffer Instruction ---
+    // The cancelling half of a replace, once the new offer is made from `create_accounts`. The
+    // cancel checks that the signing maker made the old offer.
+    fn process_replace_offer<'a>(
+        program_id: &Pubkey,
+        old_offer_account: &AccountInfo<'a>,
+        old_order_book: &AccountInfo<'a>,
+        old_recent_cancel: &AccountInfo<'a>,
+        create_accounts: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let maker_account = create_accounts
+            .first()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let system_program = create_accounts
+            .get(5)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let maker_index_account = create_accounts
+            .get(11)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let maker_sol_account = create_accounts
+            .get(15)
+            .ok_or(SwapError::MissingRequiredAccount)?;
+
+        Self::process_cancel_offer(
+            program_id,
+            &[
+                maker_account.clone(),
+                old_offer_account.clone(),
+                system_program.clone(),
+                maker_index_account.clone(),
+                old_order_book.clone(),
+                old_recent_cancel.clone(),
+                maker_sol_account.clone(),
+            ],
+            true,
+        )
+    }
+
+    // --- Process CancelOffer Instruction ---
+    fn process_cancel_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        close_account: bool,
+    ) -> ProgramResult {
+        msg!("Processing CancelOffer...");
+        let account_info_iter = &mut accounts.iter();
+
+        let offer_maker_account = next_account_info(account_info_iter)?; // The person cancelling
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+        let _system_program = next_account_info(account_info_iter)?; // Kept for the account layout
+        let maker_index_account = next_account_info(account_info_iter)?; // Maker's open-offer tally
+        let order_book_account = next_account_info(account_info_iter)?; // The pair's book (may not exist)
+        let recent_cancel_account = next_account_info(account_info_iter)?; // Maker's last cancel on the pair (may not exist)
+
+        // Optional accounts for SOL and token refunds
+        let maker_sol_account_opt = next_account_info(account_info_iter).ok();
+        let vault_accounts = account_info_iter.as_slice(); // Only needed for sealed or yield offers
+
+        // --- Basic Checks ---
+        // A maker that's a PDA of another program shows up here as a signer when that
+        // program calls us with `invoke_signed`.
+        if !offer_maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if offer_account.owner != program_id {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+
+        let mut offer_data = Self::load_offer(offer_account)?;
+
+        // Verify offer_account PDA
+        let nonce_bytes = offer_data.nonce.to_le_bytes();
+        let offer_seeds = &[
+            b"offer",
+            offer_data.maker.as_ref(),
+            offer_data.offer_token_mint.as_ref(),
+            offer_data.receive_token_mint.as_ref(),
+            nonce_seed(&nonce_bytes),
+            &[offer_data.bump_seed],
+        ];
+        let expected_offer_key = Pubkey::create_program_address(offer_seeds, program_id)
+            .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_offer_key != *offer_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+
+        // Only the original maker can cancel an offer.
+        if offer_data.maker != *offer_maker_account.key {
+            return Err(SwapError::Unauthorized.into());
+        }
+
+        // Only open offers (active, or still waiting on their taker) can be cancelled.
+        if !matches!(
+            offer_data.status,
+            OfferStatus::Active | OfferStatus::PartiallyFilled | OfferStatus::Pending
+        ) {
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+
+        // Give a sealed offer's tokens back, or bring a yield offer's escrow home (its earnings go
+        // to the maker's SOL account, or the maker if they left that out).
+        Self::refund_sealed_tokens(&mut offer_data, offer_account, vault_accounts)?;
+        Self::withdraw_yield(
+            &mut offer_data,
+            offer_account,
+            maker_sol_account_opt.unwrap_or(offer_maker_account),
+            vault_accounts,
+        )?;
+
+        // Refund any escrowed SOL.
+        let mut refunded_lamports = 0;
+        if offer_data.escrow_sol_amount > 0 {
+            let maker_sol_account =
+                maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+            if *maker_sol_account.key != *offer_maker_account.key {
+                return Err(SwapError::IncorrectOwner.into());
+            }
+
+            // Everything above the rent we reserved at creation is the maker's escrow,
+            // so we don't need to recompute rent (which may have changed since).
+            let refund = offer_account
+                .lamports()
+                .checked_sub(offer_data.rent_reserved)
+                .ok_or(SwapError::InsufficientFunds)?;
+            // Clear the escrowed amount (and save it) before paying out, so the offer never
+            // shows escrow that has already been refunded.
+            offer_data.escrow_sol_amount = 0;
+            offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+            msg!("Refunding {} SOL from escrow to maker...", refund);
+            Self::transfer_sol_from_pda(offer_account, maker_sol_account, refund)?;
+            refunded_lamports = refund;
+        }
+
+        // If it had already run out of time, say so; otherwise it was withdrawn (Declined).
+        let now = current_timestamp()?;
+        let final_status = if offer_data.expiration.is_some_and(|exp| now > exp) {
+            OfferStatus::Expired
+        } else {
+            OfferStatus::Declined
+        };
+        offer_data.cancelled_at = now; // Starts the clock on reopening it
+        Self::delist_from_order_book(
+            program_id,
+            order_book_account,
+            &offer_data,
+            offer_account.key,
+        )?;
+        Self::record_cancel(program_id, recent_cancel_account, &offer_data)?;
+        Self::close_out_offer(&mut offer_data, final_status, offer_account)?;
+        Self::release_maker_slot(program_id, &offer_data.maker, maker_index_account)?;
+        if close_account {
+            let maker_sol_account =
+                maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
+            if *maker_sol_account.key != *offer_maker_account.key {
+                return Err(SwapError::IncorrectOwner.into());
+            }
+            Self::close_offer_account(offer_account, maker_sol_account)?;
+        }
+
+        Self::log_event(&SwapEvent::OfferCancelled {
+            offer: *offer_account.key,
+            maker: *offer_maker_account.key,
+            status: final_status,
+            refunded_lamports,
+        })?;
+        msg!("Offer cancelled successfully!");
+        Ok(())
+    }
+
+    // --- Process RejectOffer Instruction ---
+    fn process_reject_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        close_account: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let taker_account = next_account_info(account_info_iter)?; // The named taker, saying no
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+        let maker_account = next_account_info(account_info_iter)?; // Gets the refund
+        let maker_index_account = next_account_info(account_info_iter)?; // Maker's open-offer tally
+        let order_book_account = next_account_info(account_info_iter)?; // The pair's book (may not exist)
+        let vault_accounts = account_info_iter.as_slice(); // Only needed for sealed or yield offers
+
+        if !taker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if offer_data.offer_type != OfferType::Direct {
+            msg!("Only direct offers can be rejected");
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+        if offer_data.taker != Some(*taker_account.key) {
+            return Err(SwapError::Unauthorized.into());
+        }
+        if !matches!(
+            offer_data.status,
+            OfferStatus::Active | OfferStatus::PartiallyFilled | OfferStatus::Pending
+        ) {
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+        if *maker_account.key != offer_data.maker {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+
+        // Give a sealed offer's tokens back to the maker, or bring a yield offer's escrow home.
+        Self::refund_sealed_tokens(&mut offer_data, offer_account, vault_accounts)?;
+        Self::withdraw_yield(
+            &mut offer_data,
+            offer_account,
+            maker_account,
+            vault_accounts,
+        )?;
+
+        // Everything above the rent is the maker's escrow, as in a cancel.
+        let mut refunded_lamports = 0;
+        if offer_data.escrow_sol_amount > 0 {
+            let refund = offer_account
+                .lamports()
+                .checked_sub(offer_data.rent_reserved)
+                .ok_or(SwapError::InsufficientFunds)?;
+            offer_data.escrow_sol_amount = 0;
+            offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+            msg!("Refunding {} SOL from escrow to maker...", refund);
+            Self::transfer_sol_from_pda(offer_account, maker_account, refund)?;
+            refunded_lamports = refund;
+        }
+
+        Self::delist_from_order_book(
+            program_id,
+            order_book_account,
+            &offer_data,
+            offer_account.key,
+        )?;
+        Self::close_out_offer(&mut offer_data, OfferStatus::Declined, offer_account)?;
+        Self::release_maker_slot(program_id, &offer_data.maker, maker_index_account)?;
+        if close_account {
+            Self::close_offer_account(offer_account, maker_account)?;
+        }
+
+        Self::log_event(&SwapEvent::OfferCancelled {
+            offer: *offer_account.key,
+            maker: *maker_account.key,
+            status: OfferStatus::Declined,
+            refunded_lamports,
+        })?;
+        msg!("Offer rejected by its taker");
+        Ok(())
+    }
+
+    // --- Helper to cancel a one-shot offer an accept was turned away from ---
+    // The accept has to succeed for the cancel to stick, so this returns `Ok` with nothing traded.
+    fn cancel_one_shot(
+        program_id: &Pubkey,
+        offer_data: &mut Offer,
+        offer_account: &AccountInfo,
+        maker_account: &AccountInfo,
+        maker_index_account: &AccountInfo,
+        order_book_account: &AccountInfo,
+        blocker: AcceptBlocker,
+    ) -> ProgramResult {
+        if offer_data.maker != *maker_account.key {
+            return Err(SwapError::OfferMismatch.into());
+        }
+
+        let mut refunded_lamports = 0;
+        if offer_data.escrow_sol_amount > 0 {
+            let refund = offer_account
+                .lamports()
+                .checked_sub(offer_data.rent_reserved)
+                .ok_or(SwapError::InsufficientFunds)?;
+            offer_data.escrow_sol_amount = 0;
+            Self::transfer_sol_from_pda(offer_account, maker_account, refund)?;
+            refunded_lamports = refund;
+        }
+
+        offer_data.cancelled_at = current_timestamp()?;
+        Self::delist_from_order_book(
+            program_id,
+            order_book_account,
+            offer_data,
+            offer_account.key,
+        )?;
+        Self::close_out_offer(offer_data, OfferStatus::Declined, offer_account)?;
+        Self::release_maker_slot(program_id, &offer_data.maker, maker_index_account)?;
+
+        Self::log_event(&SwapEvent::OfferCancelled {
+            offer: *offer_account.key,
+            maker: *maker_account.key,
+            status: OfferStatus::Declined,
+            refunded_lamports,
+        })?;
+        msg!(
+            "Accept turned away ({:?}), so the one-shot offer was cancelled",
+            blocker
+        );
+        Ok(())
+    }
+
+    // --- Process ConfirmInterest Instruction ---
+    fn process_confirm_interest(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let taker_account = next_account_info(account_info_iter)?; // The named taker
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+
+        if !taker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if offer_data.taker != Some(*taker_account.key) {
+            return Err(SwapError::Unauthorized.into());
+        }
+        if offer_data.status != OfferStatus::Pending {
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+        if let Some(exp) = offer_data.expiration
+            && current_timestamp()? > exp
+        {
+            return Err(SwapError::OfferExpired.into());
+        }
+
+        offer_data.status = OfferStatus::Active;
+        offer_data.last_updated_at = current_timestamp()?;
+        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+
+        msg!("Offer confirmed, it can be accepted now!");
+        Ok(())
+    }
+
+    // --- Process ReopenOffer Instruction ---
+    fn process_reopen_offer(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let offer_maker_account = next_account_info(account_info_iter)?; // The person reopening
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+        let system_program = next_account_info(account_info_iter)?; // To re-escrow SOL
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+        let maker_index_account = next_account_info(account_info_iter)?; // Maker's open-offer tally
+        let order_book_account = next_account_info(account_info_iter)?; // The pair's book (may not exist)
+
+        if !offer_maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let mut offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if offer_data.maker != *offer_maker_account.key {
+            return Err(SwapError::Unauthorized.into());
+        }
+
+        // Only a recent cancel can be taken back, and only if there's still an offer to go back to.
+        let config = Self::load_config(program_id, config_account)?;
+        Self::check_not_paused(config.as_ref())?;
+        let reopen_grace = config.as_ref().map_or(0, |config| config.reopen_grace);
+        let now = current_timestamp()?;
+        if offer_data.status != OfferStatus::Declined
+            || offer_data.offer_token_amount == 0 // Wiped when it closed
+            || now > offer_data.cancelled_at.saturating_add(reopen_grace)
+        {
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+        if offer_data.expiration.is_some_and(|exp| now > exp) {
+            return Err(SwapError::OfferExpired.into());
+        }
+
+        Self::open_maker_slot(
+            program_id,
+            offer_maker_account,
+            maker_index_account,
+            system_program,
+            config.as_ref(),
+        )?;
+
+        // A buy offer's SOL went back on cancel, so it has to come back in.
+        if is_sol_side(&offer_data.offer_token_mint) {
+            let escrow_sol = offer_data.offer_token_amount;
+            msg!("Re-escrowing {} SOL...", escrow_sol);
+            Self::transfer_sol(
+                &[
+                    offer_maker_account.clone(),
+                    offer_account.clone(),
+                    system_program.clone(),
+                ],
+                escrow_sol,
+                None, // Not signed by program
+            )?;
+            offer_data.escrow_sol_amount = escrow_sol;
+        }
+
+        // Back to how it was before the cancel, including any fills it had.
+        offer_data.status = if offer_data.settled_offer_amount > 0 {
+            OfferStatus::PartiallyFilled
+        } else {
+            OfferStatus::Active
+        };
+        offer_data.cancelled_at = 0;
+        offer_data.last_updated_at = now;
+        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+        Self::list_in_order_book(
+            program_id,
+            order_book_account,
+            &offer_data,
+            offer_account.key,
+        )?;
+
+        msg!("Offer reopened!");
+        Ok(())
+    }
+
+    // --- Process QuoteAccept Instruction ---
+    fn process_quote_accept(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        fill_amount: Option<u64>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let offer_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let history_account_opt = next_account_info(account_info_iter).ok();
+        let metadata_account_opt = next_account_info(account_info_iter).ok();
+
+        let offer = Self::load_verified_offer(program_id, offer_account)?;
+        let fee_bps = Self::load_config(program_id, config_account)?.map_or(0, |c| c.fee_bps);
+        if !offer.status.is_live() {
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+
+        // Same two cases as the accept: escrowed SOL buys the taker's tokens, or the taker
+        // pays SOL for the maker's tokens. Royalties follow the token being sold.
+        let (token_amount, sol_amount, sold_mint) = if offer.escrow_sol_amount > 0 {
+            let (token_amount, sol_amount) = Self::escrow_release_amounts(&offer, fill_amount)?;
+            (token_amount, sol_amount, offer.receive_token_mint)
+        } else {
+            let reference = Self::reference_trade(program_id, &offer, history_account_opt)?;
+            let (token_amount, sol_amount) = Self::token_sale_amounts(
+                &offer,
+                fill_amount,
+                reference.as_ref(),
+                current_timestamp()?,
+            )?;
+            (token_amount, sol_amount, offer.offer_token_mint)
+        };
+        let royalty = Self::royalty_total(&Self::royalty_payouts(
+            metadata_account_opt,
+            &sold_mint,
+            sol_amount,
+        )?)?;
+        // The fee comes out of whatever the maker receives, so only a token seller's SOL shrinks.
+        let (protocol_fee, seller_proceeds) = if offer.escrow_sol_amount > 0 {
+            let seller_proceeds = sol_amount
+                .checked_sub(royalty)
+                .ok_or(SwapError::MathOverflow)?;
+            (Self::protocol_fee(token_amount, fee_bps)?, seller_proceeds)
+        } else {
+            let protocol_fee = Self::protocol_fee(sol_amount, fee_bps)?;
+            let seller_proceeds = sol_amount
+                .checked_sub(royalty)
+                .and_then(|proceeds| proceeds.checked_sub(protocol_fee))
+                .ok_or(SwapError::MathOverflow)?;
+            (protocol_fee, seller_proceeds)
+        };
+
+        let quote = Quote {
+            token_amount,
+            sol_amount,
+            seller_proceeds,
+            royalty,
+            protocol_fee,
+        };
+        msg!("Quote: {:?}", quote);
+        set_return_data(&borsh::to_vec(&quote)?);
+        Ok(())
+    }
+
+    // --- Process GetStatuses Instruction ---
+    fn process_get_statuses(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        if accounts.len() > MAX_STATUS_BATCH {
+            msg!(
+                "Too many offers: {} (max {})",
+                accounts.len(),
+                MAX_STATUS_BATCH
+            );
+            return Err(SwapError::InvalidInstruction.into());
+        }
+
+        let mut statuses: Vec<(Pubkey, OfferStatus)> = Vec::with_capacity(accounts.len());
+        for account in accounts {
+            // Only our own lockers can hold offers; anything else we just skip.
+            if account.owner != program_id {
+                continue;
+            }
+            if let Ok(offer) = Offer::load(&account.data.borrow()) {
+                statuses.push((*account.key, offer.status));
+            }
+        }
+
+        set_return_data(&borsh::to_vec(&statuses)?);
+        Ok(())
+    }
+
+    // --- Process SweepExpired Instruction ---
+    fn process_sweep_expired(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let (keeper_account, offer_groups) = accounts
+            .split_first()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if !keeper_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if offer_groups.len() % 4 != 0 {
+            return Err(SwapError::MissingRequiredAccount.into());
+        }
+        if offer_groups.len() / 4 > MAX_SWEEP_BATCH {
+            msg!(
+                "Too many offers: {} (max {})",
+                offer_groups.len() / 4,
+                MAX_SWEEP_BATCH
+            );
+            return Err(SwapError::InvalidInstruction.into());
+        }
+
+        let now = current_timestamp()?;
+        let mut events = Vec::new();
+        for group in offer_groups.chunks_exact(4) {
+            let (offer_account, maker_account, maker_index_account, order_book_account) =
+                (&group[0], &group[1], &group[2], &group[3]);
+
+            // --- Can this one go? If not, say why and move on. ---
+            let mut offer_data = match Self::load_verified_offer(program_id, offer_account) {
+                Ok(offer_data) => offer_data,
+                Err(err) => {
+                    msg!("Skipping {}: not an offer ({:?})", offer_account.key, err);
+                    continue;
+                }
+            };
+            let expiration = match offer_data.expiration {
+                Some(exp)
+                    if matches!(
+                        offer_data.status,
+                        OfferStatus::Active | OfferStatus::PartiallyFilled | OfferStatus::Pending
+                    ) && now > exp =>
+                {
+                    exp
+                }
+                _ => {
+                    msg!(
+                        "Skipping {}: not an expired active offer",
+                        offer_account.key
+                    );
+                    continue;
+                }
+            };
+            if offer_data.escrow_token_amount > 0 {
+                msg!("Skipping {}: sealed, cancel it instead", offer_account.key);
+                continue;
+            }
+            if offer_data.yield_principal > 0 {
+                msg!(
+                    "Skipping {}: escrow is earning yield, cancel it instead",
+                    offer_account.key
+                );
+                continue;
+            }
+            let (expected_index_key, _) =
+                Pubkey::find_program_address(&[b"maker", offer_data.maker.as_ref()], program_id);
+            if offer_data.maker != *maker_account.key
+                || expected_index_key != *maker_index_account.key
+            {
+                msg!("Skipping {}: wrong maker accounts", offer_account.key);
+                continue;
+            }
+            let (expected_book_key, _) = Pubkey::find_program_address(
+                &[
+                    b"book",
+                    offer_data.offer_token_mint.as_ref(),
+                    offer_data.receive_token_mint.as_ref(),
+                ],
+                program_id,
+            );
+            if expected_book_key != *order_book_account.key {
+                msg!("Skipping {}: wrong order book", offer_account.key);
+                continue;
+            }
+
+            // Escrow goes back first: if the maker's wallet can't take it, nothing has changed yet.
+            let refunded_lamports = if offer_data.escrow_sol_amount > 0 {
+                let Some(refund) = offer_account
+                    .lamports()
+                    .checked_sub(offer_data.rent_reserved)
+                else {
+                    msg!("Skipping {}: holds less than its rent", offer_account.key);
+                    continue;
+                };
+                if let Err(err) = Self::transfer_sol_from_pda(offer_account, maker_account, refund)
+                {
+                    msg!("Skipping {}: refund failed ({:?})", offer_account.key, err);
+                    continue;
+                }
+                offer_data.escrow_sol_amount = 0;
+                refund
+            } else {
+                0
+            };
+            Self::delist_from_order_book(
+                program_id,
+                order_book_account,
+                &offer_data,
+                offer_account.key,
+            )?;
+            Self::close_out_offer(&mut offer_data, OfferStatus::Expired, offer_account)?;
+            Self::release_maker_slot(program_id, maker_account.key, maker_index_account)?;
+
+            let event = OfferExpiredEvent {
+                offer: *offer_account.key,
+                maker: *maker_account.key,
+                refunded_lamports,
+                expiration,
+            };
+            solana_program::log::sol_log_data(&[OFFER_EXPIRED_EVENT, &borsh::to_vec(&event)?]);
+            events.push(event);
+        }
+
+        msg!("Swept {} expired offers", events.len());
+        set_return_data(&borsh::to_vec(&events)?);
+        Ok(())
+    }
+
+    // --- Process ExpireOffer Instruction ---
+    fn process_expire_offer(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let offer_account = next_account_info(account_info_iter)?; // The stale offer (PDA)
+        let maker_account = next_account_info(account_info_iter)?; // Gets the rent back
+        let maker_sol_account = next_account_info(account_info_iter)?; // Gets the escrow back
+        let system_program = next_account_info(account_info_iter)?;
+        let maker_index_account = next_account_info(account_info_iter)?; // Maker's open-offer tally
+        let order_book_account = next_account_info(account_info_iter)?; // The pair's book (may not exist)
+        let vault_accounts = account_info_iter.as_slice(); // Only needed for sealed offers
+
+        if system_program.key != &solana_system_interface::program::ID {
+            return Err(SwapError::InvalidSystemProgram.into());
+        }
+        let mut offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if offer_data.maker != *maker_account.key {
+            return Err(SwapError::OfferMismatch.into());
+        }
+        if *maker_sol_account.key != offer_data.maker {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+        let now = current_timestamp()?;
+        let expiration = match offer_data.expiration {
+            Some(exp)
+                if matches!(
+                    offer_data.status,
+                    OfferStatus::Active | OfferStatus::PartiallyFilled | OfferStatus::Pending
+                ) && now > exp =>
+            {
+                exp
+            }
+            _ => {
+                msg!("Only open offers past their expiration can be expired");
+                return Err(SwapError::InvalidOfferStatus.into());
+            }
+        };
+
+        // Tokens (or a yield offer's escrow) first: that's a CPI, and the lamports below are
+        // moved by hand.
+        Self::refund_sealed_tokens(&mut offer_data, offer_account, vault_accounts)?;
+        Self::withdraw_yield(
+            &mut offer_data,
+            offer_account,
+            maker_sol_account,
+            vault_accounts,
+        )?;
+        let refunded_lamports = if offer_data.escrow_sol_amount > 0 {
+            let refund = offer_account
+                .lamports()
+                .checked_sub(offer_data.rent_reserved)
+                .ok_or(SwapError::InsufficientFunds)?;
+            Self::transfer_sol_from_pda(offer_account, maker_sol_account, refund)?;
+            offer_data.escrow_sol_amount = 0;
+            refund
+        } else {
+            0
+        };
+        Self::delist_from_order_book(
+            program_id,
+            order_book_account,
+            &offer_data,
+            offer_account.key,
+        )?;
+        Self::close_out_offer(&mut offer_data, OfferStatus::Expired, offer_account)?;
+        Self::release_maker_slot(program_id, maker_account.key, maker_index_account)?;
+
+        let rent = offer_account.lamports();
+        Self::close_offer_account(offer_account, maker_account)?;
+
+        let event = OfferExpiredEvent {
+            offer: *offer_account.key,
+            maker: *maker_account.key,
+            refunded_lamports,
+            expiration,
+        };
+        solana_program::log::sol_log_data(&[OFFER_EXPIRED_EVENT, &borsh::to_vec(&event)?]);
+        msg!(
+            "Offer expired and closed, {} lamports of rent returned",
+            rent
+        );
+        Ok(())
+    }
+
+    // --- Process CancelAllForPair Instruction ---
+    fn process_cancel_all_for_pair(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_token_mint: Pubkey,
+        receive_token_mint: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let offer_maker_account = next_account_info(account_info_iter)?; // The person cancelling
+        let maker_index_account = next_account_info(account_info_iter)?; // Maker's open-offer tally
+        let recent_cancel_account = next_account_info(account_info_iter)?; // For the cool-down (may not exist)
+        let order_book_account = next_account_info(account_info_iter)?; // The pair's book (may not exist)
+        let offer_accounts = account_info_iter.as_slice();
+
+        if !offer_maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if offer_accounts.len() > MAX_CANCEL_BATCH {
+            msg!(
+                "Too many offers: {} (max {})",
+                offer_accounts.len(),
+                MAX_CANCEL_BATCH
+            );
+            return Err(SwapError::InvalidInstruction.into());
+        }
+
+        let now = current_timestamp()?;
+        let mut cancelled = 0;
+        for offer_account in offer_accounts {
+            // --- Is this one of the maker's open offers for the pair? If not, move on. ---
+            let mut offer_data = match Self::load_verified_offer(program_id, offer_account) {
+                Ok(offer_data) => offer_data,
+                Err(err) => {
+                    msg!("Skipping {}: not an offer ({:?})", offer_account.key, err);
+                    continue;
+                }
+            };
+            if offer_data.maker != *offer_maker_account.key {
+                msg!("Skipping {}: not yours", offer_account.key);
+                continue;
+            }
+            if offer_data.offer_token_mint != offer_token_mint
+                || offer_data.receive_token_mint != receive_token_mint
+            {
+                msg!("Skipping {}: another pair", offer_account.key);
+                continue;
+            }
+            if !matches!(
+                offer_data.status,
+                OfferStatus::Active | OfferStatus::PartiallyFilled | OfferStatus::Pending
+            ) {
+                msg!("Skipping {}: not open", offer_account.key);
+                continue;
+            }
+            if offer_data.escrow_token_amount > 0 {
+                msg!(
+                    "Skipping {}: sealed, cancel it on its own",
+                    offer_account.key
+                );
+                continue;
+            }
+            if offer_data.yield_principal > 0 {
+                msg!(
+                    "Skipping {}: escrow is earning yield, cancel it on its own",
+                    offer_account.key
+                );
+                continue;
+            }
+
+            // Same as `CancelOffer`: escrow back, then Declined (or Expired if it already was).
+            let mut refunded_lamports = 0;
+            if offer_data.escrow_sol_amount > 0 {
+                refunded_lamports = offer_account
+                    .lamports()
+                    .checked_sub(offer_data.rent_reserved)
+                    .ok_or(SwapError::InsufficientFunds)?;
+                Self::transfer_sol_from_pda(offer_account, offer_maker_account, refunded_lamports)?;
+                offer_data.escrow_sol_amount = 0;
+            }
+            let final_status = if offer_data.expiration.is_some_and(|exp| now > exp) {
+                OfferStatus::Expired
+            } else {
+                OfferStatus::Declined
+            };
+            offer_data.cancelled_at = now;
+            Self::delist_from_order_book(
+                program_id,
+                order_book_account,
+                &offer_data,
+                offer_account.key,
+            )?;
+            Self::record_cancel(program_id, recent_cancel_account, &offer_data)?;
+            Self::close_out_offer(&mut offer_data, final_status, offer_account)?;
+            Self::release_maker_slot(program_id, &offer_data.maker, maker_index_account)?;
+            Self::log_event(&SwapEvent::OfferCancelled {
+                offer: *offer_account.key,
+                maker: *offer_maker_account.key,
+                status: final_status,
+                refunded_lamports,
+            })?;
+            cancelled += 1;
+        }
+
+        msg!("Cancelled {} offers", cancelled);
+        Ok(())
+    }
+
+    // --- Process UpdateOffer Instruction ---
+    fn process_update_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        new_offer_amount: Option<u64>,
+        new_receive_amount: Option<u64>,
+        new_expiration: Option<Option<i64>>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let offer_maker_account = next_account_info(account_info_iter)?; // The person repricing
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+        let system_program = next_account_info(account_info_iter)?; // To escrow more SOL
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+        let settlement_account = next_account_info(account_info_iter)?; // Must be empty
+        let vault_accounts = account_info_iter.as_slice(); // Sealed offers only
+
+        if !offer_maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let config = Self::load_config(program_id, config_account)?;
+        Self::update_offer(
+            program_id,
+            offer_maker_account,
+            offer_account,
+            system_program,
+            config.as_ref(),
+            settlement_account,
+            vault_accounts,
+            new_offer_amount,
+            new_receive_amount,
+            new_expiration,
+        )
+    }
+
+    // --- Process TopUpEscrow Instruction ---
+    fn process_top_up_escrow(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let offer_maker_account = next_account_info(account_info_iter)?; // The person topping up
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+        let system_program = next_account_info(account_info_iter)?; // To move the SOL
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+
+        if !offer_maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let config = Self::load_config(program_id, config_account)?;
+        let mut offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if offer_data.maker != *offer_maker_account.key {
+            return Err(SwapError::Unauthorized.into());
+        }
+        if !offer_data.status.is_live() {
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+        let now = current_timestamp()?;
+        if offer_data.expiration.is_some_and(|exp| now > exp) {
+            return Err(SwapError::OfferExpired.into());
+        }
+        if offer_data.escrow_sol_amount == 0 {
+            msg!("Only offers escrowing SOL can be topped up");
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        Self::check_escrow_home(&offer_data)?;
+        if amount == 0 {
+            return Err(SwapError::InvalidAmount.into());
+        }
+
+        // The escrow is what's offered, so both go up together.
+        let escrow_sol = offer_data
+            .escrow_sol_amount
+            .checked_add(amount)
+            .ok_or(SwapError::MathOverflow)?;
+        Self::check_escrow_cap(config.as_ref(), escrow_sol)?;
+        offer_data.offer_token_amount = offer_data
+            .offer_token_amount
+            .checked_add(amount)
+            .ok_or(SwapError::MathOverflow)?;
+        offer_data.escrow_sol_amount = escrow_sol;
+        offer_data.last_updated_at = now;
+
+        msg!("Escrowing {} more lamports...", amount);
+        Self::transfer_sol(
+            &[
+                offer_maker_account.clone(),
+                offer_account.clone(),
+                system_program.clone(),
+            ],
             amount,
-            mint_decimals,
+            None, // Maker is signing directly
         )?;
-        // Call the SPL Token program to actually do the transfer.
-        if let Some(seeds) = signer_seeds {
-            invoke_signed(&transfer_instruction, account_infos, &[seeds])?;
+        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    // --- Process MigrateOffer Instruction ---
+    fn process_migrate_offer(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let payer_account = next_account_info(account_info_iter)?; // Pays for the extra room
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+        let system_program = next_account_info(account_info_iter)?; // To move the rent
+
+        if !payer_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if offer_account.owner != program_id {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+        // Reads any layout we know, upgraded to the current one.
+        let mut offer_data = Offer::load(&offer_account.data.borrow())?;
+        Self::check_offer_address(program_id, offer_account, &offer_data)?;
+        if offer_account.data_len() >= Offer::MAX_LEN {
+            msg!("Offer is already on version {}", Offer::CURRENT_VERSION);
+            return Ok(());
+        }
+
+        // Keep the bigger locker rent-exempt.
+        let rent = Rent::get()?;
+        let extra_rent = rent
+            .minimum_balance(Offer::MAX_LEN)
+            .saturating_sub(rent.minimum_balance(offer_account.data_len()));
+        if extra_rent > 0 {
+            Self::transfer_sol(
+                &[
+                    payer_account.clone(),
+                    offer_account.clone(),
+                    system_program.clone(),
+                ],
+                extra_rent,
+                None, // Payer is signing directly
+            )?;
+        }
+        offer_data.rent_reserved = offer_data
+            .rent_reserved
+            .checked_add(extra_rent)
+            .ok_or(SwapError::MathOverflow)?;
+
+        offer_account.resize(Offer::MAX_LEN)?;
+        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+        msg!("Offer migrated to version {}", Offer::CURRENT_VERSION);
+        Ok(())
+    }
+
+    // --- Process CanAccept Instruction ---
+    fn process_can_accept(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        taker: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let offer_account = next_account_info(account_info_iter)?;
+        let offer_mint_pause = next_account_info(account_info_iter)?;
+        let receive_mint_pause = next_account_info(account_info_iter)?;
+
+        let offer = Self::load_verified_offer(program_id, offer_account)?;
+        let now = current_timestamp()?;
+        let mut reason = accept_blocker(&offer, taker, now);
+        if reason.is_none() {
+            for (mint_pause, mint) in [
+                (offer_mint_pause, &offer.offer_token_mint),
+                (receive_mint_pause, &offer.receive_token_mint),
+            ] {
+                match Self::check_mint_not_paused(program_id, mint_pause, mint) {
+                    Err(ProgramError::Custom(code)) if code == SwapError::ProgramPaused as u32 => {
+                        reason = Some(AcceptBlocker::MintPaused);
+                    }
+                    result => result?,
+                }
+            }
+        }
+
+        let eligibility = AcceptEligibility {
+            allowed: reason.is_none(),
+            reason,
+        };
+        msg!("Eligibility of {}: {:?}", taker, eligibility);
+        set_return_data(&borsh::to_vec(&eligibility)?);
+        Ok(())
+    }
+
+    // --- Process UpdateOfferBatch Instruction ---
+    fn process_update_offer_batch(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        updates: &[OfferUpdate],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let offer_maker_account = next_account_info(account_info_iter)?; // The person repricing
+        let system_program = next_account_info(account_info_iter)?; // To escrow more SOL
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+        let offer_accounts = account_info_iter.as_slice(); // (offer, settlement) pairs
+
+        if !offer_maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if updates.len() > MAX_UPDATE_BATCH {
+            msg!(
+                "Too many offers: {} (max {})",
+                updates.len(),
+                MAX_UPDATE_BATCH
+            );
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        if offer_accounts.len() != updates.len() * 2 {
+            return Err(SwapError::MissingRequiredAccount.into());
+        }
+
+        let config = Self::load_config(program_id, config_account)?;
+        let mut updated = 0;
+        for (update, pair) in updates.iter().zip(offer_accounts.chunks_exact(2)) {
+            let (offer_account, settlement_account) = (&pair[0], &pair[1]);
+            if let Err(err) = Self::update_offer(
+                program_id,
+                offer_maker_account,
+                offer_account,
+                system_program,
+                config.as_ref(),
+                settlement_account,
+                &[], // No vault accounts, so sealed offers keep their amount
+                update.new_offer_amount,
+                update.new_receive_amount,
+                None,
+            ) {
+                msg!("Skipping {}: {:?}", offer_account.key, err);
+                continue;
+            }
+            updated += 1;
+        }
+
+        msg!("Updated {} offers", updated);
+        Ok(())
+    }
+
+    // --- Helper to apply one `UpdateOffer` ---
+    // Everything but the signer check, so `UpdateOfferBatch` can skip the offers it refuses.
+    #[allow(clippy::too_many_arguments)]
+    fn update_offer<'a>(
+        program_id: &Pubkey,
+        offer_maker_account: &AccountInfo<'a>,
+        offer_account: &AccountInfo<'a>,
+        system_program: &AccountInfo<'a>,
+        config: Option<&ProgramConfig>,
+        settlement_account: &AccountInfo<'a>,
+        vault_accounts: &[AccountInfo<'a>],
+        new_offer_amount: Option<u64>,
+        new_receive_amount: Option<u64>,
+        new_expiration: Option<Option<i64>>,
+    ) -> ProgramResult {
+        let mut offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if offer_data.maker != *offer_maker_account.key {
+            return Err(SwapError::Unauthorized.into());
+        }
+        if offer_data.status != OfferStatus::Active || offer_data.is_counter_offer {
+            return Err(SwapError::InvalidOfferStatus.into());
+        }
+        if let Some(exp) = offer_data.expiration
+            && current_timestamp()? > exp
+        {
+            return Err(SwapError::OfferExpired.into());
+        }
+        Self::check_escrow_home(&offer_data)?;
+
+        // A taker who booked a large trade agreed to the old terms.
+        let (settlement_key, _) =
+            Pubkey::find_program_address(&[b"settle", offer_account.key.as_ref()], program_id);
+        if settlement_key != *settlement_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if settlement_account.owner == program_id {
+            return Err(SwapError::SettlementPending.into());
+        }
+
+        // --- Work out the new terms, checked like `CreateOffer` checks them ---
+        let is_ratio_order = offer_data.offer_type == OfferType::RatioOrder;
+        if is_ratio_order && new_receive_amount.is_some() {
+            msg!("A ratio order's price comes from its rate");
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        if offer_data.offer_type == OfferType::DutchAuction && new_receive_amount.is_some() {
+            msg!("A Dutch auction's price comes from its schedule");
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        if offer_data.offer_type == OfferType::DutchAuction && new_expiration.is_some() {
+            msg!("A Dutch auction's schedule ends at its expiration");
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        let expiration = new_expiration.unwrap_or(offer_data.expiration);
+        Self::check_start_time(offer_data.start_time, expiration)?;
+        let offer_amount = new_offer_amount.unwrap_or(offer_data.offer_token_amount);
+        let receive_amount = if is_ratio_order {
+            Self::ratio_amount(offer_data.rate_num, offer_data.rate_den, offer_amount)?
         } else {
-            invoke(&transfer_instruction, account_infos)?;
+            new_receive_amount.unwrap_or(offer_data.receive_token_amount)
+        };
+        if offer_amount == 0 || receive_amount == 0 {
+            return Err(SwapError::InvalidAmount.into());
+        }
+        if offer_data.min_fill > offer_amount {
+            return Err(SwapError::InvalidFillAmount.into());
+        }
+
+        // --- Move the escrow to match the new amount ---
+        if offer_data.escrow_sol_amount > 0 {
+            if let Some(config) = config
+                && offer_amount < config.min_escrow_sol
+            {
+                return Err(SwapError::AmountTooSmall.into());
+            }
+            Self::check_escrow_cap(config, offer_amount)?;
+            let escrow_sol = offer_data.escrow_sol_amount;
+            if offer_amount > escrow_sol {
+                msg!("Escrowing {} more lamports...", offer_amount - escrow_sol);
+                Self::transfer_sol(
+                    &[
+                        offer_maker_account.clone(),
+                        offer_account.clone(),
+                        system_program.clone(),
+                    ],
+                    offer_amount - escrow_sol,
+                    None, // Maker is signing directly
+                )?;
+            } else if offer_amount < escrow_sol {
+                msg!(
+                    "Refunding {} lamports of escrow...",
+                    escrow_sol - offer_amount
+                );
+                Self::transfer_sol_from_pda(
+                    offer_account,
+                    offer_maker_account,
+                    escrow_sol - offer_amount,
+                )?;
+            }
+            offer_data.escrow_sol_amount = offer_amount;
+        } else if offer_data.escrow_token_amount > 0
+            && offer_amount != offer_data.escrow_token_amount
+        {
+            let [
+                offer_token_vault,
+                maker_token_account,
+                offer_token_mint,
+                token_program,
+                ..,
+            ] = vault_accounts
+            else {
+                return Err(SwapError::MissingRequiredAccount.into());
+            };
+            if *offer_token_mint.key != offer_data.offer_token_mint {
+                return Err(SwapError::TokenMismatch.into());
+            }
+            Self::check_offer_vault(offer_account, offer_token_vault, offer_token_mint.key)?;
+            Self::check_token_account(
+                maker_token_account,
+                &offer_data.maker,
+                offer_token_mint.key,
+            )?;
+            let mint_info = Self::unpack_mint(offer_token_mint)?;
+            let escrow_tokens = offer_data.escrow_token_amount;
+            if offer_amount > escrow_tokens {
+                msg!("Sealing {} more tokens...", offer_amount - escrow_tokens);
+                Self::transfer_spl_token(
+                    &[
+                        maker_token_account.clone(),
+                        offer_token_mint.clone(),
+                        offer_token_vault.clone(),
+                        offer_maker_account.clone(),
+                        token_program.clone(),
+                    ],
+                    offer_amount - escrow_tokens,
+                    mint_info.decimals,
+                    None, // Maker is signing directly
+                )?;
+            } else {
+                msg!("Unsealing {} tokens...", escrow_tokens - offer_amount);
+                let nonce_bytes = offer_data.nonce.to_le_bytes();
+                let offer_seeds: &[&[u8]] = &[
+                    b"offer",
+                    offer_data.maker.as_ref(),
+                    offer_data.offer_token_mint.as_ref(),
+                    offer_data.receive_token_mint.as_ref(),
+                    nonce_seed(&nonce_bytes),
+                    &[offer_data.bump_seed],
+                ];
+                Self::transfer_spl_token(
+                    &[
+                        offer_token_vault.clone(),
+                        offer_token_mint.clone(),
+                        maker_token_account.clone(),
+                        offer_account.clone(),
+                        token_program.clone(),
+                    ],
+                    escrow_tokens - offer_amount,
+                    mint_info.decimals,
+                    Some(offer_seeds), // Program is signing for the vault
+                )?;
+            }
+            offer_data.escrow_token_amount = offer_amount;
         }
+
+        offer_data.offer_token_amount = offer_amount;
+        offer_data.receive_token_amount = receive_amount;
+        offer_data.expiration = expiration;
+        offer_data.last_updated_at = current_timestamp()?;
+        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+
+        msg!(
+            "Offer updated: {} offered for {}",
+            offer_amount,
+            receive_amount
+        );
         Ok(())
     }
 
-    // --- Process CreateOffer Instruction ---
-    fn process_create_offer(
+    // --- Process InitializeConfig Instruction ---
+    fn process_initialize_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let admin_account = next_account_info(account_info_iter)?; // Who will run things
+        let config_account = next_account_info(account_info_iter)?; // The config locker (PDA)
+        let system_program = next_account_info(account_info_iter)?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let (expected_config_key, bump_seed) =
+            Pubkey::find_program_address(&[b"config"], program_id);
+        if expected_config_key != *config_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        // There's only ever one config, and once it exists nobody can take it over.
+        if config_account.data_len() != 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+
+        let space = ProgramConfig::MAX_LEN;
+        let rent_lamports = Rent::get()?.minimum_balance(space);
+        invoke_signed(
+            &system_instruction::create_account(
+                admin_account.key,
+                config_account.key,
+                rent_lamports,
+                space as u64,
+                program_id,
+            ),
+            &[
+                admin_account.clone(),
+                config_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[b"config", &[bump_seed]]],
+        )?;
+
+        let config = ProgramConfig {
+            admin: *admin_account.key,
+            bump_seed,
+            max_active_offers_per_maker: 0, // No limit until the admin sets one
+            create_fee: 0,                  // Listing is free until the admin sets a fee
+            fee_collector: *admin_account.key,
+            zeroize_terminal_offers: false, // Finished offers keep their details by default
+            reject_amounts_over_supply: false, // Just warn about oversized offers
+            large_trade_threshold: 0,       // Every trade settles right away
+            settlement_delay: 0,
+            pending_authority: None,
+            min_escrow_sol: 0,
+            reopen_grace: 0,
+            fee_bps: 0, // Accepts are free until the admin sets a fee
+            treasury: *admin_account.key,
+            max_escrow_per_offer: 0, // Offers can escrow any amount
+            paused: false,
+            recreate_cooldown: 0, // Cancelled offers can be posted again right away
+            allowed_mints: Vec::new(), // Any mint can be traded
+        };
+        config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+        msg!("Config initialized, admin is {}", admin_account.key);
+        Ok(())
+    }
+
+    // --- Process PauseMint Instruction ---
+    fn process_pause_mint(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let mint_pause_account = next_account_info(account_info_iter)?; // The pause locker (PDA)
+        let mint = next_account_info(account_info_iter)?; // The mint to pause
+        let system_program = next_account_info(account_info_iter)?;
+
+        Self::check_admin(program_id, admin_account, config_account)?;
+
+        let (expected_key, bump_seed) =
+            Pubkey::find_program_address(&[b"mintpause", mint.key.as_ref()], program_id);
+        if expected_key != *mint_pause_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        // Pausing twice doesn't make sense.
+        if mint_pause_account.data_len() != 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+
+        let space = MintPause::MAX_LEN;
+        let rent_lamports = Rent::get()?.minimum_balance(space);
+        invoke_signed(
+            &system_instruction::create_account(
+                admin_account.key,
+                mint_pause_account.key,
+                rent_lamports,
+                space as u64,
+                program_id,
+            ),
+            &[
+                admin_account.clone(),
+                mint_pause_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[b"mintpause", mint.key.as_ref(), &[bump_seed]]],
+        )?;
+
+        let mint_pause = MintPause {
+            mint: *mint.key,
+            bump_seed,
+        };
+        mint_pause.serialize(&mut &mut mint_pause_account.data.borrow_mut()[..])?;
+
+        msg!("Mint {} paused", mint.key);
+        Ok(())
+    }
+
+    // --- Process UnpauseMint Instruction ---
+    fn process_unpause_mint(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let mint_pause_account = next_account_info(account_info_iter)?;
+
+        Self::check_admin(program_id, admin_account, config_account)?;
+
+        if mint_pause_account.owner != program_id {
+            return Err(SwapError::AccountNotInitialized.into());
+        }
+        let mint_pause = MintPause::try_from_slice(&mint_pause_account.data.borrow())?;
+        let expected_key = Pubkey::create_program_address(
+            &[
+                b"mintpause",
+                mint_pause.mint.as_ref(),
+                &[mint_pause.bump_seed],
+            ],
+            program_id,
+        )
+        .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_key != *mint_pause_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+
+        // Close the pause locker: wipe it and hand its rent back to the admin.
+        // Once it has no lamports left, Solana cleans it up after the transaction.
+        Self::transfer_sol_from_pda(
+            mint_pause_account,
+            admin_account,
+            mint_pause_account.lamports(),
+        )?;
+        mint_pause_account.data.borrow_mut().fill(0);
+
+        msg!("Mint {} unpaused", mint_pause.mint);
+        Ok(())
+    }
+
+    // --- Process InitOrderBook Instruction ---
+    fn process_init_order_book(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
-        offer_type: OfferType,
-        offer_token_amount: u64,
-        receive_token_amount: u64,
-        expiration: Option<i64>,
-        bump_seed: u8,
+        capacity: u16,
     ) -> ProgramResult {
-        msg!("Processing CreateOffer...");
         let account_info_iter = &mut accounts.iter();
 
-        // Get all the lockers we need from the list.
-        let maker_account = next_account_info(account_info_iter)?; // The person making the offer
-        let offer_account = next_account_info(account_info_iter)?; // The new locker for this offer (PDA)
-        let maker_token_account = next_account_info(account_info_iter)?; // Maker's token account (for what they offer)
-        let offer_token_mint = next_account_info(account_info_iter)?; // The type of token being offered
-        let receive_token_mint = next_account_info(account_info_iter)?; // The type of token/SOL expected
-        let system_program = next_account_info(account_info_iter)?; // Solana's basic program
-        let token_program = next_account_info(account_info_iter)?; // SPL Token program
-        let rent_sysvar = next_account_info(account_info_iter)?; // Rent checker
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let order_book_account = next_account_info(account_info_iter)?; // The book locker (PDA)
+        let offer_token_mint = next_account_info(account_info_iter)?;
+        let receive_token_mint = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+
+        Self::check_admin(program_id, admin_account, config_account)?;
+
+        // A book with no room would shut the market; pause the mint for that instead.
+        if capacity == 0 || capacity > OrderBook::MAX_CAPACITY {
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        let (expected_key, bump_seed) = Pubkey::find_program_address(
+            &[
+                b"book",
+                offer_token_mint.key.as_ref(),
+                receive_token_mint.key.as_ref(),
+            ],
+            program_id,
+        );
+        if expected_key != *order_book_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        // One book per pair.
+        if order_book_account.data_len() != 0 {
+            return Err(SwapError::InvalidAccountData.into());
+        }
+
+        let space = OrderBook::space(capacity);
+        let rent_lamports = Rent::get()?.minimum_balance(space);
+        invoke_signed(
+            &system_instruction::create_account(
+                admin_account.key,
+                order_book_account.key,
+                rent_lamports,
+                space as u64,
+                program_id,
+            ),
+            &[
+                admin_account.clone(),
+                order_book_account.clone(),
+                system_program.clone(),
+            ],
+            &[&[
+                b"book",
+                offer_token_mint.key.as_ref(),
+                receive_token_mint.key.as_ref(),
+                &[bump_seed],
+            ]],
+        )?;
+
+        let book = OrderBook {
+            offer_token_mint: *offer_token_mint.key,
+            receive_token_mint: *receive_token_mint.key,
+            capacity,
+            bump_seed,
+            offers: Vec::new(),
+        };
+        book.serialize(&mut &mut order_book_account.data.borrow_mut()[..])?;
+
+        msg!("Order book opened with room for {} offers", capacity);
+        Ok(())
+    }
+
+    // --- Process PruneOrderBook Instruction ---
+    fn process_prune_order_book(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let (order_book_account, offer_accounts) = accounts
+            .split_first()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+        if order_book_account.owner != program_id {
+            return Err(SwapError::AccountNotInitialized.into());
+        }
+        let mut book = OrderBook::load(&order_book_account.data.borrow())?;
+        let expected_key = Pubkey::create_program_address(
+            &[
+                b"book",
+                book.offer_token_mint.as_ref(),
+                book.receive_token_mint.as_ref(),
+                &[book.bump_seed],
+            ],
+            program_id,
+        )
+        .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_key != *order_book_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+
+        let listed = book.offers.len();
+        for offer_account in offer_accounts {
+            // Closed offers are gone from under us; the rest have to say they're done.
+            let still_open = offer_account.owner == program_id
+                && Offer::load(&offer_account.data.borrow()).is_ok_and(|offer| {
+                    matches!(
+                        offer.status,
+                        OfferStatus::Active | OfferStatus::PartiallyFilled | OfferStatus::Pending
+                    )
+                });
+            if !still_open {
+                book.offers.retain(|key| key != offer_account.key);
+            }
+        }
+
+        let pruned = listed - book.offers.len();
+        if pruned > 0 {
+            let mut data = order_book_account.data.borrow_mut();
+            data.fill(0);
+            book.serialize(&mut &mut data[..])?;
+        }
+        msg!("Pruned {} offers from the order book", pruned);
+        Ok(())
+    }
+
+    // --- Process UpdateConfig Instruction ---
+    fn process_update_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        field: ConfigField,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+
+        let mut config = Self::check_admin(program_id, admin_account, config_account)?;
+        match field {
+            ConfigField::MaxActiveOffersPerMaker(max_active_offers) => {
+                config.max_active_offers_per_maker = max_active_offers;
+            }
+            ConfigField::CreateFee(create_fee) => config.create_fee = create_fee,
+            ConfigField::FeeCollector(fee_collector) => config.fee_collector = fee_collector,
+            ConfigField::ZeroizeTerminalOffers(zeroize) => config.zeroize_terminal_offers = zeroize,
+            ConfigField::RejectAmountsOverSupply(reject) => {
+                config.reject_amounts_over_supply = reject;
+            }
+            ConfigField::LargeTradeThreshold(threshold) => config.large_trade_threshold = threshold,
+            ConfigField::SettlementDelay(delay) => config.settlement_delay = delay,
+            ConfigField::MinEscrowSol(lamports) => config.min_escrow_sol = lamports,
+            ConfigField::ReopenGrace(grace) => config.reopen_grace = grace,
+            ConfigField::FeeBps(fee_bps) => {
+                if fee_bps > ProgramConfig::MAX_FEE_BPS {
+                    return Err(SwapError::FeeTooHigh.into());
+                }
+                config.fee_bps = fee_bps;
+            }
+            ConfigField::Treasury(treasury) => config.treasury = treasury,
+            ConfigField::MaxEscrowPerOffer(lamports) => config.max_escrow_per_offer = lamports,
+            ConfigField::RecreateCooldown(secs) => config.recreate_cooldown = secs,
+        }
+        config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+        msg!("Config updated: {:?}", field);
+        Ok(())
+    }
+
+    // --- Process AddAllowedMint / RemoveAllowedMint Instructions ---
+    fn process_set_mint_allowed(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        mint: Pubkey,
+        allowed: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+
+        let mut config = Self::check_admin(program_id, admin_account, config_account)?;
+        let position = config
+            .allowed_mints
+            .iter()
+            .position(|listed| *listed == mint);
+        match (allowed, position) {
+            (true, None) => {
+                if config.allowed_mints.len() >= ProgramConfig::MAX_ALLOWED_MINTS {
+                    msg!(
+                        "The allowlist is full ({} mints)",
+                        ProgramConfig::MAX_ALLOWED_MINTS
+                    );
+                    return Err(SwapError::InvalidInstruction.into());
+                }
+                config.allowed_mints.push(mint);
+            }
+            (false, Some(position)) => {
+                config.allowed_mints.remove(position);
+            }
+            (true, Some(_)) | (false, None) => {
+                msg!(
+                    "Mint {} is {} on the allowlist",
+                    mint,
+                    if allowed { "already" } else { "not" }
+                );
+                return Err(SwapError::InvalidInstruction.into());
+            }
+        }
+        // The list may have got shorter, so clear what used to be past its end.
+        let mut data = config_account.data.borrow_mut();
+        data.fill(0);
+        config.serialize(&mut &mut data[..])?;
+
+        msg!(
+            "Mint {} {} the allowlist",
+            mint,
+            if allowed { "added to" } else { "removed from" }
+        );
+        Ok(())
+    }
+
+    // --- Process SetPaused Instruction ---
+    fn process_set_paused(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        paused: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+
+        let mut config = Self::check_admin(program_id, admin_account, config_account)?;
+        config.paused = paused;
+        config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+        msg!("Program {}", if paused { "paused" } else { "unpaused" });
+        Ok(())
+    }
+
+    // --- Process SetPendingAuthority Instruction ---
+    fn process_set_pending_authority(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        new_authority: Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+
+        let mut config = Self::check_admin(program_id, admin_account, config_account)?;
+        config.pending_authority = Some(new_authority);
+        config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+        msg!("Admin role offered to {}", new_authority);
+        Ok(())
+    }
 
-        // Optional accounts
-        let maker_sol_account_opt = next_account_info(account_info_iter).ok();
-        let taker_account_opt = next_account_info(account_info_iter).ok();
+    // --- Process AcceptAuthority Instruction ---
+    fn process_accept_authority(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
 
-        // --- Basic Checks ---
-        // 1. Is the maker signing this?
-        if !maker_account.is_signer {
+        let new_authority_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+
+        if !new_authority_account.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
+        let mut config = Self::load_verified_config(program_id, config_account)?;
+        if config.pending_authority != Some(*new_authority_account.key) {
+            return Err(SwapError::Unauthorized.into());
+        }
+        config.admin = *new_authority_account.key;
+        config.pending_authority = None;
+        config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
 
-        // 2. Verify the offer_account is a PDA derived from our program.
-        let offer_seeds = &[
-            b"offer",                        // A constant string seed
-            maker_account.key.as_ref(),      // Maker's public key as a seed
-            offer_token_mint.key.as_ref(),   // Offered token mint as a seed
-            receive_token_mint.key.as_ref(), // Received token mint as a seed
-            &[bump_seed],                    // The bump seed
-        ];
-        let (expected_offer_key, expected_bump_seed) =
-            Pubkey::find_program_address(offer_seeds, program_id);
+        msg!("{} is now the admin", new_authority_account.key);
+        Ok(())
+    }
 
-        if expected_offer_key != *offer_account.key || expected_bump_seed != bump_seed {
-            return Err(SwapError::InvalidProgramAddress.into());
-        }
+    // --- Process ReclaimEmptyOffer Instruction ---
+    fn process_reclaim_empty_offer(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        offer_token_mint: Pubkey,
+        receive_token_mint: Pubkey,
+        bump_seed: u8,
+        nonce: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
 
-        // 3. Create the offer account if it doesn't exist and is not rent-exempt.
-        // The offer_account must be writable and owned by the system program for creation.
-        if offer_account.data_len() == 0 {
-            let space = Offer::MAX_LEN; // Max size for our offer data
-            let rent = &Rent::from_account_info(rent_sysvar)?;
-            let rent_lamports = rent.minimum_balance(space);
+        let maker_account = next_account_info(account_info_iter)?; // Gets the lamports
+        let offer_account = next_account_info(account_info_iter)?; // The half-made offer
 
-            invoke_signed(
-                &system_instruction::create_account(
-                    maker_account.key, // Payer
-                    offer_account.key, // New account address (PDA)
-                    rent_lamports,     // Rent
-                    space as u64,      // Size
-                    program_id,        // Owner
-                ),
-                &[
-                    maker_account.clone(),
-                    offer_account.clone(),
-                    system_program.clone(),
-                ],
-                &[offer_seeds], // Sign with the PDA seeds
-            )?;
-        } else {
-            // If account already exists, it must be owned by our program and empty
-            if offer_account.owner != program_id || !offer_account.data_is_empty() {
-                return Err(SwapError::InvalidAccountData.into());
-            }
+        if !maker_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
         }
-
-        // 4. Check if maker_token_account is actually a token account and owned by maker.
-        let maker_token_account_data = TokenAccount::unpack(&maker_token_account.data.borrow())?;
-        if maker_token_account_data.owner != *maker_account.key {
+        if offer_account.owner != program_id {
             return Err(SwapError::IncorrectOwner.into());
         }
-        if maker_token_account_data.mint != *offer_token_mint.key {
-            return Err(SwapError::TokenMismatch.into());
+        // Only the maker's own PDA, since the maker's key is part of the seeds.
+        let nonce_bytes = nonce.to_le_bytes();
+        let expected_offer_key = Pubkey::create_program_address(
+            &[
+                b"offer",
+                maker_account.key.as_ref(),
+                offer_token_mint.as_ref(),
+                receive_token_mint.as_ref(),
+                nonce_seed(&nonce_bytes),
+                &[bump_seed],
+            ],
+            program_id,
+        )
+        .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_offer_key != *offer_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        // Anything written means it's a real offer, which goes through `CancelOffer` instead.
+        if offer_account.data.borrow().iter().any(|byte| *byte != 0) {
+            return Err(SwapError::InvalidAccountData.into());
         }
 
-        // --- Handle Direct Offers ---
-        let taker_pubkey = if offer_type == OfferType::Direct {
-            let taker_account = taker_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
-            Some(*taker_account.key)
-        } else {
-            None
-        };
+        let lamports = offer_account.lamports();
+        Self::transfer_sol_from_pda(offer_account, maker_account, lamports)?;
 
-        // --- Escrow SOL if it's a "Buy" offer (maker offers SOL for tokens) ---
-        let mut escrow_sol = 0;
-        // Using a dummy Pubkey::new_from_array([0; 32]) to represent SOL.
-        // In a real app, consider using spl_token::native_mint::ID for wrapped SOL or a specific flag.
-        if offer_type == OfferType::PublicBuy
-            || (offer_type == OfferType::Direct
-                && *offer_token_mint.key == Pubkey::new_from_array([0; 32]))
-        {
-            // If the maker is offering SOL, they need to send it to our program's escrow.
-            escrow_sol = offer_token_amount; // The amount of SOL they are offering
+        msg!("Reclaimed {} lamports from empty offer", lamports);
+        Ok(())
+    }
 
-            let maker_sol_account =
-                maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
-            if *maker_sol_account.key != *maker_account.key {
-                return Err(SwapError::IncorrectOwner.into()); // Ensure it's the maker's SOL account
-            }
+    // --- Process ForceRecoverTokens Instruction ---
+    fn process_force_recover_tokens(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        maker: Pubkey,
+        receive_token_mint: Pubkey,
+        bump_seed: u8,
+        nonce: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
 
-            msg!("Transferring {} SOL to escrow...", escrow_sol);
-            Self::transfer_sol(
-                &[
-                    maker_sol_account.clone(),
-                    offer_account.clone(),
-                    system_program.clone(),
-                ],
-                escrow_sol,
-                None, // Not signed by program
-            )?;
-            msg!("SOL transferred to escrow.");
-        } else if offer_token_amount > maker_token_account_data.amount {
-            // If maker is offering tokens, they must have enough.
-            return Err(SwapError::InsufficientFunds.into());
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let offer_account = next_account_info(account_info_iter)?; // The unreadable offer
+        let offer_token_vault = next_account_info(account_info_iter)?; // Its stranded tokens
+        let recovery_token_account = next_account_info(account_info_iter)?; // Where they go
+        let offer_token_mint = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        Self::check_admin(program_id, admin_account, config_account)?;
+        Self::check_token_program(token_program)?;
+        if offer_account.owner != program_id {
+            return Err(SwapError::IncorrectOwner.into());
+        }
+        // This is only for offers nothing else can deal with.
+        if Offer::load(&offer_account.data.borrow()).is_ok() {
+            msg!("The offer can still be read; cancel (or migrate) it instead");
+            return Err(SwapError::InvalidInstruction.into());
         }
 
-        // --- Create and Save the Offer Data ---
-        let offer = Offer {
-            offer_type,
-            status: OfferStatus::Active, // New offers are always active
-            maker: *maker_account.key,
-            taker: taker_pubkey,
-            offer_token_mint: *offer_token_mint.key,
-            offer_token_amount,
-            receive_token_mint: *receive_token_mint.key,
-            receive_token_amount,
-            escrow_sol_amount: escrow_sol,
-            expiration,
-            is_counter_offer: false,
-            original_offer_id: None,
-            bump_seed, // Store the bump seed in the offer data
-        };
+        let nonce_bytes = nonce.to_le_bytes();
+        let offer_seeds: &[&[u8]] = &[
+            b"offer",
+            maker.as_ref(),
+            offer_token_mint.key.as_ref(),
+            receive_token_mint.as_ref(),
+            nonce_seed(&nonce_bytes),
+            &[bump_seed],
+        ];
+        let expected_offer_key = Pubkey::create_program_address(offer_seeds, program_id)
+            .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_offer_key != *offer_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        Self::check_offer_vault(offer_account, offer_token_vault, offer_token_mint.key)?;
 
-        // Save the offer data into the `offer_account` locker using borsh.
-        offer.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+        let vault_amount = Self::unpack_token_account(offer_token_vault)?.amount;
+        let mint_info = Self::unpack_mint(offer_token_mint)?;
+        Self::transfer_spl_token(
+            &[
+                offer_token_vault.clone(),
+                offer_token_mint.clone(),
+                recovery_token_account.clone(),
+                offer_account.clone(),
+                token_program.clone(),
+            ],
+            vault_amount,
+            mint_info.decimals,
+            Some(offer_seeds), // The offer PDA owns the vault
+        )?;
 
-        msg!("Offer created successfully!");
+        msg!(
+            "Recovered {} tokens from {} to {}",
+            vault_amount,
+            offer_account.key,
+            recovery_token_account.key
+        );
         Ok(())
     }
 
-    // --- Process AcceptOffer Instruction ---
-    fn process_accept_offer(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
-        msg!("Processing AcceptOffer...");
+    // --- Process MatchOffers Instruction ---
+    fn process_match_offers(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
 
-        // Get all the lockers we need.
-        let taker_account = next_account_info(account_info_iter)?; // The person accepting
-        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
-        let maker_account = next_account_info(account_info_iter)?; // The original maker
-        let maker_token_account = next_account_info(account_info_iter)?; // Maker's token account
-        let taker_token_account = next_account_info(account_info_iter)?; // Taker's token account
-        let offer_token_mint = next_account_info(account_info_iter)?; // Offered token type (mint)
-        let receive_token_mint = next_account_info(account_info_iter)?; // Received token type (mint)
-        let system_program = next_account_info(account_info_iter)?; // System program
-        let token_program = next_account_info(account_info_iter)?; // Token program
-
-        // Optional accounts for SOL transfers
-        let maker_sol_account_opt = next_account_info(account_info_iter).ok();
-        let taker_sol_account_opt = next_account_info(account_info_iter).ok();
+        let matcher_account = next_account_info(account_info_iter)?; // Who's pairing the offers up
+        let offer_a_account = next_account_info(account_info_iter)?; // Offer A's locker (X for Y)
+        let offer_b_account = next_account_info(account_info_iter)?; // Offer B's locker (Y for X)
+        let maker_a_account = next_account_info(account_info_iter)?;
+        let maker_b_account = next_account_info(account_info_iter)?;
+        let maker_a_offer_token_account = next_account_info(account_info_iter)?; // A's X (sends)
+        let maker_a_receive_token_account = next_account_info(account_info_iter)?; // A's Y (receives)
+        let maker_b_offer_token_account = next_account_info(account_info_iter)?; // B's Y (sends)
+        let maker_b_receive_token_account = next_account_info(account_info_iter)?; // B's X (receives)
+        let mint_x = next_account_info(account_info_iter)?;
+        let mint_y = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let mint_x_pause = next_account_info(account_info_iter)?;
+        let mint_y_pause = next_account_info(account_info_iter)?;
+        let maker_a_index = next_account_info(account_info_iter)?;
+        let maker_b_index = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+        let order_book_a = next_account_info(account_info_iter)?; // X-for-Y book (may not exist)
+        let order_book_b = next_account_info(account_info_iter)?; // Y-for-X book (may not exist)
 
         // --- Basic Checks ---
-        if !taker_account.is_signer {
+        if !matcher_account.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
-        if offer_account.owner != program_id {
-            return Err(SwapError::IncorrectOwner.into());
+        Self::check_token_program(token_program)?;
+        Self::check_not_paused(Self::load_config(program_id, config_account)?.as_ref())?;
+        if offer_a_account.key == offer_b_account.key {
+            return Err(SwapError::OfferMismatch.into()); // An offer can't match itself
         }
 
-        // Load the offer data from its locker using borsh.
-        let mut offer_data = Offer::try_from_slice(&offer_account.data.borrow())?;
-
-        // Verify the offer_account is a PDA derived from our program and the stored bump seed.
-        let offer_seeds = &[
-            b"offer",
-            offer_data.maker.as_ref(),
-            offer_data.offer_token_mint.as_ref(),
-            offer_data.receive_token_mint.as_ref(),
-            &[offer_data.bump_seed],
-        ];
-        let (expected_offer_key, _) = Pubkey::find_program_address(offer_seeds, program_id);
+        let mut offer_a = Self::load_verified_offer(program_id, offer_a_account)?;
+        let mut offer_b = Self::load_verified_offer(program_id, offer_b_account)?;
 
-        if expected_offer_key != *offer_account.key {
-            return Err(SwapError::InvalidProgramAddress.into());
+        // Both offers have to be live.
+        let now = current_timestamp()?;
+        for offer in [&offer_a, &offer_b] {
+            if !offer.status.is_live() {
+                return Err(SwapError::InvalidOfferStatus.into());
+            }
+            if offer.expiration.is_some_and(|exp| now > exp) {
+                return Err(SwapError::OfferExpired.into());
+            }
+            if !offer.has_started(now) {
+                return Err(SwapError::OfferNotStarted.into());
+            }
         }
 
-        // Check offer status and expiration.
-        if offer_data.status != OfferStatus::Active {
-            return Err(SwapError::InvalidOfferStatus.into());
+        // The makers passed in must be the offers' makers, and both must sign off on
+        // their tokens leaving (we don't hold them).
+        if offer_a.maker != *maker_a_account.key || offer_b.maker != *maker_b_account.key {
+            return Err(SwapError::OfferMismatch.into());
         }
-        if let Some(exp) = offer_data.expiration {
-            if solana_program::clock::Clock::get()?.unix_timestamp > exp {
-                offer_data.status = OfferStatus::Expired;
-                offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
-                return Err(SwapError::OfferExpired.into());
-            }
+        let a_sealed = offer_a.escrow_token_amount > 0;
+        let b_sealed = offer_b.escrow_token_amount > 0;
+        if (!a_sealed && !maker_a_account.is_signer) || (!b_sealed && !maker_b_account.is_signer) {
+            return Err(ProgramError::MissingRequiredSignature);
         }
 
-        // Check if it's a direct offer and the taker is correct.
-        if offer_data.offer_type == OfferType::Direct
-            && offer_data.taker != Some(*taker_account.key)
-        {
+        // A direct offer can only be matched with the person it was made for.
+        if offer_a.offer_type == OfferType::Direct && offer_a.taker != Some(offer_b.maker) {
+            return Err(SwapError::Unauthorized.into());
+        }
+        if offer_b.offer_type == OfferType::Direct && offer_b.taker != Some(offer_a.maker) {
             return Err(SwapError::Unauthorized.into());
         }
 
-        // Verify maker_account is the actual maker.
-        if offer_data.maker != *maker_account.key {
+        // The two offers must be exact mirror images of each other.
+        if offer_a.offer_token_mint != offer_b.receive_token_mint
+            || offer_a.receive_token_mint != offer_b.offer_token_mint
+            || offer_a.offer_token_amount != offer_b.receive_token_amount
+            || offer_a.receive_token_amount != offer_b.offer_token_amount
+        {
             return Err(SwapError::OfferMismatch.into());
         }
+        if *mint_x.key != offer_a.offer_token_mint || *mint_y.key != offer_a.receive_token_mint {
+            return Err(SwapError::TokenMismatch.into());
+        }
+        // Only token-for-token offers can be matched; SOL sits in escrow and moves differently.
+        if is_sol_side(mint_x.key)
+            || is_sol_side(mint_y.key)
+            || offer_a.escrow_sol_amount > 0
+            || offer_b.escrow_sol_amount > 0
+        {
+            return Err(SwapError::TokenMismatch.into());
+        }
+        Self::check_mint_not_paused(program_id, mint_x_pause, mint_x.key)?;
+        Self::check_mint_not_paused(program_id, mint_y_pause, mint_y.key)?;
 
-        // Check token account ownership and mints
-        let maker_token_account_data = TokenAccount::unpack(&maker_token_account.data.borrow())?;
-        let taker_token_account_data = TokenAccount::unpack(&taker_token_account.data.borrow())?;
+        // Sealed offers send from their vault; the rest send straight from the maker.
+        if a_sealed {
+            Self::check_offer_vault(offer_a_account, maker_a_offer_token_account, mint_x.key)?;
+        } else {
+            Self::check_token_account(maker_a_offer_token_account, &offer_a.maker, mint_x.key)?;
+        }
+        Self::check_token_account(
+            maker_a_receive_token_account,
+            &offer_a.proceeds_owner(),
+            mint_y.key,
+        )?;
+        if b_sealed {
+            Self::check_offer_vault(offer_b_account, maker_b_offer_token_account, mint_y.key)?;
+        } else {
+            Self::check_token_account(maker_b_offer_token_account, &offer_b.maker, mint_y.key)?;
+        }
+        Self::check_token_account(
+            maker_b_receive_token_account,
+            &offer_b.proceeds_owner(),
+            mint_x.key,
+        )?;
+        let offer_a_nonce = offer_a.nonce.to_le_bytes();
+        let offer_a_seeds: &[&[u8]] = &[
+            b"offer",
+            offer_a.maker.as_ref(),
+            offer_a.offer_token_mint.as_ref(),
+            offer_a.receive_token_mint.as_ref(),
+            nonce_seed(&offer_a_nonce),
+            &[offer_a.bump_seed],
+        ];
+        let offer_b_nonce = offer_b.nonce.to_le_bytes();
+        let offer_b_seeds: &[&[u8]] = &[
+            b"offer",
+            offer_b.maker.as_ref(),
+            offer_b.offer_token_mint.as_ref(),
+            offer_b.receive_token_mint.as_ref(),
+            nonce_seed(&offer_b_nonce),
+            &[offer_b.bump_seed],
+        ];
 
-        if maker_token_account_data.owner != *maker_account.key {
-            return Err(SwapError::IncorrectOwner.into());
+        // --- Leg 1: A's X goes to B ---
+        let mint_x_info = Self::unpack_mint(mint_x)?;
+        Self::transfer_spl_token(
+            &[
+                maker_a_offer_token_account.clone(),
+                mint_x.clone(),
+                maker_b_receive_token_account.clone(),
+                if a_sealed {
+                    offer_a_account.clone()
+                } else {
+                    maker_a_account.clone()
+                },
+                token_program.clone(),
+            ],
+            offer_a.offer_token_amount,
+            mint_x_info.decimals,
+            a_sealed.then_some(offer_a_seeds), // The offer signs for its vault
+        )?;
+
+        // --- Leg 2: B's Y goes to A ---
+        let mint_y_info = Self::unpack_mint(mint_y)?;
+        Self::transfer_spl_token(
+            &[
+                maker_b_offer_token_account.clone(),
+                mint_y.clone(),
+                maker_a_receive_token_account.clone(),
+                if b_sealed {
+                    offer_b_account.clone()
+                } else {
+                    maker_b_account.clone()
+                },
+                token_program.clone(),
+            ],
+            offer_b.offer_token_amount,
+            mint_y_info.decimals,
+            b_sealed.then_some(offer_b_seeds), // The offer signs for its vault
+        )?;
+
+        // Both offers are done, and both vaults (if any) are empty.
+        offer_a.escrow_token_amount = 0;
+        offer_b.escrow_token_amount = 0;
+        Self::delist_from_order_book(program_id, order_book_a, &offer_a, offer_a_account.key)?;
+        Self::delist_from_order_book(program_id, order_book_b, &offer_b, offer_b_account.key)?;
+        Self::close_out_offer(&mut offer_a, OfferStatus::Accepted, offer_a_account)?;
+        Self::close_out_offer(&mut offer_b, OfferStatus::Accepted, offer_b_account)?;
+        Self::release_maker_slot(program_id, &offer_a.maker, maker_a_index)?;
+        Self::release_maker_slot(program_id, &offer_b.maker, maker_b_index)?;
+
+        msg!("Offers matched successfully!");
+        Ok(())
+    }
+
+    // --- Process RingSettle Instruction ---
+    fn process_ring_settle(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        // One account for each of A, B and C.
+        fn next_three<'a, 'b>(
+            iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+        ) -> Result<[&'a AccountInfo<'b>; 3], ProgramError> {
+            Ok([
+                next_account_info(iter)?,
+                next_account_info(iter)?,
+                next_account_info(iter)?,
+            ])
         }
-        if taker_token_account_data.owner != *taker_account.key {
-            return Err(SwapError::IncorrectOwner.into());
+
+        let settler_account = next_account_info(account_info_iter)?; // Who's closing the ring
+        let offer_accounts = next_three(account_info_iter)?;
+        let maker_accounts = next_three(account_info_iter)?;
+        let offer_token_accounts = next_three(account_info_iter)?; // What each maker gives (sends)
+        let receive_token_accounts = next_three(account_info_iter)?; // What each maker wants (receives)
+        let mints = next_three(account_info_iter)?; // The mint each offer gives
+        let token_program = next_account_info(account_info_iter)?;
+        let mint_pauses = next_three(account_info_iter)?;
+        let maker_indexes = next_three(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?; // Program settings (may not exist)
+        let order_books = next_three(account_info_iter)?; // Each offer's pair's book (may not exist)
+
+        // --- Basic Checks ---
+        if !settler_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Self::check_token_program(token_program)?;
+        Self::check_not_paused(Self::load_config(program_id, config_account)?.as_ref())?;
+        // Three different offers, or it's not much of a ring.
+        if offer_accounts[0].key == offer_accounts[1].key
+            || offer_accounts[1].key == offer_accounts[2].key
+            || offer_accounts[2].key == offer_accounts[0].key
+        {
+            return Err(SwapError::OfferMismatch.into());
         }
 
-        // --- Perform the Swap! ---
-        // Case 1: Maker offered SOL (escrow_sol_amount > 0), Taker offers Tokens
-        if offer_data.escrow_sol_amount > 0 {
-            msg!("Executing SOL for Token swap...");
+        let mut offers = [
+            Self::load_verified_offer(program_id, offer_accounts[0])?,
+            Self::load_verified_offer(program_id, offer_accounts[1])?,
+            Self::load_verified_offer(program_id, offer_accounts[2])?,
+        ];
 
-            // Ensure correct mints for token accounts
-            if maker_token_account_data.mint != *receive_token_mint.key {
-                return Err(SwapError::TokenMismatch.into());
+        let now = current_timestamp()?;
+        for i in 0..3 {
+            // Whoever's next in the ring gets this offer's tokens.
+            let (offer, next) = (&offers[i], &offers[(i + 1) % 3]);
+            if !offer.status.is_live() {
+                return Err(SwapError::InvalidOfferStatus.into());
             }
-            if taker_token_account_data.mint != *offer_token_mint.key {
-                // Taker gives tokens (offer_token_mint)
-                return Err(SwapError::TokenMismatch.into());
+            if offer.expiration.is_some_and(|exp| now > exp) {
+                return Err(SwapError::OfferExpired.into());
             }
-
-            // Transfer SOL from escrow (offer_account) to maker_account
-            let maker_sol_account =
-                maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
-            if *maker_sol_account.key != *maker_account.key {
-                return Err(SwapError::IncorrectOwner.into());
+            if !offer.has_started(now) {
+                return Err(SwapError::OfferNotStarted.into());
             }
-            Self::transfer_sol(
-                &[
-                    offer_account.clone(),
-                    maker_sol_account.clone(),
-                    system_program.clone(),
-                ],
-                offer_data.escrow_sol_amount,
-                Some(offer_seeds), // Program is signing for the escrow account
-            )?;
-
-            // Transfer tokens from taker to maker
-            let mint_info = Mint::unpack(&receive_token_mint.data.borrow())?; // Get decimals for the token taker is giving
-            Self::transfer_spl_token(
-                &[
-                    taker_token_account.clone(),
-                    receive_token_mint.clone(),
-                    maker_token_account.clone(),
-                    taker_account.clone(),
-                    token_program.clone(),
-                ],
-                offer_data.receive_token_amount,
-                mint_info.decimals,
-                None, // Taker is signing directly
-            )?;
-            msg!("SOL for Token swap completed.");
-        } else {
-            // Case 2: Maker offered Tokens (escrow_sol_amount == 0), Taker offers SOL
-            msg!("Executing Token for SOL swap...");
-
-            // Ensure correct mints for token accounts
-            if maker_token_account_data.mint != *offer_token_mint.key {
-                // Maker gives tokens (offer_token_mint)
+            if offer.maker != *maker_accounts[i].key {
+                return Err(SwapError::OfferMismatch.into());
+            }
+            if offer.escrow_token_amount == 0 && !maker_accounts[i].is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            // A direct offer can only go to the person it was made for.
+            if offer.offer_type == OfferType::Direct && offer.taker != Some(next.maker) {
+                return Err(SwapError::Unauthorized.into());
+            }
+            // The ring has to close: what this offer gives is exactly what the next one wants.
+            if offer.offer_token_mint != next.receive_token_mint
+                || offer.offer_token_amount != next.receive_token_amount
+            {
+                return Err(SwapError::OfferMismatch.into());
+            }
+            if *mints[i].key != offer.offer_token_mint {
                 return Err(SwapError::TokenMismatch.into());
             }
-            if taker_token_account_data.mint != *receive_token_mint.key {
+            // Tokens only, just like `MatchOffers`.
+            if is_sol_side(&offer.offer_token_mint) || offer.escrow_sol_amount > 0 {
                 return Err(SwapError::TokenMismatch.into());
             }
+            Self::check_mint_not_paused(program_id, mint_pauses[i], mints[i].key)?;
+
+            if offer.escrow_token_amount > 0 {
+                Self::check_offer_vault(offer_accounts[i], offer_token_accounts[i], mints[i].key)?;
+            } else {
+                Self::check_token_account(offer_token_accounts[i], &offer.maker, mints[i].key)?;
+            }
+            Self::check_token_account(
+                receive_token_accounts[i],
+                &offer.proceeds_owner(),
+                &offer.receive_token_mint,
+            )?;
+        }
 
-            // Transfer tokens from maker to taker
-            let mint_info = Mint::unpack(&offer_token_mint.data.borrow())?; // Get decimals for the token maker is giving
+        // --- The Legs: each offer's tokens go to the next maker ---
+        for i in 0..3 {
+            let offer = &offers[i];
+            let sealed = offer.escrow_token_amount > 0;
+            let nonce_bytes = offer.nonce.to_le_bytes();
+            let offer_seeds: &[&[u8]] = &[
+                b"offer",
+                offer.maker.as_ref(),
+                offer.offer_token_mint.as_ref(),
+                offer.receive_token_mint.as_ref(),
+                nonce_seed(&nonce_bytes),
+                &[offer.bump_seed],
+            ];
+            let mint_info = Self::unpack_mint(mints[i])?;
             Self::transfer_spl_token(
                 &[
-                    maker_token_account.clone(),
-                    offer_token_mint.clone(),
-                    taker_token_account.clone(),
-                    maker_account.clone(),
+                    offer_token_accounts[i].clone(),
+                    mints[i].clone(),
+                    receive_token_accounts[(i + 1) % 3].clone(),
+                    if sealed {
+                        offer_accounts[i].clone()
+                    } else {
+                        maker_accounts[i].clone()
+                    },
                     token_program.clone(),
                 ],
-                offer_data.offer_token_amount,
+                offer.offer_token_amount,
                 mint_info.decimals,
-                None, // Maker is signing directly
+                sealed.then_some(offer_seeds), // The offer signs for its vault
             )?;
+        }
 
-            // Transfer SOL from taker to maker
-            let taker_sol_account =
-                taker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
-            let maker_sol_account =
-                maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
-            if *taker_sol_account.key != *taker_account.key
-                || *maker_sol_account.key != *maker_account.key
-            {
-                return Err(SwapError::IncorrectOwner.into());
-            }
-            Self::transfer_sol(
-                &[
-                    taker_sol_account.clone(),
-                    maker_sol_account.clone(),
-                    system_program.clone(),
-                ],
-                offer_data.receive_token_amount,
-                None, // Not signed by program
+        // All three are done, and their vaults (if any) are empty.
+        for i in 0..3 {
+            offers[i].escrow_token_amount = 0;
+            Self::delist_from_order_book(
+                program_id,
+                order_books[i],
+                &offers[i],
+                offer_accounts[i].key,
             )?;
-            msg!("Token for SOL swap completed.");
+            Self::close_out_offer(&mut offers[i], OfferStatus::Accepted, offer_accounts[i])?;
+            Self::release_maker_slot(program_id, &offers[i].maker, maker_indexes[i])?;
         }
 
-        // Update offer status to Accepted.
-        offer_data.status = OfferStatus::Accepted;
-        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?; // Use borsh to pack!
-
-        msg!("Offer accepted successfully!");
+        msg!("Ring settled successfully!");
         Ok(())
     }
 
-    // --- Process CounterOffer Instruction ---
-    fn process_counter_offer(
-        program_id: &Pubkey,
-        accounts: &[AccountInfo],
-        offer_token_amount: u64,
-        receive_token_amount: u64,
-        expiration: Option<i64>,
-        bump_seed: u8,
-    ) -> ProgramResult {
-        msg!("Processing CounterOffer...");
+    // --- Process MakePublic Instruction ---
+    fn process_make_public(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
 
-        let counter_maker_account = next_account_info(account_info_iter)?; // The person making the counter
-        let original_offer_account = next_account_info(account_info_iter)?; // The original offer's locker (PDA)
-        let new_offer_account = next_account_info(account_info_iter)?; // New locker for the counter-offer (PDA)
-        let counter_maker_token_account = next_account_info(account_info_iter)?; // Counter-maker's token account
-        let offer_token_mint = next_account_info(account_info_iter)?; // Token offered in counter
-        let receive_token_mint = next_account_info(account_info_iter)?; // Token received in counter
-        let system_program = next_account_info(account_info_iter)?;
-        let token_program = next_account_info(account_info_iter)?;
-        let rent_sysvar = next_account_info(account_info_iter)?;
-
-        // Optional accounts
-        let counter_maker_sol_account_opt = next_account_info(account_info_iter).ok();
-        let original_maker_sol_account_opt = next_account_info(account_info_iter).ok();
+        let offer_maker_account = next_account_info(account_info_iter)?; // The person opening it up
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
 
-        // --- Basic Checks ---
-        if !counter_maker_account.is_signer {
+        if !offer_maker_account.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
-        if original_offer_account.owner != program_id {
-            return Err(SwapError::IncorrectOwner.into());
-        }
-
-        let mut original_offer_data = Offer::try_from_slice(&original_offer_account.data.borrow())?;
-
-        // Verify original_offer_account PDA
-        let original_offer_seeds = &[
-            b"offer",
-            original_offer_data.maker.as_ref(),
-            original_offer_data.offer_token_mint.as_ref(),
-            original_offer_data.receive_token_mint.as_ref(),
-            &[original_offer_data.bump_seed],
-        ];
-        let (expected_original_offer_key, _) =
-            Pubkey::find_program_address(original_offer_seeds, program_id);
-        if expected_original_offer_key != *original_offer_account.key {
-            return Err(SwapError::InvalidProgramAddress.into());
-        }
 
-        // Check if the counter-maker is either the original maker or the original taker.
-        if *counter_maker_account.key != original_offer_data.maker
-            && original_offer_data.taker != Some(*counter_maker_account.key)
-        {
+        let mut offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if offer_data.maker != *offer_maker_account.key {
             return Err(SwapError::Unauthorized.into());
         }
-
-        // Check if the original offer is active.
-        if original_offer_data.status != OfferStatus::Active {
+        if !offer_data.status.is_live() {
             return Err(SwapError::InvalidOfferStatus.into());
         }
-
-        // --- Handle Escrowed SOL from Original Offer ---
-        if original_offer_data.escrow_sol_amount > 0 {
-            // If the original offer had SOL in escrow, refund it to the original maker.
-            let original_maker_sol_account =
-                original_maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
-            if *original_maker_sol_account.key != original_offer_data.maker {
-                return Err(SwapError::IncorrectOwner.into());
-            }
-
-            msg!(
-                "Refunding {} SOL from original offer escrow to original maker.",
-                original_offer_data.escrow_sol_amount
-            );
-            Self::transfer_sol(
-                &[
-                    original_offer_account.clone(),
-                    original_maker_sol_account.clone(),
-                    system_program.clone(),
-                ],
-                original_offer_data.escrow_sol_amount,
-                Some(original_offer_seeds), // Program is signing for the escrow account
-            )?;
-            original_offer_data.escrow_sol_amount = 0; // Clear escrow amount
+        if offer_data.offer_type != OfferType::Direct {
+            return Err(SwapError::InvalidInstruction.into()); // Already public
         }
 
-        // --- Create New Counter-Offer Account (PDA) ---
-        let new_offer_seeds = &[
-            b"offer",
-            counter_maker_account.key.as_ref(),
-            offer_token_mint.key.as_ref(),
-            receive_token_mint.key.as_ref(),
-            &[bump_seed],
-        ];
-        let (expected_new_offer_key, expected_bump_seed) =
-            Pubkey::find_program_address(new_offer_seeds, program_id);
-
-        if expected_new_offer_key != *new_offer_account.key || expected_bump_seed != bump_seed {
-            return Err(SwapError::InvalidProgramAddress.into());
-        }
+        // SOL in escrow means the maker is buying; otherwise they're selling tokens.
+        offer_data.offer_type = if offer_data.escrow_sol_amount > 0 {
+            OfferType::PublicBuy
+        } else {
+            OfferType::PublicSell
+        };
+        offer_data.taker = None;
+        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
 
-        if new_offer_account.data_len() == 0 {
-            let space = Offer::MAX_LEN;
-            let rent = &Rent::from_account_info(rent_sysvar)?;
-            let rent_lamports = rent.minimum_balance(space);
+        msg!("Offer is now public!");
+        Ok(())
+    }
 
-            invoke_signed(
-                &system_instruction::create_account(
-                    counter_maker_account.key, // Payer
-                    new_offer_account.key,     // New account address (PDA)
-                    rent_lamports,             // Rent
-                    space as u64,              // Size
-                    program_id,                // Owner
-                ),
-                &[
-                    counter_maker_account.clone(),
-                    new_offer_account.clone(),
-                    system_program.clone(),
-                ],
-                &[new_offer_seeds], // Sign with the PDA seeds
-            )?;
-        } else {
-            if new_offer_account.owner != program_id || !new_offer_account.data_is_empty() {
-                return Err(SwapError::InvalidAccountData.into());
-            }
-        }
+    // --- Process AbortSettlement Instruction ---
+    fn process_abort_settlement(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
 
-        // --- Escrow SOL for the New Counter-Offer if applicable ---
-        let mut new_escrow_sol = 0;
-        if *offer_token_mint.key == Pubkey::new_from_array([0; 32]) {
-            // If counter-maker offers SOL
-            new_escrow_sol = offer_token_amount;
-            let counter_maker_sol_account =
-                counter_maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
-            if *counter_maker_sol_account.key != *counter_maker_account.key {
-                return Err(SwapError::IncorrectOwner.into());
-            }
+        let authority_account = next_account_info(account_info_iter)?; // Maker or taker
+        let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+        let settlement_account = next_account_info(account_info_iter)?; // The waiting trade
+        let taker_account = next_account_info(account_info_iter)?; // Gets the rent back
 
-            msg!(
-                "Transferring {} SOL to new counter-offer escrow...",
-                new_escrow_sol
-            );
-            Self::transfer_sol(
-                &[
-                    counter_maker_sol_account.clone(),
-                    new_offer_account.clone(),
-                    system_program.clone(),
-                ],
-                new_escrow_sol,
-                None, // Not signed by program
-            )?;
-            msg!("SOL transferred to new escrow.");
-        } else {
-            // Check if counter-maker has enough tokens if they are offering tokens.
-            let counter_maker_token_account_data =
-                TokenAccount::unpack(&counter_maker_token_account.data.borrow())?;
-            if offer_token_amount > counter_maker_token_account_data.amount {
-                return Err(SwapError::InsufficientFunds.into());
-            }
-            if counter_maker_token_account_data.mint != *offer_token_mint.key {
-                return Err(SwapError::TokenMismatch.into());
-            }
+        if !authority_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
         }
 
-        // --- Create and Save the New Counter Offer Data ---
-        let counter_offer = Offer {
-            offer_type: original_offer_data.offer_type, // Keep the same type (direct/public)
-            status: OfferStatus::Active,
-            maker: *counter_maker_account.key,
-            taker: if original_offer_data.maker == *counter_maker_account.key {
-                original_offer_data.taker // If maker countered, taker is still the same
-            } else {
-                Some(original_offer_data.maker) // If taker countered, maker is now the taker
-            },
-            offer_token_mint: *offer_token_mint.key,
-            offer_token_amount,
-            receive_token_mint: *receive_token_mint.key,
-            receive_token_amount,
-            escrow_sol_amount: new_escrow_sol,
-            expiration,
-            is_counter_offer: true,
-            original_offer_id: Some(*original_offer_account.key),
-            bump_seed,
-        };
+        let offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if settlement_account.owner != program_id {
+            return Err(SwapError::AccountNotInitialized.into());
+        }
+        let settlement = Settlement::load(&settlement_account.data.borrow())?;
+        let expected_key = Pubkey::create_program_address(
+            &[
+                b"settle",
+                offer_account.key.as_ref(),
+                &[settlement.bump_seed],
+            ],
+            program_id,
+        )
+        .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if expected_key != *settlement_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
 
-        counter_offer.serialize(&mut &mut new_offer_account.data.borrow_mut()[..])?;
+        if *authority_account.key != offer_data.maker && *authority_account.key != settlement.taker
+        {
+            return Err(SwapError::Unauthorized.into());
+        }
+        if *taker_account.key != settlement.taker {
+            return Err(SwapError::IncorrectOwner.into());
+        }
 
-        // Update the original offer's status to Countered.
-        original_offer_data.status = OfferStatus::Countered;
-        original_offer_data.serialize(&mut &mut original_offer_account.data.borrow_mut()[..])?;
+        // Close the settlement; the offer is free to be accepted again.
+        Self::transfer_sol_from_pda(
+            settlement_account,
+            taker_account,
+            settlement_account.lamports(),
+        )?;
+        settlement_account.data.borrow_mut().fill(0);
 
-        msg!("Counter-offer created successfully!");
+        msg!("Settlement aborted by {}", authority_account.key);
         Ok(())
     }
 
-    // --- Process CancelOffer Instruction ---
-    fn process_cancel_offer(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
-        msg!("Processing CancelOffer...");
+    // --- Process RequestTerms Instruction ---
+    fn process_request_terms(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        proposed_receive: u64,
+    ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
 
-        let offer_maker_account = next_account_info(account_info_iter)?; // The person cancelling
+        let taker_account = next_account_info(account_info_iter)?; // The person haggling
         let offer_account = next_account_info(account_info_iter)?; // The offer's locker (PDA)
+        let negotiation_account = next_account_info(account_info_iter)?; // Where the note goes
         let system_program = next_account_info(account_info_iter)?;
 
-        // Optional account for SOL refund
-        let maker_sol_account_opt = next_account_info(account_info_iter).ok();
-
-        // --- Basic Checks ---
-        if !offer_maker_account.is_signer {
+        if !taker_account.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
-        if offer_account.owner != program_id {
-            return Err(SwapError::IncorrectOwner.into());
-        }
-
-        let mut offer_data = Offer::try_from_slice(&offer_account.data.borrow())?;
 
-        // Verify offer_account PDA
-        let offer_seeds = &[
-            b"offer",
-            offer_data.maker.as_ref(),
-            offer_data.offer_token_mint.as_ref(),
-            offer_data.receive_token_mint.as_ref(),
-            &[offer_data.bump_seed],
-        ];
-        let (expected_offer_key, _) = Pubkey::find_program_address(offer_seeds, program_id);
-        if expected_offer_key != *offer_account.key {
-            return Err(SwapError::InvalidProgramAddress.into());
+        // Only live offers are worth haggling over, and only by someone who could accept them.
+        let offer_data = Self::load_verified_offer(program_id, offer_account)?;
+        if !offer_data.status.is_live() {
+            return Err(SwapError::InvalidOfferStatus.into());
         }
-
-        // Only the original maker can cancel an offer.
-        if offer_data.maker != *offer_maker_account.key {
+        let now = current_timestamp()?;
+        if offer_data.expiration.is_some_and(|exp| now > exp) {
+            return Err(SwapError::OfferExpired.into());
+        }
+        if offer_data.maker == *taker_account.key {
+            return Err(SwapError::Unauthorized.into()); // No haggling with yourself
+        }
+        if offer_data.offer_type == OfferType::Direct
+            && offer_data.taker != Some(*taker_account.key)
+        {
             return Err(SwapError::Unauthorized.into());
         }
 
-        // Only active offers can be cancelled.
-        if offer_data.status != OfferStatus::Active {
-            return Err(SwapError::InvalidOfferStatus.into());
+        let (expected_key, bump_seed) = Pubkey::find_program_address(
+            &[
+                b"nego",
+                offer_account.key.as_ref(),
+                taker_account.key.as_ref(),
+            ],
+            program_id,
+        );
+        if expected_key != *negotiation_account.key {
+            return Err(SwapError::InvalidProgramAddress.into());
         }
 
-        // Refund any escrowed SOL.
-        if offer_data.escrow_sol_amount > 0 {
-            let maker_sol_account =
-                maker_sol_account_opt.ok_or(SwapError::MissingRequiredAccount)?;
-            if *maker_sol_account.key != *offer_maker_account.key {
-                return Err(SwapError::IncorrectOwner.into());
-            }
-
-            msg!(
-                "Refunding {} SOL from escrow to maker...",
-                offer_data.escrow_sol_amount
-            );
-            Self::transfer_sol(
+        if negotiation_account.data_len() == 0 {
+            let space = Negotiation::MAX_LEN;
+            let rent_lamports = Rent::get()?.minimum_balance(space);
+            invoke_signed(
+                &system_instruction::create_account(
+                    taker_account.key,
+                    negotiation_account.key,
+                    rent_lamports,
+                    space as u64,
+                    program_id,
+                ),
                 &[
-                    offer_account.clone(),
-                    maker_sol_account.clone(),
+                    taker_account.clone(),
+                    negotiation_account.clone(),
                     system_program.clone(),
                 ],
-                offer_data.escrow_sol_amount,
-                Some(offer_seeds), // Program is signing for the escrow account
+                &[&[
+                    b"nego",
+                    offer_account.key.as_ref(),
+                    taker_account.key.as_ref(),
+                    &[bump_seed],
+                ]],
             )?;
-            offer_data.escrow_sol_amount = 0; // Clear the escrowed amount
+        } else if negotiation_account.owner != program_id {
+            return Err(SwapError::IncorrectOwner.into());
         }
 
-        // Set offer status to Declined.
-        offer_data.status = OfferStatus::Declined;
-        offer_data.serialize(&mut &mut offer_account.data.borrow_mut()[..])?;
+        let negotiation = Negotiation {
+            offer: *offer_account.key,
+            taker: *taker_account.key,
+            proposed_receive,
+            bump_seed,
+        };
+        negotiation.serialize(&mut &mut negotiation_account.data.borrow_mut()[..])?;
 
-        msg!("Offer cancelled successfully!");
+        msg!(
+            "{} proposes {} instead of {}",
+            taker_account.key,
+            proposed_receive,
+            offer_data.receive_token_amount
+        );
         Ok(())
     }
 }
@@ -0,0 +1,151 @@
+// Helpers for driving the program in `solana-program-test`, shared by our own integration
+// tests and available to anyone testing against us (behind the `test-sdk` feature, so none of
+// this ends up in the on-chain build). Every helper takes the `(banks client, payer, blockhash)`
+// triple that `ProgramTest::start` hands back, and the payer pays for everything.
+use solana_program::{hash::Hash, program_pack::Pack, pubkey::Pubkey};
+use solana_program_test::{BanksClient, ProgramTest, processor};
+use solana_sdk::{
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use solana_system_interface::instruction as system_instruction;
+use spl_token::state::{Account as TokenAccount, Mint};
+
+use crate::Processor;
+
+// What `ProgramTest::start` returns: the banks client, the fee payer, and a recent blockhash.
+pub type TestContext = (BanksClient, Keypair, Hash);
+
+// A fresh test validator with the program loaded at `program_id`.
+pub async fn start(program_id: Pubkey) -> TestContext {
+    ProgramTest::new("soffer", program_id, processor!(Processor::process))
+        .start()
+        .await
+}
+
+// Helper to fund an account with lamports
+pub async fn fund_account(context: &mut TestContext, pubkey: &Pubkey, lamports: u64) {
+    let transfer_ix = system_instruction::transfer(&context.1.pubkey(), pubkey, lamports);
+    let mut transaction = Transaction::new_with_payer(&[transfer_ix], Some(&context.1.pubkey()));
+    transaction.sign(&[&context.1], context.2);
+    context.0.process_transaction(transaction).await.unwrap();
+}
+
+// A new mint under the legacy token program.
+pub async fn create_mint(
+    context: &mut TestContext,
+    mint_authority: &Keypair,
+    freeze_authority: Option<&Pubkey>,
+    decimals: u8,
+) -> Pubkey {
+    let mint_keypair = Keypair::new(); // A new unique ID for our token blueprint
+    let rent = context.0.get_rent().await.unwrap(); // Get rent info
+    let rent_lamports = rent.minimum_balance(Mint::LEN); // How much SOL for the mint account
+
+    // Create the mint account on our mini-playground.
+    let create_mint_account_ix = system_instruction::create_account(
+        &context.1.pubkey(),    // Who pays for the account
+        &mint_keypair.pubkey(), // The new mint account's address
+        rent_lamports,          // Rent amount
+        Mint::LEN as u64,       // Size of the account
+        &spl_token::id(),       // Owner of the account (SPL Token program)
+    );
+
+    // Initialize the mint (set up its rules, like who can create new tokens).
+    let init_mint_ix = spl_token::instruction::initialize_mint(
+        &spl_token::id(),         // SPL Token program ID
+        &mint_keypair.pubkey(),   // Our new mint account
+        &mint_authority.pubkey(), // Who can create new tokens
+        freeze_authority,         // Who can freeze tokens (optional)
+        decimals,                 // How many decimal places our token has
+    )
+    .unwrap();
+
+    // Bundle these instructions into a transaction and send it.
+    let mut transaction = Transaction::new_with_payer(
+        &[create_mint_account_ix, init_mint_ix],
+        Some(&context.1.pubkey()),
+    );
+    transaction.sign(&[&context.1, &mint_keypair], context.2);
+    context.0.process_transaction(transaction).await.unwrap();
+
+    mint_keypair.pubkey() // Return the address of our new token blueprint
+}
+
+// A new (non-associated) token account for `mint`, owned by `owner`.
+pub async fn create_token_account(
+    context: &mut TestContext,
+    owner: &Keypair,
+    mint: &Pubkey,
+) -> Pubkey {
+    let token_account_keypair = Keypair::new(); // A new unique ID for our token wallet
+    let rent = context.0.get_rent().await.unwrap();
+    let rent_lamports = rent.minimum_balance(TokenAccount::LEN);
+
+    // Create the token account.
+    let create_token_account_ix = system_instruction::create_account(
+        &context.1.pubkey(),
+        &token_account_keypair.pubkey(),
+        rent_lamports,
+        TokenAccount::LEN as u64,
+        &spl_token::id(),
+    );
+
+    // Initialize the token account (link it to a specific token blueprint and owner).
+    let init_token_account_ix = spl_token::instruction::initialize_account(
+        &spl_token::id(),
+        &token_account_keypair.pubkey(),
+        mint,
+        &owner.pubkey(),
+    )
+    .unwrap();
+
+    // Bundle and send the transaction.
+    let mut transaction = Transaction::new_with_payer(
+        &[create_token_account_ix, init_token_account_ix],
+        Some(&context.1.pubkey()),
+    );
+    transaction.sign(&[&context.1, &token_account_keypair], context.2);
+    context.0.process_transaction(transaction).await.unwrap();
+
+    token_account_keypair.pubkey() // Return the address of our new token wallet
+}
+// This is like printing new trading cards and putting them in a wallet.
+pub async fn mint_to(
+    context: &mut TestContext,
+    mint: &Pubkey,
+    token_account: &Pubkey,
+    mint_authority: &Keypair,
+    amount: u64,
+) {
+    let mint_to_ix = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        mint,
+        token_account,
+        &mint_authority.pubkey(),
+        &[],
+        amount, // No multi-signers needed here
+    )
+    .unwrap();
+
+    let mut transaction = Transaction::new_with_payer(&[mint_to_ix], Some(&context.1.pubkey()));
+    transaction.sign(&[&context.1, mint_authority], context.2);
+    context.0.process_transaction(transaction).await.unwrap();
+}
+
+// Lamports held by `pubkey`.
+pub async fn get_sol_balance(context: &mut TestContext, pubkey: &Pubkey) -> u64 {
+    context.0.get_balance(*pubkey).await.unwrap()
+}
+
+// Tokens held by a token account (which has to exist).
+pub async fn get_token_balance(context: &mut TestContext, token_account: &Pubkey) -> u64 {
+    let account = context
+        .0
+        .get_account(*token_account)
+        .await
+        .unwrap()
+        .unwrap();
+    let token_account_data = TokenAccount::unpack(&account.data).unwrap();
+    token_account_data.amount
+}
@@ -0,0 +1,738 @@
+// Plain unit tests for the pure helper functions (no mini-playground needed!).
+use soffer::{
+    AcceptBlocker, CompactOffer, Offer, OfferStatus, OfferType, PRICE_SCALE, SOL_DECIMALS,
+    SwapError, SwapEvent, Trade, TradeHistory, accept_accounts, accept_blocker, creation_cost,
+    cross_rate, dutch_auction_price, effective_status, is_sol_side, mul_div, pack_compact,
+    price_per_token, select_match, time_remaining, unpack_compact, vwap,
+};
+use solana_program::{
+    instruction::AccountMeta, program_error::ProgramError, pubkey::Pubkey, rent::Rent,
+};
+use solana_system_interface::program as system_program;
+
+fn trade(base_amount: u64, quote_amount: u64) -> Trade {
+    Trade {
+        base_amount,
+        quote_amount,
+        timestamp: 0,
+    }
+}
+
+// A public offer of `offer_token_amount` X for `receive_token_amount` Y.
+fn offer(
+    maker: Pubkey,
+    offer_token_mint: Pubkey,
+    offer_token_amount: u64,
+    receive_token_mint: Pubkey,
+    receive_token_amount: u64,
+) -> Offer {
+    Offer {
+        version: Offer::CURRENT_VERSION,
+        offer_type: OfferType::PublicSell,
+        status: OfferStatus::Active,
+        maker,
+        taker: None,
+        offer_token_mint,
+        offer_token_amount,
+        receive_token_mint,
+        receive_token_amount,
+        escrow_sol_amount: 0,
+        expiration: None,
+        is_counter_offer: false,
+        original_offer_id: None,
+        bump_seed: 255,
+        min_fill: 0,
+        rent_reserved: 0,
+        zeroize_on_terminal: false,
+        escrow_token_amount: 0,
+        rate_num: 0,
+        rate_den: 0,
+        settled_offer_amount: 0,
+        settled_receive_amount: 0,
+        cancelled_at: 0,
+        receive_recipient: None,
+        premium_bps: 0,
+        yield_strategy: None,
+        yield_principal: 0,
+        nonce: 0,
+        created_at: 0,
+        last_updated_at: 0,
+        start_time: None,
+        end_price: 0,
+        client_id: [0; 8],
+        auto_cancel_on_fail: false,
+        offer_decimals: None,
+        min_acceptable_receive: 0,
+    }
+}
+
+#[test]
+fn test_vwap_empty_history() {
+    let history = TradeHistory::default();
+    assert_eq!(vwap(&history), None);
+    assert_eq!(history.latest(), None);
+}
+
+#[test]
+fn test_vwap_known_sequence() {
+    let mut history = TradeHistory::default();
+    history.record(trade(100, 200)); // 2.0 per token
+    history.record(trade(300, 300)); // 1.0 per token
+
+    // (200 + 300) / (100 + 300) = 1.25 per token
+    assert_eq!(vwap(&history), Some(PRICE_SCALE + PRICE_SCALE / 4));
+    assert_eq!(history.latest(), Some(&trade(300, 300)));
+}
+
+#[test]
+fn test_vwap_ring_buffer_drops_oldest() {
+    let mut history = TradeHistory::default();
+    history.record(trade(1, 1_000)); // This one will be overwritten
+    for _ in 0..TradeHistory::CAPACITY {
+        history.record(trade(10, 50));
+    }
+
+    assert_eq!(history.trades.len(), TradeHistory::CAPACITY);
+    assert_eq!(vwap(&history), Some(5 * PRICE_SCALE));
+    assert_eq!(history.latest(), Some(&trade(10, 50)));
+}
+
+#[test]
+fn test_vwap_overflow_returns_none() {
+    let mut history = TradeHistory::default();
+    history.record(trade(1, u64::MAX)); // Price is far too big for a u64 once scaled
+    assert_eq!(vwap(&history), None);
+}
+
+#[test]
+fn test_select_match_picks_best_price() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let target = offer(Pubkey::new_unique(), mint_x, 100, mint_y, 200); // Wants 2 Y per X
+
+    let mut expired = offer(Pubkey::new_unique(), mint_y, 1_000, mint_x, 100);
+    expired.expiration = Some(50);
+    let mut accepted = offer(Pubkey::new_unique(), mint_y, 1_000, mint_x, 100);
+    accepted.status = OfferStatus::Accepted;
+    let mut direct_elsewhere = offer(Pubkey::new_unique(), mint_y, 1_000, mint_x, 100);
+    direct_elsewhere.offer_type = OfferType::Direct;
+    direct_elsewhere.taker = Some(Pubkey::new_unique());
+    let candidates = [
+        offer(Pubkey::new_unique(), mint_x, 500, mint_y, 100), // Same direction
+        offer(Pubkey::new_unique(), mint_y, 150, mint_x, 100), // Only 1.5 Y per X, too cheap
+        expired,
+        accepted,
+        direct_elsewhere,
+        offer(Pubkey::new_unique(), mint_y, 200, mint_x, 100), // Exactly the asking price
+        offer(Pubkey::new_unique(), mint_y, 300, mint_x, 100), // 3 Y per X, the best
+    ];
+
+    assert_eq!(
+        select_match(&target, &candidates, 100),
+        Some(&candidates[6])
+    );
+    // Before the expiry, the 10 Y per X offer is the best.
+    assert_eq!(select_match(&target, &candidates, 10), Some(&candidates[2]));
+    assert_eq!(select_match(&target, &candidates[..5], 100), None);
+}
+
+#[test]
+fn test_select_match_tie_goes_to_first_candidate() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (maker_a, maker_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let target = offer(Pubkey::new_unique(), mint_x, 10, mint_y, 10);
+    let candidates = [
+        offer(Pubkey::new_unique(), mint_y, 10, mint_x, 10),
+        offer(maker_a, mint_y, 20, mint_x, 10), // 2 Y per X
+        offer(maker_b, mint_y, 40, mint_x, 20), // Also 2 Y per X, but listed later
+    ];
+    assert_eq!(
+        select_match(&target, &candidates, 0).map(|o| o.maker),
+        Some(maker_a)
+    );
+
+    // Same offers the other way round: now the other one was first.
+    let reversed = [
+        offer(maker_b, mint_y, 40, mint_x, 20),
+        offer(maker_a, mint_y, 20, mint_x, 10),
+    ];
+    assert_eq!(
+        select_match(&target, &reversed, 0).map(|o| o.maker),
+        Some(maker_b)
+    );
+}
+
+#[test]
+fn test_cross_rate_composes_shared_mint() {
+    let (mint_x, mint_y, mint_z) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let a = offer(Pubkey::new_unique(), mint_x, 10, mint_y, 30); // 10 X for 30 Y
+    let b = offer(Pubkey::new_unique(), mint_y, 60, mint_z, 8); // 60 Y for 8 Z
+
+    // 10 X cost 30 Y, and 30 Y cost 4 Z, so 10 X for 4 Z (or 5 X for 2 Z).
+    assert_eq!(cross_rate(&a, &b), Some((5, 2)));
+
+    // Works for any pair that links up: 60 Y cost 8 Z, and 8 Z cost 16 X.
+    let c = offer(Pubkey::new_unique(), mint_z, 1, mint_x, 2); // 1 Z for 2 X
+    assert_eq!(cross_rate(&b, &c), Some((15, 4)));
+    // ...but not the other way round, since c wants X and b gives Y.
+    assert_eq!(cross_rate(&c, &b), None);
+}
+
+#[test]
+fn test_cross_rate_rejects_non_composable_pairs() {
+    let (mint_x, mint_y, mint_z) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let a = offer(Pubkey::new_unique(), mint_x, 10, mint_y, 30);
+
+    // b doesn't give the Y that a wants.
+    let b = offer(Pubkey::new_unique(), mint_z, 60, mint_y, 8);
+    assert_eq!(cross_rate(&a, &b), None);
+
+    // An empty side has no price.
+    let empty = offer(Pubkey::new_unique(), mint_y, 0, mint_z, 8);
+    assert_eq!(cross_rate(&a, &empty), None);
+
+    // Too big to fit once multiplied out (and nothing cancels).
+    let huge_a = offer(Pubkey::new_unique(), mint_x, u64::MAX, mint_y, 1);
+    let huge_b = offer(Pubkey::new_unique(), mint_y, u64::MAX, mint_z, 1);
+    assert_eq!(cross_rate(&huge_a, &huge_b), None);
+}
+
+#[test]
+fn test_time_remaining() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut offer = offer(Pubkey::new_unique(), mint_x, 10, mint_y, 10);
+
+    // No expiration, no countdown.
+    assert_eq!(time_remaining(&offer, 1_000), None);
+
+    offer.expiration = Some(1_000);
+    assert_eq!(time_remaining(&offer, 400), Some(600));
+    assert_eq!(time_remaining(&offer, 1_000), Some(0)); // Last second it's still good
+    assert_eq!(time_remaining(&offer, 1_001), None);
+}
+
+#[test]
+fn test_dutch_auction_price_decays_linearly() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut auction = offer(Pubkey::new_unique(), mint_x, 1, mint_y, 2_000);
+    auction.offer_type = OfferType::DutchAuction;
+    auction.end_price = 1_000;
+    auction.start_time = Some(100);
+    auction.expiration = Some(1_100);
+
+    // Halfway through, it's halfway between.
+    assert_eq!(dutch_auction_price(&auction, 600), Ok(1_500));
+    assert_eq!(dutch_auction_price(&auction, 350), Ok(1_750));
+    // Rounded in the maker's favor.
+    assert_eq!(dutch_auction_price(&auction, 101), Ok(1_999));
+
+    // Before it starts it's at the start price, and once it's over at the end price.
+    assert_eq!(dutch_auction_price(&auction, 0), Ok(2_000));
+    assert_eq!(dutch_auction_price(&auction, 1_100), Ok(1_000));
+    assert_eq!(dutch_auction_price(&auction, 5_000), Ok(1_000));
+
+    // Without a start time, it starts when it was made.
+    auction.start_time = None;
+    auction.created_at = 600;
+    assert_eq!(dutch_auction_price(&auction, 850), Ok(1_500));
+}
+
+#[test]
+fn test_effective_status_active_offer() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut offer = offer(Pubkey::new_unique(), mint_x, 10, mint_y, 10);
+
+    // Without an expiration it's active forever.
+    assert_eq!(effective_status(&offer, i64::MAX), OfferStatus::Active);
+
+    offer.expiration = Some(1_000);
+    assert_eq!(effective_status(&offer, 1_000), OfferStatus::Active); // Last second it's still good
+    offer.status = OfferStatus::PartiallyFilled;
+    assert_eq!(
+        effective_status(&offer, 1_000),
+        OfferStatus::PartiallyFilled
+    );
+}
+
+#[test]
+fn test_effective_status_lazily_expired_offer() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut offer = offer(Pubkey::new_unique(), mint_x, 10, mint_y, 10);
+    offer.expiration = Some(1_000);
+
+    // Nobody has marked it yet, but it can't be taken any more.
+    assert_eq!(effective_status(&offer, 1_001), OfferStatus::Expired);
+    assert_eq!(offer.status, OfferStatus::Active);
+    offer.status = OfferStatus::PartiallyFilled;
+    assert_eq!(effective_status(&offer, 1_001), OfferStatus::Expired);
+}
+
+#[test]
+fn test_effective_status_terminal_offer() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut offer = offer(Pubkey::new_unique(), mint_x, 10, mint_y, 10);
+    offer.expiration = Some(1_000);
+
+    // A finished offer stays whatever it finished as, however long ago it ran out.
+    for status in [
+        OfferStatus::Accepted,
+        OfferStatus::Declined,
+        OfferStatus::Countered,
+        OfferStatus::Expired,
+    ] {
+        offer.status = status;
+        assert_eq!(effective_status(&offer, 5_000), status);
+    }
+}
+
+#[test]
+fn test_compact_offer_round_trip() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut direct = offer(Pubkey::new_unique(), mint_x, 10, mint_y, 25);
+    direct.offer_type = OfferType::Direct;
+    direct.taker = Some(Pubkey::new_unique());
+    direct.expiration = Some(1_700_000_000);
+    direct.min_fill = 2;
+    direct.start_time = Some(1_600_000_000);
+    direct.escrow_sol_amount = 99; // Not part of the compact form
+
+    let packed = pack_compact(&direct);
+    assert_eq!(packed.len(), CompactOffer::MAX_LEN);
+    let compact = unpack_compact(&packed).unwrap();
+    assert_eq!(
+        compact,
+        CompactOffer {
+            offer_type: OfferType::Direct,
+            maker: direct.maker,
+            taker: direct.taker,
+            offer_token_mint: mint_x,
+            offer_token_amount: 10,
+            receive_token_mint: mint_y,
+            receive_token_amount: 25,
+            expiration: Some(1_700_000_000),
+            min_fill: 2,
+            start_time: Some(1_600_000_000),
+            rate_num: 0,
+            rate_den: 0,
+            premium_bps: 0,
+            end_price: 0,
+            min_acceptable_receive: 0,
+        }
+    );
+
+    // Without a taker, expiration or start time it's 48 bytes shorter.
+    let public = offer(Pubkey::new_unique(), mint_x, 10, mint_y, 25);
+    let packed_public = pack_compact(&public);
+    assert_eq!(packed_public.len(), CompactOffer::MAX_LEN - 48);
+    assert_eq!(unpack_compact(&packed_public).unwrap().taker, None);
+
+    // Cut short or padded out, it doesn't read.
+    assert!(unpack_compact(&packed[..packed.len() - 1]).is_err());
+    assert!(unpack_compact(&[packed.as_slice(), &[0]].concat()).is_err());
+}
+
+#[test]
+fn test_compact_offer_prices_like_the_offer() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    // A ratio order's rate comes through, so a fill costs the same worked out from either.
+    let mut ratio = offer(Pubkey::new_unique(), mint_x, 300, mint_y, 100);
+    ratio.offer_type = OfferType::RatioOrder;
+    ratio.rate_num = 1;
+    ratio.rate_den = 3;
+    let compact = unpack_compact(&pack_compact(&ratio)).unwrap();
+    assert_eq!(compact.offer_type, OfferType::RatioOrder);
+    assert_eq!((compact.rate_num, compact.rate_den), (1, 3));
+    for fill in [1, 2, 3, 7, 300] {
+        assert_eq!(
+            mul_div(fill, compact.rate_num, compact.rate_den).unwrap(),
+            mul_div(fill, ratio.rate_num, ratio.rate_den).unwrap()
+        );
+    }
+
+    // A Dutch auction made without a start time started falling when it was made.
+    let mut auction = offer(Pubkey::new_unique(), mint_x, 1, mint_y, 2_000);
+    auction.offer_type = OfferType::DutchAuction;
+    auction.created_at = 1_000;
+    auction.expiration = Some(2_000);
+    auction.end_price = 1_000;
+    auction.min_acceptable_receive = 1_200;
+    let compact = unpack_compact(&pack_compact(&auction)).unwrap();
+    assert_eq!(compact.start_time, Some(1_000));
+    assert_eq!(compact.end_price, 1_000);
+    assert_eq!(compact.min_acceptable_receive, 1_200);
+    let mut rebuilt = offer(
+        compact.maker,
+        mint_x,
+        1,
+        mint_y,
+        compact.receive_token_amount,
+    );
+    rebuilt.offer_type = compact.offer_type;
+    rebuilt.start_time = compact.start_time;
+    rebuilt.expiration = compact.expiration;
+    rebuilt.end_price = compact.end_price;
+    assert_eq!(
+        dutch_auction_price(&rebuilt, 1_500),
+        dutch_auction_price(&auction, 1_500)
+    );
+}
+
+#[test]
+fn test_price_per_token_normalizes_decimals() {
+    let usdc_like = Pubkey::new_unique(); // 6 decimals
+    let sol = spl_token::native_mint::id();
+
+    // 2.5 tokens (2_500_000 raw) for 5 SOL (5_000_000_000 lamports) is 2 SOL per token.
+    // Dividing the raw amounts would say 2_000 per token, off by 10^3.
+    let sell = offer(
+        Pubkey::new_unique(),
+        usdc_like,
+        2_500_000,
+        sol,
+        5_000_000_000,
+    );
+    assert_eq!(price_per_token(&sell, 6, 0), Some(2 * PRICE_SCALE));
+
+    // The other way round: 1 SOL for 4 tokens is 4 tokens per SOL, whatever decimals are
+    // passed for the SOL side.
+    let buy = offer(
+        Pubkey::new_unique(),
+        sol,
+        1_000_000_000,
+        usdc_like,
+        4_000_000,
+    );
+    assert_eq!(price_per_token(&buy, 0, 6), Some(4 * PRICE_SCALE));
+    assert_eq!(price_per_token(&buy, 42, 6), Some(4 * PRICE_SCALE));
+    assert_eq!(
+        price_per_token(&buy, SOL_DECIMALS, 6),
+        price_per_token(&buy, 0, 6)
+    );
+
+    // Token for token: 3 of a 9-decimal token for 1.5 of a 2-decimal token is 0.5 each.
+    let (nine, two) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let swap = offer(Pubkey::new_unique(), nine, 3_000_000_000, two, 150);
+    assert_eq!(price_per_token(&swap, 9, 2), Some(PRICE_SCALE / 2));
+
+    // Nothing offered has no price, and silly decimals don't overflow.
+    let empty = offer(Pubkey::new_unique(), usdc_like, 0, sol, 10);
+    assert_eq!(price_per_token(&empty, 6, 0), None);
+    let huge = offer(Pubkey::new_unique(), usdc_like, 1, sol, u64::MAX);
+    assert_eq!(price_per_token(&huge, 30, 0), None);
+    assert_eq!(price_per_token(&huge, 255, 0), None);
+}
+
+#[test]
+fn test_accept_accounts_match_manual_list() {
+    let program_id = Pubkey::new_unique();
+    let pda = |seeds: &[&[u8]]| Pubkey::find_program_address(seeds, &program_id).0;
+    let (maker, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (maker_tokens, taker_tokens) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (offer_key, token_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let sol = spl_token::native_mint::id();
+
+    // Selling tokens for SOL: the maker signs for their tokens, and both wallets trade SOL.
+    let sell = offer(maker, token_mint, 10, sol, 10_000);
+    let manual = vec![
+        AccountMeta::new(taker, true),
+        AccountMeta::new(offer_key, false),
+        AccountMeta::new(maker, true),
+        AccountMeta::new(maker_tokens, false),
+        AccountMeta::new(taker_tokens, false),
+        AccountMeta::new_readonly(token_mint, false),
+        AccountMeta::new_readonly(sol, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(pda(&[b"mintpause", token_mint.as_ref()]), false),
+        AccountMeta::new_readonly(pda(&[b"mintpause", sol.as_ref()]), false),
+        AccountMeta::new(pda(&[b"maker", maker.as_ref()]), false),
+        AccountMeta::new_readonly(pda(&[b"config"]), false),
+        AccountMeta::new(pda(&[b"settle", offer_key.as_ref()]), false),
+        AccountMeta::new(pda(&[b"book", token_mint.as_ref(), sol.as_ref()]), false),
+        AccountMeta::new(maker, false),
+        AccountMeta::new(taker, false),
+    ];
+    assert_eq!(
+        accept_accounts(
+            &sell,
+            &program_id,
+            &offer_key,
+            &taker,
+            &taker_tokens,
+            &maker_tokens
+        ),
+        manual
+    );
+
+    // Buying with escrowed SOL: the offer pays, so the maker doesn't need to sign.
+    let mut buy = offer(maker, sol, 10_000, token_mint, 10);
+    buy.offer_type = OfferType::PublicBuy;
+    buy.escrow_sol_amount = 10_000;
+    let accounts = accept_accounts(
+        &buy,
+        &program_id,
+        &offer_key,
+        &taker,
+        &taker_tokens,
+        &maker_tokens,
+    );
+    assert_eq!(accounts[2], AccountMeta::new(maker, false));
+    assert_eq!(accounts[5], AccountMeta::new_readonly(sol, false));
+    assert_eq!(accounts[6], AccountMeta::new_readonly(token_mint, false));
+    assert_eq!(accounts.len(), manual.len());
+}
+
+#[test]
+fn test_creation_cost_adds_rent_escrow_and_fee() {
+    let rent = Rent::default();
+    let locker_rent = rent.minimum_balance(Offer::MAX_LEN);
+
+    // A buy offer escrowing 2 SOL, with a 5_000 lamport create fee.
+    assert_eq!(
+        creation_cost(&rent, 2_000_000_000, 5_000),
+        locker_rent + 2_000_000_000 + 5_000
+    );
+    // A sell offer escrows no SOL, and with no fee it's just the rent.
+    assert_eq!(creation_cost(&rent, 0, 0), locker_rent);
+}
+
+#[test]
+fn test_mul_div_reports_overflow() {
+    // The product is worked out wide, so a huge amount scaled down still fits.
+    assert_eq!(mul_div(u64::MAX, 10_000, 10_000), Ok(u64::MAX));
+    assert_eq!(mul_div(u64::MAX, 1, 2), Ok(u64::MAX / 2));
+    assert_eq!(mul_div(u64::MAX - 1, 1, 1), Ok(u64::MAX - 1));
+    // But scaling one up past u64::MAX can't be paid out.
+    assert_eq!(mul_div(u64::MAX, 2, 1), Err(SwapError::MathOverflow));
+    assert_eq!(
+        mul_div(u64::MAX - 1, u64::MAX, 3),
+        Err(SwapError::MathOverflow)
+    );
+    // And nothing divides by zero.
+    assert_eq!(mul_div(1, 1, 0), Err(SwapError::MathOverflow));
+}
+
+#[test]
+fn test_is_sol_side_uses_native_mint() {
+    assert!(is_sol_side(&spl_token::native_mint::id()));
+    // The old all-zero placeholder is just another address now.
+    assert!(!is_sol_side(&Pubkey::new_from_array([0; 32])));
+    assert!(!is_sol_side(&Pubkey::new_unique()));
+}
+
+#[test]
+fn test_accept_blocker_checks_taker_and_timing() {
+    let (mint_x, mint_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (maker, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut direct = offer(maker, mint_x, 10, mint_y, 10);
+    direct.offer_type = OfferType::Direct;
+    direct.taker = Some(taker);
+    direct.expiration = Some(100);
+
+    assert_eq!(accept_blocker(&direct, &taker, 100), None);
+    assert_eq!(
+        accept_blocker(&direct, &Pubkey::new_unique(), 100),
+        Some(AcceptBlocker::NotTheTaker)
+    );
+    assert_eq!(
+        accept_blocker(&direct, &maker, 100),
+        Some(AcceptBlocker::SelfTrade)
+    );
+    assert_eq!(
+        accept_blocker(&direct, &taker, 101),
+        Some(AcceptBlocker::Expired)
+    );
+
+    // Status comes first: a finished offer is just finished, whoever asks.
+    direct.status = OfferStatus::Accepted;
+    assert_eq!(
+        accept_blocker(&direct, &maker, 101),
+        Some(AcceptBlocker::NotOpen)
+    );
+    direct.status = OfferStatus::Pending;
+    assert_eq!(
+        accept_blocker(&direct, &taker, 0),
+        Some(AcceptBlocker::AwaitingConfirmation)
+    );
+}
+
+#[test]
+fn test_swap_event_layout_matches_its_docs() {
+    // A new offer's front-end tag comes last, as its raw 8 bytes.
+    let created = SwapEvent::OfferCreated {
+        offer: Pubkey::new_unique(),
+        maker: Pubkey::new_unique(),
+        offer_token_mint: Pubkey::new_unique(),
+        offer_token_amount: 1,
+        receive_token_mint: Pubkey::new_unique(),
+        receive_token_amount: 2,
+        client_id: *b"wallet-a",
+    };
+    let bytes = borsh::to_vec(&created).unwrap();
+    assert_eq!(bytes[0], 0);
+    assert_eq!(&bytes[bytes.len() - 8..], b"wallet-a");
+
+    let (offer, maker) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let event = SwapEvent::OfferCancelled {
+        offer,
+        maker,
+        status: OfferStatus::Expired,
+        refunded_lamports: 5,
+    };
+    let bytes = borsh::to_vec(&event).unwrap();
+
+    // Variant, the two keys, the status byte, then the refund little-endian.
+    let mut expected = vec![2];
+    expected.extend_from_slice(offer.as_ref());
+    expected.extend_from_slice(maker.as_ref());
+    expected.push(OfferStatus::Expired as u8);
+    expected.extend_from_slice(&5u64.to_le_bytes());
+    assert_eq!(bytes, expected);
+    assert_eq!(borsh::from_slice::<SwapEvent>(&bytes).unwrap(), event);
+}
+
+#[test]
+fn test_offer_load_upgrades_older_layouts() {
+    let mut one_shot = offer(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        10,
+        Pubkey::new_unique(),
+        10,
+    );
+    // Every `Option` filled, so the newest fields are the locker's very last bytes.
+    one_shot.taker = Some(Pubkey::new_unique());
+    one_shot.expiration = Some(100);
+    one_shot.original_offer_id = Some(Pubkey::new_unique());
+    one_shot.receive_recipient = Some(Pubkey::new_unique());
+    one_shot.yield_strategy = Some(Pubkey::new_unique());
+    one_shot.start_time = Some(50);
+    one_shot.auto_cancel_on_fail = true;
+    one_shot.offer_decimals = Some(6);
+    one_shot.min_acceptable_receive = 5;
+    let data = borsh::to_vec(&one_shot).unwrap();
+    assert_eq!(data.len(), Offer::MAX_LEN);
+
+    // A version 4 offer is this one without `min_acceptable_receive`.
+    let mut v4 = data[..Offer::V4_LEN].to_vec();
+    v4[0] = 4;
+    let loaded = Offer::load(&v4).unwrap();
+    assert_eq!(loaded.version, Offer::CURRENT_VERSION);
+    assert_eq!(loaded.offer_decimals, Some(6));
+    assert_eq!(loaded.min_acceptable_receive, 0);
+
+    // A version 3 one is without `offer_decimals` too, in a locker that much shorter.
+    let mut v3 = data[..Offer::V3_LEN].to_vec();
+    v3[0] = 3;
+    let loaded = Offer::load(&v3).unwrap();
+    assert_eq!(loaded.version, Offer::CURRENT_VERSION);
+    assert!(loaded.auto_cancel_on_fail);
+    assert_eq!(loaded.offer_decimals, None);
+
+    // And a version 2 one is without `auto_cancel_on_fail` as well.
+    let mut v2 = data[..Offer::V2_LEN].to_vec();
+    v2[0] = 2;
+    let loaded = Offer::load(&v2).unwrap();
+    assert_eq!(loaded.version, Offer::CURRENT_VERSION);
+    assert!(!loaded.auto_cancel_on_fail);
+    assert_eq!(loaded.offer_decimals, None);
+    assert_eq!(loaded.receive_recipient, one_shot.receive_recipient);
+
+    // Nothing newer than this program is readable.
+    v2[0] = Offer::CURRENT_VERSION + 1;
+    assert_eq!(
+        Offer::load(&v2).unwrap_err(),
+        SwapError::UnsupportedVersion.into()
+    );
+}
+
+// Clients match on these numbers, so every one of them is spelled out here: renumbering an error
+// (or slipping a new one in anywhere but the end) has to fail this, not ship.
+#[test]
+fn test_swap_error_codes_never_change() {
+    let codes = [
+        (SwapError::InvalidInstruction, 0),
+        (SwapError::NotRentExempt, 1),
+        (SwapError::InvalidAccountData, 2),
+        (SwapError::IncorrectOwner, 3),
+        (SwapError::InsufficientFunds, 4),
+        (SwapError::InvalidOfferStatus, 5),
+        (SwapError::OfferExpired, 6),
+        (SwapError::Unauthorized, 7),
+        (SwapError::OfferMismatch, 8),
+        (SwapError::TokenMismatch, 9),
+        (SwapError::AccountNotInitialized, 10),
+        (SwapError::InvalidProgramAddress, 11),
+        (SwapError::MissingRequiredAccount, 12),
+        (SwapError::InvalidAccountInput, 13),
+        (SwapError::InvalidSystemProgram, 14),
+        (SwapError::InvalidTokenProgram, 15),
+        (SwapError::InvalidFillAmount, 16),
+        (SwapError::FillTooSmall, 17),
+        (SwapError::OfferCollision, 18),
+        (SwapError::ProgramPaused, 19),
+        (SwapError::TooManyOffers, 20),
+        (SwapError::SealedOffer, 21),
+        (SwapError::InvalidBumpSeed, 22),
+        (SwapError::AmountExceedsSupply, 23),
+        (SwapError::SettlementPending, 24),
+        (SwapError::SettlementNotReady, 25),
+        (SwapError::InvalidMetadata, 26),
+        (SwapError::InvalidAmount, 27),
+        (SwapError::AmountTooSmall, 28),
+        (SwapError::MalformedInstructionData, 29),
+        (SwapError::FeeTooHigh, 30),
+        (SwapError::MissingMakerSolAccount, 31),
+        (SwapError::SelfTrade, 32),
+        (SwapError::MathOverflow, 33),
+        (SwapError::EscrowInYield, 34),
+        (SwapError::YieldShortfall, 35),
+        (SwapError::OfferNotStarted, 36),
+        (SwapError::SlippageExceeded, 37),
+        (SwapError::InvalidApproval, 38),
+        (SwapError::AmountTooLarge, 39),
+        (SwapError::AccountFrozen, 40),
+        (SwapError::UnsupportedVersion, 41),
+        (SwapError::MintNotAllowed, 42),
+        (SwapError::InvalidMint, 43),
+        (SwapError::CooldownActive, 44),
+    ];
+    for (error, code) in codes {
+        assert_eq!(
+            ProgramError::from(error),
+            ProgramError::Custom(code),
+            "error code {code} moved"
+        );
+    }
+}
+
+#[test]
+fn test_counters_are_fixed_price() {
+    // Counters keep direct and public offers as they are...
+    for offer_type in [
+        OfferType::Direct,
+        OfferType::PublicBuy,
+        OfferType::PublicSell,
+    ] {
+        assert_eq!(offer_type.for_counter(true), offer_type);
+        assert_eq!(offer_type.for_counter(false), offer_type);
+    }
+    // ...but a ratio order's, premium order's or auction's counter has no rate, premium or
+    // falling price to go by, so it's a plain offer.
+    for offer_type in [
+        OfferType::RatioOrder,
+        OfferType::PremiumOrder,
+        OfferType::DutchAuction,
+    ] {
+        assert_eq!(offer_type.for_counter(true), OfferType::PublicBuy);
+        assert_eq!(offer_type.for_counter(false), OfferType::PublicSell);
+    }
+}
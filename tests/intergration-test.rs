@@ -1,159 +1,712 @@
 use std::str::FromStr;
 
-use soffer::{Offer, OfferStatus, OfferType, Processor, SwapInstruction};
+use soffer::{
+    ACCEPT_BATCH_GROUP_LEN, AcceptApproval, AcceptBlocker, AcceptEligibility, ConfigField,
+    FEE_BUFFER_LAMPORTS, MAX_STATUS_BATCH, METADATA_KEY_V1, METADATA_PROGRAM_ID, MakerIndex,
+    MetadataCreator, Negotiation, Offer, OfferExpiredEvent, OfferStatus, OfferType, OfferUpdate,
+    OrderBook, PROGRAM_VERSION, Processor, ProgramConfig, ProgramVersion, Quote, RecentCancel,
+    Settlement, SwapInstruction, TokenMetadata, Trade, TradeHistory, YieldInstruction,
+    accept_accounts, effective_status, nonce_seed,
+};
 // We need these tools to build our mini-playground and play with our smart contract.
-use borsh::BorshDeserialize;
 use solana_program::instruction::InstructionError;
 use solana_program::{
     account_info::AccountInfo,
+    entrypoint::ProgramResult,
     hash::Hash,
     instruction::{AccountMeta, Instruction},
+    program::invoke_signed,
+    program_error::ProgramError,
     program_pack::Pack,
     pubkey::Pubkey,
-    system_instruction,
-    system_program,
-    sysvar::{Sysvar, rent::Rent}, // For account rent calculations
+    rent::Rent,
+    sysvar::Sysvar,
 };
-use solana_program_test::{BanksClient, ProgramTest, ProgramTestContext, processor}; // Our mini-playground tools!
+use solana_program_test::{BanksClient, ProgramTest, ProgramTestBanksClientExt, processor}; // Our mini-playground tools!
+use solana_sdk::account::Account;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
 use solana_sdk::transaction::TransactionError;
 use solana_sdk::{
     signature::{Keypair, Signer}, // To create new "people" (keypairs)
     transaction::Transaction,     // To bundle instructions into a transaction
 };
+use solana_system_interface::{instruction as system_instruction, program as system_program};
+use spl_associated_token_account_client::{
+    address::get_associated_token_address, instruction::create_associated_token_account,
+};
 use spl_token::state::{Account as TokenAccount, Mint}; // For SPL token accounts and mints
+use std::sync::atomic::{AtomicU32, Ordering};
 
 // Local msg! macro for logging in tests
 macro_rules! msg {
     ($($arg:tt)*) => (println!($($arg)*));
 }
 
-use borsh::to_vec;
 use soffer::SwapError;
-use solana_program::program_error::ProgramError;
+use soffer::test_sdk::{
+    create_mint, create_token_account, fund_account, get_sol_balance, get_token_balance, mint_to,
+};
 
-// Helper to fund an account with lamports
-async fn fund_account(context: &mut (BanksClient, Keypair, Hash), pubkey: &Pubkey, lamports: u64) {
-    let transfer_ix = system_instruction::transfer(&context.1.pubkey(), pubkey, lamports);
-    let mut transaction = Transaction::new_with_payer(&[transfer_ix], Some(&context.1.pubkey()));
-    transaction.sign(&[&context.1], context.2);
+// A Token-2022 mint (no extensions), a token account for it, and `amount` tokens in that account.
+async fn create_token_2022_account_with(
+    context: &mut (BanksClient, Keypair, Hash),
+    mint_authority: &Keypair,
+    owner: &Keypair,
+    amount: u64,
+) -> (Pubkey, Pubkey) {
+    let mint_keypair = Keypair::new();
+    let token_account_keypair = Keypair::new();
+    let rent = context.0.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.1.pubkey(),
+            &mint_keypair.pubkey(),
+            rent.minimum_balance(Mint::LEN),
+            Mint::LEN as u64,
+            &spl_token_2022::id(),
+        ),
+        spl_token_2022::instruction::initialize_mint(
+            &spl_token_2022::id(),
+            &mint_keypair.pubkey(),
+            &mint_authority.pubkey(),
+            None,
+            0,
+        )
+        .unwrap(),
+        system_instruction::create_account(
+            &context.1.pubkey(),
+            &token_account_keypair.pubkey(),
+            rent.minimum_balance(TokenAccount::LEN),
+            TokenAccount::LEN as u64,
+            &spl_token_2022::id(),
+        ),
+        spl_token_2022::instruction::initialize_account(
+            &spl_token_2022::id(),
+            &token_account_keypair.pubkey(),
+            &mint_keypair.pubkey(),
+            &owner.pubkey(),
+        )
+        .unwrap(),
+        spl_token_2022::instruction::mint_to(
+            &spl_token_2022::id(),
+            &mint_keypair.pubkey(),
+            &token_account_keypair.pubkey(),
+            &mint_authority.pubkey(),
+            &[],
+            amount,
+        )
+        .unwrap(),
+    ];
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&context.1.pubkey()));
+    transaction.sign(
+        &[
+            &context.1,
+            &mint_keypair,
+            &token_account_keypair,
+            mint_authority,
+        ],
+        context.2,
+    );
     context.0.process_transaction(transaction).await.unwrap();
+    (mint_keypair.pubkey(), token_account_keypair.pubkey())
 }
 
-async fn create_mint(
-    context: &mut (BanksClient, Keypair, Hash),
-    mint_authority: &Keypair,
-    freeze_authority: Option<&Pubkey>,
-    decimals: u8,
-) -> Pubkey {
-    let mint_keypair = Keypair::new(); // A new unique ID for our token blueprint
-    let rent = context.0.get_rent().await.unwrap(); // Get rent info
-    let rent_lamports = rent.minimum_balance(Mint::LEN); // How much SOL for the mint account
+// --- Shortcuts for the newer tests ---
+fn program_id() -> Pubkey {
+    Pubkey::from_str("HpddKoiN2TNaJ8ZdWRVNbgLuAKop4JzYuEGPAM45agk8").unwrap()
+}
 
-    // Create the mint account on our mini-playground.
-    let create_mint_account_ix = system_instruction::create_account(
-        &context.1.pubkey(),    // Who pays for the account
-        &mint_keypair.pubkey(), // The new mint account's address
-        rent_lamports,          // Rent amount
-        Mint::LEN as u64,       // Size of the account
-        &spl_token::id(),       // Owner of the account (SPL Token program)
-    );
+async fn start_program() -> (BanksClient, Keypair, Hash) {
+    soffer::test_sdk::start(program_id()).await
+}
+
+// A stand-in for another program that makes offers for its users. Its PDA (seeds `[b"owner"]`)
+// is the maker; it forwards whatever it's given to us (first account), signing as that PDA.
+fn controller_id() -> Pubkey {
+    Pubkey::new_from_array([7; 32])
+}
+
+fn controller_owner_pda() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"owner"], &controller_id())
+}
+
+fn controller_process(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let (owner, bump_seed) = Pubkey::find_program_address(&[b"owner"], program_id);
+    let (soffer_program, forwarded) = accounts.split_first().unwrap();
+    let instruction = Instruction {
+        program_id: *soffer_program.key,
+        accounts: forwarded
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: *account.key,
+                is_signer: account.is_signer || *account.key == owner,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data: instruction_data.to_vec(),
+    };
+    invoke_signed(&instruction, accounts, &[&[b"owner", &[bump_seed]]])
+}
+
+// Send `instruction` through the controller instead of straight to us.
+fn via_controller(mut instruction: Instruction) -> Instruction {
+    let (owner, _) = controller_owner_pda();
+    for account in &mut instruction.accounts {
+        if account.pubkey == owner {
+            account.is_signer = false; // Only the controller can sign for it
+        }
+    }
+    instruction
+        .accounts
+        .insert(0, AccountMeta::new_readonly(program_id(), false));
+    Instruction {
+        program_id: controller_id(),
+        accounts: instruction.accounts,
+        data: instruction.data,
+    }
+}
+
+// A stand-in yield strategy. Deposits land in a vault it owns (the account after the offer), and
+// a withdrawal pays the offer everything in the vault above its rent, so whatever extra the test
+// puts in the vault shows up as yield.
+fn mock_yield_id() -> Pubkey {
+    Pubkey::new_from_array([9; 32])
+}
 
-    // Initialize the mint (set up its rules, like who can create new tokens).
-    let init_mint_ix = spl_token::instruction::initialize_mint(
-        &spl_token::id(),         // SPL Token program ID
-        &mint_keypair.pubkey(),   // Our new mint account
-        &mint_authority.pubkey(), // Who can create new tokens
-        freeze_authority,         // Who can freeze tokens (optional)
-        decimals,                 // How many decimal places our token has
+fn mock_yield_process(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let [offer_account, yield_vault, ..] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    if !offer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    match borsh::from_slice(instruction_data)? {
+        YieldInstruction::Deposit { .. } => Ok(()), // The lamports arrive once this returns
+        YieldInstruction::Withdraw => {
+            let rent = Rent::get()?.minimum_balance(yield_vault.data_len());
+            let amount = yield_vault.lamports() - rent;
+            **yield_vault.try_borrow_mut_lamports()? -= amount;
+            **offer_account.try_borrow_mut_lamports()? += amount;
+            Ok(())
+        }
+    }
+}
+
+fn config_pda() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], &program_id())
+}
+
+fn mint_pause_pda(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"mintpause", mint.as_ref()], &program_id())
+}
+
+fn maker_index_pda(maker: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"maker", maker.as_ref()], &program_id())
+}
+
+fn settlement_pda(offer_account: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"settle", offer_account.as_ref()], &program_id())
+}
+
+fn order_book_pda(offer_token_mint: &Pubkey, receive_token_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"book",
+            offer_token_mint.as_ref(),
+            receive_token_mint.as_ref(),
+        ],
+        &program_id(),
     )
-    .unwrap();
+}
 
-    // Bundle these instructions into a transaction and send it.
-    let mut transaction = Transaction::new_with_payer(
-        &[create_mint_account_ix, init_mint_ix],
-        Some(&context.1.pubkey()),
-    );
-    transaction.sign(&[&context.1, &mint_keypair], context.2);
-    context.0.process_transaction(transaction).await.unwrap();
+fn recent_cancel_pda(
+    maker: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"recent",
+            maker.as_ref(),
+            offer_token_mint.as_ref(),
+            receive_token_mint.as_ref(),
+        ],
+        &program_id(),
+    )
+}
+
+fn history_pda(base_mint: &Pubkey, quote_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"history", base_mint.as_ref(), quote_mint.as_ref()],
+        &program_id(),
+    )
+}
+
+fn offer_pda(
+    maker: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+) -> (Pubkey, u8) {
+    offer_pda_with_nonce(maker, offer_token_mint, receive_token_mint, 0)
+}
 
-    mint_keypair.pubkey() // Return the address of our new token blueprint
+fn offer_pda_with_nonce(
+    maker: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+    nonce: u64,
+) -> (Pubkey, u8) {
+    let nonce_bytes = nonce.to_le_bytes();
+    Pubkey::find_program_address(
+        &[
+            b"offer",
+            maker.as_ref(),
+            offer_token_mint.as_ref(),
+            receive_token_mint.as_ref(),
+            nonce_seed(&nonce_bytes),
+        ],
+        &program_id(),
+    )
 }
 
-async fn create_token_account(
+// Sign with the payer plus `signers`, send, and hand back the instruction error (if any).
+async fn send(
     context: &mut (BanksClient, Keypair, Hash),
-    owner: &Keypair,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), TransactionError> {
+    // Sending the very same transaction twice within a blockhash just hands back the first
+    // result, so each one gets its own (generous) compute limit to tell it apart. It goes last,
+    // so the program's instructions keep their indexes in errors.
+    static SENT: AtomicU32 = AtomicU32::new(0);
+    let mut instructions = instructions.to_vec();
+    instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(
+        1_400_000 - SENT.fetch_add(1, Ordering::Relaxed) % 100_000,
+    ));
+    let mut all_signers = vec![&context.1];
+    all_signers.extend_from_slice(signers);
+    let blockhash = context.0.get_latest_blockhash().await.unwrap();
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&context.1.pubkey()));
+    transaction.sign(&all_signers, blockhash);
+    context
+        .0
+        .process_transaction(transaction)
+        .await
+        .map_err(|e| e.unwrap())
+}
+
+// Run the instructions without committing anything and hand back the program's return data.
+async fn simulate_return_data(
+    context: &mut (BanksClient, Keypair, Hash),
+    instructions: &[Instruction],
+) -> Vec<u8> {
+    let blockhash = context.0.get_latest_blockhash().await.unwrap();
+    let mut transaction = Transaction::new_with_payer(instructions, Some(&context.1.pubkey()));
+    transaction.sign(&[&context.1], blockhash);
+    let simulation = context.0.simulate_transaction(transaction).await.unwrap();
+    simulation.result.unwrap().unwrap();
+    simulation
+        .simulation_details
+        .unwrap()
+        .return_data
+        .map(|return_data| return_data.data)
+        .unwrap_or_default()
+}
+
+fn custom_error(error: SwapError) -> TransactionError {
+    TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+}
+
+// Accounts for `CreateOffer`, with the maker's own wallet as their SOL account.
+fn create_offer_ix(
+    maker: &Pubkey,
+    maker_token_account: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+    data: SwapInstruction,
+) -> Instruction {
+    let (offer_account, _) = offer_pda(maker, offer_token_mint, receive_token_mint);
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*maker, true),
+            AccountMeta::new(offer_account, false),
+            AccountMeta::new(*maker_token_account, false),
+            AccountMeta::new_readonly(*offer_token_mint, false),
+            AccountMeta::new_readonly(*receive_token_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(mint_pause_pda(offer_token_mint).0, false),
+            AccountMeta::new_readonly(mint_pause_pda(receive_token_mint).0, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(maker_index_pda(maker).0, false),
+            AccountMeta::new(*maker, false), // fee_collector (unused without a create fee)
+            AccountMeta::new(
+                order_book_pda(offer_token_mint, receive_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(
+                recent_cancel_pda(maker, offer_token_mint, receive_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(*maker, false),
+        ],
+        data: borsh::to_vec(&data).unwrap(),
+    }
+}
+
+// Accounts for `AcceptOffer`/`AcceptOfferPartial`, with both parties' wallets as SOL accounts.
+#[allow(clippy::too_many_arguments)]
+fn accept_offer_ix(
+    taker: &Pubkey,
+    offer_account: &Pubkey,
+    maker: &Pubkey,
+    maker_token_account: &Pubkey,
+    taker_token_account: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+    data: SwapInstruction,
+) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new(*offer_account, false),
+            AccountMeta::new_readonly(*maker, true),
+            AccountMeta::new(*maker_token_account, false),
+            AccountMeta::new(*taker_token_account, false),
+            AccountMeta::new_readonly(*offer_token_mint, false),
+            AccountMeta::new_readonly(*receive_token_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(mint_pause_pda(offer_token_mint).0, false),
+            AccountMeta::new_readonly(mint_pause_pda(receive_token_mint).0, false),
+            AccountMeta::new(maker_index_pda(maker).0, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(settlement_pda(offer_account).0, false),
+            AccountMeta::new(
+                order_book_pda(offer_token_mint, receive_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(*maker, false),
+            AccountMeta::new(*taker, false),
+        ],
+        data: borsh::to_vec(&data).unwrap(),
+    }
+}
+
+// Accounts for `CancelOffer`, with the maker's own wallet getting the refund.
+fn cancel_offer_ix(
+    maker: &Pubkey,
+    offer_account: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*maker, true),
+            AccountMeta::new(*offer_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(maker_index_pda(maker).0, false),
+            AccountMeta::new(
+                order_book_pda(offer_token_mint, receive_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(
+                recent_cancel_pda(maker, offer_token_mint, receive_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(*maker, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::CancelOffer {
+            close_account: false,
+        })
+        .unwrap(),
+    }
+}
+
+// Accounts for `CounterOffer`, with both makers' own wallets as their SOL accounts.
+fn counter_offer_ix(
+    counter_maker: &Pubkey,
+    original_offer_account: &Pubkey,
+    original_maker: &Pubkey,
+    counter_maker_token_account: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+    data: SwapInstruction,
+) -> Instruction {
+    let (new_offer_account, _) = offer_pda(counter_maker, offer_token_mint, receive_token_mint);
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*counter_maker, true),
+            AccountMeta::new(*original_offer_account, false),
+            AccountMeta::new(new_offer_account, false),
+            AccountMeta::new(*counter_maker_token_account, false),
+            AccountMeta::new_readonly(*offer_token_mint, false),
+            AccountMeta::new_readonly(*receive_token_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(maker_index_pda(original_maker).0, false),
+            AccountMeta::new(maker_index_pda(counter_maker).0, false),
+            // Counters here answer an offer on the opposite pair.
+            AccountMeta::new(
+                order_book_pda(receive_token_mint, offer_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(
+                order_book_pda(offer_token_mint, receive_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(*counter_maker, false),
+            AccountMeta::new(*original_maker, false),
+        ],
+        data: borsh::to_vec(&data).unwrap(),
+    }
+}
+
+// Accounts for `MakePublic`.
+fn make_public_ix(maker: &Pubkey, offer_account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(*maker, true),
+            AccountMeta::new(*offer_account, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::MakePublic).unwrap(),
+    }
+}
+
+// Make the offer PDA's associated token account for `mint`, where a sealed offer keeps its tokens.
+async fn create_offer_vault(
+    context: &mut (BanksClient, Keypair, Hash),
+    offer_account: &Pubkey,
     mint: &Pubkey,
 ) -> Pubkey {
-    let token_account_keypair = Keypair::new(); // A new unique ID for our token wallet
-    let rent = context.0.get_rent().await.unwrap();
-    let rent_lamports = rent.minimum_balance(TokenAccount::LEN);
-
-    // Create the token account.
-    let create_token_account_ix = system_instruction::create_account(
-        &context.1.pubkey(),
-        &token_account_keypair.pubkey(),
-        rent_lamports,
-        TokenAccount::LEN as u64,
-        &spl_token::id(),
-    );
+    let create_ix =
+        create_associated_token_account(&context.1.pubkey(), offer_account, mint, &spl_token::id());
+    send(context, &[create_ix], &[]).await.unwrap();
+    get_associated_token_address(offer_account, mint)
+}
 
-    // Initialize the token account (link it to a specific token blueprint and owner).
-    let init_token_account_ix = spl_token::instruction::initialize_account(
-        &spl_token::id(),
-        &token_account_keypair.pubkey(),
-        mint,
-        &owner.pubkey(),
+fn negotiation_pda(offer_account: &Pubkey, taker: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"nego", offer_account.as_ref(), taker.as_ref()],
+        &program_id(),
     )
-    .unwrap();
+}
 
-    // Bundle and send the transaction.
-    let mut transaction = Transaction::new_with_payer(
-        &[create_token_account_ix, init_token_account_ix],
-        Some(&context.1.pubkey()),
-    );
-    transaction.sign(&[&context.1, &token_account_keypair], context.2);
-    context.0.process_transaction(transaction).await.unwrap();
+// Accounts for `RequestTerms`, with the taker paying for the negotiation account.
+fn request_terms_ix(taker: &Pubkey, offer_account: &Pubkey, proposed_receive: u64) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*taker, true),
+            AccountMeta::new_readonly(*offer_account, false),
+            AccountMeta::new(negotiation_pda(offer_account, taker).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::RequestTerms { proposed_receive }).unwrap(),
+    }
+}
 
-    token_account_keypair.pubkey() // Return the address of our new token wallet
+// Accounts for `AbortSettlement`, with the taker getting the settlement's rent back.
+fn abort_settlement_ix(authority: &Pubkey, offer_account: &Pubkey, taker: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new_readonly(*offer_account, false),
+            AccountMeta::new(settlement_pda(offer_account).0, false),
+            AccountMeta::new(*taker, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::AbortSettlement).unwrap(),
+    }
 }
-// This is like printing new trading cards and putting them in a wallet.
-async fn mint_to(
-    context: &mut (BanksClient, Keypair, Hash),
+
+// A stand-in for the Metaplex metadata of `mint`: its address, and an account to put there.
+fn nft_metadata_account(
     mint: &Pubkey,
-    token_account: &Pubkey,
-    mint_authority: &Keypair,
-    amount: u64,
-) {
-    let mint_to_ix = spl_token::instruction::mint_to(
-        &spl_token::id(),
-        mint,
-        token_account,
-        &mint_authority.pubkey(),
-        &[],
-        amount, // No multi-signers needed here
-    )
-    .unwrap();
+    seller_fee_basis_points: u16,
+    creators: &[(Pubkey, u8)],
+) -> (Pubkey, Account) {
+    let (metadata_key, _) = Pubkey::find_program_address(
+        &[b"metadata", METADATA_PROGRAM_ID.as_ref(), mint.as_ref()],
+        &METADATA_PROGRAM_ID,
+    );
+    let metadata = TokenMetadata {
+        key: METADATA_KEY_V1,
+        update_authority: Pubkey::new_unique(),
+        mint: *mint,
+        name: "Soffer Cat".to_string(),
+        symbol: "SCAT".to_string(),
+        uri: "https://example.com/cat.json".to_string(),
+        seller_fee_basis_points,
+        creators: Some(
+            creators
+                .iter()
+                .map(|&(address, share)| MetadataCreator {
+                    address,
+                    verified: true,
+                    share,
+                })
+                .collect(),
+        ),
+    };
+    let mut data = borsh::to_vec(&metadata).unwrap();
+    data.resize(679, 0); // Metaplex accounts have more after the creators
+    let account = Account {
+        lamports: 10_000_000,
+        data,
+        owner: METADATA_PROGRAM_ID,
+        executable: false,
+        rent_epoch: 0,
+    };
+    (metadata_key, account)
+}
 
-    let mut transaction = Transaction::new_with_payer(&[mint_to_ix], Some(&context.1.pubkey()));
-    transaction.sign(&[&context.1, mint_authority], context.2);
-    context.0.process_transaction(transaction).await.unwrap();
+fn initialize_config_ix(admin: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(config_pda().0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::InitializeConfig).unwrap(),
+    }
+}
+
+fn pause_mint_ix(admin: &Pubkey, mint: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(mint_pause_pda(mint).0, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::PauseMint).unwrap(),
+    }
+}
+
+fn unpause_mint_ix(admin: &Pubkey, mint: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(mint_pause_pda(mint).0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::UnpauseMint).unwrap(),
+    }
 }
-async fn get_sol_balance(context: &mut (BanksClient, Keypair, Hash), pubkey: &Pubkey) -> u64 {
-    context.0.get_balance(*pubkey).await.unwrap()
+
+fn update_config_ix(admin: &Pubkey, field: ConfigField) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(config_pda().0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::UpdateConfig { field }).unwrap(),
+    }
 }
 
-async fn get_token_balance(
-    context: &mut (BanksClient, Keypair, Hash),
-    token_account: &Pubkey,
-) -> u64 {
+fn reopen_offer_ix(
+    maker: &Pubkey,
+    offer_account: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*maker, true),
+            AccountMeta::new(*offer_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(maker_index_pda(maker).0, false),
+            AccountMeta::new(
+                order_book_pda(offer_token_mint, receive_token_mint).0,
+                false,
+            ),
+        ],
+        data: borsh::to_vec(&SwapInstruction::ReopenOffer).unwrap(),
+    }
+}
+
+fn confirm_interest_ix(taker: &Pubkey, offer_account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(*taker, true),
+            AccountMeta::new(*offer_account, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::ConfirmInterest).unwrap(),
+    }
+}
+
+// Accounts for `ExpireOffer`, without the ones a sealed offer needs for its tokens.
+fn expire_offer_ix(
+    offer_account: &Pubkey,
+    maker: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*offer_account, false),
+            AccountMeta::new(*maker, false),
+            AccountMeta::new(*maker, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(maker_index_pda(maker).0, false),
+            AccountMeta::new(
+                order_book_pda(offer_token_mint, receive_token_mint).0,
+                false,
+            ),
+        ],
+        data: borsh::to_vec(&SwapInstruction::ExpireOffer).unwrap(),
+    }
+}
+
+// Accounts for `UpdateOffer`, without the ones a sealed offer needs for its tokens.
+fn update_offer_ix(maker: &Pubkey, offer_account: &Pubkey, data: SwapInstruction) -> Instruction {
+    Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*maker, true),
+            AccountMeta::new(*offer_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new_readonly(settlement_pda(offer_account).0, false),
+        ],
+        data: borsh::to_vec(&data).unwrap(),
+    }
+}
+
+async fn get_offer(context: &mut (BanksClient, Keypair, Hash), offer_account: &Pubkey) -> Offer {
     let account = context
         .0
-        .get_account(*token_account)
+        .get_account(*offer_account)
         .await
         .unwrap()
         .unwrap();
-    let token_account_data = TokenAccount::unpack(&account.data).unwrap();
-    token_account_data.amount
+    Offer::load(&account.data).unwrap()
 }
 
 #[tokio::test]
@@ -206,7 +759,7 @@ async fn test_create_public_sell_offer_success() {
     let offer_type = OfferType::PublicSell;
 
     // Calculate the PDA for the offer account.
-    let (offer_account_pubkey, bump_seed) = Pubkey::find_program_address(
+    let (offer_account_pubkey, _) = Pubkey::find_program_address(
         &[
             b"offer",
             maker.pubkey().as_ref(),
@@ -221,7 +774,14 @@ async fn test_create_public_sell_offer_success() {
         offer_token_amount,
         receive_token_amount,
         expiration: None, // No expiration for this test
-        bump_seed,
+        min_fill: 0,
+        duration_secs: None,
+        receive_recipient: None,
+        confirm_first: false,
+        nonce: 0,
+        start_time: None,
+        client_id: [0; 8],
+        auto_cancel_on_fail: false,
     };
 
     let borsh_instruction_data = borsh::to_vec(&instruction_data).unwrap();
@@ -235,6 +795,19 @@ async fn test_create_public_sell_offer_success() {
         AccountMeta::new_readonly(system_program::id(), false), // system_program
         AccountMeta::new_readonly(spl_token::id(), false),    // token_program
         AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false), // rent_sysvar
+        AccountMeta::new_readonly(mint_pause_pda(&offer_token_mint).0, false),
+        AccountMeta::new_readonly(mint_pause_pda(&receive_token_mint).0, false),
+        AccountMeta::new_readonly(config_pda().0, false),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new(maker.pubkey(), false), // fee_collector (unused without a create fee)
+        AccountMeta::new(
+            order_book_pda(&offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
+        AccountMeta::new(
+            recent_cancel_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
         AccountMeta::new(maker_sol_account, false), // maker_sol_account (writable, for rent or future SOL transfers)
     ];
 
@@ -266,7 +839,7 @@ async fn test_create_public_sell_offer_success() {
         Pubkey::from_str("HpddKoiN2TNaJ8ZdWRVNbgLuAKop4JzYuEGPAM45agk8").unwrap()
     ); // Check if our program owns it
 
-    let offer_data = Offer::try_from_slice(&offer_account.data).unwrap(); // Unpack the data
+    let offer_data = Offer::load(&offer_account.data).unwrap(); // Unpack the data
     assert_eq!(offer_data.offer_type, OfferType::PublicSell);
     assert_eq!(offer_data.status, OfferStatus::Active);
     assert_eq!(offer_data.maker, maker.pubkey());
@@ -303,8 +876,11 @@ async fn test_accept_public_sell_offer_success() {
     let maker = Keypair::new();
     let taker = Keypair::new();
     let mint_authority = Keypair::new();
+    // Maker starts with 1 SOL (for rent), taker with 10 SOL (to pay for the tokens)
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
     let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await; // Maker offers this token
-    let receive_token_mint = Pubkey::new_from_array([0; 32]); // Taker offers SOL (represented by dummy Pubkey)
+    let receive_token_mint = spl_token::native_mint::id(); // Taker offers SOL (marked by the native mint)
 
     // Maker's accounts
     let maker_offer_token_account =
@@ -317,23 +893,24 @@ async fn test_accept_public_sell_offer_success() {
         100,
     )
     .await;
-    let maker_sol_account = context.1.pubkey(); // Use payer's account for SOL
+    let maker_sol_account = maker.pubkey(); // Maker's own wallet holds their SOL
 
     // Taker's accounts
     let taker_receive_token_account =
         create_token_account(&mut context, &taker, &offer_token_mint).await; // Taker will receive this token
-    let taker_sol_account = context.1.pubkey(); // Use payer's account for SOL
+    let taker_sol_account = taker.pubkey(); // Taker's own wallet pays the SOL
 
     // Create the offer (Maker sells 10 tokens for 5 SOL)
     let offer_token_amount = 10;
     let receive_sol_amount = 5_000_000_000; // 5 SOL
     let offer_type = OfferType::PublicSell;
 
-    let (offer_account_pubkey, bump_seed) = Pubkey::find_program_address(
+    let (offer_account_pubkey, _) = Pubkey::find_program_address(
         &[
             b"offer",
             maker.pubkey().as_ref(),
-            offer_token_mint.as_ref(), // SOL placeholder
+            offer_token_mint.as_ref(),
+            receive_token_mint.as_ref(), // SOL (the native mint)
         ],
         &Pubkey::from_str("HpddKoiN2TNaJ8ZdWRVNbgLuAKop4JzYuEGPAM45agk8").unwrap(),
     );
@@ -343,7 +920,14 @@ async fn test_accept_public_sell_offer_success() {
         offer_token_amount,
         receive_token_amount: receive_sol_amount,
         expiration: None,
-        bump_seed,
+        min_fill: 0,
+        duration_secs: None,
+        receive_recipient: None,
+        confirm_first: false,
+        nonce: 0,
+        start_time: None,
+        client_id: [0; 8],
+        auto_cancel_on_fail: false,
     };
     let borsh_create_offer_in_data = borsh::to_vec(&create_offer_in_data).unwrap();
 
@@ -352,10 +936,23 @@ async fn test_accept_public_sell_offer_success() {
         AccountMeta::new(offer_account_pubkey, false),
         AccountMeta::new(maker_offer_token_account, false),
         AccountMeta::new_readonly(offer_token_mint, false),
-        AccountMeta::new_readonly(receive_token_mint, false), // SOL placeholder
+        AccountMeta::new_readonly(receive_token_mint, false), // SOL (the native mint)
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+        AccountMeta::new_readonly(mint_pause_pda(&offer_token_mint).0, false),
+        AccountMeta::new_readonly(mint_pause_pda(&receive_token_mint).0, false),
+        AccountMeta::new_readonly(config_pda().0, false),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new(maker.pubkey(), false), // fee_collector (unused without a create fee)
+        AccountMeta::new(
+            order_book_pda(&offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
+        AccountMeta::new(
+            recent_cancel_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
         AccountMeta::new(maker_sol_account, false), // Maker's SOL account
     ];
 
@@ -371,18 +968,32 @@ async fn test_accept_public_sell_offer_success() {
     context.0.process_transaction(transaction).await.unwrap();
 
     // Now accept the offer (Taker pays 5 SOL for 10 tokens)
-    let accept_offer_ix_data = borsh::to_vec(&SwapInstruction::AcceptOffer).unwrap();
+    let accept_offer_ix_data = borsh::to_vec(&SwapInstruction::AcceptOffer {
+        close_account: false,
+        min_receive_amount: None,
+        max_send_amount: None,
+    })
+    .unwrap();
 
     let accept_offer_accounts = vec![
         AccountMeta::new(taker.pubkey(), true), // taker_account (signer)
-        AccountMeta::new(offer_account_pubkey, true), // offer_account (writable)
-        AccountMeta::new_readonly(maker.pubkey(), false), // maker_account (read-only)
+        AccountMeta::new(offer_account_pubkey, false), // offer_account (writable)
+        AccountMeta::new_readonly(maker.pubkey(), true), // maker_account (signs for their token transfer)
         AccountMeta::new(maker_offer_token_account, false), // maker_token_account (writable)
         AccountMeta::new(taker_receive_token_account, false), // taker_token_account (writable)
         AccountMeta::new_readonly(offer_token_mint, false), // offer_token_mint
-        AccountMeta::new_readonly(receive_token_mint, false), // receive_token_mint (SOL placeholder)
+        AccountMeta::new_readonly(receive_token_mint, false), // receive_token_mint (SOL, the native mint)
         AccountMeta::new_readonly(system_program::id(), false), // system_program
         AccountMeta::new_readonly(spl_token::id(), false),    // token_program
+        AccountMeta::new_readonly(mint_pause_pda(&offer_token_mint).0, false),
+        AccountMeta::new_readonly(mint_pause_pda(&receive_token_mint).0, false),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new_readonly(config_pda().0, false),
+        AccountMeta::new(settlement_pda(&offer_account_pubkey).0, false),
+        AccountMeta::new(
+            order_book_pda(&offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
         AccountMeta::new(maker_sol_account, false), // maker_sol_account (writable, to receive SOL)
         AccountMeta::new(taker_sol_account, false), // taker_sol_account (writable, to pay SOL)
     ];
@@ -412,10 +1023,13 @@ async fn test_accept_public_sell_offer_success() {
         get_sol_balance(&mut context, &taker_sol_account).await,
         5_000_000_000
     ); // Taker's SOL decreased by 5
+    let rent = context.0.get_rent().await.unwrap();
+    let offer_rent = rent.minimum_balance(Offer::MAX_LEN);
+    let maker_index_rent = rent.minimum_balance(MakerIndex::MAX_LEN);
     assert_eq!(
         get_sol_balance(&mut context, &maker_sol_account).await,
-        6_000_000_000
-    ); // Maker's SOL increased by 5 (initial 1 SOL + 5 SOL from taker)
+        6_000_000_000 - offer_rent - maker_index_rent
+    ); // Maker's SOL increased by 5 (initial 1 SOL - offer and maker index rent + 5 SOL from taker)
 
     // Verify offer status is Accepted
     let offer_account = context
@@ -424,7 +1038,7 @@ async fn test_accept_public_sell_offer_success() {
         .await
         .unwrap()
         .unwrap();
-    let offer_data = Offer::try_from_slice(&offer_account.data).unwrap();
+    let offer_data = Offer::load(&offer_account.data).unwrap();
     assert_eq!(offer_data.status, OfferStatus::Accepted);
 
     msg!("test_accept_public_sell_offer_success PASSED");
@@ -450,22 +1064,23 @@ async fn test_cancel_offer_success() {
 
     let maker = Keypair::new();
     let mint_authority = Keypair::new();
-    let offer_token_mint = Pubkey::new_from_array([0; 32]); // Maker offers SOL
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await; // Maker starts with 10 SOL
+    let offer_token_mint = spl_token::native_mint::id(); // Maker offers SOL
     let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await; // Maker wants this token
 
     // Maker's accounts
-    let maker_sol_account = context.1.pubkey(); // Use payer's account for SOL
+    let maker_sol_account = maker.pubkey(); // The maker pays the escrow from their own wallet
 
     // Create the offer (Maker offers 5 SOL for 10 tokens)
     let offer_sol_amount = 5_000_000_000; // 5 SOL
     let receive_token_amount = 10;
     let offer_type = OfferType::PublicBuy;
 
-    let (offer_account_pubkey, bump_seed) = Pubkey::find_program_address(
+    let (offer_account_pubkey, _) = Pubkey::find_program_address(
         &[
             b"offer",
             maker.pubkey().as_ref(),
-            offer_token_mint.as_ref(), // SOL placeholder
+            offer_token_mint.as_ref(), // SOL (the native mint)
             receive_token_mint.as_ref(),
         ],
         &Pubkey::from_str("HpddKoiN2TNaJ8ZdWRVNbgLuAKop4JzYuEGPAM45agk8").unwrap(),
@@ -476,7 +1091,14 @@ async fn test_cancel_offer_success() {
         offer_token_amount: offer_sol_amount,
         receive_token_amount,
         expiration: None,
-        bump_seed,
+        min_fill: 0,
+        duration_secs: None,
+        receive_recipient: None,
+        confirm_first: false,
+        nonce: 0,
+        start_time: None,
+        client_id: [0; 8],
+        auto_cancel_on_fail: false,
     })
     .unwrap();
 
@@ -484,11 +1106,24 @@ async fn test_cancel_offer_success() {
         AccountMeta::new(maker.pubkey(), true),
         AccountMeta::new(offer_account_pubkey, false),
         AccountMeta::new_readonly(Pubkey::new_unique(), false), // Dummy token account, not used for SOL offer
-        AccountMeta::new_readonly(offer_token_mint, false),     // SOL placeholder
+        AccountMeta::new_readonly(offer_token_mint, false),     // SOL (the native mint)
         AccountMeta::new_readonly(receive_token_mint, false),
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+        AccountMeta::new_readonly(mint_pause_pda(&offer_token_mint).0, false),
+        AccountMeta::new_readonly(mint_pause_pda(&receive_token_mint).0, false),
+        AccountMeta::new_readonly(config_pda().0, false),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new(maker.pubkey(), false), // fee_collector (unused without a create fee)
+        AccountMeta::new(
+            order_book_pda(&offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
+        AccountMeta::new(
+            recent_cancel_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
         AccountMeta::new(maker_sol_account, false), // Maker's SOL account
     ];
 
@@ -516,6 +1151,12 @@ async fn test_cancel_offer_success() {
                 .unwrap()
                 .minimum_balance(Offer::MAX_LEN)
     ); // SOL + rent for PDA
+    let maker_index_rent = context
+        .0
+        .get_rent()
+        .await
+        .unwrap()
+        .minimum_balance(MakerIndex::MAX_LEN);
     let initial_maker_sol_balance = get_sol_balance(&mut context, &maker_sol_account).await;
     assert_eq!(
         initial_maker_sol_balance,
@@ -526,15 +1167,28 @@ async fn test_cancel_offer_success() {
                 .await
                 .unwrap()
                 .minimum_balance(Offer::MAX_LEN)
-    ); // Maker's SOL decreased by escrow + rent
+            - maker_index_rent
+    ); // Maker's SOL decreased by escrow + rent (offer and maker index)
 
     // Now cancel the offer
-    let cancel_offer_ix_data = borsh::to_vec(&SwapInstruction::CancelOffer).unwrap();
+    let cancel_offer_ix_data = borsh::to_vec(&SwapInstruction::CancelOffer {
+        close_account: false,
+    })
+    .unwrap();
 
     let cancel_offer_accounts = vec![
         AccountMeta::new(maker.pubkey(), true), // offer_maker_account (signer)
-        AccountMeta::new(offer_account_pubkey, true), // offer_account (writable)
+        AccountMeta::new(offer_account_pubkey, false), // offer_account (writable)
         AccountMeta::new_readonly(system_program::id(), false), // system_program
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new(
+            order_book_pda(&offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
+        AccountMeta::new(
+            recent_cancel_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
         AccountMeta::new(maker_sol_account, false), // maker_sol_account (writable, to receive refund)
     ];
 
@@ -571,7 +1225,8 @@ async fn test_cancel_offer_success() {
                 .await
                 .unwrap()
                 .minimum_balance(Offer::MAX_LEN)
-    ); // Maker's SOL back to initial (minus rent for PDA)
+            - maker_index_rent
+    ); // Maker's SOL back to initial (minus rent for the PDAs)
 
     let offer_account = context
         .0
@@ -579,7 +1234,7 @@ async fn test_cancel_offer_success() {
         .await
         .unwrap()
         .unwrap();
-    let offer_data = Offer::try_from_slice(&offer_account.data).unwrap();
+    let offer_data = Offer::load(&offer_account.data).unwrap();
     assert_eq!(offer_data.status, OfferStatus::Declined);
 
     msg!("test_cancel_offer_success PASSED");
@@ -603,6 +1258,7 @@ async fn test_create_offer_insufficient_funds() {
 
     let maker = Keypair::new();
     let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await; // Enough SOL for rent
     let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
     let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
 
@@ -616,7 +1272,7 @@ async fn test_create_offer_insufficient_funds() {
     let receive_token_amount = 5;
     let offer_type = OfferType::PublicSell; // Maker offers tokens
 
-    let (offer_account_pubkey, bump_seed) = Pubkey::find_program_address(
+    let (offer_account_pubkey, _) = Pubkey::find_program_address(
         &[
             b"offer",
             maker.pubkey().as_ref(),
@@ -631,7 +1287,14 @@ async fn test_create_offer_insufficient_funds() {
         offer_token_amount,
         receive_token_amount,
         expiration: None,
-        bump_seed,
+        min_fill: 0,
+        duration_secs: None,
+        receive_recipient: None,
+        confirm_first: false,
+        nonce: 0,
+        start_time: None,
+        client_id: [0; 8],
+        auto_cancel_on_fail: false,
     };
 
     let accounts = vec![
@@ -643,6 +1306,19 @@ async fn test_create_offer_insufficient_funds() {
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+        AccountMeta::new_readonly(mint_pause_pda(&offer_token_mint).0, false),
+        AccountMeta::new_readonly(mint_pause_pda(&receive_token_mint).0, false),
+        AccountMeta::new_readonly(config_pda().0, false),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new(maker.pubkey(), false), // fee_collector (unused without a create fee)
+        AccountMeta::new(
+            order_book_pda(&offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
+        AccountMeta::new(
+            recent_cancel_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
         AccountMeta::new(maker_sol_account, false),
     ];
 
@@ -689,22 +1365,26 @@ async fn test_accept_offer_expired() {
         },
     );
 
-    // Set a specific clock for testing expiration
-    // program_test.set_sysvar_account::<solana_program::sysvar::clock::Clock>(
-    //     &solana_program::sysvar::clock::Clock {
-    //         unix_timestamp: 100, // Set initial time
-    //         ..Default::default()
-    //     }
-    //     .into(),
-    // );
-
-    let mut context = program_test.start().await;
+    // Start with a context so the test can set the clock itself.
+    let program_context = program_test.start_with_context().await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: 100, // Set initial time
+        ..clock.clone()
+    });
 
     let maker = Keypair::new();
     let taker = Keypair::new();
     let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
     let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
-    let receive_token_mint = Pubkey::new_from_array([0; 32]);
+    let receive_token_mint = spl_token::native_mint::id();
 
     let maker_offer_token_account =
         create_token_account(&mut context, &maker, &offer_token_mint).await;
@@ -716,27 +1396,19 @@ async fn test_accept_offer_expired() {
         100,
     )
     .await;
-    let maker_sol_account = context.1.pubkey();
-    // context.set_account(
-    //     &maker_sol_account.pubkey(),
-    //     &solana_sdk::account::Account::new(1_000_000_000, 0, &system_program::id()),
-    // );
+    let maker_sol_account = maker.pubkey();
 
     let taker_receive_token_account =
         create_token_account(&mut context, &taker, &offer_token_mint).await;
-    let taker_sol_account = context.1.pubkey();
-    // context.set_account(
-    //     &taker_sol_account.pubkey(),
-    //     &solana_sdk::account::Account::new(10_000_000_000, 0, &system_program::id()),
-    // );
+    let taker_sol_account = taker.pubkey();
 
-    // Create the offer with an expiration in the past
+    // Create the offer while it's still good
     let offer_token_amount = 10;
     let receive_sol_amount = 5_000_000_000;
     let offer_type = OfferType::PublicSell;
-    let expiration_time = 50; // Offer expires at time 50, but current time is 100
+    let expiration_time = 150; // Offer expires at time 150, and the current time is 100
 
-    let (offer_account_pubkey, bump_seed) = Pubkey::find_program_address(
+    let (offer_account_pubkey, _) = Pubkey::find_program_address(
         &[
             b"offer",
             maker.pubkey().as_ref(),
@@ -751,7 +1423,14 @@ async fn test_accept_offer_expired() {
         offer_token_amount,
         receive_token_amount: receive_sol_amount,
         expiration: Some(expiration_time),
-        bump_seed,
+        min_fill: 0,
+        duration_secs: None,
+        receive_recipient: None,
+        confirm_first: false,
+        nonce: 0,
+        start_time: None,
+        client_id: [0; 8],
+        auto_cancel_on_fail: false,
     })
     .unwrap();
 
@@ -764,6 +1443,19 @@ async fn test_accept_offer_expired() {
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+        AccountMeta::new_readonly(mint_pause_pda(&offer_token_mint).0, false),
+        AccountMeta::new_readonly(mint_pause_pda(&receive_token_mint).0, false),
+        AccountMeta::new_readonly(config_pda().0, false),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new(maker.pubkey(), false), // fee_collector (unused without a create fee)
+        AccountMeta::new(
+            order_book_pda(&offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
+        AccountMeta::new(
+            recent_cancel_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
         AccountMeta::new(maker_sol_account, false),
     ];
 
@@ -778,19 +1470,39 @@ async fn test_accept_offer_expired() {
     transaction.sign(&[&context.1, &maker], context.2);
     context.0.process_transaction(transaction).await.unwrap();
 
+    // Time moves on past the expiration.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: 200,
+        ..clock
+    });
+
     // Try to accept the expired offer
-    let accept_offer_ix_data = borsh::to_vec(&SwapInstruction::AcceptOffer).unwrap();
+    let accept_offer_ix_data = borsh::to_vec(&SwapInstruction::AcceptOffer {
+        close_account: false,
+        min_receive_amount: None,
+        max_send_amount: None,
+    })
+    .unwrap();
 
     let accept_offer_accounts = vec![
         AccountMeta::new(taker.pubkey(), true),
-        AccountMeta::new(offer_account_pubkey, true),
-        AccountMeta::new_readonly(maker.pubkey(), false),
+        AccountMeta::new(offer_account_pubkey, false),
+        AccountMeta::new_readonly(maker.pubkey(), true),
         AccountMeta::new(maker_offer_token_account, false),
         AccountMeta::new(taker_receive_token_account, false),
         AccountMeta::new_readonly(offer_token_mint, false),
         AccountMeta::new_readonly(receive_token_mint, false),
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(mint_pause_pda(&offer_token_mint).0, false),
+        AccountMeta::new_readonly(mint_pause_pda(&receive_token_mint).0, false),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new_readonly(config_pda().0, false),
+        AccountMeta::new(settlement_pda(&offer_account_pubkey).0, false),
+        AccountMeta::new(
+            order_book_pda(&offer_token_mint, &receive_token_mint).0,
+            false,
+        ),
         AccountMeta::new(maker_sol_account, false),
         AccountMeta::new(taker_sol_account, false),
     ];
@@ -820,15 +1532,20 @@ async fn test_accept_offer_expired() {
         )
     );
 
-    // Verify offer status is updated to Expired
+    // The failed transaction rolls back every write, including the lazy `Expired` mark,
+    // so the offer is still stored as Active and nothing was swapped.
     let offer_account = context
         .0
         .get_account(offer_account_pubkey)
         .await
         .unwrap()
         .unwrap();
-    let offer_data = Offer::try_from_slice(&offer_account.data).unwrap();
-    assert_eq!(offer_data.status, OfferStatus::Expired);
+    let offer_data = Offer::load(&offer_account.data).unwrap();
+    assert_eq!(offer_data.status, OfferStatus::Active);
+    assert_eq!(
+        get_token_balance(&mut context, &maker_offer_token_account).await,
+        100
+    );
 
     msg!("test_accept_offer_expired PASSED");
 }
@@ -849,11 +1566,18 @@ async fn test_counter_offer_success() {
         },
     );
 
-    let mut context = program_test.start().await;
+    let program_context = program_test.start_with_context().await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
 
     let maker = Keypair::new();
     let taker = Keypair::new();
     let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
     let maker_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
     let taker_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
 
@@ -896,7 +1620,7 @@ async fn test_counter_offer_success() {
     let initial_receive_token_amount = 5;
     let offer_type = OfferType::PublicSell;
 
-    let (original_offer_account_pubkey, original_bump_seed) = Pubkey::find_program_address(
+    let (original_offer_account_pubkey, _) = Pubkey::find_program_address(
         &[
             b"offer",
             maker.pubkey().as_ref(),
@@ -911,7 +1635,14 @@ async fn test_counter_offer_success() {
         offer_token_amount: initial_offer_token_amount,
         receive_token_amount: initial_receive_token_amount,
         expiration: None,
-        bump_seed: original_bump_seed,
+        min_fill: 0,
+        duration_secs: None,
+        receive_recipient: None,
+        confirm_first: false,
+        nonce: 0,
+        start_time: None,
+        client_id: [0; 8],
+        auto_cancel_on_fail: false,
     })
     .unwrap();
 
@@ -924,6 +1655,19 @@ async fn test_counter_offer_success() {
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+        AccountMeta::new_readonly(mint_pause_pda(&maker_token_mint).0, false),
+        AccountMeta::new_readonly(mint_pause_pda(&taker_token_mint).0, false),
+        AccountMeta::new_readonly(config_pda().0, false),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new(maker.pubkey(), false), // fee_collector (unused without a create fee)
+        AccountMeta::new(
+            order_book_pda(&maker_token_mint, &taker_token_mint).0,
+            false,
+        ),
+        AccountMeta::new(
+            recent_cancel_pda(&maker.pubkey(), &maker_token_mint, &taker_token_mint).0,
+            false,
+        ),
         AccountMeta::new(maker_sol_account, false),
     ];
 
@@ -938,12 +1682,21 @@ async fn test_counter_offer_success() {
     // Only sign with payer and maker (maker is the only signer in the instruction)
     transaction.sign(&[&context.1, &maker], context.2); // Only sign with actual signers
     context.0.process_transaction(transaction).await.unwrap();
+    let created = get_offer(&mut context, &original_offer_account_pubkey).await;
+    assert_eq!(created.last_updated_at, created.created_at);
+
+    // The taker takes a minute to think it over.
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 60,
+        ..clock
+    });
 
     // Taker makes a counter-offer: offers 7 taker_token_mint for 10 maker_token_mint
     let counter_offer_token_amount = 7;
     let counter_receive_token_amount = 10;
 
-    let (new_offer_account_pubkey, new_bump_seed) = Pubkey::find_program_address(
+    let (new_offer_account_pubkey, _) = Pubkey::find_program_address(
         &[
             b"offer",
             taker.pubkey().as_ref(),   // Counter-maker is now taker
@@ -957,13 +1710,13 @@ async fn test_counter_offer_success() {
         offer_token_amount: counter_offer_token_amount,
         receive_token_amount: counter_receive_token_amount,
         expiration: None,
-        bump_seed: new_bump_seed,
+        start_time: None,
     })
     .unwrap();
 
     let counter_offer_accounts = vec![
         AccountMeta::new(taker.pubkey(), true), // counter_maker_account (signer)
-        AccountMeta::new(original_offer_account_pubkey, true), // original_offer_account (writable)
+        AccountMeta::new(original_offer_account_pubkey, false), // original_offer_account (writable)
         AccountMeta::new(new_offer_account_pubkey, false), // new_offer_account (writable, PDA)
         AccountMeta::new(taker_offer_token_account, false), // counter_maker_token_account (writable)
         AccountMeta::new_readonly(taker_token_mint, false), // offer_token_mint (for counter)
@@ -971,6 +1724,17 @@ async fn test_counter_offer_success() {
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+        AccountMeta::new_readonly(config_pda().0, false),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new(maker_index_pda(&taker.pubkey()).0, false),
+        AccountMeta::new(
+            order_book_pda(&maker_token_mint, &taker_token_mint).0,
+            false,
+        ), // original_order_book
+        AccountMeta::new(
+            order_book_pda(&taker_token_mint, &maker_token_mint).0,
+            false,
+        ), // counter_order_book
         AccountMeta::new(taker_sol_account, false), // counter_maker_sol_account (if offering SOL in counter)
         AccountMeta::new(maker_sol_account, false), // original_maker_sol_account (for refund if original had SOL escrow)
     ];
@@ -994,8 +1758,13 @@ async fn test_counter_offer_success() {
         .await
         .unwrap()
         .unwrap();
-    let original_offer_data = Offer::try_from_slice(&original_offer_account.data).unwrap();
+    let original_offer_data = Offer::load(&original_offer_account.data).unwrap();
     assert_eq!(original_offer_data.status, OfferStatus::Countered);
+    assert_eq!(original_offer_data.created_at, created.created_at);
+    assert_eq!(
+        original_offer_data.last_updated_at,
+        created.last_updated_at + 60
+    );
 
     // Verify new counter-offer account was created and contains correct data
     let new_offer_account = context
@@ -1009,7 +1778,7 @@ async fn test_counter_offer_success() {
         Pubkey::from_str("HpddKoiN2TNaJ8ZdWRVNbgLuAKop4JzYuEGPAM45agk8").unwrap()
     );
 
-    let new_offer_data = Offer::try_from_slice(&new_offer_account.data).unwrap();
+    let new_offer_data = Offer::load(&new_offer_account.data).unwrap();
     assert_eq!(new_offer_data.offer_type, OfferType::PublicSell); // Type remains same as original
     assert_eq!(new_offer_data.status, OfferStatus::Active);
     assert_eq!(new_offer_data.maker, taker.pubkey()); // Taker is now the maker of the counter-offer
@@ -1023,11 +1792,10234 @@ async fn test_counter_offer_success() {
         new_offer_data.receive_token_amount,
         counter_receive_token_amount
     );
-    assert_eq!(new_offer_data.is_counter_offer, true);
+    assert!(new_offer_data.is_counter_offer);
     assert_eq!(
         new_offer_data.original_offer_id,
         Some(original_offer_account_pubkey)
     );
+    assert_eq!(new_offer_data.created_at, created.created_at + 60);
 
     msg!("test_counter_offer_success PASSED");
 }
+
+#[tokio::test]
+async fn test_accept_offer_wrong_mint_accounts() {
+    let program_id = Pubkey::from_str("HpddKoiN2TNaJ8ZdWRVNbgLuAKop4JzYuEGPAM45agk8").unwrap();
+    let program_test = ProgramTest::new("soffer", program_id, processor!(Processor::process));
+    let mut context = program_test.start().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let other_mint = create_mint(&mut context, &mint_authority, None, 0).await; // A decoy token type
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+
+    let maker_offer_token_account =
+        create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_offer_token_account,
+        &mint_authority,
+        100,
+    )
+    .await;
+
+    // Maker sells 10 real tokens for 5 SOL.
+    let (offer_account_pubkey, _) = Pubkey::find_program_address(
+        &[
+            b"offer",
+            maker.pubkey().as_ref(),
+            offer_token_mint.as_ref(),
+            receive_token_mint.as_ref(),
+        ],
+        &program_id,
+    );
+    let create_offer_ix = Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new(offer_account_pubkey, false),
+            AccountMeta::new(maker_offer_token_account, false),
+            AccountMeta::new_readonly(offer_token_mint, false),
+            AccountMeta::new_readonly(receive_token_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+            AccountMeta::new_readonly(mint_pause_pda(&offer_token_mint).0, false),
+            AccountMeta::new_readonly(mint_pause_pda(&receive_token_mint).0, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+            AccountMeta::new(maker.pubkey(), false), // fee_collector (unused without a create fee)
+            AccountMeta::new(
+                order_book_pda(&offer_token_mint, &receive_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(
+                recent_cancel_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(maker.pubkey(), false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 5_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        })
+        .unwrap(),
+    };
+    let mut transaction =
+        Transaction::new_with_payer(&[create_offer_ix], Some(&context.1.pubkey()));
+    transaction.sign(&[&context.1, &maker], context.2);
+    context.0.process_transaction(transaction).await.unwrap();
+
+    // The taker passes the decoy mint everywhere, so the token accounts agree with the
+    // mint accounts, but none of it matches what the offer was made for.
+    let maker_decoy_token_account = create_token_account(&mut context, &maker, &other_mint).await;
+    mint_to(
+        &mut context,
+        &other_mint,
+        &maker_decoy_token_account,
+        &mint_authority,
+        100,
+    )
+    .await;
+    let taker_decoy_token_account = create_token_account(&mut context, &taker, &other_mint).await;
+
+    let accept_offer_ix = Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(taker.pubkey(), true),
+            AccountMeta::new(offer_account_pubkey, false),
+            AccountMeta::new_readonly(maker.pubkey(), true),
+            AccountMeta::new(maker_decoy_token_account, false),
+            AccountMeta::new(taker_decoy_token_account, false),
+            AccountMeta::new_readonly(other_mint, false), // Not the offer's token!
+            AccountMeta::new_readonly(receive_token_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(mint_pause_pda(&other_mint).0, false),
+            AccountMeta::new_readonly(mint_pause_pda(&receive_token_mint).0, false),
+            AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(settlement_pda(&offer_account_pubkey).0, false),
+            AccountMeta::new(
+                order_book_pda(&offer_token_mint, &receive_token_mint).0,
+                false,
+            ),
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new(taker.pubkey(), false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        })
+        .unwrap(),
+    };
+    let mut transaction =
+        Transaction::new_with_payer(&[accept_offer_ix], Some(&context.1.pubkey()));
+    transaction.sign(&[&context.1, &taker, &maker], context.2);
+
+    let err = context
+        .0
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.unwrap(),
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(SwapError::TokenMismatch as u32)
+        )
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &maker_decoy_token_account).await,
+        100
+    ); // Nothing moved
+    msg!("test_accept_offer_wrong_mint_accounts PASSED");
+}
+
+#[tokio::test]
+async fn test_partial_fill_respects_min_fill() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        17,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    // Maker sells 17 tokens for 17_000 lamports, but nobody may take fewer than 10 at a time.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 17,
+            receive_token_amount: 17_000,
+            expiration: None,
+            min_fill: 10,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let partial_accept = |fill_amount| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &offer_token_mint,
+            &receive_token_mint,
+            SwapInstruction::AcceptOfferPartial { fill_amount },
+        )
+    };
+
+    // A 5-token nibble is below the minimum.
+    let err = send(&mut context, &[partial_accept(5)], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::FillTooSmall));
+
+    // Taking 10 is fine and leaves 7 behind.
+    send(&mut context, &[partial_accept(10)], &[&taker, &maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::PartiallyFilled);
+    assert_eq!(offer.offer_token_amount, 7);
+    assert_eq!(offer.receive_token_amount, 7_000);
+
+    // The last 7 are below the minimum, but they're everything that's left, so it's allowed.
+    send(&mut context, &[partial_accept(7)], &[&taker, &maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    assert_eq!(offer.offer_token_amount, 0);
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        17
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &taker.pubkey()).await,
+        1_000_000_000 - 17_000
+    );
+    msg!("test_partial_fill_respects_min_fill PASSED");
+}
+
+#[tokio::test]
+async fn test_get_version() {
+    let mut context = start_program().await;
+
+    let get_version_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![],
+        data: borsh::to_vec(&SwapInstruction::GetVersion).unwrap(),
+    };
+    let return_data = simulate_return_data(&mut context, &[get_version_ix]).await;
+    let version: ProgramVersion = borsh::from_slice(&return_data).unwrap();
+
+    assert_eq!(version, PROGRAM_VERSION);
+    assert_eq!(version.major.to_string(), env!("CARGO_PKG_VERSION_MAJOR"));
+    assert_eq!(version.minor.to_string(), env!("CARGO_PKG_VERSION_MINOR"));
+    assert_eq!(version.patch.to_string(), env!("CARGO_PKG_VERSION_PATCH"));
+    msg!("test_get_version PASSED");
+}
+
+#[tokio::test]
+async fn test_cancel_refunds_escrow_after_rent_change() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = spl_token::native_mint::id(); // Maker offers SOL
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // Maker puts 5 SOL in escrow for 10 tokens.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 5_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let rent = context.0.get_rent().await.unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.rent_reserved, rent.minimum_balance(Offer::MAX_LEN));
+
+    // Someone tips the locker, and then rent gets twice as expensive.
+    fund_account(&mut context, &offer_account, 1_000_000).await;
+    program_context.set_sysvar(&solana_program::rent::Rent {
+        lamports_per_byte_year: rent.lamports_per_byte_year * 2,
+        ..rent
+    });
+    assert_ne!(
+        context
+            .0
+            .get_rent()
+            .await
+            .unwrap()
+            .minimum_balance(Offer::MAX_LEN),
+        offer.rent_reserved
+    );
+
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &offer_token_mint,
+            &receive_token_mint,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+
+    // The locker keeps exactly what was reserved at creation; everything else goes back.
+    assert_eq!(
+        get_sol_balance(&mut context, &offer_account).await,
+        offer.rent_reserved
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 5_000_000_000 + 1_000_000
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Declined);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    msg!("test_cancel_refunds_escrow_after_rent_change PASSED");
+}
+
+#[tokio::test]
+async fn test_get_statuses() {
+    let mut context = start_program().await;
+
+    let mint_authority = Keypair::new();
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // Two makers want tokens for SOL; one of them changes their mind.
+    let mut buy_offers = vec![];
+    for _ in 0..2 {
+        let maker = Keypair::new();
+        fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+        let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+        let create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &Pubkey::new_unique(),
+            &sol,
+            &token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicBuy,
+                offer_token_amount: 1_000,
+                receive_token_amount: 1,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+        buy_offers.push((maker, offer_account));
+    }
+    let (active_offer, declined_offer) = (buy_offers[0].1, buy_offers[1].1);
+    let cancel_ix = cancel_offer_ix(
+        &buy_offers[1].0.pubkey(),
+        &declined_offer,
+        &sol,
+        &token_mint,
+    );
+    send(&mut context, &[cancel_ix], &[&buy_offers[1].0])
+        .await
+        .unwrap();
+
+    // A third maker sells tokens for SOL, and a taker takes all of them.
+    let seller = Keypair::new();
+    let taker = Keypair::new();
+    fund_account(&mut context, &seller.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let seller_token_account = create_token_account(&mut context, &seller, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &seller_token_account,
+        &mint_authority,
+        5,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    let (accepted_offer, _) = offer_pda(&seller.pubkey(), &token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &seller.pubkey(),
+        &seller_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 5,
+            receive_token_amount: 5_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&seller]).await.unwrap();
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &accepted_offer,
+        &seller.pubkey(),
+        &seller_token_account,
+        &taker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    send(&mut context, &[accept_ix], &[&taker, &seller])
+        .await
+        .unwrap();
+
+    // Ask for all three, with a token mint mixed in that should be skipped.
+    let get_statuses_ix = |offers: &[Pubkey]| Instruction {
+        program_id: program_id(),
+        accounts: offers
+            .iter()
+            .map(|offer| AccountMeta::new_readonly(*offer, false))
+            .collect(),
+        data: borsh::to_vec(&SwapInstruction::GetStatuses).unwrap(),
+    };
+    let return_data = simulate_return_data(
+        &mut context,
+        &[get_statuses_ix(&[
+            active_offer,
+            token_mint,
+            declined_offer,
+            accepted_offer,
+        ])],
+    )
+    .await;
+    let statuses: Vec<(Pubkey, OfferStatus)> = borsh::from_slice(&return_data).unwrap();
+    assert_eq!(
+        statuses,
+        vec![
+            (active_offer, OfferStatus::Active),
+            (declined_offer, OfferStatus::Declined),
+            (accepted_offer, OfferStatus::Accepted),
+        ]
+    );
+
+    // Asking about more offers than fit in the return data is refused.
+    let too_many = vec![active_offer; MAX_STATUS_BATCH + 1];
+    let err = send(&mut context, &[get_statuses_ix(&too_many)], &[])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+    msg!("test_get_statuses PASSED");
+}
+
+#[tokio::test]
+async fn test_counter_offer_collision() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // The maker "counters" their own offer with the same mints, so the new PDA is the old one.
+    let counter_ix = counter_offer_ix(
+        &maker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CounterOffer {
+            offer_token_amount: 10,
+            receive_token_amount: 2_000,
+            expiration: None,
+            start_time: None,
+        },
+    );
+    assert_eq!(counter_ix.accounts[1].pubkey, counter_ix.accounts[2].pubkey);
+    let err = send(&mut context, &[counter_ix], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::OfferCollision));
+
+    // The original offer is untouched.
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    assert_eq!(offer.receive_token_amount, 1_000);
+    msg!("test_counter_offer_collision PASSED");
+}
+
+#[tokio::test]
+async fn test_offers_on_same_pair_with_different_nonces() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 5_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &taker_token_account,
+        &mint_authority,
+        5,
+    )
+    .await;
+
+    // Two bids for the same token at different prices, told apart by their nonce.
+    let mut offers = vec![];
+    for (nonce, lamports) in [(0, 1_000_000_000), (1, 1_500_000_000)] {
+        let (offer_account, _) = offer_pda_with_nonce(&maker.pubkey(), &sol, &token_mint, nonce);
+        let mut create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &Pubkey::new_unique(),
+            &sol,
+            &token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicBuy,
+                offer_token_amount: lamports,
+                receive_token_amount: 5,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        create_ix.accounts[1].pubkey = offer_account;
+        send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+        offers.push(offer_account);
+    }
+    assert_ne!(offers[0], offers[1]);
+    assert_eq!(offers[0], offer_pda(&maker.pubkey(), &sol, &token_mint).0);
+    let offer = get_offer(&mut context, &offers[1]).await;
+    assert_eq!(offer.nonce, 1);
+    assert_eq!(offer.escrow_sol_amount, 1_500_000_000);
+
+    // The taker sells into the better bid...
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offers[1],
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &sol,
+        &token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        5
+    );
+    let offer = get_offer(&mut context, &offers[1]).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+
+    // ...and the maker pulls the other one, which was never touched.
+    let offer = get_offer(&mut context, &offers[0]).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    assert_eq!(offer.escrow_sol_amount, 1_000_000_000);
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offers[0],
+            &sol,
+            &token_mint,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    let offer = get_offer(&mut context, &offers[0]).await;
+    assert_eq!(offer.status, OfferStatus::Declined);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    msg!("test_offers_on_same_pair_with_different_nonces PASSED");
+}
+
+#[tokio::test]
+async fn test_offers_keep_the_client_id_they_were_made_with() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 5_000_000_000).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = Pubkey::new_unique();
+
+    // Two front-ends post a bid each for the same maker, and tag it as theirs.
+    for (nonce, client_id) in [(0, *b"wallet-a"), (1, *b"dex-ui-b")] {
+        let (offer_account, _) = offer_pda_with_nonce(&maker.pubkey(), &sol, &token_mint, nonce);
+        let mut create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &Pubkey::new_unique(),
+            &sol,
+            &token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicBuy,
+                offer_token_amount: 1_000_000_000,
+                receive_token_amount: 5,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce,
+                start_time: None,
+                client_id,
+                auto_cancel_on_fail: false,
+            },
+        );
+        create_ix.accounts[1].pubkey = offer_account;
+        send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+        let offer = get_offer(&mut context, &offer_account).await;
+        assert_eq!(offer.client_id, client_id);
+    }
+    msg!("test_offers_keep_the_client_id_they_were_made_with PASSED");
+}
+
+#[tokio::test]
+async fn test_replace_offer_with_different_receive_mint() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let stranger = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &stranger.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let other_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (old_offer, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // The maker would rather have 5 of the other token than SOL.
+    let replace_ix = |maker: &Pubkey, maker_token_account: &Pubkey| {
+        let mut ix = create_offer_ix(
+            maker,
+            maker_token_account,
+            &offer_token_mint,
+            &other_mint,
+            SwapInstruction::ReplaceOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 5,
+                expiration: None,
+                min_fill: 0,
+                receive_recipient: None,
+                nonce: 0,
+            },
+        );
+        ix.accounts.splice(
+            0..0,
+            [
+                AccountMeta::new(old_offer, false),
+                AccountMeta::new(order_book_pda(&offer_token_mint, &sol).0, false),
+                AccountMeta::new(recent_cancel_pda(maker, &offer_token_mint, &sol).0, false),
+            ],
+        );
+        ix
+    };
+
+    // Someone else can't replace the maker's offer with their own.
+    let stranger_token_account =
+        create_token_account(&mut context, &stranger, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &stranger_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let err = send(
+        &mut context,
+        &[replace_ix(&stranger.pubkey(), &stranger_token_account)],
+        &[&stranger],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+
+    send(
+        &mut context,
+        &[replace_ix(&maker.pubkey(), &maker_token_account)],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+
+    // The old offer is gone, rent and all...
+    assert!(context.0.get_account(old_offer).await.unwrap().is_none());
+    // ...and the new one is up, on the new pair.
+    let (new_offer, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &other_mint);
+    let offer = get_offer(&mut context, &new_offer).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    assert_eq!(offer.receive_token_mint, other_mint);
+    assert_eq!(offer.offer_token_amount, 10);
+    assert_eq!(offer.receive_token_amount, 5);
+    let index_account = context
+        .0
+        .get_account(maker_index_pda(&maker.pubkey()).0)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        borsh::from_slice::<MakerIndex>(&index_account.data)
+            .unwrap()
+            .active_offers,
+        1
+    );
+    msg!("test_replace_offer_with_different_receive_mint PASSED");
+}
+
+#[tokio::test]
+async fn test_pause_single_mint() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let mint_authority = Keypair::new();
+    let taker = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[initialize_config_ix(&admin.pubkey())],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let paused_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let healthy_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // A maker selling `mint` for SOL, with tokens ready to go.
+    let mut sellers = vec![];
+    for mint in [paused_mint, paused_mint, healthy_mint] {
+        let maker = Keypair::new();
+        fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+        let maker_token_account = create_token_account(&mut context, &maker, &mint).await;
+        mint_to(
+            &mut context,
+            &mint,
+            &maker_token_account,
+            &mint_authority,
+            10,
+        )
+        .await;
+        sellers.push((maker, maker_token_account, mint));
+    }
+    let create_ix = |(maker, maker_token_account, mint): &(Keypair, Pubkey, Pubkey)| {
+        create_offer_ix(
+            &maker.pubkey(),
+            maker_token_account,
+            mint,
+            &sol,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 1_000,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+    let taker_paused_account = create_token_account(&mut context, &taker, &paused_mint).await;
+    let taker_healthy_account = create_token_account(&mut context, &taker, &healthy_mint).await;
+    let accept_ix = |(maker, maker_token_account, mint): &(Keypair, Pubkey, Pubkey),
+                     taker_token_account: &Pubkey| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            &offer_pda(&maker.pubkey(), mint, &sol).0,
+            &maker.pubkey(),
+            maker_token_account,
+            taker_token_account,
+            mint,
+            &sol,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )
+    };
+
+    // One offer on the soon-to-be-paused mint is already out there.
+    send(&mut context, &[create_ix(&sellers[0])], &[&sellers[0].0])
+        .await
+        .unwrap();
+
+    // Only the admin can pause.
+    let err = send(
+        &mut context,
+        &[pause_mint_ix(&taker.pubkey(), &paused_mint)],
+        &[&taker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+    send(
+        &mut context,
+        &[pause_mint_ix(&admin.pubkey(), &paused_mint)],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    // No new offers or accepts on the paused mint...
+    let err = send(&mut context, &[create_ix(&sellers[1])], &[&sellers[1].0])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::ProgramPaused));
+    let err = send(
+        &mut context,
+        &[accept_ix(&sellers[0], &taker_paused_account)],
+        &[&taker, &sellers[0].0],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::ProgramPaused));
+
+    // ...but the other mint trades as usual.
+    send(&mut context, &[create_ix(&sellers[2])], &[&sellers[2].0])
+        .await
+        .unwrap();
+    send(
+        &mut context,
+        &[accept_ix(&sellers[2], &taker_healthy_account)],
+        &[&taker, &sellers[2].0],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_healthy_account).await,
+        10
+    );
+
+    // Cancels still go through so people can walk away.
+    let (paused_offer, _) = offer_pda(&sellers[0].0.pubkey(), &paused_mint, &sol);
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &sellers[0].0.pubkey(),
+            &paused_offer,
+            &paused_mint,
+            &sol,
+        )],
+        &[&sellers[0].0],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_offer(&mut context, &paused_offer).await.status,
+        OfferStatus::Declined
+    );
+
+    // After unpausing, the mint is back in business.
+    send(
+        &mut context,
+        &[unpause_mint_ix(&admin.pubkey(), &paused_mint)],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    assert!(
+        context
+            .0
+            .get_account(mint_pause_pda(&paused_mint).0)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    send(&mut context, &[create_ix(&sellers[1])], &[&sellers[1].0])
+        .await
+        .unwrap();
+    msg!("test_pause_single_mint PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_maps_spl_insufficient_funds() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let maker_stash = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // Sell offers don't lock the tokens up, so the maker can move most of them away.
+    let move_away_ix = spl_token::instruction::transfer(
+        &spl_token::id(),
+        &maker_token_account,
+        &maker_stash,
+        &maker.pubkey(),
+        &[],
+        8,
+    )
+    .unwrap();
+    send(&mut context, &[move_away_ix], &[&maker])
+        .await
+        .unwrap();
+
+    // The token program refuses the transfer, and we report it as our own error.
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    let err = send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InsufficientFunds));
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        0
+    );
+    msg!("test_accept_maps_spl_insufficient_funds PASSED");
+}
+
+#[tokio::test]
+async fn test_token_for_token_accept_needs_maker_receive_account() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    let maker_offer_mint_stash =
+        create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+    let taker_receive_account =
+        create_token_account(&mut context, &taker, &receive_token_mint).await;
+    mint_to(
+        &mut context,
+        &receive_token_mint,
+        &taker_receive_account,
+        &mint_authority,
+        20,
+    )
+    .await;
+
+    // 10 of one token for 20 of another.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 20,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let accept_ix = |maker_receive_account: Option<Pubkey>| {
+        let mut ix = accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &offer_token_mint,
+            &receive_token_mint,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        );
+        // Slots 15 and 16 hold the received mint's token accounts instead of wallets.
+        ix.accounts.truncate(15);
+        if let Some(maker_receive_account) = maker_receive_account {
+            ix.accounts
+                .push(AccountMeta::new(maker_receive_account, false));
+            ix.accounts
+                .push(AccountMeta::new(taker_receive_account, false));
+        }
+        ix
+    };
+
+    // Without somewhere for the maker to be paid, the accept is refused outright...
+    let err = send(&mut context, &[accept_ix(None)], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MissingRequiredAccount));
+
+    // ...and so it is with a maker account for the wrong mint.
+    let err = send(
+        &mut context,
+        &[accept_ix(Some(maker_offer_mint_stash))],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::TokenMismatch));
+
+    // Nothing moved, and the offer is still up.
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        10
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_receive_account).await,
+        20
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    msg!("test_token_for_token_accept_needs_maker_receive_account PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_token_for_token_swap() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    let maker_receive_account =
+        create_token_account(&mut context, &maker, &receive_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+    let taker_receive_account =
+        create_token_account(&mut context, &taker, &receive_token_mint).await;
+    mint_to(
+        &mut context,
+        &receive_token_mint,
+        &taker_receive_account,
+        &mint_authority,
+        20,
+    )
+    .await;
+
+    // 10 of one token for 20 of another, no SOL on either side.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 20,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let maker_sol_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+
+    let mut accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    accept_ix.accounts.truncate(15);
+    accept_ix
+        .accounts
+        .push(AccountMeta::new(maker_receive_account, false));
+    accept_ix
+        .accounts
+        .push(AccountMeta::new(taker_receive_account, false));
+    send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+
+    // Each side's tokens went to the other, and no SOL changed hands.
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        0
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        10
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_receive_account).await,
+        0
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &maker_receive_account).await,
+        20
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_sol_before
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    msg!("test_accept_token_for_token_swap PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_rejects_trade_outside_slippage_bounds() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 2_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    // 10 tokens for 1 SOL.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let accept_ix = |min_receive_amount: Option<u64>, max_send_amount: Option<u64>| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &offer_token_mint,
+            &sol,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount,
+                max_send_amount,
+            },
+        )
+    };
+
+    // The taker only signed up to pay half a SOL...
+    let err = send(
+        &mut context,
+        &[accept_ix(None, Some(500_000_000))],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::SlippageExceeded));
+
+    // ...or expected more tokens than are on offer.
+    let err = send(
+        &mut context,
+        &[accept_ix(Some(11), None)],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::SlippageExceeded));
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+
+    // Exactly at the bounds, it goes through.
+    send(
+        &mut context,
+        &[accept_ix(Some(10), Some(1_000_000_000))],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        10
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    msg!("test_accept_rejects_trade_outside_slippage_bounds PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_rejects_frozen_taker_account() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 2_000_000_000).await;
+    let offer_token_mint = create_mint(
+        &mut context,
+        &mint_authority,
+        Some(&mint_authority.pubkey()),
+        0,
+    )
+    .await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    // 10 tokens for 1 SOL.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // The mint's freeze authority locks the taker's account before they accept.
+    let freeze_ix = spl_token::instruction::freeze_account(
+        &spl_token::id(),
+        &taker_token_account,
+        &offer_token_mint,
+        &mint_authority.pubkey(),
+        &[],
+    )
+    .unwrap();
+    send(&mut context, &[freeze_ix], &[&mint_authority])
+        .await
+        .unwrap();
+
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &offer_token_mint,
+        &sol,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    let err = send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::AccountFrozen));
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    assert_eq!(
+        get_sol_balance(&mut context, &taker.pubkey()).await,
+        2_000_000_000
+    );
+    msg!("test_accept_rejects_frozen_taker_account PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_with_maker_signed_approval() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 2_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    // 10 tokens for 1 SOL, with the offer as the maker's delegate for them.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    let approve_ix = spl_token::instruction::approve(
+        &spl_token::id(),
+        &maker_token_account,
+        &offer_account,
+        &maker.pubkey(),
+        &[],
+        10,
+    )
+    .unwrap();
+    send(&mut context, &[create_ix, approve_ix], &[&maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+
+    // The maker signs the terms off-chain.
+    let approval = AcceptApproval {
+        offer: offer_account,
+        taker: taker.pubkey(),
+        fill_amount: None,
+        offer_token_amount: offer.offer_token_amount,
+        receive_token_amount: offer.receive_token_amount,
+        offer_last_updated_at: offer.last_updated_at,
+        expires_at: offer.created_at + 3600,
+    };
+    let message = borsh::to_vec(&approval).unwrap();
+    let signature: [u8; 64] = maker.sign_message(&message).into();
+    let verify_ix = solana_ed25519_program::new_ed25519_instruction_with_signature(
+        &message,
+        &signature,
+        &maker.pubkey().to_bytes(),
+    );
+
+    // Only the taker signs; the maker is just along for the accounts.
+    let accept_ix = |approval: AcceptApproval| {
+        let mut ix = accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &offer_token_mint,
+            &sol,
+            SwapInstruction::AcceptWithApproval { approval },
+        );
+        ix.accounts[2].is_signer = false;
+        ix.accounts.insert(
+            0,
+            AccountMeta::new_readonly(solana_program::sysvar::instructions::ID, false),
+        );
+        ix
+    };
+
+    // Terms the maker never signed (a later deadline) are refused...
+    let tampered = AcceptApproval {
+        expires_at: approval.expires_at + 86_400,
+        ..approval
+    };
+    let err = send(
+        &mut context,
+        &[verify_ix.clone(), accept_ix(tampered)],
+        &[&taker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(SwapError::InvalidApproval as u32)
+        )
+    );
+
+    // ...as is the signed approval without the signature check in front of it.
+    let err = send(&mut context, &[accept_ix(approval)], &[&taker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidApproval));
+
+    // The real thing trades with just the taker signing.
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(
+        &mut context,
+        &[verify_ix.clone(), accept_ix(approval)],
+        &[&taker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        10
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_000_000_000
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+
+    // And it's spent: the offer isn't live any more.
+    let err = send(&mut context, &[verify_ix, accept_ix(approval)], &[&taker])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, TransactionError::InstructionError(1, _)));
+    msg!("test_accept_with_maker_signed_approval PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_refuses_to_overflow_recipient_balance() {
+    let mut program_context =
+        ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+            .start_with_context()
+            .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    // The taker's account already holds nearly all a `u64` can.
+    let mut account = context
+        .0
+        .get_account(taker_token_account)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut token_account = TokenAccount::unpack(&account.data).unwrap();
+    token_account.amount = u64::MAX - 5;
+    TokenAccount::pack(token_account, &mut account.data).unwrap();
+    program_context.set_account(&taker_token_account, &account.into());
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &offer_token_mint,
+        &sol,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    let err = send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MathOverflow));
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        10
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    msg!("test_accept_refuses_to_overflow_recipient_balance PASSED");
+}
+
+#[tokio::test]
+async fn test_max_active_offers_per_maker() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::MaxActiveOffersPerMaker(2)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    // The maker wants three different tokens for SOL, one offer each.
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let mut wanted_mints = vec![];
+    for _ in 0..3 {
+        wanted_mints.push(create_mint(&mut context, &mint_authority, None, 0).await);
+    }
+    let create_ix = |receive_token_mint: &Pubkey| {
+        create_offer_ix(
+            &maker.pubkey(),
+            &Pubkey::new_unique(),
+            &sol,
+            receive_token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicBuy,
+                offer_token_amount: 1_000,
+                receive_token_amount: 1,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+    let active_offers = |account: solana_sdk::account::Account| {
+        borsh::from_slice::<MakerIndex>(&account.data)
+            .unwrap()
+            .active_offers
+    };
+    let maker_index = maker_index_pda(&maker.pubkey()).0;
+
+    send(&mut context, &[create_ix(&wanted_mints[0])], &[&maker])
+        .await
+        .unwrap();
+    send(&mut context, &[create_ix(&wanted_mints[1])], &[&maker])
+        .await
+        .unwrap();
+    let index_account = context.0.get_account(maker_index).await.unwrap().unwrap();
+    assert_eq!(active_offers(index_account), 2);
+
+    // A third one at the same time is one too many.
+    let err = send(&mut context, &[create_ix(&wanted_mints[2])], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::TooManyOffers));
+
+    // Once one is cancelled it stops counting, and there's room again.
+    let (first_offer, _) = offer_pda(&maker.pubkey(), &sol, &wanted_mints[0]);
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &first_offer,
+            &sol,
+            &wanted_mints[0],
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    send(&mut context, &[create_ix(&wanted_mints[2])], &[&maker])
+        .await
+        .unwrap();
+    let index_account = context.0.get_account(maker_index).await.unwrap().unwrap();
+    assert_eq!(active_offers(index_account), 2);
+    msg!("test_max_active_offers_per_maker PASSED");
+}
+
+#[tokio::test]
+async fn test_match_mirror_offers() {
+    let mut context = start_program().await;
+
+    let maker_a = Keypair::new();
+    let maker_b = Keypair::new();
+    let matcher = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker_a.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker_b.pubkey(), 1_000_000_000).await;
+    let mint_x = create_mint(&mut context, &mint_authority, None, 0).await;
+    let mint_y = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // A has 10 X and wants 4 Y; B has 4 Y and wants 10 X.
+    let a_x = create_token_account(&mut context, &maker_a, &mint_x).await;
+    let a_y = create_token_account(&mut context, &maker_a, &mint_y).await;
+    let b_y = create_token_account(&mut context, &maker_b, &mint_y).await;
+    let b_x = create_token_account(&mut context, &maker_b, &mint_x).await;
+    mint_to(&mut context, &mint_x, &a_x, &mint_authority, 10).await;
+    mint_to(&mut context, &mint_y, &b_y, &mint_authority, 4).await;
+
+    let (offer_a, _) = offer_pda(&maker_a.pubkey(), &mint_x, &mint_y);
+    let (offer_b, _) = offer_pda(&maker_b.pubkey(), &mint_y, &mint_x);
+    let create_a = create_offer_ix(
+        &maker_a.pubkey(),
+        &a_x,
+        &mint_x,
+        &mint_y,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 4,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    let create_b = create_offer_ix(
+        &maker_b.pubkey(),
+        &b_y,
+        &mint_y,
+        &mint_x,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 4,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_a], &[&maker_a]).await.unwrap();
+    send(&mut context, &[create_b], &[&maker_b]).await.unwrap();
+
+    let match_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(matcher.pubkey(), true),
+            AccountMeta::new(offer_a, false),
+            AccountMeta::new(offer_b, false),
+            AccountMeta::new_readonly(maker_a.pubkey(), true),
+            AccountMeta::new_readonly(maker_b.pubkey(), true),
+            AccountMeta::new(a_x, false),
+            AccountMeta::new(a_y, false),
+            AccountMeta::new(b_y, false),
+            AccountMeta::new(b_x, false),
+            AccountMeta::new_readonly(mint_x, false),
+            AccountMeta::new_readonly(mint_y, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(mint_pause_pda(&mint_x).0, false),
+            AccountMeta::new_readonly(mint_pause_pda(&mint_y).0, false),
+            AccountMeta::new(maker_index_pda(&maker_a.pubkey()).0, false),
+            AccountMeta::new(maker_index_pda(&maker_b.pubkey()).0, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(order_book_pda(&mint_x, &mint_y).0, false),
+            AccountMeta::new(order_book_pda(&mint_y, &mint_x).0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::MatchOffers).unwrap(),
+    };
+    send(
+        &mut context,
+        std::slice::from_ref(&match_ix),
+        &[&matcher, &maker_a, &maker_b],
+    )
+    .await
+    .unwrap();
+
+    // Both legs went through.
+    assert_eq!(get_token_balance(&mut context, &a_x).await, 0);
+    assert_eq!(get_token_balance(&mut context, &a_y).await, 4);
+    assert_eq!(get_token_balance(&mut context, &b_y).await, 0);
+    assert_eq!(get_token_balance(&mut context, &b_x).await, 10);
+    assert_eq!(
+        get_offer(&mut context, &offer_a).await.status,
+        OfferStatus::Accepted
+    );
+    assert_eq!(
+        get_offer(&mut context, &offer_b).await.status,
+        OfferStatus::Accepted
+    );
+
+    // And they can't be matched a second time.
+    let err = send(&mut context, &[match_ix], &[&matcher, &maker_a, &maker_b])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidOfferStatus));
+    msg!("test_match_mirror_offers PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_pays_sol_to_fresh_taker_wallet() {
+    let mut context = start_program().await;
+
+    let mint_authority = Keypair::new();
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // The taker's wallet has never held any SOL; the payer covers their fees.
+    let taker = Keypair::new();
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &taker_token_account,
+        &mint_authority,
+        2,
+    )
+    .await;
+    assert_eq!(get_sol_balance(&mut context, &taker.pubkey()).await, 0);
+
+    // Two makers each want 1 token: one pays 1 SOL, the other only a few lamports.
+    let mut buy_offers = vec![];
+    for lamports in [1_000_000_000, 1_000] {
+        let maker = Keypair::new();
+        fund_account(&mut context, &maker.pubkey(), 2_000_000_000).await;
+        let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+        let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+        let create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &Pubkey::new_unique(),
+            &sol,
+            &token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicBuy,
+                offer_token_amount: lamports,
+                receive_token_amount: 1,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+        buy_offers.push((maker, maker_token_account, offer_account));
+    }
+    let accept_ix = |(maker, maker_token_account, offer_account): &(Keypair, Pubkey, Pubkey)| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            offer_account,
+            &maker.pubkey(),
+            maker_token_account,
+            &taker_token_account,
+            &sol,
+            &token_mint,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )
+    };
+
+    // A few lamports can't make an empty wallet rent-exempt, so that accept is refused.
+    let err = send(
+        &mut context,
+        &[accept_ix(&buy_offers[1])],
+        &[&taker, &buy_offers[1].0],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::NotRentExempt));
+
+    // A whole SOL is plenty: the wallet ends up holding exactly the escrow.
+    send(
+        &mut context,
+        &[accept_ix(&buy_offers[0])],
+        &[&taker, &buy_offers[0].0],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &taker.pubkey()).await,
+        1_000_000_000
+    );
+    assert_eq!(get_token_balance(&mut context, &buy_offers[0].1).await, 1);
+    let offer = get_offer(&mut context, &buy_offers[0].2).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    assert_eq!(
+        get_sol_balance(&mut context, &buy_offers[0].2).await,
+        offer.rent_reserved
+    );
+    msg!("test_accept_pays_sol_to_fresh_taker_wallet PASSED");
+}
+
+#[tokio::test]
+async fn test_create_fee_goes_to_collector() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let fee_collector = Keypair::new();
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let create_fee = 5_000_000;
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::CreateFee(create_fee)),
+            update_config_ix(
+                &admin.pubkey(),
+                ConfigField::FeeCollector(fee_collector.pubkey()),
+            ),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+
+    // The fee has to go to the configured collector, not wherever the maker likes.
+    let err = send(&mut context, std::slice::from_ref(&create_ix), &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidAccountInput));
+
+    create_ix.accounts[12] = AccountMeta::new(fee_collector.pubkey(), false);
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let rent = context.0.get_rent().await.unwrap();
+    let rent_paid =
+        rent.minimum_balance(Offer::MAX_LEN) + rent.minimum_balance(MakerIndex::MAX_LEN);
+    assert_eq!(
+        get_sol_balance(&mut context, &fee_collector.pubkey()).await,
+        create_fee
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before - rent_paid - create_fee
+    );
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Active
+    );
+    msg!("test_create_fee_goes_to_collector PASSED");
+}
+
+#[tokio::test]
+async fn test_cancel_zeroizes_offer_when_enabled() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::ZeroizeTerminalOffers(true)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (offer_account, bump_seed) =
+        offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::Direct,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: Some(i64::MAX),
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    create_ix
+        .accounts
+        .push(AccountMeta::new_readonly(taker.pubkey(), false));
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.taker,
+        Some(taker.pubkey())
+    );
+
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &offer_token_mint,
+            &receive_token_mint,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+
+    // Only what's needed to find the locker survives; who and how much are gone.
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Declined);
+    assert_eq!(offer.taker, None);
+    assert_eq!(offer.offer_token_amount, 0);
+    assert_eq!(offer.receive_token_amount, 0);
+    assert_eq!(offer.expiration, None);
+    assert_eq!(offer.maker, maker.pubkey());
+    assert_eq!(offer.offer_token_mint, offer_token_mint);
+    assert_eq!(offer.bump_seed, bump_seed);
+    msg!("test_cancel_zeroizes_offer_when_enabled PASSED");
+}
+
+#[tokio::test]
+async fn test_counter_authorization_before_and_after_make_public() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let stranger = Keypair::new();
+    let mint_authority = Keypair::new();
+    for wallet in [&maker, &taker, &stranger] {
+        fund_account(&mut context, &wallet.pubkey(), 1_000_000_000).await;
+    }
+    let mint_x = create_mint(&mut context, &mint_authority, None, 0).await;
+    let mint_y = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    let maker_x_account = create_token_account(&mut context, &maker, &mint_x).await;
+    mint_to(&mut context, &mint_x, &maker_x_account, &mint_authority, 10).await;
+    let taker_y_account = create_token_account(&mut context, &taker, &mint_y).await;
+    mint_to(&mut context, &mint_y, &taker_y_account, &mint_authority, 10).await;
+    let stranger_y_account = create_token_account(&mut context, &stranger, &mint_y).await;
+    mint_to(
+        &mut context,
+        &mint_y,
+        &stranger_y_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // Maker offers 10 X for 5 Y, to the taker only.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &mint_x, &mint_y);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_x_account,
+        &mint_x,
+        &mint_y,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::Direct,
+            offer_token_amount: 10,
+            receive_token_amount: 5,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    create_ix
+        .accounts
+        .push(AccountMeta::new_readonly(taker.pubkey(), false));
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let counter_ix = |counter_maker: &Keypair, token_account: &Pubkey| {
+        counter_offer_ix(
+            &counter_maker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            token_account,
+            &mint_y,
+            &mint_x,
+            SwapInstruction::CounterOffer {
+                offer_token_amount: 6,
+                receive_token_amount: 10,
+                expiration: None,
+                start_time: None,
+            },
+        )
+    };
+
+    // While it's direct, someone who isn't a party can't counter it.
+    let err = send(
+        &mut context,
+        &[counter_ix(&stranger, &stranger_y_account)],
+        &[&stranger],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+
+    // Only the maker can open the offer up.
+    let err = send(
+        &mut context,
+        &[make_public_ix(&taker.pubkey(), &offer_account)],
+        &[&taker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+    send(
+        &mut context,
+        &[make_public_ix(&maker.pubkey(), &offer_account)],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.offer_type, OfferType::PublicSell);
+    assert_eq!(offer.taker, None);
+
+    // The taker is no longer named on the offer, but can still counter it.
+    send(
+        &mut context,
+        &[counter_ix(&taker, &taker_y_account)],
+        &[&taker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Countered
+    );
+    let (counter_account, _) = offer_pda(&taker.pubkey(), &mint_y, &mint_x);
+    let counter = get_offer(&mut context, &counter_account).await;
+    assert_eq!(counter.maker, taker.pubkey());
+    assert_eq!(counter.original_offer_id, Some(offer_account));
+    msg!("test_counter_authorization_before_and_after_make_public PASSED");
+}
+
+#[tokio::test]
+async fn test_create_keeps_fee_buffer_for_paying_maker() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    let offer_token_mint = spl_token::native_mint::id(); // Maker offers SOL
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // The maker pays this transaction's fee, the offer's rent, and the escrow, and would be
+    // left with less than the fee buffer.
+    let escrow = 1_000_000;
+    let rent = context.0.get_rent().await.unwrap();
+    let fee = 5_000; // One signature
+    fund_account(
+        &mut context,
+        &maker.pubkey(),
+        fee + rent.minimum_balance(Offer::MAX_LEN) + escrow + FEE_BUFFER_LAMPORTS - 1,
+    )
+    .await;
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: escrow,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    let blockhash = context.0.get_latest_blockhash().await.unwrap();
+    let mut transaction = Transaction::new_with_payer(&[create_ix], Some(&maker.pubkey()));
+    transaction.sign(&[&maker], blockhash);
+    let err = context
+        .0
+        .process_transaction(transaction)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(err, custom_error(SwapError::InsufficientFunds));
+    assert!(
+        context
+            .0
+            .get_account(offer_account)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    msg!("test_create_keeps_fee_buffer_for_paying_maker PASSED");
+}
+
+#[tokio::test]
+async fn test_cancel_marks_expired_offer_expired() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = spl_token::native_mint::id(); // Maker offers SOL
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // Maker puts 1 SOL in escrow, good for the next minute.
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 10,
+            expiration: Some(clock.unix_timestamp + 60),
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // An hour goes by before the maker gets around to cancelling.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 3_600,
+        ..clock
+    });
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &offer_token_mint,
+            &receive_token_mint,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Expired);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_000_000_000
+    );
+    msg!("test_cancel_marks_expired_offer_expired PASSED");
+}
+
+#[tokio::test]
+async fn test_request_terms_records_negotiation() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // The maker can't haggle with themselves.
+    let err = send(
+        &mut context,
+        &[request_terms_ix(&maker.pubkey(), &offer_account, 900)],
+        &[&maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+
+    let (negotiation_account, negotiation_bump) = negotiation_pda(&offer_account, &taker.pubkey());
+    send(
+        &mut context,
+        &[request_terms_ix(&taker.pubkey(), &offer_account, 800)],
+        &[&taker],
+    )
+    .await
+    .unwrap();
+    let read_negotiation = |data: Vec<u8>| borsh::from_slice::<Negotiation>(&data).unwrap();
+    let account = context
+        .0
+        .get_account(negotiation_account)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.owner, program_id());
+    assert_eq!(
+        read_negotiation(account.data),
+        Negotiation {
+            offer: offer_account,
+            taker: taker.pubkey(),
+            proposed_receive: 800,
+            bump_seed: negotiation_bump,
+        }
+    );
+
+    // Asking again just replaces the old proposal; the offer itself is untouched.
+    send(
+        &mut context,
+        &[request_terms_ix(&taker.pubkey(), &offer_account, 900)],
+        &[&taker],
+    )
+    .await
+    .unwrap();
+    let account = context
+        .0
+        .get_account(negotiation_account)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(read_negotiation(account.data).proposed_receive, 900);
+    assert_eq!(
+        get_offer(&mut context, &offer_account)
+            .await
+            .receive_token_amount,
+        1_000
+    );
+    msg!("test_request_terms_records_negotiation PASSED");
+}
+
+#[tokio::test]
+async fn test_match_sealed_offers_from_vaults() {
+    let mut context = start_program().await;
+
+    let maker_a = Keypair::new();
+    let maker_b = Keypair::new();
+    let matcher = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker_a.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker_b.pubkey(), 1_000_000_000).await;
+    let mint_x = create_mint(&mut context, &mint_authority, None, 0).await;
+    let mint_y = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // A has 10 X and wants 4 Y; B has 4 Y and wants 10 X.
+    let a_x = create_token_account(&mut context, &maker_a, &mint_x).await;
+    let a_y = create_token_account(&mut context, &maker_a, &mint_y).await;
+    let b_y = create_token_account(&mut context, &maker_b, &mint_y).await;
+    let b_x = create_token_account(&mut context, &maker_b, &mint_x).await;
+    mint_to(&mut context, &mint_x, &a_x, &mint_authority, 10).await;
+    mint_to(&mut context, &mint_y, &b_y, &mint_authority, 4).await;
+
+    // Both makers seal their tokens into their offer's vault when listing.
+    let (offer_a, _) = offer_pda(&maker_a.pubkey(), &mint_x, &mint_y);
+    let (offer_b, _) = offer_pda(&maker_b.pubkey(), &mint_y, &mint_x);
+    let vault_a = create_offer_vault(&mut context, &offer_a, &mint_x).await;
+    let vault_b = create_offer_vault(&mut context, &offer_b, &mint_y).await;
+    for (maker, token_account, offer_mint, receive_mint, amounts, vault) in [
+        (&maker_a, a_x, mint_x, mint_y, (10, 4), vault_a),
+        (&maker_b, b_y, mint_y, mint_x, (4, 10), vault_b),
+    ] {
+        let mut create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &token_account,
+            &offer_mint,
+            &receive_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: amounts.0,
+                receive_token_amount: amounts.1,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        create_ix
+            .accounts
+            .push(AccountMeta::new_readonly(maker.pubkey(), false)); // No taker for a public offer
+        create_ix.accounts.push(AccountMeta::new(vault, false));
+        send(&mut context, &[create_ix], &[maker]).await.unwrap();
+    }
+    assert_eq!(get_token_balance(&mut context, &a_x).await, 0);
+    assert_eq!(get_token_balance(&mut context, &vault_a).await, 10);
+    assert_eq!(get_token_balance(&mut context, &b_y).await, 0);
+    assert_eq!(get_token_balance(&mut context, &vault_b).await, 4);
+    assert_eq!(
+        get_offer(&mut context, &offer_a).await.escrow_token_amount,
+        10
+    );
+
+    // A sealed offer pays out of its vault, so the maker's own account won't do.
+    let taker = Keypair::new();
+    let taker_y = create_token_account(&mut context, &taker, &mint_y).await;
+    let err = send(
+        &mut context,
+        &[accept_offer_ix(
+            &taker.pubkey(),
+            &offer_a,
+            &maker_a.pubkey(),
+            &a_x,
+            &taker_y,
+            &mint_x,
+            &mint_y,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )],
+        &[&taker, &maker_a],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidAccountInput));
+
+    // The matcher settles both sides alone; neither maker has to be around.
+    let match_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(matcher.pubkey(), true),
+            AccountMeta::new(offer_a, false),
+            AccountMeta::new(offer_b, false),
+            AccountMeta::new_readonly(maker_a.pubkey(), false),
+            AccountMeta::new_readonly(maker_b.pubkey(), false),
+            AccountMeta::new(vault_a, false),
+            AccountMeta::new(a_y, false),
+            AccountMeta::new(vault_b, false),
+            AccountMeta::new(b_x, false),
+            AccountMeta::new_readonly(mint_x, false),
+            AccountMeta::new_readonly(mint_y, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(mint_pause_pda(&mint_x).0, false),
+            AccountMeta::new_readonly(mint_pause_pda(&mint_y).0, false),
+            AccountMeta::new(maker_index_pda(&maker_a.pubkey()).0, false),
+            AccountMeta::new(maker_index_pda(&maker_b.pubkey()).0, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(order_book_pda(&mint_x, &mint_y).0, false),
+            AccountMeta::new(order_book_pda(&mint_y, &mint_x).0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::MatchOffers).unwrap(),
+    };
+    send(&mut context, &[match_ix], &[&matcher]).await.unwrap();
+
+    // Both vaults emptied into the other maker's account.
+    assert_eq!(get_token_balance(&mut context, &vault_a).await, 0);
+    assert_eq!(get_token_balance(&mut context, &vault_b).await, 0);
+    assert_eq!(get_token_balance(&mut context, &a_y).await, 4);
+    assert_eq!(get_token_balance(&mut context, &b_x).await, 10);
+    for offer_account in [offer_a, offer_b] {
+        let offer = get_offer(&mut context, &offer_account).await;
+        assert_eq!(offer.status, OfferStatus::Accepted);
+        assert_eq!(offer.escrow_token_amount, 0);
+    }
+    msg!("test_match_sealed_offers_from_vaults PASSED");
+}
+
+#[tokio::test]
+async fn test_create_always_uses_the_canonical_bump() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (offer_account, canonical_bump) =
+        offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    // Any lower bump that still makes a PDA gives a second, non-canonical address.
+    let shadow_account = (0..canonical_bump)
+        .rev()
+        .find_map(|bump| {
+            Pubkey::create_program_address(
+                &[
+                    b"offer",
+                    maker.pubkey().as_ref(),
+                    offer_token_mint.as_ref(),
+                    receive_token_mint.as_ref(),
+                    &[bump],
+                ],
+                &program_id(),
+            )
+            .ok()
+        })
+        .unwrap();
+
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+
+    // The offer can't be put at the shadow address...
+    let mut shadow_ix = create_ix.clone();
+    shadow_ix.accounts[1].pubkey = shadow_account;
+    let err = send(&mut context, &[shadow_ix], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidProgramAddress));
+
+    // ...only at the canonical one, and that bump is what the offer keeps.
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.bump_seed, canonical_bump);
+    msg!("test_create_always_uses_the_canonical_bump PASSED");
+}
+
+#[tokio::test]
+async fn test_pda_maker_cancels_through_cpi() {
+    let mut program_test = ProgramTest::new("soffer", program_id(), processor!(Processor::process));
+    program_test.add_program(
+        "controller",
+        controller_id(),
+        processor!(controller_process),
+    );
+    let mut context = program_test.start().await;
+
+    // The controller's PDA makes a 1 SOL buy offer.
+    let (maker, _) = controller_owner_pda();
+    fund_account(&mut context, &maker, 2_000_000_000).await;
+    let mint_authority = Keypair::new();
+    let offer_token_mint = spl_token::native_mint::id(); // Maker offers SOL
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let (offer_account, _) = offer_pda(&maker, &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker,
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+
+    send(&mut context, &[via_controller(create_ix)], &[])
+        .await
+        .unwrap();
+    assert_eq!(get_offer(&mut context, &offer_account).await.maker, maker);
+
+    let maker_balance_before = get_sol_balance(&mut context, &maker).await;
+    send(
+        &mut context,
+        &[via_controller(cancel_offer_ix(
+            &maker,
+            &offer_account,
+            &offer_token_mint,
+            &receive_token_mint,
+        ))],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Declined);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    assert_eq!(
+        get_sol_balance(&mut context, &maker).await,
+        maker_balance_before + 1_000_000_000
+    );
+    msg!("test_pda_maker_cancels_through_cpi PASSED");
+}
+
+#[tokio::test]
+async fn test_create_rejects_amount_over_supply() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // Only 10 tokens exist, but the maker scaled the amount for 6 decimals the mint doesn't have.
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10_000_000,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+
+    // With the toggle off it's only a warning, and the maker just doesn't have the tokens.
+    let err = send(&mut context, std::slice::from_ref(&create_ix), &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InsufficientFunds));
+
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::RejectAmountsOverSupply(true)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    let err = send(&mut context, &[create_ix], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::AmountExceedsSupply));
+    msg!("test_create_rejects_amount_over_supply PASSED");
+}
+
+// Sets up a seller offering 5 tokens for 5_000 lamports, with trades over 3 tokens held for a
+// minute. Hands back (seller, taker, offer, seller's token account, taker's token account, mint).
+async fn setup_large_trade_offer(
+    context: &mut (BanksClient, Keypair, Hash),
+) -> (Keypair, Keypair, Pubkey, Pubkey, Pubkey, Pubkey) {
+    let admin = Keypair::new();
+    let seller = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(context, &seller.pubkey(), 1_000_000_000).await;
+    fund_account(context, &taker.pubkey(), 1_000_000_000).await;
+    send(
+        context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::LargeTradeThreshold(3)),
+            update_config_ix(&admin.pubkey(), ConfigField::SettlementDelay(60)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let token_mint = create_mint(context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id();
+    let seller_token_account = create_token_account(context, &seller, &token_mint).await;
+    mint_to(
+        context,
+        &token_mint,
+        &seller_token_account,
+        &mint_authority,
+        5,
+    )
+    .await;
+    let taker_token_account = create_token_account(context, &taker, &token_mint).await;
+    let (offer_account, _) = offer_pda(&seller.pubkey(), &token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &seller.pubkey(),
+        &seller_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 5,
+            receive_token_amount: 5_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(context, &[create_ix], &[&seller]).await.unwrap();
+    (
+        seller,
+        taker,
+        offer_account,
+        seller_token_account,
+        taker_token_account,
+        token_mint,
+    )
+}
+
+#[tokio::test]
+async fn test_large_accept_waits_then_finalizes() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+    let (seller, taker, offer_account, seller_token_account, taker_token_account, token_mint) =
+        setup_large_trade_offer(&mut context).await;
+    let sol = spl_token::native_mint::id();
+    let accept_ix = |data| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &seller.pubkey(),
+            &seller_token_account,
+            &taker_token_account,
+            &token_mint,
+            &sol,
+            data,
+        )
+    };
+
+    // Taking all 5 tokens is a large trade, so it only gets booked.
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+    send(
+        &mut context,
+        &[accept_ix(SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        })],
+        &[&taker, &seller],
+    )
+    .await
+    .unwrap();
+    let settlement_account = context
+        .0
+        .get_account(settlement_pda(&offer_account).0)
+        .await
+        .unwrap()
+        .unwrap();
+    let settlement = Settlement::load(&settlement_account.data).unwrap();
+    assert_eq!(settlement.taker, taker.pubkey());
+    assert_eq!(settlement.fill_amount, None);
+    assert_eq!(settlement.ready_at, clock.unix_timestamp + 60);
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        0
+    );
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Active
+    );
+
+    // Nobody else can take the offer while the trade waits, and it can't settle early.
+    let err = send(
+        &mut context,
+        &[accept_ix(SwapInstruction::AcceptOfferPartial {
+            fill_amount: 1,
+        })],
+        &[&taker, &seller],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::SettlementPending));
+    // (The taker pays the fee for this try, so it isn't a copy of the real finalize below.)
+    let mut early_finalize = Transaction::new_with_payer(
+        &[accept_ix(SwapInstruction::FinalizeSettlement)],
+        Some(&taker.pubkey()),
+    );
+    early_finalize.sign(
+        &[&taker, &seller],
+        context.0.get_latest_blockhash().await.unwrap(),
+    );
+    let err = context
+        .0
+        .process_transaction(early_finalize)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(err, custom_error(SwapError::SettlementNotReady));
+
+    // A minute later it goes through like a normal accept.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 60,
+        ..clock
+    });
+    let seller_balance_before = get_sol_balance(&mut context, &seller.pubkey()).await;
+    send(
+        &mut context,
+        &[accept_ix(SwapInstruction::FinalizeSettlement)],
+        &[&taker, &seller],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        5
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &seller.pubkey()).await,
+        seller_balance_before + 5_000
+    );
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Accepted
+    );
+    assert!(
+        context
+            .0
+            .get_account(settlement_pda(&offer_account).0)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    msg!("test_large_accept_waits_then_finalizes PASSED");
+}
+
+#[tokio::test]
+async fn test_abort_settlement_before_delay() {
+    let mut context = start_program().await;
+    let (seller, taker, offer_account, seller_token_account, taker_token_account, token_mint) =
+        setup_large_trade_offer(&mut context).await;
+    let sol = spl_token::native_mint::id();
+
+    // A small trade isn't held up at all.
+    send(
+        &mut context,
+        &[accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &seller.pubkey(),
+            &seller_token_account,
+            &taker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::AcceptOfferPartial { fill_amount: 1 },
+        )],
+        &[&taker, &seller],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        1
+    );
+
+    // The rest is a large trade (4 > 3), so it waits.
+    let taker_balance_before = get_sol_balance(&mut context, &taker.pubkey()).await;
+    send(
+        &mut context,
+        &[accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &seller.pubkey(),
+            &seller_token_account,
+            &taker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )],
+        &[&taker, &seller],
+    )
+    .await
+    .unwrap();
+    let settlement_key = settlement_pda(&offer_account).0;
+    let settlement_rent = get_sol_balance(&mut context, &settlement_key).await;
+    assert!(settlement_rent > 0);
+
+    // Only the two parties may call it off.
+    let stranger = Keypair::new();
+    let err = send(
+        &mut context,
+        &[abort_settlement_ix(
+            &stranger.pubkey(),
+            &offer_account,
+            &taker.pubkey(),
+        )],
+        &[&stranger],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+
+    // The maker backs out before the delay is up; the taker gets the rent back.
+    send(
+        &mut context,
+        &[abort_settlement_ix(
+            &seller.pubkey(),
+            &offer_account,
+            &taker.pubkey(),
+        )],
+        &[&seller],
+    )
+    .await
+    .unwrap();
+    assert!(
+        context
+            .0
+            .get_account(settlement_key)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &taker.pubkey()).await,
+        taker_balance_before
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        1
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &seller_token_account).await,
+        4
+    );
+
+    // With nothing pending anymore, there's nothing to finalize.
+    let err = send(
+        &mut context,
+        &[accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &seller.pubkey(),
+            &seller_token_account,
+            &taker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::FinalizeSettlement,
+        )],
+        &[&taker, &seller],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::AccountNotInitialized));
+    msg!("test_abort_settlement_before_delay PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_pays_nft_royalties_to_creators() {
+    let mut program_context =
+        ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+            .start_with_context()
+            .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let seller = Keypair::new();
+    let taker = Keypair::new();
+    let (creator_a, creator_b) = (Keypair::new(), Keypair::new());
+    let mint_authority = Keypair::new();
+    for wallet in [&seller, &taker, &creator_a, &creator_b] {
+        fund_account(&mut context, &wallet.pubkey(), 1_000_000_000).await;
+    }
+    let nft_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id();
+    let seller_token_account = create_token_account(&mut context, &seller, &nft_mint).await;
+    mint_to(
+        &mut context,
+        &nft_mint,
+        &seller_token_account,
+        &mint_authority,
+        1,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &nft_mint).await;
+
+    // The NFT has a 5% royalty, split 60/40 between two creators.
+    let (metadata_key, metadata_account) = nft_metadata_account(
+        &nft_mint,
+        500,
+        &[(creator_a.pubkey(), 60), (creator_b.pubkey(), 40)],
+    );
+    program_context.set_account(&metadata_key, &metadata_account.clone().into());
+
+    let (offer_account, _) = offer_pda(&seller.pubkey(), &nft_mint, &sol);
+    let create_ix = create_offer_ix(
+        &seller.pubkey(),
+        &seller_token_account,
+        &nft_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 1,
+            receive_token_amount: 100_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&seller]).await.unwrap();
+    let accept_ix = |metadata: Pubkey, creators: [Pubkey; 2]| {
+        let mut ix = accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &seller.pubkey(),
+            &seller_token_account,
+            &taker_token_account,
+            &nft_mint,
+            &sol,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        );
+        // No fee to need a treasury, and not a premium order, so placeholders will do.
+        ix.accounts
+            .extend(vec![AccountMeta::new_readonly(config_pda().0, false); 2]);
+        ix.accounts.push(AccountMeta::new_readonly(metadata, false));
+        ix.accounts
+            .extend(creators.map(|creator| AccountMeta::new(creator, false)));
+        ix
+    };
+
+    // Made-up metadata, or the creators out of order, don't get past the check.
+    let fake_metadata = Keypair::new().pubkey();
+    program_context.set_account(&fake_metadata, &metadata_account.into());
+    let err = send(
+        &mut context,
+        &[accept_ix(
+            fake_metadata,
+            [creator_a.pubkey(), creator_b.pubkey()],
+        )],
+        &[&taker, &seller],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidMetadata));
+    let err = send(
+        &mut context,
+        &[accept_ix(
+            metadata_key,
+            [creator_b.pubkey(), creator_a.pubkey()],
+        )],
+        &[&taker, &seller],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidMetadata));
+
+    let seller_before = get_sol_balance(&mut context, &seller.pubkey()).await;
+    let creator_a_before = get_sol_balance(&mut context, &creator_a.pubkey()).await;
+    let creator_b_before = get_sol_balance(&mut context, &creator_b.pubkey()).await;
+    send(
+        &mut context,
+        &[accept_ix(
+            metadata_key,
+            [creator_a.pubkey(), creator_b.pubkey()],
+        )],
+        &[&taker, &seller],
+    )
+    .await
+    .unwrap();
+
+    // 5% of 100_000 is 5_000: 3_000 and 2_000 to the creators, the rest to the seller.
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        1
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &seller.pubkey()).await,
+        seller_before + 95_000
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &creator_a.pubkey()).await,
+        creator_a_before + 3_000
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &creator_b.pubkey()).await,
+        creator_b_before + 2_000
+    );
+    msg!("test_accept_pays_nft_royalties_to_creators PASSED");
+}
+
+#[tokio::test]
+async fn test_create_rejects_zero_amounts_and_empty_maker_account() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let someone_else = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    // The tokens exist, they just aren't the maker's.
+    let other_token_account =
+        create_token_account(&mut context, &someone_else, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &other_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let create_ix = |offer_token_amount, receive_token_amount| {
+        create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &offer_token_mint,
+            &receive_token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount,
+                receive_token_amount,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+
+    // Nothing for something, or something for nothing, isn't an offer.
+    for (offer_amount, receive_amount) in [(0, 1_000), (5, 0)] {
+        let err = send(
+            &mut context,
+            &[create_ix(offer_amount, receive_amount)],
+            &[&maker],
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err, custom_error(SwapError::InvalidAmount));
+    }
+
+    // A sensible amount still needs tokens behind it.
+    let err = send(&mut context, &[create_ix(5, 1_000)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InsufficientFunds));
+    msg!("test_create_rejects_zero_amounts_and_empty_maker_account PASSED");
+}
+
+#[tokio::test]
+async fn test_two_step_authority_handoff() {
+    let mut context = start_program().await;
+
+    let old_admin = Keypair::new();
+    let new_admin = Keypair::new();
+    let stranger = Keypair::new();
+    fund_account(&mut context, &old_admin.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[initialize_config_ix(&old_admin.pubkey())],
+        &[&old_admin],
+    )
+    .await
+    .unwrap();
+    let authority_ix = |signer: &Pubkey, data: SwapInstruction| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(*signer, true),
+            AccountMeta::new(config_pda().0, false),
+        ],
+        data: borsh::to_vec(&data).unwrap(),
+    };
+
+    // Only the admin can propose, and nobody can accept before that.
+    let err = send(
+        &mut context,
+        &[authority_ix(
+            &stranger.pubkey(),
+            SwapInstruction::SetPendingAuthority {
+                new_authority: stranger.pubkey(),
+            },
+        )],
+        &[&stranger],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+    let err = send(
+        &mut context,
+        &[authority_ix(
+            &new_admin.pubkey(),
+            SwapInstruction::AcceptAuthority,
+        )],
+        &[&new_admin],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+
+    send(
+        &mut context,
+        &[authority_ix(
+            &old_admin.pubkey(),
+            SwapInstruction::SetPendingAuthority {
+                new_authority: new_admin.pubkey(),
+            },
+        )],
+        &[&old_admin],
+    )
+    .await
+    .unwrap();
+
+    // Proposed isn't the same as in charge: the old admin still runs things for now,
+    // and only the proposed key can accept.
+    send(
+        &mut context,
+        &[update_config_ix(
+            &old_admin.pubkey(),
+            ConfigField::CreateFee(1),
+        )],
+        &[&old_admin],
+    )
+    .await
+    .unwrap();
+    let err = send(
+        &mut context,
+        &[authority_ix(
+            &stranger.pubkey(),
+            SwapInstruction::AcceptAuthority,
+        )],
+        &[&stranger],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+
+    send(
+        &mut context,
+        &[authority_ix(
+            &new_admin.pubkey(),
+            SwapInstruction::AcceptAuthority,
+        )],
+        &[&new_admin],
+    )
+    .await
+    .unwrap();
+
+    // The new admin can change settings; the old one can't anymore.
+    send(
+        &mut context,
+        &[update_config_ix(
+            &new_admin.pubkey(),
+            ConfigField::CreateFee(2),
+        )],
+        &[&new_admin],
+    )
+    .await
+    .unwrap();
+    let err = send(
+        &mut context,
+        &[update_config_ix(
+            &old_admin.pubkey(),
+            ConfigField::CreateFee(3),
+        )],
+        &[&old_admin],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+    msg!("test_two_step_authority_handoff PASSED");
+}
+
+#[tokio::test]
+async fn test_ratio_order_fills_in_uneven_chunks() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id();
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+
+    // A ratio order has to come with its rate.
+    let err = send(
+        &mut context,
+        &[create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::RatioOrder,
+                offer_token_amount: 10,
+                receive_token_amount: 23_333,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+
+    // 10 tokens at 7_000 lamports per 3 tokens.
+    send(
+        &mut context,
+        &[create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::CreateRatioOrder {
+                offer_token_amount: 10,
+                rate_num: 7_000,
+                rate_den: 3,
+                expiration: None,
+                min_fill: 0,
+            },
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.offer_type, OfferType::RatioOrder);
+    assert_eq!((offer.rate_num, offer.rate_den), (7_000, 3));
+    assert_eq!(offer.receive_token_amount, 23_333);
+
+    // Each chunk costs exactly its share at the rate, rounded down.
+    for (fill, cost, left_to_pay) in [(3, 7_000, 16_333), (4, 9_333, 7_000)] {
+        let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+        send(
+            &mut context,
+            &[accept_offer_ix(
+                &taker.pubkey(),
+                &offer_account,
+                &maker.pubkey(),
+                &maker_token_account,
+                &taker_token_account,
+                &token_mint,
+                &sol,
+                SwapInstruction::AcceptOfferPartial { fill_amount: fill },
+            )],
+            &[&taker, &maker],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            get_sol_balance(&mut context, &maker.pubkey()).await,
+            maker_balance_before + cost
+        );
+        assert_eq!(
+            get_offer(&mut context, &offer_account)
+                .await
+                .receive_token_amount,
+            left_to_pay
+        );
+    }
+
+    // The last 3 tokens cost the same as the first 3 did.
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(
+        &mut context,
+        &[accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 7_000
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        10
+    );
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Accepted
+    );
+    msg!("test_ratio_order_fills_in_uneven_chunks PASSED");
+}
+
+#[tokio::test]
+async fn test_force_recover_tokens_from_corrupted_offer() {
+    let mut program_context =
+        ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+            .start_with_context()
+            .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[initialize_config_ix(&admin.pubkey())],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id();
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let recovery_token_account = create_token_account(&mut context, &admin, &token_mint).await;
+
+    // The maker seals 10 tokens in the offer's vault.
+    let (offer_account, bump_seed) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let vault = create_offer_vault(&mut context, &offer_account, &token_mint).await;
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    create_ix
+        .accounts
+        .push(AccountMeta::new_readonly(maker.pubkey(), false)); // No taker for a public offer
+    create_ix.accounts.push(AccountMeta::new(vault, false));
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    assert_eq!(get_token_balance(&mut context, &vault).await, 10);
+
+    let recover_ix = |admin: &Pubkey| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new_readonly(offer_account, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(recovery_token_account, false),
+            AccountMeta::new_readonly(token_mint, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::ForceRecoverTokens {
+            maker: maker.pubkey(),
+            receive_token_mint: sol,
+            bump_seed,
+            nonce: 0,
+        })
+        .unwrap(),
+    };
+
+    // A healthy offer can't be emptied this way.
+    let err = send(&mut context, &[recover_ix(&admin.pubkey())], &[&admin])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+
+    // Scramble the offer type so the offer no longer reads.
+    let mut offer_data = context.0.get_account(offer_account).await.unwrap().unwrap();
+    offer_data.data[0] = 0xff;
+    program_context.set_account(&offer_account, &offer_data.into());
+    program_context.get_new_latest_blockhash().await.unwrap();
+
+    // Only the admin can pull the tokens out.
+    let err = send(&mut context, &[recover_ix(&maker.pubkey())], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+    send(&mut context, &[recover_ix(&admin.pubkey())], &[&admin])
+        .await
+        .unwrap();
+    assert_eq!(get_token_balance(&mut context, &vault).await, 0);
+    assert_eq!(
+        get_token_balance(&mut context, &recovery_token_account).await,
+        10
+    );
+    msg!("test_force_recover_tokens_from_corrupted_offer PASSED");
+}
+
+#[tokio::test]
+async fn test_quote_accept_breaks_down_payouts() {
+    let mut program_context =
+        ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+            .start_with_context()
+            .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let seller = Keypair::new();
+    let creator = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &seller.pubkey(), 1_000_000_000).await;
+    let nft_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id();
+    let seller_token_account = create_token_account(&mut context, &seller, &nft_mint).await;
+    mint_to(
+        &mut context,
+        &nft_mint,
+        &seller_token_account,
+        &mint_authority,
+        4,
+    )
+    .await;
+    let (metadata_key, metadata_account) =
+        nft_metadata_account(&nft_mint, 250, &[(creator.pubkey(), 100)]);
+    program_context.set_account(&metadata_key, &metadata_account.into());
+
+    let (offer_account, _) = offer_pda(&seller.pubkey(), &nft_mint, &sol);
+    let create_ix = create_offer_ix(
+        &seller.pubkey(),
+        &seller_token_account,
+        &nft_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 4,
+            receive_token_amount: 1_000_003,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&seller]).await.unwrap();
+    let quote = |fill_amount, with_metadata: bool| {
+        let mut accounts = vec![
+            AccountMeta::new_readonly(offer_account, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new_readonly(config_pda().0, false), // Not a premium order
+        ];
+        if with_metadata {
+            accounts.push(AccountMeta::new_readonly(metadata_key, false));
+        }
+        Instruction {
+            program_id: program_id(),
+            accounts,
+            data: borsh::to_vec(&SwapInstruction::QuoteAccept { fill_amount }).unwrap(),
+        }
+    };
+    let read_quote = |data: Vec<u8>| borsh::from_slice::<Quote>(&data).unwrap();
+
+    // The whole lot: 2.5% of 1_000_003 lamports goes to the creator, rounded down.
+    let full = read_quote(simulate_return_data(&mut context, &[quote(None, true)]).await);
+    assert_eq!(
+        full,
+        Quote {
+            token_amount: 4,
+            sol_amount: 1_000_003,
+            seller_proceeds: 975_003,
+            royalty: 25_000,
+            protocol_fee: 0,
+        }
+    );
+    assert_eq!(
+        full.seller_proceeds + full.royalty + full.protocol_fee,
+        full.sol_amount
+    );
+
+    // A partial fill is priced like the accept would price it (rounded up for the seller).
+    let partial = read_quote(simulate_return_data(&mut context, &[quote(Some(1), true)]).await);
+    assert_eq!(partial.token_amount, 1);
+    assert_eq!(partial.sol_amount, 250_001);
+    assert_eq!(partial.royalty, 6_250);
+    assert_eq!(
+        partial.seller_proceeds + partial.royalty + partial.protocol_fee,
+        partial.sol_amount
+    );
+
+    // Without the metadata there's no royalty to take out.
+    let plain = read_quote(simulate_return_data(&mut context, &[quote(None, false)]).await);
+    assert_eq!(plain.royalty, 0);
+    assert_eq!(plain.seller_proceeds, 1_000_003);
+
+    // Nothing moved.
+    assert_eq!(
+        get_token_balance(&mut context, &seller_token_account).await,
+        4
+    );
+    msg!("test_quote_accept_breaks_down_payouts PASSED");
+}
+
+#[tokio::test]
+async fn test_cancel_refunds_escrow_only_once() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = spl_token::native_mint::id(); // Maker offers SOL
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 2_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let cancel_ix = cancel_offer_ix(
+        &maker.pubkey(),
+        &offer_account,
+        &offer_token_mint,
+        &receive_token_mint,
+    );
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+
+    // Two cancels back to back: the second sees the offer already closed out with no escrow,
+    // and the whole transaction (first refund included) is rolled back.
+    let mut transaction = Transaction::new_with_payer(
+        &[cancel_ix.clone(), cancel_ix.clone()],
+        Some(&context.1.pubkey()),
+    );
+    let blockhash = context.0.get_latest_blockhash().await.unwrap();
+    transaction.sign(&[&context.1, &maker], blockhash);
+    let err = context
+        .0
+        .process_transaction(transaction)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(SwapError::InvalidOfferStatus as u32)
+        )
+    );
+    assert_eq!(
+        get_offer(&mut context, &offer_account)
+            .await
+            .escrow_sol_amount,
+        2_000_000_000
+    );
+
+    // One cancel refunds the escrow and leaves nothing behind to refund again.
+    send(&mut context, &[cancel_ix], &[&maker]).await.unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Declined);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    assert_eq!(
+        get_sol_balance(&mut context, &offer_account).await,
+        offer.rent_reserved
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 2_000_000_000
+    );
+    msg!("test_cancel_refunds_escrow_only_once PASSED");
+}
+
+#[tokio::test]
+async fn test_reclaim_empty_offer_pda() {
+    let mut program_context =
+        ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+            .start_with_context()
+            .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let maker = Keypair::new();
+    let stranger = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let (offer_token_mint, receive_token_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (offer_account, bump_seed) =
+        offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+
+    // A create that got as far as making the account, but never wrote the offer.
+    let rent = context.0.get_rent().await.unwrap();
+    let lamports = rent.minimum_balance(Offer::MAX_LEN);
+    program_context.set_account(
+        &offer_account,
+        &Account {
+            lamports,
+            data: vec![0; Offer::MAX_LEN],
+            owner: program_id(),
+            executable: false,
+            rent_epoch: 0,
+        }
+        .into(),
+    );
+    let reclaim_ix = |maker: &Pubkey| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(*maker, true),
+            AccountMeta::new(offer_account, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::ReclaimEmptyOffer {
+            offer_token_mint,
+            receive_token_mint,
+            bump_seed,
+            nonce: 0,
+        })
+        .unwrap(),
+    };
+
+    // The PDA is derived from the maker, so nobody else can claim it.
+    let err = send(
+        &mut context,
+        &[reclaim_ix(&stranger.pubkey())],
+        &[&stranger],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidProgramAddress));
+
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(&mut context, &[reclaim_ix(&maker.pubkey())], &[&maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + lamports
+    );
+    assert!(
+        context
+            .0
+            .get_account(offer_account)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    msg!("test_reclaim_empty_offer_pda PASSED");
+}
+
+#[tokio::test]
+async fn test_reclaim_refuses_real_offer() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = spl_token::native_mint::id(); // Maker offers SOL
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let (offer_account, bump_seed) =
+        offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // An offer with escrow in it has to be cancelled, not reclaimed.
+    let reclaim_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new(offer_account, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::ReclaimEmptyOffer {
+            offer_token_mint,
+            receive_token_mint,
+            bump_seed,
+            nonce: 0,
+        })
+        .unwrap(),
+    };
+    let err = send(&mut context, &[reclaim_ix], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidAccountData));
+    assert_eq!(
+        get_offer(&mut context, &offer_account)
+            .await
+            .escrow_sol_amount,
+        1_000_000_000
+    );
+    msg!("test_reclaim_refuses_real_offer PASSED");
+}
+
+#[tokio::test]
+async fn test_partial_fills_draw_down_sol_escrow() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &taker_token_account,
+        &mint_authority,
+        7,
+    )
+    .await;
+
+    // The maker escrows 1 SOL for 7 tokens.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 7,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let partial_ix = |fill_amount| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &sol,
+            &token_mint,
+            SwapInstruction::AcceptOfferPartial { fill_amount },
+        )
+    };
+
+    // Each fill gets its share of what's left, rounded down: 2/7 of 1 SOL, then 3/5 of the rest.
+    for (fill, released, escrow_left, tokens_left) in [
+        (2, 285_714_285, 714_285_715, 5),
+        (3, 428_571_429, 285_714_286, 2),
+    ] {
+        let taker_balance_before = get_sol_balance(&mut context, &taker.pubkey()).await;
+        send(&mut context, &[partial_ix(fill)], &[&taker, &maker])
+            .await
+            .unwrap();
+        assert_eq!(
+            get_sol_balance(&mut context, &taker.pubkey()).await,
+            taker_balance_before + released
+        );
+        let offer = get_offer(&mut context, &offer_account).await;
+        assert_eq!(offer.status, OfferStatus::PartiallyFilled);
+        assert_eq!(offer.escrow_sol_amount, escrow_left);
+        assert_eq!(offer.offer_token_amount, escrow_left);
+        assert_eq!(offer.receive_token_amount, tokens_left);
+        assert_eq!(
+            get_sol_balance(&mut context, &offer_account).await,
+            offer.rent_reserved + escrow_left
+        );
+    }
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        5
+    );
+
+    // Delivering more than the maker still wants doesn't work; the rest closes it out.
+    let err = send(&mut context, &[partial_ix(3)], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidFillAmount));
+    send(&mut context, &[partial_ix(2)], &[&taker, &maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    assert_eq!(
+        get_sol_balance(&mut context, &offer_account).await,
+        offer.rent_reserved
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        7
+    );
+    msg!("test_partial_fills_draw_down_sol_escrow PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_rejects_offer_owned_token_account() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 10_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // The taker hands in the offer PDA's own token account instead of theirs.
+    let offer_vault = create_offer_vault(&mut context, &offer_account, &offer_token_mint).await;
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_vault,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    let err = send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidAccountInput));
+
+    // Nothing moved.
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        10
+    );
+    msg!("test_accept_rejects_offer_owned_token_account PASSED");
+}
+
+#[tokio::test]
+async fn test_create_offer_with_duration() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = |expiration, duration_secs| {
+        create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &offer_token_mint,
+            &receive_token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 10_000,
+                expiration,
+                min_fill: 0,
+                duration_secs,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+
+    // Both at once is ambiguous.
+    let err = send(
+        &mut context,
+        &[create_ix(Some(i64::MAX), Some(3_600))],
+        &[&maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+
+    // An hour from now, by the cluster's clock.
+    send(&mut context, &[create_ix(None, Some(3_600))], &[&maker])
+        .await
+        .unwrap();
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.expiration, Some(clock.unix_timestamp + 3_600));
+    msg!("test_create_offer_with_duration PASSED");
+}
+
+#[tokio::test]
+async fn test_ring_settle_three_offers() {
+    let mut context = start_program().await;
+
+    let makers = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let settler = Keypair::new();
+    let mint_authority = Keypair::new();
+    for maker in &makers {
+        fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    }
+    let mut mints = Vec::new();
+    for _ in 0..3 {
+        mints.push(create_mint(&mut context, &mint_authority, None, 0).await);
+    }
+
+    // A gives 10 X for 6 Z, B gives 5 Y for 10 X, and C gives 6 Z for 5 Y.
+    let gives = [(0, 10), (1, 5), (2, 6)]; // (mint, amount)
+    let wants = [(2, 6), (0, 10), (1, 5)];
+    let mut offers = Vec::new();
+    let mut give_accounts = Vec::new();
+    let mut want_accounts = Vec::new();
+    for (i, maker) in makers.iter().enumerate() {
+        let (give_mint, give_amount) = (mints[gives[i].0], gives[i].1);
+        let (want_mint, want_amount) = (mints[wants[i].0], wants[i].1);
+        let give_account = create_token_account(&mut context, maker, &give_mint).await;
+        let want_account = create_token_account(&mut context, maker, &want_mint).await;
+        mint_to(
+            &mut context,
+            &give_mint,
+            &give_account,
+            &mint_authority,
+            give_amount,
+        )
+        .await;
+        let (offer_account, _) = offer_pda(&maker.pubkey(), &give_mint, &want_mint);
+        let create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &give_account,
+            &give_mint,
+            &want_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: give_amount,
+                receive_token_amount: want_amount,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[create_ix], &[maker]).await.unwrap();
+        offers.push(offer_account);
+        give_accounts.push(give_account);
+        want_accounts.push(want_account);
+    }
+
+    let ring_ix = |order: [usize; 3]| {
+        let mut accounts = vec![AccountMeta::new_readonly(settler.pubkey(), true)];
+        accounts.extend(order.map(|i| AccountMeta::new(offers[i], false)));
+        accounts.extend(order.map(|i| AccountMeta::new_readonly(makers[i].pubkey(), true)));
+        accounts.extend(order.map(|i| AccountMeta::new(give_accounts[i], false)));
+        accounts.extend(order.map(|i| AccountMeta::new(want_accounts[i], false)));
+        accounts.extend(order.map(|i| AccountMeta::new_readonly(mints[gives[i].0], false)));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+        accounts.extend(
+            order.map(|i| AccountMeta::new_readonly(mint_pause_pda(&mints[gives[i].0]).0, false)),
+        );
+        accounts
+            .extend(order.map(|i| AccountMeta::new(maker_index_pda(&makers[i].pubkey()).0, false)));
+        accounts.push(AccountMeta::new_readonly(config_pda().0, false));
+        accounts.extend(order.map(|i| {
+            AccountMeta::new(
+                order_book_pda(&mints[gives[i].0], &mints[wants[i].0]).0,
+                false,
+            )
+        }));
+        Instruction {
+            program_id: program_id(),
+            accounts,
+            data: borsh::to_vec(&SwapInstruction::RingSettle).unwrap(),
+        }
+    };
+    let signers = [&settler, &makers[0], &makers[1], &makers[2]];
+
+    // Going round the other way, B's Y isn't what A wants.
+    let err = send(&mut context, &[ring_ix([1, 0, 2])], &signers)
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::OfferMismatch));
+
+    send(&mut context, &[ring_ix([0, 1, 2])], &signers)
+        .await
+        .unwrap();
+
+    // Everybody gave everything and got what they wanted.
+    for i in 0..3 {
+        assert_eq!(get_token_balance(&mut context, &give_accounts[i]).await, 0);
+        assert_eq!(
+            get_token_balance(&mut context, &want_accounts[i]).await,
+            wants[i].1
+        );
+        assert_eq!(
+            get_offer(&mut context, &offers[i]).await.status,
+            OfferStatus::Accepted
+        );
+    }
+
+    // Starting the same ring from B is the same ring, but it's already settled.
+    let err = send(&mut context, &[ring_ix([1, 2, 0])], &signers)
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidOfferStatus));
+    msg!("test_ring_settle_three_offers PASSED");
+}
+
+#[tokio::test]
+async fn test_min_escrow_sol_rejects_dust_buy_offers() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::MinEscrowSol(1_000_000)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let buy_ix = |lamports| {
+        create_offer_ix(
+            &maker.pubkey(),
+            &Pubkey::new_unique(), // No token account needed for a SOL offer
+            &sol,
+            &token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicBuy,
+                offer_token_amount: lamports,
+                receive_token_amount: 5,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+
+    // A single lamport for 5 tokens is dust.
+    let err = send(&mut context, &[buy_ix(1)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::AmountTooSmall));
+
+    // Right at the minimum is fine.
+    send(&mut context, &[buy_ix(1_000_000)], &[&maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.escrow_sol_amount, 1_000_000);
+    msg!("test_min_escrow_sol_rejects_dust_buy_offers PASSED");
+}
+
+#[tokio::test]
+async fn test_top_up_escrow_stops_at_the_cap() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::MaxEscrowPerOffer(10_000_000)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 6_000_000,
+            receive_token_amount: 5,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let top_up_ix = |amount| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new(offer_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda().0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::TopUpEscrow { amount }).unwrap(),
+    };
+
+    // Up to one lamport short of the cap...
+    send(&mut context, &[top_up_ix(3_999_999)], &[&maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.escrow_sol_amount, 9_999_999);
+    assert_eq!(offer.offer_token_amount, 9_999_999);
+
+    // ...and the next top-up over it is turned away, leaving the escrow alone.
+    let offer_lamports_before = get_sol_balance(&mut context, &offer_account).await;
+    let err = send(&mut context, &[top_up_ix(2)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::AmountTooLarge));
+    // So is one that would overflow the escrow outright.
+    let err = send(&mut context, &[top_up_ix(u64::MAX)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MathOverflow));
+    assert_eq!(
+        get_sol_balance(&mut context, &offer_account).await,
+        offer_lamports_before
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.escrow_sol_amount, 9_999_999);
+
+    // Exactly at the cap is fine.
+    send(&mut context, &[top_up_ix(1)], &[&maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.escrow_sol_amount, 10_000_000);
+    msg!("test_top_up_escrow_stops_at_the_cap PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_records_settled_amounts() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id();
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        4,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+
+    // 4 tokens at 7_000 lamports per 3 is listed at 9_333 for the lot.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    send(
+        &mut context,
+        &[create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::CreateRatioOrder {
+                offer_token_amount: 4,
+                rate_num: 7_000,
+                rate_den: 3,
+                expiration: None,
+                min_fill: 0,
+            },
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_offer(&mut context, &offer_account)
+            .await
+            .receive_token_amount,
+        9_333
+    );
+
+    // Bought in small chunks, each one rounds down: 2_333 for one token, 4_666 for two.
+    let fill_ix = |fill_amount| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::AcceptOfferPartial { fill_amount },
+        )
+    };
+    send(&mut context, &[fill_ix(1)], &[&taker, &maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(
+        (offer.settled_offer_amount, offer.settled_receive_amount),
+        (1, 2_333)
+    );
+    // A fresh blockhash, so this isn't the very same transaction as the first fill.
+    context.2 = context
+        .0
+        .get_new_latest_blockhash(&context.2)
+        .await
+        .unwrap();
+    send(&mut context, &[fill_ix(1)], &[&taker, &maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.settled_offer_amount, 2);
+    send(&mut context, &[fill_ix(2)], &[&taker, &maker])
+        .await
+        .unwrap();
+
+    // What really traded, not what was listed.
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    assert_eq!(
+        (offer.settled_offer_amount, offer.settled_receive_amount),
+        (4, 9_332)
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &taker.pubkey()).await,
+        1_000_000_000 - 9_332
+    );
+    msg!("test_accept_records_settled_amounts PASSED");
+}
+
+#[tokio::test]
+async fn test_sweep_expired_emits_events_for_expired_only() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let buyer = Keypair::new();
+    let seller = Keypair::new();
+    let patient_seller = Keypair::new();
+    let keeper = Keypair::new();
+    let mint_authority = Keypair::new();
+    for wallet in [&buyer, &seller, &patient_seller] {
+        fund_account(&mut context, &wallet.pubkey(), 10_000_000_000).await;
+    }
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+    let expiration = clock.unix_timestamp + 60;
+
+    // The buyer escrows 1 SOL and the seller lists 10 tokens, both for a minute.
+    // The patient seller's offer never expires.
+    let (buy_offer, _) = offer_pda(&buyer.pubkey(), &sol, &token_mint);
+    let buy_ix = create_offer_ix(
+        &buyer.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 10,
+            expiration: Some(expiration),
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[buy_ix], &[&buyer]).await.unwrap();
+    let mut sell_offers = Vec::new();
+    for (seller, expiration) in [(&seller, Some(expiration)), (&patient_seller, None)] {
+        let token_account = create_token_account(&mut context, seller, &token_mint).await;
+        mint_to(
+            &mut context,
+            &token_mint,
+            &token_account,
+            &mint_authority,
+            10,
+        )
+        .await;
+        let (offer_account, _) = offer_pda(&seller.pubkey(), &token_mint, &sol);
+        let sell_ix = create_offer_ix(
+            &seller.pubkey(),
+            &token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 1_000_000_000,
+                expiration,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[sell_ix], &[seller]).await.unwrap();
+        sell_offers.push(offer_account);
+    }
+    let (sell_offer, patient_offer) = (sell_offers[0], sell_offers[1]);
+
+    // An hour later a keeper sweeps all three.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 3_600,
+        ..clock
+    });
+    let mut accounts = vec![AccountMeta::new_readonly(keeper.pubkey(), true)];
+    for (offer_account, maker, book) in [
+        (
+            buy_offer,
+            buyer.pubkey(),
+            order_book_pda(&sol, &token_mint).0,
+        ),
+        (
+            patient_offer,
+            patient_seller.pubkey(),
+            order_book_pda(&token_mint, &sol).0,
+        ),
+        (
+            sell_offer,
+            seller.pubkey(),
+            order_book_pda(&token_mint, &sol).0,
+        ),
+    ] {
+        accounts.push(AccountMeta::new(offer_account, false));
+        accounts.push(AccountMeta::new(maker, false));
+        accounts.push(AccountMeta::new(maker_index_pda(&maker).0, false));
+        accounts.push(AccountMeta::new(book, false));
+    }
+    let sweep_ix = Instruction {
+        program_id: program_id(),
+        accounts,
+        data: borsh::to_vec(&SwapInstruction::SweepExpired).unwrap(),
+    };
+    let buyer_balance_before = get_sol_balance(&mut context, &buyer.pubkey()).await;
+    let blockhash = context.0.get_latest_blockhash().await.unwrap();
+    let mut transaction = Transaction::new_with_payer(&[sweep_ix], Some(&context.1.pubkey()));
+    transaction.sign(&[&context.1, &keeper], blockhash);
+    let result = context
+        .0
+        .process_transaction_with_metadata(transaction)
+        .await
+        .unwrap();
+    assert_eq!(result.result, Ok(()));
+
+    // One event each for the two that ran out of time, in the order they were swept.
+    let return_data = result.metadata.unwrap().return_data.unwrap();
+    assert_eq!(return_data.program_id, program_id());
+    let events: Vec<OfferExpiredEvent> = borsh::from_slice(&return_data.data).unwrap();
+    assert_eq!(
+        events,
+        vec![
+            OfferExpiredEvent {
+                offer: buy_offer,
+                maker: buyer.pubkey(),
+                refunded_lamports: 1_000_000_000,
+                expiration,
+            },
+            OfferExpiredEvent {
+                offer: sell_offer,
+                maker: seller.pubkey(),
+                refunded_lamports: 0,
+                expiration,
+            },
+        ]
+    );
+
+    // The expired ones are closed out (with the escrow back home); the other one is untouched.
+    let offer = get_offer(&mut context, &buy_offer).await;
+    assert_eq!(offer.status, OfferStatus::Expired);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    assert_eq!(
+        get_sol_balance(&mut context, &buyer.pubkey()).await,
+        buyer_balance_before + 1_000_000_000
+    );
+    assert_eq!(
+        get_offer(&mut context, &sell_offer).await.status,
+        OfferStatus::Expired
+    );
+    assert_eq!(
+        get_offer(&mut context, &patient_offer).await.status,
+        OfferStatus::Active
+    );
+    msg!("test_sweep_expired_emits_events_for_expired_only PASSED");
+}
+
+#[tokio::test]
+async fn test_reopen_cancelled_offer_within_grace() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::ReopenGrace(600)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &taker_token_account,
+        &mint_authority,
+        20,
+    )
+    .await;
+
+    // The maker escrows 1 SOL for 10 tokens, then cancels by mistake.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &sol,
+            &token_mint,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Declined);
+    assert_eq!(offer.cancelled_at, clock.unix_timestamp);
+
+    // Five minutes later they take it back, putting the SOL in again.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 300,
+        ..clock.clone()
+    });
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(
+        &mut context,
+        &[reopen_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &sol,
+            &token_mint,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    assert_eq!(offer.escrow_sol_amount, 1_000_000_000);
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before - 1_000_000_000
+    );
+
+    // And it trades like it never went away.
+    let taker_balance_before = get_sol_balance(&mut context, &taker.pubkey()).await;
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &sol,
+        &token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Accepted
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        10
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &taker.pubkey()).await,
+        taker_balance_before + 1_000_000_000
+    );
+
+    // A sell offer (of the taker's other 10) cancelled and left for longer than the grace
+    // stays cancelled.
+    let (sell_offer, _) = offer_pda(&taker.pubkey(), &token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &taker.pubkey(),
+        &taker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&taker]).await.unwrap();
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &taker.pubkey(),
+            &sell_offer,
+            &token_mint,
+            &sol,
+        )],
+        &[&taker],
+    )
+    .await
+    .unwrap();
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 300 + 601,
+        ..clock
+    });
+    let err = send(
+        &mut context,
+        &[reopen_offer_ix(
+            &taker.pubkey(),
+            &sell_offer,
+            &token_mint,
+            &sol,
+        )],
+        &[&taker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidOfferStatus));
+    msg!("test_reopen_cancelled_offer_within_grace PASSED");
+}
+
+#[tokio::test]
+async fn test_receive_recipient_gets_the_proceeds() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let treasury = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &treasury.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    let treasury_token_account = create_token_account(&mut context, &treasury, &token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &taker_token_account,
+        &mint_authority,
+        5,
+    )
+    .await;
+
+    // The maker sells 10 tokens and buys 5, with the treasury collecting both times.
+    let (sell_offer, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let (buy_offer, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    for (offer_mint, receive_mint, token_account, offer_type, amounts) in [
+        (
+            token_mint,
+            sol,
+            maker_token_account,
+            OfferType::PublicSell,
+            (10, 1_000_000_000),
+        ),
+        (
+            sol,
+            token_mint,
+            Pubkey::new_unique(), // No token account needed for a SOL offer
+            OfferType::PublicBuy,
+            (500_000_000, 5),
+        ),
+    ] {
+        let create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &token_account,
+            &offer_mint,
+            &receive_mint,
+            SwapInstruction::CreateOffer {
+                offer_type,
+                offer_token_amount: amounts.0,
+                receive_token_amount: amounts.1,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: Some(treasury.pubkey()),
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    }
+    let offer = get_offer(&mut context, &sell_offer).await;
+    assert_eq!(offer.receive_recipient, Some(treasury.pubkey()));
+
+    // Paying the maker's own wallet instead doesn't work.
+    let err = send(
+        &mut context,
+        &[accept_offer_ix(
+            &taker.pubkey(),
+            &sell_offer,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::IncorrectOwner));
+
+    // Built from the offer, the accept pays the treasury.
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let treasury_balance_before = get_sol_balance(&mut context, &treasury.pubkey()).await;
+    let accept_sell = Instruction {
+        program_id: program_id(),
+        accounts: accept_accounts(
+            &offer,
+            &program_id(),
+            &sell_offer,
+            &taker.pubkey(),
+            &taker_token_account,
+            &maker_token_account,
+        ),
+        data: borsh::to_vec(&SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        })
+        .unwrap(),
+    };
+    send(&mut context, &[accept_sell], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &treasury.pubkey()).await,
+        treasury_balance_before + 1_000_000_000
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before
+    );
+
+    // The tokens the maker buys land in the treasury's token account.
+    let offer = get_offer(&mut context, &buy_offer).await;
+    let accept_buy = Instruction {
+        program_id: program_id(),
+        accounts: accept_accounts(
+            &offer,
+            &program_id(),
+            &buy_offer,
+            &taker.pubkey(),
+            &taker_token_account,
+            &treasury_token_account,
+        ),
+        data: borsh::to_vec(&SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        })
+        .unwrap(),
+    };
+    send(&mut context, &[accept_buy], &[&taker]).await.unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &treasury_token_account).await,
+        5
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        0
+    );
+    msg!("test_receive_recipient_gets_the_proceeds PASSED");
+}
+
+#[tokio::test]
+async fn test_trailing_instruction_bytes_are_rejected() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 10_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // `AcceptOffer` takes a flag and `AcceptOfferPartial` an amount; either with a byte left
+    // over is a broken client.
+    for data in [
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+        SwapInstruction::AcceptOfferPartial { fill_amount: 5 },
+    ] {
+        let mut accept_ix = accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &offer_token_mint,
+            &receive_token_mint,
+            data,
+        );
+        accept_ix.data.push(0);
+        let err = send(&mut context, &[accept_ix], &[&taker, &maker])
+            .await
+            .unwrap_err();
+        assert_eq!(err, custom_error(SwapError::MalformedInstructionData));
+    }
+
+    // Nothing was traded, and a button that doesn't exist is still just an unknown instruction.
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Active
+    );
+    let unknown_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![],
+        data: vec![255],
+    };
+    let err = send(&mut context, &[unknown_ix], &[]).await.unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+    msg!("test_trailing_instruction_bytes_are_rejected PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_sealed_offer_from_vault() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // The maker seals 10 tokens for 1 SOL into the offer's vault.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let vault = create_offer_vault(&mut context, &offer_account, &token_mint).await;
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    create_ix
+        .accounts
+        .push(AccountMeta::new_readonly(maker.pubkey(), false)); // No taker for a public offer
+    create_ix.accounts.push(AccountMeta::new(vault, false));
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        0
+    );
+    assert_eq!(get_token_balance(&mut context, &vault).await, 10);
+
+    // The taker buys 4 straight out of the vault; the maker doesn't sign.
+    let offer = get_offer(&mut context, &offer_account).await;
+    let accounts = accept_accounts(
+        &offer,
+        &program_id(),
+        &offer_account,
+        &taker.pubkey(),
+        &taker_token_account,
+        &maker_token_account,
+    );
+    assert_eq!(accounts[2], AccountMeta::new(maker.pubkey(), false));
+    assert_eq!(accounts[3], AccountMeta::new(vault, false));
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let accept_ix = Instruction {
+        program_id: program_id(),
+        accounts,
+        data: borsh::to_vec(&SwapInstruction::AcceptOfferPartial { fill_amount: 4 }).unwrap(),
+    };
+    send(&mut context, &[accept_ix], &[&taker]).await.unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        4
+    );
+    assert_eq!(get_token_balance(&mut context, &vault).await, 6);
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 400_000_000
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::PartiallyFilled);
+    assert_eq!(offer.escrow_token_amount, 6);
+
+    // Cancelling hands the rest of the vault back to the maker.
+    let mut cancel_ix = cancel_offer_ix(&maker.pubkey(), &offer_account, &token_mint, &sol);
+    cancel_ix.accounts.extend([
+        AccountMeta::new(vault, false),
+        AccountMeta::new(maker_token_account, false),
+        AccountMeta::new_readonly(token_mint, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+    send(&mut context, &[cancel_ix], &[&maker]).await.unwrap();
+    assert_eq!(get_token_balance(&mut context, &vault).await, 0);
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        6
+    );
+    msg!("test_accept_sealed_offer_from_vault PASSED");
+}
+
+#[tokio::test]
+async fn test_cancel_sealed_offer_refunds_all_tokens() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        25,
+    )
+    .await;
+
+    // The maker seals 10 of their 25 tokens into the offer's vault.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let vault = create_offer_vault(&mut context, &offer_account, &token_mint).await;
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    create_ix
+        .accounts
+        .push(AccountMeta::new_readonly(maker.pubkey(), false)); // No taker for a public offer
+    create_ix.accounts.push(AccountMeta::new(vault, false));
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    assert_eq!(get_token_balance(&mut context, &vault).await, 10);
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        15
+    );
+
+    // Without the vault accounts there's nothing to refund from.
+    let cancel_ix = cancel_offer_ix(&maker.pubkey(), &offer_account, &token_mint, &sol);
+    let err = send(&mut context, std::slice::from_ref(&cancel_ix), &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MissingRequiredAccount));
+
+    // With them, every sealed token goes home.
+    let mut cancel_ix = cancel_ix;
+    cancel_ix.accounts.extend([
+        AccountMeta::new(vault, false),
+        AccountMeta::new(maker_token_account, false),
+        AccountMeta::new_readonly(token_mint, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+    send(&mut context, &[cancel_ix], &[&maker]).await.unwrap();
+    assert_eq!(get_token_balance(&mut context, &vault).await, 0);
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        25
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Declined);
+    assert_eq!(offer.escrow_token_amount, 0);
+    msg!("test_cancel_sealed_offer_refunds_all_tokens PASSED");
+}
+
+// A direct offer of 10 tokens for 1 SOL to `taker` that waits for them to confirm.
+async fn create_pending_direct_offer(
+    context: &mut (BanksClient, Keypair, Hash),
+    maker: &Keypair,
+    taker: &Pubkey,
+) -> (Pubkey, Pubkey, Pubkey) {
+    let mint_authority = Keypair::new();
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(context, maker, &token_mint).await;
+    mint_to(
+        context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::Direct,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: true,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    create_ix
+        .accounts
+        .push(AccountMeta::new_readonly(*taker, false));
+    send(context, &[create_ix], &[maker]).await.unwrap();
+    (offer_account, token_mint, maker_token_account)
+}
+
+#[tokio::test]
+async fn test_confirm_interest_then_accept() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let stranger = Keypair::new();
+    for wallet in [&maker, &taker, &stranger] {
+        fund_account(&mut context, &wallet.pubkey(), 10_000_000_000).await;
+    }
+    let sol = spl_token::native_mint::id();
+    let (offer_account, token_mint, maker_token_account) =
+        create_pending_direct_offer(&mut context, &maker, &taker.pubkey()).await;
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Pending
+    );
+
+    // Only the named taker can confirm.
+    let err = send(
+        &mut context,
+        &[confirm_interest_ix(&stranger.pubkey(), &offer_account)],
+        &[&stranger],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+    send(
+        &mut context,
+        &[confirm_interest_ix(&taker.pubkey(), &offer_account)],
+        &[&taker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Active
+    );
+
+    // Now it takes like any other direct offer.
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    send(
+        &mut context,
+        &[accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        10
+    );
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Accepted
+    );
+    msg!("test_confirm_interest_then_accept PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_pending_offer_fails() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let (offer_account, token_mint, maker_token_account) =
+        create_pending_direct_offer(&mut context, &maker, &taker.pubkey()).await;
+
+    // The taker hasn't confirmed, so it can't be accepted yet.
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    let err = send(
+        &mut context,
+        &[accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &token_mint,
+            &sol,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidOfferStatus));
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        0
+    );
+
+    // The maker can still withdraw it while it waits.
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &token_mint,
+            &sol,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Declined
+    );
+    msg!("test_accept_pending_offer_fails PASSED");
+}
+
+#[tokio::test]
+async fn test_counter_refunds_sol_escrow_to_maker() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &taker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // The maker escrows 2 SOL for 10 tokens.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 2_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+
+    // The taker wants 3 SOL instead; the maker's 2 SOL come straight back out of the escrow.
+    let mut counter_ix = counter_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &taker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CounterOffer {
+            offer_token_amount: 10,
+            receive_token_amount: 3_000_000_000,
+            expiration: None,
+            start_time: None,
+        },
+    );
+
+    // Without the maker's wallet there's nowhere to refund to, so the counter is refused.
+    let original_maker_sol = counter_ix.accounts.pop().unwrap();
+    let err = send(&mut context, std::slice::from_ref(&counter_ix), &[&taker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MissingMakerSolAccount));
+
+    counter_ix.accounts.push(original_maker_sol);
+    send(&mut context, &[counter_ix], &[&taker]).await.unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 2_000_000_000
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Countered);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    assert_eq!(
+        get_sol_balance(&mut context, &offer_account).await,
+        offer.rent_reserved
+    );
+    msg!("test_counter_refunds_sol_escrow_to_maker PASSED");
+}
+
+#[tokio::test]
+async fn test_expire_offer_refunds_and_closes() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let buyer = Keypair::new();
+    let seller = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &buyer.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &seller.pubkey(), 10_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let seller_token_account = create_token_account(&mut context, &seller, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &seller_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // The buyer escrows 1 SOL and the seller seals 10 tokens, both for a minute.
+    let (buy_offer, _) = offer_pda(&buyer.pubkey(), &sol, &token_mint);
+    let (sell_offer, _) = offer_pda(&seller.pubkey(), &token_mint, &sol);
+    let vault = create_offer_vault(&mut context, &sell_offer, &token_mint).await;
+    for (maker, token_account, offer_mint, receive_mint, offer_type, amounts) in [
+        (
+            &buyer,
+            Pubkey::new_unique(), // No token account needed for a SOL offer
+            sol,
+            token_mint,
+            OfferType::PublicBuy,
+            (1_000_000_000, 10),
+        ),
+        (
+            &seller,
+            seller_token_account,
+            token_mint,
+            sol,
+            OfferType::PublicSell,
+            (10, 1_000_000_000),
+        ),
+    ] {
+        let mut create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &token_account,
+            &offer_mint,
+            &receive_mint,
+            SwapInstruction::CreateOffer {
+                offer_type,
+                offer_token_amount: amounts.0,
+                receive_token_amount: amounts.1,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: Some(60),
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        if offer_type == OfferType::PublicSell {
+            create_ix
+                .accounts
+                .push(AccountMeta::new_readonly(maker.pubkey(), false)); // No taker for a public offer
+            create_ix.accounts.push(AccountMeta::new(vault, false));
+        }
+        send(&mut context, &[create_ix], &[maker]).await.unwrap();
+    }
+    let mut expire_sell_ix = expire_offer_ix(&sell_offer, &seller.pubkey(), &token_mint, &sol);
+    expire_sell_ix.accounts.extend([
+        AccountMeta::new(vault, false),
+        AccountMeta::new(seller_token_account, false),
+        AccountMeta::new_readonly(token_mint, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+
+    // Nobody can expire them early.
+    let err = send(
+        &mut context,
+        &[expire_offer_ix(
+            &buy_offer,
+            &buyer.pubkey(),
+            &sol,
+            &token_mint,
+        )],
+        &[],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidOfferStatus));
+
+    // An hour later anyone can, and the makers get everything back.
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 3_600,
+        ..clock
+    });
+    let buy_offer_lamports = get_sol_balance(&mut context, &buy_offer).await;
+    let buyer_balance_before = get_sol_balance(&mut context, &buyer.pubkey()).await;
+    send(
+        &mut context,
+        &[
+            expire_offer_ix(&buy_offer, &buyer.pubkey(), &sol, &token_mint),
+            expire_sell_ix,
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &buyer.pubkey()).await,
+        buyer_balance_before + buy_offer_lamports
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &seller_token_account).await,
+        10
+    );
+    assert_eq!(get_token_balance(&mut context, &vault).await, 0);
+    for offer_account in [buy_offer, sell_offer] {
+        assert!(
+            context
+                .0
+                .get_account(offer_account)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+    msg!("test_expire_offer_refunds_and_closes PASSED");
+}
+
+#[tokio::test]
+async fn test_cancel_all_for_pair_skips_other_offers() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let stranger = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &stranger.pubkey(), 10_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // The maker buys and sells the token; a stranger buys it too.
+    let mut offers = Vec::new();
+    for (owner, token_account, offer_mint, receive_mint, offer_type, amounts) in [
+        (
+            &maker,
+            Pubkey::new_unique(), // No token account needed for a SOL offer
+            sol,
+            token_mint,
+            OfferType::PublicBuy,
+            (1_000_000_000, 10),
+        ),
+        (
+            &maker,
+            maker_token_account,
+            token_mint,
+            sol,
+            OfferType::PublicSell,
+            (10, 1_000_000_000),
+        ),
+        (
+            &stranger,
+            Pubkey::new_unique(),
+            sol,
+            token_mint,
+            OfferType::PublicBuy,
+            (2_000_000_000, 10),
+        ),
+    ] {
+        let (offer_account, _) = offer_pda(&owner.pubkey(), &offer_mint, &receive_mint);
+        let create_ix = create_offer_ix(
+            &owner.pubkey(),
+            &token_account,
+            &offer_mint,
+            &receive_mint,
+            SwapInstruction::CreateOffer {
+                offer_type,
+                offer_token_amount: amounts.0,
+                receive_token_amount: amounts.1,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[create_ix], &[owner]).await.unwrap();
+        offers.push(offer_account);
+    }
+    let (buy_offer, sell_offer, stranger_offer) = (offers[0], offers[1], offers[2]);
+
+    // Winding down buying: only the maker's buy offer goes, and its escrow comes back.
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let cancel_all_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+            AccountMeta::new(
+                recent_cancel_pda(&maker.pubkey(), &sol, &token_mint).0,
+                false,
+            ),
+            AccountMeta::new(order_book_pda(&sol, &token_mint).0, false),
+            AccountMeta::new(buy_offer, false),
+            AccountMeta::new(sell_offer, false),
+            AccountMeta::new(stranger_offer, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::CancelAllForPair {
+            offer_token_mint: sol,
+            receive_token_mint: token_mint,
+        })
+        .unwrap(),
+    };
+    send(&mut context, &[cancel_all_ix], &[&maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_000_000_000
+    );
+    assert_eq!(
+        get_offer(&mut context, &buy_offer).await.status,
+        OfferStatus::Declined
+    );
+    for offer_account in [sell_offer, stranger_offer] {
+        assert_eq!(
+            get_offer(&mut context, &offer_account).await.status,
+            OfferStatus::Active
+        );
+    }
+    msg!("test_cancel_all_for_pair_skips_other_offers PASSED");
+}
+
+#[tokio::test]
+async fn test_cancel_and_accept_can_close_the_offer() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // The maker buys with 1 SOL and sells 10 tokens.
+    let (buy_offer, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let (sell_offer, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    for (token_account, offer_mint, receive_mint, offer_type, amounts) in [
+        (
+            Pubkey::new_unique(), // No token account needed for a SOL offer
+            sol,
+            token_mint,
+            OfferType::PublicBuy,
+            (1_000_000_000, 10),
+        ),
+        (
+            maker_token_account,
+            token_mint,
+            sol,
+            OfferType::PublicSell,
+            (10, 1_000_000_000),
+        ),
+    ] {
+        let create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &token_account,
+            &offer_mint,
+            &receive_mint,
+            SwapInstruction::CreateOffer {
+                offer_type,
+                offer_token_amount: amounts.0,
+                receive_token_amount: amounts.1,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    }
+
+    // Cancelling the buy offer with close hands back the escrow and the rent.
+    let buy_offer_lamports = get_sol_balance(&mut context, &buy_offer).await;
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let mut cancel_ix = cancel_offer_ix(&maker.pubkey(), &buy_offer, &sol, &token_mint);
+    cancel_ix.data = borsh::to_vec(&SwapInstruction::CancelOffer {
+        close_account: true,
+    })
+    .unwrap();
+    send(&mut context, &[cancel_ix], &[&maker]).await.unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + buy_offer_lamports
+    );
+    assert!(context.0.get_account(buy_offer).await.unwrap().is_none());
+
+    // Accepting the sell offer with close gives the maker the price plus the rent.
+    let sell_offer_lamports = get_sol_balance(&mut context, &sell_offer).await;
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let mut accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &sell_offer,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::AcceptOffer {
+            close_account: true,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    accept_ix.accounts[2] = AccountMeta::new(maker.pubkey(), true); // Gets the rent
+    send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        10
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_000_000_000 + sell_offer_lamports
+    );
+    assert!(context.0.get_account(sell_offer).await.unwrap().is_none());
+    msg!("test_cancel_and_accept_can_close_the_offer PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_fee_goes_to_treasury() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let treasury = Keypair::new();
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    for wallet in [&admin, &treasury, &maker, &taker] {
+        fund_account(&mut context, &wallet.pubkey(), 5_000_000_000).await;
+    }
+    send(
+        &mut context,
+        &[initialize_config_ix(&admin.pubkey())],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    // More than 10% is refused.
+    let err = send(
+        &mut context,
+        &[update_config_ix(
+            &admin.pubkey(),
+            ConfigField::FeeBps(1_001),
+        )],
+        &[&admin],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::FeeTooHigh));
+    send(
+        &mut context,
+        &[
+            update_config_ix(&admin.pubkey(), ConfigField::FeeBps(250)),
+            update_config_ix(&admin.pubkey(), ConfigField::Treasury(treasury.pubkey())),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    let treasury_token_account = create_token_account(&mut context, &treasury, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &taker_token_account,
+        &mint_authority,
+        40,
+    )
+    .await;
+
+    // The maker sells 10 tokens for 1 SOL, and buys 40 back for another.
+    let (sell_offer, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let (buy_offer, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    for (offer_type, offer_mint, receive_mint, amounts) in [
+        (OfferType::PublicSell, token_mint, sol, (10, 1_000_000_000)),
+        (OfferType::PublicBuy, sol, token_mint, (1_000_000_000, 40)),
+    ] {
+        let create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &offer_mint,
+            &receive_mint,
+            SwapInstruction::CreateOffer {
+                offer_type,
+                offer_token_amount: amounts.0,
+                receive_token_amount: amounts.1,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    }
+
+    let accept_ix = |offer_account: &Pubkey, offer_mint: &Pubkey, receive_mint: &Pubkey| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            offer_mint,
+            receive_mint,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )
+    };
+
+    // With a fee set, the treasury has to be there, and be the configured one.
+    let err = send(
+        &mut context,
+        &[accept_ix(&sell_offer, &token_mint, &sol)],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MissingRequiredAccount));
+    let mut sell_ix = accept_ix(&sell_offer, &token_mint, &sol);
+    sell_ix
+        .accounts
+        .push(AccountMeta::new(admin.pubkey(), false));
+    let err = send(
+        &mut context,
+        std::slice::from_ref(&sell_ix),
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidAccountInput));
+
+    // Selling for SOL: 2.5% of the SOL goes to the treasury wallet.
+    sell_ix.accounts[17] = AccountMeta::new(treasury.pubkey(), false);
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(&mut context, &[sell_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &treasury.pubkey()).await,
+        5_000_000_000 + 25_000_000
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 975_000_000
+    );
+
+    // Buying tokens: 2.5% of the 40 tokens goes to the treasury's token account.
+    let mut buy_ix = accept_ix(&buy_offer, &sol, &token_mint);
+    buy_ix
+        .accounts
+        .push(AccountMeta::new(treasury_token_account, false));
+    send(&mut context, &[buy_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &treasury_token_account).await,
+        1
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        39
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        10
+    );
+    msg!("test_accept_fee_goes_to_treasury PASSED");
+}
+
+#[tokio::test]
+async fn test_premium_order_prices_off_latest_trade() {
+    let program_test = ProgramTest::new("soffer", program_id(), processor!(Processor::process));
+    let mut program_context = program_test.start_with_context().await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 5_000_000_000).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // 10 tokens at 5% over the last trade, or 1 SOL for the lot if nothing has traded yet.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreatePremiumOrder {
+            offer_token_amount: 10,
+            premium_bps: 500,
+            floor_receive_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.offer_type, OfferType::PremiumOrder);
+    assert_eq!(offer.premium_bps, 500);
+
+    let history_key = history_pda(&token_mint, &sol).0;
+    let quote_ix = |history: Pubkey| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(offer_account, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new_readonly(history, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::QuoteAccept { fill_amount: None }).unwrap(),
+    };
+
+    // No history yet, so it's the floor.
+    let quote = borsh::from_slice::<Quote>(
+        &simulate_return_data(&mut context, &[quote_ix(history_key)]).await,
+    )
+    .unwrap();
+    assert_eq!(quote.sol_amount, 1_000_000_000);
+
+    // The latest trade was 2 tokens for 0.3 SOL, so 10 tokens at 5% over is 1.575 SOL.
+    let mut history = TradeHistory::default();
+    history.record(Trade {
+        base_amount: 1,
+        quote_amount: 50_000_000,
+        timestamp: 0,
+    });
+    history.record(Trade {
+        base_amount: 2,
+        quote_amount: 300_000_000,
+        timestamp: 1,
+    });
+    let mut data = borsh::to_vec(&history).unwrap();
+    data.resize(TradeHistory::MAX_LEN, 0);
+    program_context.set_account(
+        &history_key,
+        &Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: program_id(),
+            executable: false,
+            rent_epoch: 0,
+        }
+        .into(),
+    );
+    let quote = borsh::from_slice::<Quote>(
+        &simulate_return_data(&mut context, &[quote_ix(history_key)]).await,
+    )
+    .unwrap();
+    assert_eq!(quote.sol_amount, 1_575_000_000);
+
+    let mut accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    // No fee, so any treasury will do; the history has to be the pair's real one.
+    accept_ix
+        .accounts
+        .push(AccountMeta::new(taker.pubkey(), false));
+    accept_ix
+        .accounts
+        .push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+    let err = send(
+        &mut context,
+        std::slice::from_ref(&accept_ix),
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidProgramAddress));
+
+    accept_ix.accounts[18] = AccountMeta::new_readonly(history_key, false);
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_575_000_000
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        10
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    assert_eq!(offer.settled_receive_amount, 1_575_000_000);
+    msg!("test_premium_order_prices_off_latest_trade PASSED");
+}
+
+#[tokio::test]
+async fn test_update_offer_moves_sol_escrow() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let stranger = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &stranger.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // The maker escrows 1 SOL for 10 tokens, good for an hour or so.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: Some(3_600),
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let offer_lamports = get_sol_balance(&mut context, &offer_account).await;
+
+    // Only the maker gets to reprice.
+    let mut stranger_ix = update_offer_ix(
+        &stranger.pubkey(),
+        &offer_account,
+        SwapInstruction::UpdateOffer {
+            new_offer_amount: Some(1),
+            new_receive_amount: None,
+            new_expiration: None,
+        },
+    );
+    stranger_ix.accounts[0] = AccountMeta::new(stranger.pubkey(), true);
+    let err = send(&mut context, &[stranger_ix], &[&stranger])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+
+    // Bidding 2 SOL pulls another 1 SOL into escrow.
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let raise_ix = update_offer_ix(
+        &maker.pubkey(),
+        &offer_account,
+        SwapInstruction::UpdateOffer {
+            new_offer_amount: Some(2_000_000_000),
+            new_receive_amount: None,
+            new_expiration: None,
+        },
+    );
+    send(&mut context, &[raise_ix], &[&maker]).await.unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before - 1_000_000_000
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.escrow_sol_amount, 2_000_000_000);
+    assert_eq!(offer.offer_token_amount, 2_000_000_000);
+
+    // Half a SOL for 5 tokens, never expiring, hands 1.5 SOL back.
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let lower_ix = update_offer_ix(
+        &maker.pubkey(),
+        &offer_account,
+        SwapInstruction::UpdateOffer {
+            new_offer_amount: Some(500_000_000),
+            new_receive_amount: Some(5),
+            new_expiration: Some(None),
+        },
+    );
+    send(&mut context, &[lower_ix], &[&maker]).await.unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_500_000_000
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.escrow_sol_amount, 500_000_000);
+    assert_eq!(offer.receive_token_amount, 5);
+    assert_eq!(offer.expiration, None);
+    assert_eq!(offer.status, OfferStatus::Active);
+    assert_eq!(
+        get_sol_balance(&mut context, &offer_account).await,
+        offer_lamports - 500_000_000
+    );
+
+    // Nothing for nothing, and nothing once it's cancelled.
+    let zero_ix = update_offer_ix(
+        &maker.pubkey(),
+        &offer_account,
+        SwapInstruction::UpdateOffer {
+            new_offer_amount: None,
+            new_receive_amount: Some(0),
+            new_expiration: None,
+        },
+    );
+    let err = send(&mut context, &[zero_ix], &[&maker]).await.unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidAmount));
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &sol,
+            &token_mint,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    let late_ix = update_offer_ix(
+        &maker.pubkey(),
+        &offer_account,
+        SwapInstruction::UpdateOffer {
+            new_offer_amount: None,
+            new_receive_amount: Some(6),
+            new_expiration: None,
+        },
+    );
+    let err = send(&mut context, &[late_ix], &[&maker]).await.unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidOfferStatus));
+    msg!("test_update_offer_moves_sol_escrow PASSED");
+}
+
+#[tokio::test]
+async fn test_update_offer_keeps_expiration_in_line_with_schedule() {
+    let mut context = start_program().await;
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        1,
+    )
+    .await;
+
+    // A Dutch auction's price falls until its expiration, so that can't move.
+    let (auction_account, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let create_auction_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateDutchAuction {
+            offer_token_amount: 1,
+            start_price: 2_000_000_000,
+            end_price: 1_000_000_000,
+            start_time: Some(clock.unix_timestamp),
+            expiration: clock.unix_timestamp + 3_600,
+            min_acceptable_receive: 0,
+        },
+    );
+    send(&mut context, &[create_auction_ix], &[&maker])
+        .await
+        .unwrap();
+    let stretch_ix = update_offer_ix(
+        &maker.pubkey(),
+        &auction_account,
+        SwapInstruction::UpdateOffer {
+            new_offer_amount: None,
+            new_receive_amount: None,
+            new_expiration: Some(Some(clock.unix_timestamp + 7_200)),
+        },
+    );
+    let err = send(&mut context, &[stretch_ix], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+    assert_eq!(
+        get_offer(&mut context, &auction_account).await.expiration,
+        Some(clock.unix_timestamp + 3_600)
+    );
+
+    // A bid that opens in an hour can't be made to expire before then.
+    let (bid_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let create_bid_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 10,
+            expiration: Some(clock.unix_timestamp + 7_200),
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: Some(clock.unix_timestamp + 3_600),
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_bid_ix], &[&maker])
+        .await
+        .unwrap();
+    let expire_at = |expiration| {
+        update_offer_ix(
+            &maker.pubkey(),
+            &bid_account,
+            SwapInstruction::UpdateOffer {
+                new_offer_amount: None,
+                new_receive_amount: None,
+                new_expiration: Some(expiration),
+            },
+        )
+    };
+    let err = send(
+        &mut context,
+        &[expire_at(Some(clock.unix_timestamp + 1_800))],
+        &[&maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+
+    // Later than that, or never, is fine.
+    send(
+        &mut context,
+        &[expire_at(Some(clock.unix_timestamp + 5_400))],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_offer(&mut context, &bid_account).await.expiration,
+        Some(clock.unix_timestamp + 5_400)
+    );
+    send(&mut context, &[expire_at(None)], &[&maker])
+        .await
+        .unwrap();
+    assert_eq!(get_offer(&mut context, &bid_account).await.expiration, None);
+    msg!("test_update_offer_keeps_expiration_in_line_with_schedule PASSED");
+}
+
+#[tokio::test]
+async fn test_update_offer_batch_reprices_makers_offers() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let stranger = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &stranger.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+
+    // The maker sells two tokens for SOL; a stranger sells the first one too.
+    let mut offers = vec![];
+    let first_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let second_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    for (seller, mint) in [
+        (&maker, first_mint),
+        (&maker, second_mint),
+        (&stranger, first_mint),
+    ] {
+        let token_account = create_token_account(&mut context, seller, &mint).await;
+        mint_to(&mut context, &mint, &token_account, &mint_authority, 10).await;
+        let (offer_account, _) = offer_pda(&seller.pubkey(), &mint, &sol);
+        let create_ix = create_offer_ix(
+            &seller.pubkey(),
+            &token_account,
+            &mint,
+            &sol,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 1_000,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[create_ix], &[seller]).await.unwrap();
+        offers.push(offer_account);
+    }
+
+    // Prices went up: 1_500 for the first lot, and 5 of the second for 2_000.
+    let updates = vec![
+        OfferUpdate {
+            new_offer_amount: None,
+            new_receive_amount: Some(1_500),
+        },
+        OfferUpdate {
+            new_offer_amount: Some(5),
+            new_receive_amount: Some(2_000),
+        },
+        OfferUpdate {
+            new_offer_amount: None,
+            new_receive_amount: Some(1),
+        },
+    ];
+    let mut accounts = vec![
+        AccountMeta::new(maker.pubkey(), true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(config_pda().0, false),
+    ];
+    for offer_account in &offers {
+        accounts.push(AccountMeta::new(*offer_account, false));
+        accounts.push(AccountMeta::new_readonly(
+            settlement_pda(offer_account).0,
+            false,
+        ));
+    }
+    let batch_ix = Instruction {
+        program_id: program_id(),
+        accounts,
+        data: borsh::to_vec(&SwapInstruction::UpdateOfferBatch { updates }).unwrap(),
+    };
+    send(&mut context, &[batch_ix], &[&maker]).await.unwrap();
+
+    let first = get_offer(&mut context, &offers[0]).await;
+    assert_eq!(
+        (first.offer_token_amount, first.receive_token_amount),
+        (10, 1_500)
+    );
+    let second = get_offer(&mut context, &offers[1]).await;
+    assert_eq!(
+        (second.offer_token_amount, second.receive_token_amount),
+        (5, 2_000)
+    );
+    // The stranger's offer isn't the maker's to reprice, so it was skipped.
+    let theirs = get_offer(&mut context, &offers[2]).await;
+    assert_eq!(theirs.receive_token_amount, 1_000);
+    msg!("test_update_offer_batch_reprices_makers_offers PASSED");
+}
+
+#[tokio::test]
+async fn test_maker_cannot_accept_own_offer() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // Neither the whole offer nor a piece of it.
+    for data in [
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+        SwapInstruction::AcceptOfferPartial { fill_amount: 5 },
+    ] {
+        let accept_ix = accept_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &maker_token_account,
+            &token_mint,
+            &sol,
+            data,
+        );
+        let err = send(&mut context, &[accept_ix], &[&maker])
+            .await
+            .unwrap_err();
+        assert_eq!(err, custom_error(SwapError::SelfTrade));
+    }
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    assert_eq!(offer.offer_token_amount, 10);
+    msg!("test_maker_cannot_accept_own_offer PASSED");
+}
+
+// What `CanAccept` says about `taker` taking the offer.
+async fn can_accept(
+    context: &mut (BanksClient, Keypair, Hash),
+    offer_account: &Pubkey,
+    offer_token_mint: &Pubkey,
+    receive_token_mint: &Pubkey,
+    taker: &Pubkey,
+) -> AcceptEligibility {
+    let can_accept_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(*offer_account, false),
+            AccountMeta::new_readonly(mint_pause_pda(offer_token_mint).0, false),
+            AccountMeta::new_readonly(mint_pause_pda(receive_token_mint).0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::CanAccept { taker: *taker }).unwrap(),
+    };
+    borsh::from_slice(&simulate_return_data(context, &[can_accept_ix]).await).unwrap()
+}
+
+#[tokio::test]
+async fn test_can_accept_reports_eligibility() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let stranger = Keypair::new();
+    for wallet in [&admin, &maker, &taker] {
+        fund_account(&mut context, &wallet.pubkey(), 10_000_000_000).await;
+    }
+    let sol = spl_token::native_mint::id();
+    let (offer_account, token_mint, _) =
+        create_pending_direct_offer(&mut context, &maker, &taker.pubkey()).await;
+    let blocked = |reason| AcceptEligibility {
+        allowed: false,
+        reason: Some(reason),
+    };
+
+    // Nobody can take it until the named taker confirms.
+    assert_eq!(
+        can_accept(
+            &mut context,
+            &offer_account,
+            &token_mint,
+            &sol,
+            &taker.pubkey()
+        )
+        .await,
+        blocked(AcceptBlocker::AwaitingConfirmation)
+    );
+    send(
+        &mut context,
+        &[confirm_interest_ix(&taker.pubkey(), &offer_account)],
+        &[&taker],
+    )
+    .await
+    .unwrap();
+
+    // Then only they can.
+    assert_eq!(
+        can_accept(
+            &mut context,
+            &offer_account,
+            &token_mint,
+            &sol,
+            &taker.pubkey()
+        )
+        .await,
+        AcceptEligibility {
+            allowed: true,
+            reason: None,
+        }
+    );
+    assert_eq!(
+        can_accept(
+            &mut context,
+            &offer_account,
+            &token_mint,
+            &sol,
+            &stranger.pubkey()
+        )
+        .await,
+        blocked(AcceptBlocker::NotTheTaker)
+    );
+    assert_eq!(
+        can_accept(
+            &mut context,
+            &offer_account,
+            &token_mint,
+            &sol,
+            &maker.pubkey()
+        )
+        .await,
+        blocked(AcceptBlocker::SelfTrade)
+    );
+
+    // ...unless the token is paused.
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            pause_mint_ix(&admin.pubkey(), &token_mint),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        can_accept(
+            &mut context,
+            &offer_account,
+            &token_mint,
+            &sol,
+            &taker.pubkey()
+        )
+        .await,
+        blocked(AcceptBlocker::MintPaused)
+    );
+    msg!("test_can_accept_reports_eligibility PASSED");
+}
+
+#[tokio::test]
+async fn test_yield_offer_deposits_on_create_and_withdraws_on_cancel() {
+    let mut program_test = ProgramTest::new("soffer", program_id(), processor!(Processor::process));
+    program_test.add_program(
+        "mock_yield",
+        mock_yield_id(),
+        processor!(mock_yield_process),
+    );
+    // The vault has already earned 1_000_000 lamports by the time the offer comes back.
+    let yield_vault = Pubkey::new_unique();
+    let vault_rent = Rent::default().minimum_balance(0);
+    program_test.add_account(
+        yield_vault,
+        Account {
+            lamports: vault_rent + 1_000_000,
+            owner: mock_yield_id(),
+            ..Default::default()
+        },
+    );
+    let mut context = program_test.start().await;
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = spl_token::native_mint::id(); // Maker offers SOL
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // 5 SOL for 10 tokens, with the escrow earning yield in the meantime.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateYieldOffer {
+            offer_token_amount: 5_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            yield_strategy: mock_yield_id(),
+        },
+    );
+    create_ix.accounts.extend([
+        AccountMeta::new_readonly(Pubkey::new_unique(), false), // No taker
+        AccountMeta::new_readonly(mock_yield_id(), false),
+        AccountMeta::new(yield_vault, false),
+    ]);
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // The escrow went straight on to the strategy's vault.
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.yield_strategy, Some(mock_yield_id()));
+    assert_eq!(offer.yield_principal, 5_000_000_000);
+    assert_eq!(offer.escrow_sol_amount, 5_000_000_000);
+    assert_eq!(
+        get_sol_balance(&mut context, &offer_account).await,
+        offer.rent_reserved
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &yield_vault).await,
+        vault_rent + 1_000_000 + 5_000_000_000
+    );
+
+    // Cancelling without the strategy's accounts would leave the escrow behind, so it can't.
+    let cancel_ix = cancel_offer_ix(
+        &maker.pubkey(),
+        &offer_account,
+        &offer_token_mint,
+        &receive_token_mint,
+    );
+    let err = send(&mut context, std::slice::from_ref(&cancel_ix), &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MissingRequiredAccount));
+
+    // With them, everything comes back, and the maker gets the yield along with the escrow.
+    let mut cancel_ix = cancel_ix;
+    cancel_ix.accounts.extend([
+        AccountMeta::new_readonly(mock_yield_id(), false),
+        AccountMeta::new(yield_vault, false),
+    ]);
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(&mut context, &[cancel_ix], &[&maker]).await.unwrap();
+
+    assert_eq!(
+        get_sol_balance(&mut context, &yield_vault).await,
+        vault_rent
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 5_000_000_000 + 1_000_000
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Declined);
+    assert_eq!(offer.yield_principal, 0);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    msg!("test_yield_offer_deposits_on_create_and_withdraws_on_cancel PASSED");
+}
+
+#[tokio::test]
+async fn test_withdraw_yield_pays_earnings_to_the_maker() {
+    let mut program_test = ProgramTest::new("soffer", program_id(), processor!(Processor::process));
+    program_test.add_program(
+        "mock_yield",
+        mock_yield_id(),
+        processor!(mock_yield_process),
+    );
+    // The vault has already earned 1_000_000 lamports by the time the offer comes back.
+    let yield_vault = Pubkey::new_unique();
+    let vault_rent = Rent::default().minimum_balance(0);
+    program_test.add_account(
+        yield_vault,
+        Account {
+            lamports: vault_rent + 1_000_000,
+            owner: mock_yield_id(),
+            ..Default::default()
+        },
+    );
+    let mut context = program_test.start().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let offer_token_mint = spl_token::native_mint::id(); // Maker offers SOL
+    let receive_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &receive_token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &receive_token_mint).await;
+    mint_to(
+        &mut context,
+        &receive_token_mint,
+        &taker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // 5 SOL for 10 tokens, with the escrow earning yield in the meantime.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateYieldOffer {
+            offer_token_amount: 5_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            yield_strategy: mock_yield_id(),
+        },
+    );
+    create_ix.accounts.extend([
+        AccountMeta::new_readonly(Pubkey::new_unique(), false), // No taker
+        AccountMeta::new_readonly(mock_yield_id(), false),
+        AccountMeta::new(yield_vault, false),
+    ]);
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // Anyone can bring the escrow home, but only to the maker: the earnings go straight to them.
+    let withdraw_ix = |maker: &Pubkey| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(offer_account, false),
+            AccountMeta::new(*maker, false),
+            AccountMeta::new_readonly(mock_yield_id(), false),
+            AccountMeta::new(yield_vault, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::WithdrawYield).unwrap(),
+    };
+    let err = send(&mut context, &[withdraw_ix(&taker.pubkey())], &[])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::IncorrectOwner));
+
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(&mut context, &[withdraw_ix(&maker.pubkey())], &[])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_000_000
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.yield_principal, 0);
+    assert_eq!(
+        get_sol_balance(&mut context, &offer_account).await,
+        offer.rent_reserved + offer.escrow_sol_amount
+    );
+
+    // An accept that keeps the record leaves nothing but its rent behind.
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    assert_eq!(
+        get_sol_balance(&mut context, &offer_account).await,
+        offer.rent_reserved
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        10
+    );
+    msg!("test_withdraw_yield_pays_earnings_to_the_maker PASSED");
+}
+
+#[tokio::test]
+async fn test_swap_token_2022_mint_for_sol() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 2_000_000_000).await;
+
+    // The maker holds 100 tokens of a Token-2022 mint; the taker gets an account for them too.
+    let (offer_token_mint, maker_token_account) =
+        create_token_2022_account_with(&mut context, &mint_authority, &maker, 100).await;
+    let taker_token_account = Keypair::new();
+    let rent = context.0.get_rent().await.unwrap();
+    let create_taker_account = [
+        system_instruction::create_account(
+            &context.1.pubkey(),
+            &taker_token_account.pubkey(),
+            rent.minimum_balance(TokenAccount::LEN),
+            TokenAccount::LEN as u64,
+            &spl_token_2022::id(),
+        ),
+        spl_token_2022::instruction::initialize_account(
+            &spl_token_2022::id(),
+            &taker_token_account.pubkey(),
+            &offer_token_mint,
+            &taker.pubkey(),
+        )
+        .unwrap(),
+    ];
+    send(&mut context, &create_taker_account, &[&taker_token_account])
+        .await
+        .unwrap();
+    let taker_token_account = taker_token_account.pubkey();
+    let receive_token_mint = spl_token::native_mint::id(); // Maker wants SOL
+
+    // 40 tokens for 1 SOL, with Token-2022 as the token program.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 40,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    create_ix.accounts[6] = AccountMeta::new_readonly(spl_token_2022::id(), false);
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let mut accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    accept_ix.accounts[8] = AccountMeta::new_readonly(spl_token_2022::id(), false);
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        60
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        40
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_000_000_000
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+
+    // Anything besides the two token programs is still refused.
+    let mut create_ix = create_offer_ix(
+        &taker.pubkey(),
+        &taker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    create_ix.accounts[6] = AccountMeta::new_readonly(system_program::id(), false);
+    let err = send(&mut context, &[create_ix], &[&taker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidTokenProgram));
+    msg!("test_swap_token_2022_mint_for_sol PASSED");
+}
+
+#[tokio::test]
+async fn test_offer_cannot_be_taken_before_start_time() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id();
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        100,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    // A drop of 10 tokens for 1 SOL that opens in an hour.
+    let start_time = clock.unix_timestamp + 3_600;
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = |expiration: Option<i64>| {
+        create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &offer_token_mint,
+            &receive_token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 1_000_000_000,
+                expiration,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: Some(start_time),
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+
+    // One that would expire before it opens is refused.
+    let err = send(&mut context, &[create_ix(Some(start_time - 1))], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+    send(&mut context, &[create_ix(None)], &[&maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.start_time,
+        Some(start_time)
+    );
+
+    let accept_ix = |close_account: bool| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &offer_token_mint,
+            &receive_token_mint,
+            SwapInstruction::AcceptOffer {
+                close_account,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )
+    };
+    let eligibility = can_accept(
+        &mut context,
+        &offer_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        &taker.pubkey(),
+    )
+    .await;
+    assert_eq!(eligibility.reason, Some(AcceptBlocker::NotStarted));
+    let err = send(&mut context, &[accept_ix(false)], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::OfferNotStarted));
+
+    // Once the clock reaches the start time, it goes through.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: start_time,
+        ..clock
+    });
+    send(&mut context, &[accept_ix(true)], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        10
+    );
+    msg!("test_offer_cannot_be_taken_before_start_time PASSED");
+}
+
+#[tokio::test]
+async fn test_dutch_auction_sells_at_average_price_at_midpoint() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        1,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    // One NFT, from 2 SOL down to 1 SOL over an hour.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &sol);
+    let create_ix = |end_price: u64| {
+        create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &offer_token_mint,
+            &sol,
+            SwapInstruction::CreateDutchAuction {
+                offer_token_amount: 1,
+                start_price: 2_000_000_000,
+                end_price,
+                start_time: Some(clock.unix_timestamp),
+                expiration: clock.unix_timestamp + 3_600,
+                min_acceptable_receive: 0,
+            },
+        )
+    };
+
+    // The price can only come down.
+    let err = send(&mut context, &[create_ix(3_000_000_000)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+    send(&mut context, &[create_ix(1_000_000_000)], &[&maker])
+        .await
+        .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.offer_type, OfferType::DutchAuction);
+    assert_eq!(offer.end_price, 1_000_000_000);
+
+    // Half an hour in, it costs the average of the two.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 1_800,
+        ..clock
+    });
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &offer_token_mint,
+        &sol,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_500_000_000
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        1
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Accepted);
+    assert_eq!(offer.settled_receive_amount, 1_500_000_000);
+    msg!("test_dutch_auction_sells_at_average_price_at_midpoint PASSED");
+}
+
+#[tokio::test]
+async fn test_dutch_auction_expires_below_its_floor() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id(); // SOL (the native mint)
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        1,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    // One NFT, from 2 SOL down to 1 SOL over an hour, but never for less than 1.6 SOL.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &sol);
+    let create_ix = |min_acceptable_receive: u64| {
+        create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &offer_token_mint,
+            &sol,
+            SwapInstruction::CreateDutchAuction {
+                offer_token_amount: 1,
+                start_price: 2_000_000_000,
+                end_price: 1_000_000_000,
+                start_time: Some(clock.unix_timestamp),
+                expiration: clock.unix_timestamp + 3_600,
+                min_acceptable_receive,
+            },
+        )
+    };
+
+    // A floor above the start price would end it before it began.
+    let err = send(&mut context, &[create_ix(2_500_000_000)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+    send(&mut context, &[create_ix(1_600_000_000)], &[&maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_offer(&mut context, &offer_account)
+            .await
+            .min_acceptable_receive,
+        1_600_000_000
+    );
+
+    // Ten minutes in it's still above the floor...
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 600,
+        ..clock.clone()
+    });
+    let eligibility = can_accept(
+        &mut context,
+        &offer_account,
+        &offer_token_mint,
+        &sol,
+        &taker.pubkey(),
+    )
+    .await;
+    assert!(eligibility.allowed);
+
+    // ...but at half an hour it's come down to 1.5 SOL, so it's over.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 1_800,
+        ..clock.clone()
+    });
+    let eligibility = can_accept(
+        &mut context,
+        &offer_account,
+        &offer_token_mint,
+        &sol,
+        &taker.pubkey(),
+    )
+    .await;
+    assert_eq!(eligibility.reason, Some(AcceptBlocker::Expired));
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &offer_token_mint,
+        &sol,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    let err = send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::OfferExpired));
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        0
+    );
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(
+        effective_status(&offer, clock.unix_timestamp + 1_800),
+        OfferStatus::Expired
+    );
+    msg!("test_dutch_auction_expires_below_its_floor PASSED");
+}
+
+#[tokio::test]
+async fn test_clock_moved_forward_expires_offer() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let offer_token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let receive_token_mint = spl_token::native_mint::id();
+    let maker_token_account = create_token_account(&mut context, &maker, &offer_token_mint).await;
+    mint_to(
+        &mut context,
+        &offer_token_mint,
+        &maker_token_account,
+        &mint_authority,
+        100,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &offer_token_mint).await;
+
+    // 10 tokens for 1 SOL, good for another minute.
+    let expiration = clock.unix_timestamp + 60;
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &offer_token_mint, &receive_token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: Some(expiration),
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // Right at the expiration it can still be taken...
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: expiration,
+        ..clock.clone()
+    });
+    let eligibility = can_accept(
+        &mut context,
+        &offer_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        &taker.pubkey(),
+    )
+    .await;
+    assert!(eligibility.allowed);
+
+    // ...but a second later the accept finds it expired, and so does a keeper.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: expiration + 1,
+        ..clock
+    });
+    let eligibility = can_accept(
+        &mut context,
+        &offer_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        &taker.pubkey(),
+    )
+    .await;
+    assert_eq!(eligibility.reason, Some(AcceptBlocker::Expired));
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &offer_token_mint,
+        &receive_token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    let err = send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::OfferExpired));
+
+    send(
+        &mut context,
+        &[expire_offer_ix(
+            &offer_account,
+            &maker.pubkey(),
+            &offer_token_mint,
+            &receive_token_mint,
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        0
+    );
+    assert_eq!(
+        context.0.get_account(offer_account).await.unwrap(),
+        None // Expired and closed
+    );
+    msg!("test_clock_moved_forward_expires_offer PASSED");
+}
+
+#[tokio::test]
+async fn test_instruction_builder_lays_out_accounts() {
+    use soffer::instruction_builder::{self, Amounts};
+
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id();
+
+    // The builder uses associated token accounts, so that's what both parties get.
+    let mut token_accounts = vec![];
+    for owner in [&maker, &taker] {
+        let create_ix = create_associated_token_account(
+            &context.1.pubkey(),
+            &owner.pubkey(),
+            &token_mint,
+            &spl_token::id(),
+        );
+        send(&mut context, &[create_ix], &[]).await.unwrap();
+        token_accounts.push(get_associated_token_address(&owner.pubkey(), &token_mint));
+    }
+    let (maker_token_account, taker_token_account) = (token_accounts[0], token_accounts[1]);
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        100,
+    )
+    .await;
+
+    // 10 tokens for 1 SOL, then the taker buys 4 of them.
+    let create_ix = instruction_builder::create_offer(
+        &program_id(),
+        &maker.pubkey(),
+        &token_mint,
+        &sol,
+        Amounts {
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+        },
+        None,
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let (offer_account, _) =
+        instruction_builder::offer_address(&program_id(), &maker.pubkey(), &token_mint, &sol, 0);
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.offer_type, OfferType::PublicSell);
+
+    let accept_ix =
+        instruction_builder::accept_offer(&program_id(), &offer, &taker.pubkey(), Some(4));
+    send(&mut context, &[accept_ix], &[&taker, &maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        4
+    );
+
+    // The taker counters the rest with half a SOL, escrowed, then thinks better of it.
+    let offer = get_offer(&mut context, &offer_account).await;
+    let counter_ix = instruction_builder::counter_offer(
+        &program_id(),
+        &taker.pubkey(),
+        &offer,
+        Amounts {
+            offer_token_amount: 500_000_000,
+            receive_token_amount: 6,
+        },
+        None,
+    );
+    send(&mut context, &[counter_ix], &[&taker]).await.unwrap();
+    let (counter_account, _) =
+        instruction_builder::offer_address(&program_id(), &taker.pubkey(), &sol, &token_mint, 0);
+    let counter = get_offer(&mut context, &counter_account).await;
+    assert!(counter.is_counter_offer);
+    assert_eq!(counter.escrow_sol_amount, 500_000_000);
+
+    let taker_balance_before = get_sol_balance(&mut context, &taker.pubkey()).await;
+    let cancel_ix = instruction_builder::cancel_offer(&program_id(), &counter, false);
+    send(&mut context, &[cancel_ix], &[&taker]).await.unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &taker.pubkey()).await,
+        taker_balance_before + 500_000_000
+    );
+    let counter = get_offer(&mut context, &counter_account).await;
+    assert_eq!(counter.status, OfferStatus::Declined);
+    msg!("test_instruction_builder_lays_out_accounts PASSED");
+}
+
+#[tokio::test]
+async fn test_migrate_offer_upgrades_old_layout() {
+    let mut program_context =
+        ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+            .start_with_context()
+            .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let maker = Keypair::new();
+    let stranger = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &stranger.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicBuy,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // Turn it into a version 1 offer: no version byte, and a locker (and rent) to match.
+    let rent = context.0.get_rent().await.unwrap();
+    let mut account = context.0.get_account(offer_account).await.unwrap().unwrap();
+    let mut old_offer = Offer::load(&account.data).unwrap();
+    old_offer.rent_reserved = rent.minimum_balance(Offer::V1_LEN);
+    account.data = borsh::to_vec(&old_offer).unwrap()[1..].to_vec();
+    account.data.resize(Offer::V1_LEN, 0);
+    account.lamports -= rent.minimum_balance(Offer::MAX_LEN) - rent.minimum_balance(Offer::V1_LEN);
+    program_context.set_account(&offer_account, &account.into());
+    program_context.get_new_latest_blockhash().await.unwrap();
+
+    // Old offers still read, but can't be changed until they're migrated.
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.version, Offer::CURRENT_VERSION);
+    assert_eq!(offer.escrow_sol_amount, 1_000_000_000);
+    let err = send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &sol,
+            &token_mint,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::UnsupportedVersion));
+
+    // Anyone can migrate it.
+    let migrate_ix = || Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(stranger.pubkey(), true),
+            AccountMeta::new(offer_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::MigrateOffer).unwrap(),
+    };
+    send(&mut context, &[migrate_ix()], &[&stranger])
+        .await
+        .unwrap();
+    let account = context.0.get_account(offer_account).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), Offer::MAX_LEN);
+    assert_eq!(account.data[0], Offer::CURRENT_VERSION);
+    assert_eq!(
+        account.lamports,
+        rent.minimum_balance(Offer::MAX_LEN) + 1_000_000_000
+    );
+    assert_eq!(
+        Offer::load(&account.data).unwrap(),
+        Offer {
+            rent_reserved: rent.minimum_balance(Offer::MAX_LEN),
+            ..old_offer
+        }
+    );
+
+    // Migrating again is a no-op, and now the maker can cancel.
+    program_context.get_new_latest_blockhash().await.unwrap();
+    send(&mut context, &[migrate_ix()], &[&stranger])
+        .await
+        .unwrap();
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &sol,
+            &token_mint,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 1_000_000_000
+    );
+
+    // Anything that isn't a layout we know is refused.
+    let mut account = context.0.get_account(offer_account).await.unwrap().unwrap();
+    account.data[0] = Offer::CURRENT_VERSION + 1;
+    program_context.set_account(&offer_account, &account.into());
+    program_context.get_new_latest_blockhash().await.unwrap();
+    let err = send(&mut context, &[migrate_ix()], &[&stranger])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::UnsupportedVersion));
+    msg!("test_migrate_offer_upgrades_old_layout PASSED");
+}
+
+#[tokio::test]
+async fn test_order_book_caps_offers_per_pair() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let admin = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    let makers = [Keypair::new(), Keypair::new(), Keypair::new()];
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    for maker in &makers {
+        fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    }
+    send(
+        &mut context,
+        &[initialize_config_ix(&admin.pubkey())],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let (book_key, _) = order_book_pda(&sol, &token_mint);
+    let offers = makers
+        .each_ref()
+        .map(|maker| offer_pda(&maker.pubkey(), &sol, &token_mint).0);
+
+    // The admin gives the pair a book with room for two offers (but not for none).
+    let init_book_ix = |capacity| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(admin.pubkey(), true),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(book_key, false),
+            AccountMeta::new_readonly(sol, false),
+            AccountMeta::new_readonly(token_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::InitOrderBook { capacity }).unwrap(),
+    };
+    let err = send(&mut context, &[init_book_ix(0)], &[&admin])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+    send(&mut context, &[init_book_ix(2)], &[&admin])
+        .await
+        .unwrap();
+
+    // Each maker bids 1 SOL for 10 tokens; the last one's bid only lasts a minute.
+    let create_ix = |maker: &Pubkey, duration_secs| {
+        create_offer_ix(
+            maker,
+            &Pubkey::new_unique(), // No token account needed for a SOL offer
+            &sol,
+            &token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicBuy,
+                offer_token_amount: 1_000_000_000,
+                receive_token_amount: 10,
+                expiration: None,
+                min_fill: 0,
+                duration_secs,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+    let get_book = |banks_client: BanksClient| async move {
+        let account = banks_client.get_account(book_key).await.unwrap().unwrap();
+        OrderBook::load(&account.data).unwrap()
+    };
+    for maker in &makers[..2] {
+        send(&mut context, &[create_ix(&maker.pubkey(), None)], &[maker])
+            .await
+            .unwrap();
+    }
+    assert_eq!(get_book(context.0.clone()).await.offers, offers[..2]);
+
+    // The book is full, so the third bid has to wait.
+    let err = send(
+        &mut context,
+        &[create_ix(&makers[2].pubkey(), Some(60))],
+        &[&makers[2]],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::TooManyOffers));
+
+    // A cancel frees a slot.
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &makers[0].pubkey(),
+            &offers[0],
+            &sol,
+            &token_mint,
+        )],
+        &[&makers[0]],
+    )
+    .await
+    .unwrap();
+    send(
+        &mut context,
+        &[create_ix(&makers[2].pubkey(), Some(60))],
+        &[&makers[2]],
+    )
+    .await
+    .unwrap();
+    assert_eq!(get_book(context.0.clone()).await.offers, offers[1..]);
+
+    // So does an accept that finishes an offer.
+    let maker_token_account = create_token_account(&mut context, &makers[1], &token_mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &taker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offers[1],
+        &makers[1].pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &sol,
+        &token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    send(&mut context, &[accept_ix], &[&taker, &makers[1]])
+        .await
+        .unwrap();
+    assert_eq!(get_book(context.0.clone()).await.offers, offers[2..]);
+
+    // Pruning only takes off offers that say they're done, so one that has merely run out of
+    // time stays listed...
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 3_600,
+        ..clock
+    });
+    let prune_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(book_key, false),
+            AccountMeta::new_readonly(offers[2], false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::PruneOrderBook).unwrap(),
+    };
+    send(&mut context, &[prune_ix], &[]).await.unwrap();
+    assert_eq!(get_book(context.0.clone()).await.offers, offers[2..]);
+
+    // ...until a keeper expires it, which frees its slot like a cancel.
+    send(
+        &mut context,
+        &[expire_offer_ix(
+            &offers[2],
+            &makers[2].pubkey(),
+            &sol,
+            &token_mint,
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    assert!(get_book(context.0.clone()).await.offers.is_empty());
+    msg!("test_order_book_caps_offers_per_pair PASSED");
+}
+
+#[tokio::test]
+async fn test_order_book_lists_counters_and_reopened_offers() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let mint_authority = Keypair::new();
+    let makers = [Keypair::new(), Keypair::new(), Keypair::new()];
+    let counterers = [Keypair::new(), Keypair::new()];
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    for wallet in makers.iter().chain(&counterers) {
+        fund_account(&mut context, &wallet.pubkey(), 10_000_000_000).await;
+    }
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::ReopenGrace(600)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let counterer_token_accounts = [
+        create_token_account(&mut context, &counterers[0], &token_mint).await,
+        create_token_account(&mut context, &counterers[1], &token_mint).await,
+    ];
+    for token_account in &counterer_token_accounts {
+        mint_to(
+            &mut context,
+            &token_mint,
+            token_account,
+            &mint_authority,
+            10,
+        )
+        .await;
+    }
+
+    // One slot each way: bids (SOL for tokens) and asks (tokens for SOL).
+    let (bid_book, _) = order_book_pda(&sol, &token_mint);
+    let (ask_book, _) = order_book_pda(&token_mint, &sol);
+    for (book_key, (offer_mint, receive_mint)) in
+        [(bid_book, (sol, token_mint)), (ask_book, (token_mint, sol))]
+    {
+        let init_book_ix = Instruction {
+            program_id: program_id(),
+            accounts: vec![
+                AccountMeta::new(admin.pubkey(), true),
+                AccountMeta::new_readonly(config_pda().0, false),
+                AccountMeta::new(book_key, false),
+                AccountMeta::new_readonly(offer_mint, false),
+                AccountMeta::new_readonly(receive_mint, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+            data: borsh::to_vec(&SwapInstruction::InitOrderBook { capacity: 1 }).unwrap(),
+        };
+        send(&mut context, &[init_book_ix], &[&admin])
+            .await
+            .unwrap();
+    }
+    let get_book = |banks_client: BanksClient, book_key: Pubkey| async move {
+        let account = banks_client.get_account(book_key).await.unwrap().unwrap();
+        OrderBook::load(&account.data).unwrap()
+    };
+
+    // Each maker bids 1 SOL for 10 tokens.
+    let bids = makers
+        .each_ref()
+        .map(|maker| offer_pda(&maker.pubkey(), &sol, &token_mint).0);
+    let create_ix = |maker: &Pubkey| {
+        create_offer_ix(
+            maker,
+            &Pubkey::new_unique(), // No token account needed for a SOL offer
+            &sol,
+            &token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicBuy,
+                offer_token_amount: 1_000_000_000,
+                receive_token_amount: 10,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+    let counter_ix = |counterer: usize, bid: usize| {
+        counter_offer_ix(
+            &counterers[counterer].pubkey(),
+            &bids[bid],
+            &makers[bid].pubkey(),
+            &counterer_token_accounts[counterer],
+            &token_mint,
+            &sol,
+            SwapInstruction::CounterOffer {
+                offer_token_amount: 10,
+                receive_token_amount: 1_500_000_000,
+                expiration: None,
+                start_time: None,
+            },
+        )
+    };
+
+    // A counter takes its original's place: the bid comes out, the counter goes in as an ask.
+    send(
+        &mut context,
+        &[create_ix(&makers[0].pubkey())],
+        &[&makers[0]],
+    )
+    .await
+    .unwrap();
+    send(&mut context, &[counter_ix(0, 0)], &[&counterers[0]])
+        .await
+        .unwrap();
+    assert!(
+        get_book(context.0.clone(), bid_book)
+            .await
+            .offers
+            .is_empty()
+    );
+    assert_eq!(
+        get_book(context.0.clone(), ask_book).await.offers,
+        [offer_pda(&counterers[0].pubkey(), &token_mint, &sol).0]
+    );
+
+    // With the asks full, the next counter has nowhere to go.
+    send(
+        &mut context,
+        &[create_ix(&makers[1].pubkey())],
+        &[&makers[1]],
+    )
+    .await
+    .unwrap();
+    let err = send(&mut context, &[counter_ix(1, 1)], &[&counterers[1]])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::TooManyOffers));
+    assert_eq!(
+        get_offer(&mut context, &bids[1]).await.status,
+        OfferStatus::Active
+    );
+
+    // A reopened bid goes back in, so it can't while someone else has taken its slot.
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &makers[1].pubkey(),
+            &bids[1],
+            &sol,
+            &token_mint,
+        )],
+        &[&makers[1]],
+    )
+    .await
+    .unwrap();
+    send(
+        &mut context,
+        &[create_ix(&makers[2].pubkey())],
+        &[&makers[2]],
+    )
+    .await
+    .unwrap();
+    let reopen_ix = reopen_offer_ix(&makers[1].pubkey(), &bids[1], &sol, &token_mint);
+    let err = send(
+        &mut context,
+        std::slice::from_ref(&reopen_ix),
+        &[&makers[1]],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::TooManyOffers));
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &makers[2].pubkey(),
+            &bids[2],
+            &sol,
+            &token_mint,
+        )],
+        &[&makers[2]],
+    )
+    .await
+    .unwrap();
+    send(&mut context, &[reopen_ix], &[&makers[1]])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_book(context.0.clone(), bid_book).await.offers,
+        [bids[1]]
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_all_for_pair_frees_order_book_slots() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let other_maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &other_maker.pubkey(), 10_000_000_000).await;
+    send(
+        &mut context,
+        &[initialize_config_ix(&admin.pubkey())],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // The pair's book has room for two bids, and the maker takes both slots.
+    let (book_key, _) = order_book_pda(&sol, &token_mint);
+    let init_book_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(admin.pubkey(), true),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(book_key, false),
+            AccountMeta::new_readonly(sol, false),
+            AccountMeta::new_readonly(token_mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::InitOrderBook { capacity: 2 }).unwrap(),
+    };
+    send(&mut context, &[init_book_ix], &[&admin])
+        .await
+        .unwrap();
+    let create_ix = |maker: &Pubkey, nonce| {
+        let mut ix = create_offer_ix(
+            maker,
+            &Pubkey::new_unique(), // No token account needed for a SOL offer
+            &sol,
+            &token_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicBuy,
+                offer_token_amount: 1_000_000_000,
+                receive_token_amount: 10,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        ix.accounts[1].pubkey = offer_pda_with_nonce(maker, &sol, &token_mint, nonce).0;
+        ix
+    };
+    let get_book = |banks_client: BanksClient| async move {
+        let account = banks_client.get_account(book_key).await.unwrap().unwrap();
+        OrderBook::load(&account.data).unwrap()
+    };
+    let maker_offers = [
+        offer_pda(&maker.pubkey(), &sol, &token_mint).0,
+        offer_pda_with_nonce(&maker.pubkey(), &sol, &token_mint, 1).0,
+    ];
+    for nonce in 0..2 {
+        send(
+            &mut context,
+            &[create_ix(&maker.pubkey(), nonce)],
+            &[&maker],
+        )
+        .await
+        .unwrap();
+    }
+    assert_eq!(get_book(context.0.clone()).await.offers, maker_offers);
+    let err = send(
+        &mut context,
+        &[create_ix(&other_maker.pubkey(), 0)],
+        &[&other_maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::TooManyOffers));
+
+    // Cancelling them all at once gives both slots back, so the other maker's bid fits.
+    let mut cancel_all_accounts = vec![
+        AccountMeta::new(maker.pubkey(), true),
+        AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+        AccountMeta::new(
+            recent_cancel_pda(&maker.pubkey(), &sol, &token_mint).0,
+            false,
+        ),
+        AccountMeta::new(book_key, false),
+    ];
+    cancel_all_accounts.extend(
+        maker_offers
+            .iter()
+            .map(|offer| AccountMeta::new(*offer, false)),
+    );
+    let cancel_all_ix = Instruction {
+        program_id: program_id(),
+        accounts: cancel_all_accounts,
+        data: borsh::to_vec(&SwapInstruction::CancelAllForPair {
+            offer_token_mint: sol,
+            receive_token_mint: token_mint,
+        })
+        .unwrap(),
+    };
+    send(&mut context, &[cancel_all_ix], &[&maker])
+        .await
+        .unwrap();
+    assert!(get_book(context.0.clone()).await.offers.is_empty());
+
+    send(
+        &mut context,
+        &[create_ix(&other_maker.pubkey(), 0)],
+        &[&other_maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_book(context.0.clone()).await.offers,
+        [offer_pda(&other_maker.pubkey(), &sol, &token_mint).0]
+    );
+    msg!("test_cancel_all_for_pair_frees_order_book_slots PASSED");
+}
+
+#[tokio::test]
+async fn test_mint_allowlist_limits_create_and_counter() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    send(
+        &mut context,
+        &[initialize_config_ix(&admin.pubkey())],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    let sol = spl_token::native_mint::id();
+    let listed_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let unlisted_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let mut maker_token_accounts = vec![];
+    for mint in [listed_mint, unlisted_mint] {
+        let token_account = create_token_account(&mut context, &maker, &mint).await;
+        mint_to(&mut context, &mint, &token_account, &mint_authority, 10).await;
+        maker_token_accounts.push(token_account);
+    }
+
+    let allowlist_ix = |data: SwapInstruction| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(admin.pubkey(), true),
+            AccountMeta::new(config_pda().0, false),
+        ],
+        data: borsh::to_vec(&data).unwrap(),
+    };
+    for mint in [listed_mint, sol] {
+        send(
+            &mut context,
+            &[allowlist_ix(SwapInstruction::AddAllowedMint { mint })],
+            &[&admin],
+        )
+        .await
+        .unwrap();
+    }
+    // Listing a mint twice is a mistake.
+    let err = send(
+        &mut context,
+        &[allowlist_ix(SwapInstruction::AddAllowedMint { mint: sol })],
+        &[&admin],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidInstruction));
+
+    // 10 tokens for 1 SOL, on either mint.
+    let sell_ix = |i: usize| {
+        let mint = [listed_mint, unlisted_mint][i];
+        create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_accounts[i],
+            &mint,
+            &sol,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 1_000_000_000,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+    send(&mut context, &[sell_ix(0)], &[&maker]).await.unwrap();
+    let err = send(&mut context, &[sell_ix(1)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MintNotAllowed));
+
+    // Once SOL comes off the list, nobody can counter with it either.
+    send(
+        &mut context,
+        &[allowlist_ix(SwapInstruction::RemoveAllowedMint {
+            mint: sol,
+        })],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &listed_mint, &sol);
+    let counter_ix = counter_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL counter
+        &sol,
+        &listed_mint,
+        SwapInstruction::CounterOffer {
+            offer_token_amount: 500_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            start_time: None,
+        },
+    );
+    let err = send(&mut context, &[counter_ix], &[&taker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MintNotAllowed));
+
+    // With the list empty again, any mint goes.
+    send(
+        &mut context,
+        &[allowlist_ix(SwapInstruction::RemoveAllowedMint {
+            mint: listed_mint,
+        })],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    let config = context
+        .0
+        .get_account(config_pda().0)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        ProgramConfig::load(&config.data)
+            .unwrap()
+            .allowed_mints
+            .is_empty()
+    );
+    send(&mut context, &[sell_ix(1)], &[&maker]).await.unwrap();
+    msg!("test_mint_allowlist_limits_create_and_counter PASSED");
+}
+
+#[tokio::test]
+async fn test_paused_program_rejects_create_but_allows_cancel() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let other_maker = Keypair::new();
+    let matcher = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    for wallet in [&maker, &other_maker, &matcher] {
+        fund_account(&mut context, &wallet.pubkey(), 1_000_000_000).await;
+    }
+    send(
+        &mut context,
+        &[initialize_config_ix(&admin.pubkey())],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+    let sol = spl_token::native_mint::id();
+    let mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let other_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &mint).await;
+    let maker_other_account = create_token_account(&mut context, &maker, &other_mint).await;
+    let other_maker_token_account = create_token_account(&mut context, &other_maker, &mint).await;
+    let other_maker_other_account =
+        create_token_account(&mut context, &other_maker, &other_mint).await;
+    mint_to(
+        &mut context,
+        &mint,
+        &maker_token_account,
+        &mint_authority,
+        20,
+    )
+    .await;
+    mint_to(
+        &mut context,
+        &other_mint,
+        &other_maker_other_account,
+        &mint_authority,
+        5,
+    )
+    .await;
+
+    // Two mirror-image token offers, ready to be matched: 10 tokens for 5 of the other, and back.
+    let token_offer_ix = |maker: &Keypair, token_account, (offer_mint, receive_mint), amounts| {
+        let (offer_token_amount, receive_token_amount) = amounts;
+        create_offer_ix(
+            &maker.pubkey(),
+            token_account,
+            offer_mint,
+            receive_mint,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount,
+                receive_token_amount,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        )
+    };
+    send(
+        &mut context,
+        &[token_offer_ix(
+            &maker,
+            &maker_token_account,
+            (&mint, &other_mint),
+            (10, 5),
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    send(
+        &mut context,
+        &[token_offer_ix(
+            &other_maker,
+            &other_maker_other_account,
+            (&other_mint, &mint),
+            (5, 10),
+        )],
+        &[&other_maker],
+    )
+    .await
+    .unwrap();
+    let match_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(matcher.pubkey(), true),
+            AccountMeta::new(offer_pda(&maker.pubkey(), &mint, &other_mint).0, false),
+            AccountMeta::new(
+                offer_pda(&other_maker.pubkey(), &other_mint, &mint).0,
+                false,
+            ),
+            AccountMeta::new_readonly(maker.pubkey(), true),
+            AccountMeta::new_readonly(other_maker.pubkey(), true),
+            AccountMeta::new(maker_token_account, false),
+            AccountMeta::new(maker_other_account, false),
+            AccountMeta::new(other_maker_other_account, false),
+            AccountMeta::new(other_maker_token_account, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new_readonly(other_mint, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(mint_pause_pda(&mint).0, false),
+            AccountMeta::new_readonly(mint_pause_pda(&other_mint).0, false),
+            AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+            AccountMeta::new(maker_index_pda(&other_maker.pubkey()).0, false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(order_book_pda(&mint, &other_mint).0, false),
+            AccountMeta::new(order_book_pda(&other_mint, &mint).0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::MatchOffers).unwrap(),
+    };
+
+    // 10 tokens for 1 SOL, with a fresh nonce each time.
+    let sell_ix = |nonce: u64| {
+        let (offer_account, _) = offer_pda_with_nonce(&maker.pubkey(), &mint, &sol, nonce);
+        let mut ix = create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &mint,
+            &sol,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 1_000_000_000,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        ix.accounts[1].pubkey = offer_account;
+        ix
+    };
+    send(&mut context, &[sell_ix(0)], &[&maker]).await.unwrap();
+
+    let set_paused_ix = |paused: bool| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(admin.pubkey(), true),
+            AccountMeta::new(config_pda().0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::SetPaused { paused }).unwrap(),
+    };
+    // Only the admin can pull the switch.
+    let mut not_admin_ix = set_paused_ix(true);
+    not_admin_ix.accounts[0].pubkey = maker.pubkey();
+    let err = send(&mut context, &[not_admin_ix], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+    send(&mut context, &[set_paused_ix(true)], &[&admin])
+        .await
+        .unwrap();
+
+    let err = send(&mut context, &[sell_ix(1)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::ProgramPaused));
+
+    // Matching two offers is taking them, so that's off too.
+    let err = send(
+        &mut context,
+        std::slice::from_ref(&match_ix),
+        &[&matcher, &maker, &other_maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::ProgramPaused));
+    assert_eq!(
+        get_token_balance(&mut context, &other_maker_token_account).await,
+        0
+    );
+
+    // The maker can still get their tokens back.
+    let (offer_account, _) = offer_pda_with_nonce(&maker.pubkey(), &mint, &sol, 0);
+    send(
+        &mut context,
+        &[cancel_offer_ix(
+            &maker.pubkey(),
+            &offer_account,
+            &mint,
+            &sol,
+        )],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &maker_token_account).await,
+        20
+    );
+
+    send(&mut context, &[set_paused_ix(false)], &[&admin])
+        .await
+        .unwrap();
+    send(&mut context, &[sell_ix(1)], &[&maker]).await.unwrap();
+    send(&mut context, &[match_ix], &[&matcher, &maker, &other_maker])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &other_maker_token_account).await,
+        10
+    );
+    msg!("test_paused_program_rejects_create_but_allows_cancel PASSED");
+}
+
+#[tokio::test]
+async fn test_one_shot_offer_cancels_on_rejected_accept() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let stranger = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    for wallet in [&taker, &stranger] {
+        fund_account(&mut context, &wallet.pubkey(), 1_000_000_000).await;
+    }
+    let sol = spl_token::native_mint::id();
+    let mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let other_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &mint).await;
+    let taker_other_account = create_token_account(&mut context, &taker, &other_mint).await;
+    let stranger_token_account = create_token_account(&mut context, &stranger, &mint).await;
+    for token_account in [taker_token_account, stranger_token_account] {
+        mint_to(&mut context, &mint, &token_account, &mint_authority, 10).await;
+    }
+
+    // A one-shot direct offer of 5 SOL to the taker for 10 tokens.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &mint);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed when offering SOL
+        &sol,
+        &mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::Direct,
+            offer_token_amount: 5_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: true,
+        },
+    );
+    create_ix
+        .accounts
+        .push(AccountMeta::new_readonly(taker.pubkey(), false));
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let maker_balance = get_sol_balance(&mut context, &maker.pubkey()).await;
+
+    let accept_ix = |taker: &Pubkey, taker_token_account: &Pubkey| {
+        let mut ix = accept_offer_ix(
+            taker,
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            taker_token_account,
+            &sol,
+            &mint,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        );
+        ix.accounts[2] = AccountMeta::new(maker.pubkey(), false); // The maker isn't around
+        ix
+    };
+
+    // The right taker with the wrong token account is their mistake, not a rejection.
+    let err = send(
+        &mut context,
+        &[accept_ix(&taker.pubkey(), &taker_other_account)],
+        &[&taker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::TokenMismatch));
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Active
+    );
+
+    // Someone it wasn't made for tries, and that's the end of it.
+    send(
+        &mut context,
+        &[accept_ix(&stranger.pubkey(), &stranger_token_account)],
+        &[&stranger],
+    )
+    .await
+    .unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Declined);
+    assert_eq!(offer.escrow_sol_amount, 0);
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance + 5_000_000_000
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &stranger_token_account).await,
+        10
+    );
+
+    let err = send(
+        &mut context,
+        &[accept_ix(&taker.pubkey(), &taker_token_account)],
+        &[&taker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidOfferStatus));
+    msg!("test_one_shot_offer_cancels_on_rejected_accept PASSED");
+}
+
+#[tokio::test]
+async fn test_one_shot_offer_survives_an_early_accept() {
+    let mut context = start_program().await;
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &mint).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &mint).await;
+    mint_to(
+        &mut context,
+        &mint,
+        &taker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // A one-shot direct offer of 5 SOL to the taker for 10 tokens that opens in an hour.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &mint);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed when offering SOL
+        &sol,
+        &mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::Direct,
+            offer_token_amount: 5_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: Some(clock.unix_timestamp + 3_600),
+            client_id: [0; 8],
+            auto_cancel_on_fail: true,
+        },
+    );
+    create_ix
+        .accounts
+        .push(AccountMeta::new_readonly(taker.pubkey(), false));
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // The taker it was made for showing up early is turned away, but the offer stands.
+    let mut accept_ix = accept_offer_ix(
+        &taker.pubkey(),
+        &offer_account,
+        &maker.pubkey(),
+        &maker_token_account,
+        &taker_token_account,
+        &sol,
+        &mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    accept_ix.accounts[2] = AccountMeta::new(maker.pubkey(), false);
+    let err = send(&mut context, &[accept_ix], &[&taker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::OfferNotStarted));
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+    assert_eq!(offer.escrow_sol_amount, 5_000_000_000);
+    msg!("test_one_shot_offer_survives_an_early_accept PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_offers_takes_several_in_one_instruction() {
+    let mut context = start_program().await;
+
+    let makers = [Keypair::new(), Keypair::new()];
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    for maker in &makers {
+        fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    }
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let taker_token_account = create_token_account(&mut context, &taker, &mint).await;
+
+    // Each maker sells 10 tokens for 1 SOL.
+    let mut groups = vec![];
+    let mut maker_token_accounts = vec![];
+    for maker in &makers {
+        let maker_token_account = create_token_account(&mut context, maker, &mint).await;
+        mint_to(
+            &mut context,
+            &mint,
+            &maker_token_account,
+            &mint_authority,
+            10,
+        )
+        .await;
+        let (offer_account, _) = offer_pda(&maker.pubkey(), &mint, &sol);
+        let create_ix = create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &mint,
+            &sol,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 1_000_000_000,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce: 0,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        send(&mut context, &[create_ix], &[maker]).await.unwrap();
+
+        let mut accounts = accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            &taker_token_account,
+            &mint,
+            &sol,
+            SwapInstruction::AcceptOffer {
+                close_account: false,
+                min_receive_amount: None,
+                max_send_amount: None,
+            },
+        )
+        .accounts;
+        accounts.push(AccountMeta::new(taker.pubkey(), false)); // No fee, so no treasury
+        accounts.push(AccountMeta::new_readonly(taker.pubkey(), false)); // Not a premium order
+        assert_eq!(accounts.len(), ACCEPT_BATCH_GROUP_LEN);
+        groups.push((offer_account, accounts));
+        maker_token_accounts.push(maker_token_account);
+    }
+    let accept_offers_ix = |offers: &[usize]| Instruction {
+        program_id: program_id(),
+        accounts: offers.iter().flat_map(|&i| groups[i].1.clone()).collect(),
+        data: borsh::to_vec(&SwapInstruction::AcceptOffers {
+            count: offers.len() as u8,
+        })
+        .unwrap(),
+    };
+    let signers = [&taker, &makers[0], &makers[1]];
+
+    let err = send(&mut context, &[accept_offers_ix(&[0; 9])], &signers[..2])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::TooManyOffers));
+
+    // Taking the same offer twice fails on the second go, and the first is undone with it.
+    let err = send(&mut context, &[accept_offers_ix(&[0, 0])], &signers[..2])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidOfferStatus));
+    assert_eq!(
+        get_offer(&mut context, &groups[0].0).await.status,
+        OfferStatus::Active
+    );
+
+    let taker_balance = get_sol_balance(&mut context, &taker.pubkey()).await;
+    send(&mut context, &[accept_offers_ix(&[0, 1])], &signers)
+        .await
+        .unwrap();
+    for (i, (offer_account, _)) in groups.iter().enumerate() {
+        assert_eq!(
+            get_offer(&mut context, offer_account).await.status,
+            OfferStatus::Accepted
+        );
+        assert_eq!(
+            get_token_balance(&mut context, &maker_token_accounts[i]).await,
+            0
+        );
+    }
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        20
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &taker.pubkey()).await,
+        taker_balance - 2_000_000_000
+    );
+    msg!("test_accept_offers_takes_several_in_one_instruction PASSED");
+}
+
+#[tokio::test]
+async fn test_accept_to_vault_delivers_into_custody() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 10_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &mint).await;
+    mint_to(
+        &mut context,
+        &mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let taker_token_account = create_token_account(&mut context, &taker, &mint).await;
+
+    // The custodian keeps one vault per client, at a PDA of its own program.
+    let custody_program = Pubkey::new_unique();
+    let (vault, vault_bump) =
+        Pubkey::find_program_address(&[b"vault", taker.pubkey().as_ref()], &custody_program);
+    let vault_token_account = Keypair::new();
+    let rent = context.0.get_rent().await.unwrap();
+    let create_vault_account_ixs = [
+        system_instruction::create_account(
+            &context.1.pubkey(),
+            &vault_token_account.pubkey(),
+            rent.minimum_balance(spl_token::state::Account::LEN),
+            spl_token::state::Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &vault_token_account.pubkey(),
+            &mint,
+            &vault,
+        )
+        .unwrap(),
+    ];
+    send(
+        &mut context,
+        &create_vault_account_ixs,
+        &[&vault_token_account],
+    )
+    .await
+    .unwrap();
+
+    // 10 tokens for 1 SOL.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let accept_ix = |taker_token_account: &Pubkey| {
+        accept_offer_ix(
+            &taker.pubkey(),
+            &offer_account,
+            &maker.pubkey(),
+            &maker_token_account,
+            taker_token_account,
+            &mint,
+            &sol,
+            SwapInstruction::AcceptToVault {
+                custody_program,
+                vault_seeds: vec![
+                    b"vault".to_vec(),
+                    taker.pubkey().to_bytes().to_vec(),
+                    vec![vault_bump],
+                ],
+            },
+        )
+    };
+
+    // The taker's own account isn't the vault's.
+    let err = send(
+        &mut context,
+        &[accept_ix(&taker_token_account)],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::IncorrectOwner));
+
+    let taker_balance = get_sol_balance(&mut context, &taker.pubkey()).await;
+    send(
+        &mut context,
+        &[accept_ix(&vault_token_account.pubkey())],
+        &[&taker, &maker],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_balance(&mut context, &vault_token_account.pubkey()).await,
+        10
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        0
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &taker.pubkey()).await,
+        taker_balance - 1_000_000_000
+    );
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.status,
+        OfferStatus::Accepted
+    );
+    msg!("test_accept_to_vault_delivers_into_custody PASSED");
+}
+
+#[tokio::test]
+async fn test_create_checks_the_offered_mint_and_keeps_its_decimals() {
+    let mut program_context =
+        ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+            .start_with_context()
+            .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let mint = create_mint(&mut context, &mint_authority, None, 6).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &mint).await;
+    mint_to(
+        &mut context,
+        &mint,
+        &maker_token_account,
+        &mint_authority,
+        30,
+    )
+    .await;
+
+    // 10 tokens for 1 SOL, with a fresh nonce each time.
+    let sell_ix = |nonce: u64| {
+        let (offer_account, _) = offer_pda_with_nonce(&maker.pubkey(), &mint, &sol, nonce);
+        let mut ix = create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &mint,
+            &sol,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 1_000_000_000,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        ix.accounts[1].pubkey = offer_account;
+        ix
+    };
+    send(&mut context, &[sell_ix(0)], &[&maker]).await.unwrap();
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &mint, &sol);
+    assert_eq!(
+        get_offer(&mut context, &offer_account).await.offer_decimals,
+        Some(6)
+    );
+
+    // A copy of the mint that some other program owns isn't the mint.
+    let real_mint = context.0.get_account(mint).await.unwrap().unwrap();
+    let mut copy = real_mint.clone();
+    copy.owner = Pubkey::new_unique();
+    program_context.set_account(&mint, &copy.into());
+    let err = send(&mut context, &[sell_ix(1)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidMint));
+
+    // Nor is a token program account that was never set up as one.
+    let mut blank = real_mint.clone();
+    blank.data.fill(0);
+    program_context.set_account(&mint, &blank.into());
+    let err = send(&mut context, &[sell_ix(2)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidMint));
+
+    program_context.set_account(&mint, &real_mint.into());
+    send(&mut context, &[sell_ix(1)], &[&maker]).await.unwrap();
+    msg!("test_create_checks_the_offered_mint_and_keeps_its_decimals PASSED");
+}
+
+#[tokio::test]
+async fn test_split_offer_into_two_at_different_prices() {
+    let mut program_context =
+        ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+            .start_with_context()
+            .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &mint).await;
+    mint_to(
+        &mut context,
+        &mint,
+        &maker_token_account,
+        &mint_authority,
+        100,
+    )
+    .await;
+
+    // Sell 100 tokens for 1 SOL.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 100,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let original = get_offer(&mut context, &offer_account).await;
+
+    let children = [
+        offer_pda_with_nonce(&maker.pubkey(), &mint, &sol, 1).0,
+        offer_pda_with_nonce(&maker.pubkey(), &mint, &sol, 2).0,
+    ];
+    let split_ix = |parts: Vec<(u64, u64)>| {
+        let mut accounts = vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new(offer_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda().0, false),
+            AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+            AccountMeta::new(order_book_pda(&mint, &sol).0, false),
+            AccountMeta::new_readonly(settlement_pda(&offer_account).0, false),
+        ];
+        accounts.extend(children.iter().map(|child| AccountMeta::new(*child, false)));
+        Instruction {
+            program_id: program_id(),
+            accounts,
+            data: borsh::to_vec(&SwapInstruction::SplitOffer { parts }).unwrap(),
+        }
+    };
+
+    // The pieces have to add up to the whole.
+    let err = send(
+        &mut context,
+        &[split_ix(vec![(50, 500_000_000), (40, 750_000_000)])],
+        &[&maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidAmount));
+
+    // Not while a large trade on it waits to settle.
+    let settlement_key = settlement_pda(&offer_account).0;
+    let settlement_account = |owner: Pubkey| Account {
+        lamports: 1_000_000_000,
+        data: vec![0; Settlement::MAX_LEN],
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    };
+    program_context.set_account(&settlement_key, &settlement_account(program_id()).into());
+    let err = send(
+        &mut context,
+        &[split_ix(vec![(50, 500_000_000), (50, 750_000_000)])],
+        &[&maker],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::SettlementPending));
+    program_context.set_account(
+        &settlement_key,
+        &settlement_account(system_program::id()).into(),
+    );
+
+    // Half at 0.5 SOL, half at 0.75 SOL.
+    send(
+        &mut context,
+        &[split_ix(vec![(50, 500_000_000), (50, 750_000_000)])],
+        &[&maker],
+    )
+    .await
+    .unwrap();
+
+    // The original is closed...
+    assert!(
+        context
+            .0
+            .get_account(offer_account)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    // ...and each piece is a live copy of it at its own price and nonce.
+    for (nonce, (child, price)) in children.iter().zip([500_000_000, 750_000_000]).enumerate() {
+        let offer = get_offer(&mut context, child).await;
+        assert_eq!(offer.status, OfferStatus::Active);
+        assert_eq!(offer.offer_token_amount, 50);
+        assert_eq!(offer.receive_token_amount, price);
+        assert_eq!(offer.nonce, nonce as u64 + 1);
+        assert_eq!(offer.offer_type, original.offer_type);
+        assert_eq!(offer.maker, original.maker);
+        assert_eq!(offer.escrow_sol_amount, 0);
+    }
+}
+
+#[tokio::test]
+async fn test_recreate_cooldown_blocks_reposting_a_cancelled_offer() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::RecreateCooldown(300)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let sol = spl_token::native_mint::id();
+    let mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &mint).await;
+    mint_to(
+        &mut context,
+        &mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &mint, &sol);
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    let mut cancel_ix = cancel_offer_ix(&maker.pubkey(), &offer_account, &mint, &sol);
+    cancel_ix.data = borsh::to_vec(&SwapInstruction::CancelOffer {
+        close_account: true,
+    })
+    .unwrap();
+    send(&mut context, std::slice::from_ref(&create_ix), &[&maker])
+        .await
+        .unwrap();
+    send(&mut context, &[cancel_ix], &[&maker]).await.unwrap();
+
+    let (recent_key, _) = recent_cancel_pda(&maker.pubkey(), &mint, &sol);
+    let recent_account = context.0.get_account(recent_key).await.unwrap().unwrap();
+    let recent: RecentCancel = borsh::from_slice(&recent_account.data).unwrap();
+    assert_eq!(recent.offer_token_amount, 10);
+    assert_eq!(recent.receive_token_amount, 1_000_000_000);
+    assert!(recent.cancelled_at > 0);
+
+    // Putting the very same offer straight back up is refused...
+    let err = send(&mut context, std::slice::from_ref(&create_ix), &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::CooldownActive));
+
+    // ...until the cool-down has passed.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: recent.cancelled_at + 300,
+        ..clock
+    });
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let offer = get_offer(&mut context, &offer_account).await;
+    assert_eq!(offer.status, OfferStatus::Active);
+}
+
+#[tokio::test]
+async fn test_recreate_cooldown_covers_cancel_all_for_pair() {
+    let mut context = start_program().await;
+
+    let admin = Keypair::new();
+    let maker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &admin.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    send(
+        &mut context,
+        &[
+            initialize_config_ix(&admin.pubkey()),
+            update_config_ix(&admin.pubkey(), ConfigField::RecreateCooldown(300)),
+        ],
+        &[&admin],
+    )
+    .await
+    .unwrap();
+
+    let sol = spl_token::native_mint::id();
+    let mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let maker_token_account = create_token_account(&mut context, &maker, &mint).await;
+    mint_to(
+        &mut context,
+        &mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+
+    // 10 tokens for 1 SOL, posted at the maker's first two nonces.
+    let create_ix = |nonce: u64| {
+        let mut ix = create_offer_ix(
+            &maker.pubkey(),
+            &maker_token_account,
+            &mint,
+            &sol,
+            SwapInstruction::CreateOffer {
+                offer_type: OfferType::PublicSell,
+                offer_token_amount: 10,
+                receive_token_amount: 1_000_000_000,
+                expiration: None,
+                min_fill: 0,
+                duration_secs: None,
+                receive_recipient: None,
+                confirm_first: false,
+                nonce,
+                start_time: None,
+                client_id: [0; 8],
+                auto_cancel_on_fail: false,
+            },
+        );
+        ix.accounts[1] = AccountMeta::new(
+            offer_pda_with_nonce(&maker.pubkey(), &mint, &sol, nonce).0,
+            false,
+        );
+        ix
+    };
+    send(&mut context, &[create_ix(0)], &[&maker])
+        .await
+        .unwrap();
+
+    let (recent_key, _) = recent_cancel_pda(&maker.pubkey(), &mint, &sol);
+    let cancel_all_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+            AccountMeta::new(recent_key, false),
+            AccountMeta::new(order_book_pda(&mint, &sol).0, false),
+            AccountMeta::new(offer_pda(&maker.pubkey(), &mint, &sol).0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::CancelAllForPair {
+            offer_token_mint: mint,
+            receive_token_mint: sol,
+        })
+        .unwrap(),
+    };
+    send(&mut context, &[cancel_all_ix], &[&maker])
+        .await
+        .unwrap();
+
+    // The cancel was written down...
+    let recent_account = context.0.get_account(recent_key).await.unwrap().unwrap();
+    let recent: RecentCancel = borsh::from_slice(&recent_account.data).unwrap();
+    assert_eq!(recent.offer_token_amount, 10);
+    assert_eq!(recent.receive_token_amount, 1_000_000_000);
+    assert!(recent.cancelled_at > 0);
+
+    // ...so the same offer can't go straight back up.
+    let err = send(&mut context, &[create_ix(1)], &[&maker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::CooldownActive));
+}
+
+#[tokio::test]
+async fn test_taker_rejects_direct_offer() {
+    let mut context = start_program().await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let stranger = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 2_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &stranger.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // The maker offers the taker 1 SOL for 10 tokens.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::Direct,
+            offer_token_amount: 1_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    create_ix
+        .accounts
+        .push(AccountMeta::new_readonly(taker.pubkey(), false));
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    let reject_ix = |rejecter: &Pubkey| Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(*rejecter, true),
+            AccountMeta::new(offer_account, false),
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+            AccountMeta::new(order_book_pda(&sol, &token_mint).0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::RejectOffer {
+            close_account: true,
+        })
+        .unwrap(),
+    };
+
+    // Only the taker it names can turn it down.
+    let err = send(&mut context, &[reject_ix(&stranger.pubkey())], &[&stranger])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::Unauthorized));
+
+    let offer_lamports = get_sol_balance(&mut context, &offer_account).await;
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(&mut context, &[reject_ix(&taker.pubkey())], &[&taker])
+        .await
+        .unwrap();
+
+    // The maker has the escrow and the rent back, and the offer is gone.
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + offer_lamports
+    );
+    assert!(
+        context
+            .0
+            .get_account(offer_account)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let maker_index_account = context
+        .0
+        .get_account(maker_index_pda(&maker.pubkey()).0)
+        .await
+        .unwrap()
+        .unwrap();
+    let maker_index: MakerIndex = borsh::from_slice(&maker_index_account.data).unwrap();
+    assert_eq!(maker_index.active_offers, 0);
+
+    // A public offer has no taker to reject it.
+    let (public_offer, _) = offer_pda(&maker.pubkey(), &token_mint, &sol);
+    let maker_token_account = create_token_account(&mut context, &maker, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        10,
+    )
+    .await;
+    let create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &maker_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateOffer {
+            offer_type: OfferType::PublicSell,
+            offer_token_amount: 10,
+            receive_token_amount: 1_000_000_000,
+            expiration: None,
+            min_fill: 0,
+            duration_secs: None,
+            receive_recipient: None,
+            confirm_first: false,
+            nonce: 0,
+            start_time: None,
+            client_id: [0; 8],
+            auto_cancel_on_fail: false,
+        },
+    );
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+    let mut public_reject_ix = reject_ix(&taker.pubkey());
+    public_reject_ix.accounts[1].pubkey = public_offer;
+    public_reject_ix.accounts[4].pubkey = order_book_pda(&token_mint, &sol).0;
+    let err = send(&mut context, &[public_reject_ix], &[&taker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::InvalidOfferStatus));
+}
+
+#[tokio::test]
+async fn test_counter_to_dutch_auction_keeps_its_price() {
+    let program_context = ProgramTest::new("soffer", program_id(), processor!(Processor::process))
+        .start_with_context()
+        .await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+    let clock: solana_program::clock::Clock = context.0.get_sysvar().await.unwrap();
+
+    let seller = Keypair::new();
+    let bidder = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &seller.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &bidder.pubkey(), 10_000_000_000).await;
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id();
+    let seller_token_account = create_token_account(&mut context, &seller, &token_mint).await;
+    mint_to(
+        &mut context,
+        &token_mint,
+        &seller_token_account,
+        &mint_authority,
+        1,
+    )
+    .await;
+    let bidder_token_account = create_token_account(&mut context, &bidder, &token_mint).await;
+
+    // One NFT, from 2 SOL down to 1 SOL over an hour.
+    let (auction_account, _) = offer_pda(&seller.pubkey(), &token_mint, &sol);
+    let create_ix = create_offer_ix(
+        &seller.pubkey(),
+        &seller_token_account,
+        &token_mint,
+        &sol,
+        SwapInstruction::CreateDutchAuction {
+            offer_token_amount: 1,
+            start_price: 2_000_000_000,
+            end_price: 1_000_000_000,
+            start_time: Some(clock.unix_timestamp),
+            expiration: clock.unix_timestamp + 3_600,
+            min_acceptable_receive: 0,
+        },
+    );
+    send(&mut context, &[create_ix], &[&seller]).await.unwrap();
+
+    // The bidder counters with 1.5 SOL, for as long as it takes.
+    let (counter_account, _) = offer_pda(&bidder.pubkey(), &sol, &token_mint);
+    let counter_ix = counter_offer_ix(
+        &bidder.pubkey(),
+        &auction_account,
+        &seller.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CounterOffer {
+            offer_token_amount: 1_500_000_000,
+            receive_token_amount: 1,
+            expiration: None,
+            start_time: None,
+        },
+    );
+    send(&mut context, &[counter_ix], &[&bidder]).await.unwrap();
+    let counter = get_offer(&mut context, &counter_account).await;
+    assert_eq!(counter.offer_type, OfferType::PublicBuy);
+
+    // Long after the auction would have bottomed out, the counter still pays its full 1.5 SOL.
+    program_context.set_sysvar(&solana_program::clock::Clock {
+        unix_timestamp: clock.unix_timestamp + 7_200,
+        ..clock
+    });
+    let seller_balance_before = get_sol_balance(&mut context, &seller.pubkey()).await;
+    let accept_ix = accept_offer_ix(
+        &seller.pubkey(),
+        &counter_account,
+        &bidder.pubkey(),
+        &bidder_token_account,
+        &seller_token_account,
+        &sol,
+        &token_mint,
+        SwapInstruction::AcceptOffer {
+            close_account: false,
+            min_receive_amount: None,
+            max_send_amount: None,
+        },
+    );
+    send(&mut context, &[accept_ix], &[&seller, &bidder])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &seller.pubkey()).await,
+        seller_balance_before + 1_500_000_000
+    );
+    assert_eq!(
+        get_token_balance(&mut context, &bidder_token_account).await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_reject_brings_yield_escrow_home() {
+    let mut program_test = ProgramTest::new("soffer", program_id(), processor!(Processor::process));
+    program_test.add_program(
+        "mock_yield",
+        mock_yield_id(),
+        processor!(mock_yield_process),
+    );
+    // The vault has already earned 1_000_000 lamports by the time the offer comes back.
+    let yield_vault = Pubkey::new_unique();
+    let vault_rent = Rent::default().minimum_balance(0);
+    program_test.add_account(
+        yield_vault,
+        Account {
+            lamports: vault_rent + 1_000_000,
+            owner: mock_yield_id(),
+            ..Default::default()
+        },
+    );
+    let mut program_context = program_test.start_with_context().await;
+    let mut context = (
+        program_context.banks_client.clone(),
+        program_context.payer.insecure_clone(),
+        program_context.last_blockhash,
+    );
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 10_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let sol = spl_token::native_mint::id();
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+
+    // 5 SOL for 10 tokens, with the escrow earning yield in the meantime.
+    let (offer_account, _) = offer_pda(&maker.pubkey(), &sol, &token_mint);
+    let mut create_ix = create_offer_ix(
+        &maker.pubkey(),
+        &Pubkey::new_unique(), // No token account needed for a SOL offer
+        &sol,
+        &token_mint,
+        SwapInstruction::CreateYieldOffer {
+            offer_token_amount: 5_000_000_000,
+            receive_token_amount: 10,
+            expiration: None,
+            min_fill: 0,
+            yield_strategy: mock_yield_id(),
+        },
+    );
+    create_ix.accounts.extend([
+        AccountMeta::new_readonly(Pubkey::new_unique(), false), // No taker
+        AccountMeta::new_readonly(mock_yield_id(), false),
+        AccountMeta::new(yield_vault, false),
+    ]);
+    send(&mut context, &[create_ix], &[&maker]).await.unwrap();
+
+    // Yield offers are public when they're made; make this one the taker's to turn down.
+    let mut account = context.0.get_account(offer_account).await.unwrap().unwrap();
+    let mut offer = Offer::load(&account.data).unwrap();
+    offer.offer_type = OfferType::Direct;
+    offer.taker = Some(taker.pubkey());
+    borsh::to_writer(&mut account.data[..], &offer).unwrap();
+    program_context.set_account(&offer_account, &account.into());
+
+    let mut reject_ix = Instruction {
+        program_id: program_id(),
+        accounts: vec![
+            AccountMeta::new_readonly(taker.pubkey(), true),
+            AccountMeta::new(offer_account, false),
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new(maker_index_pda(&maker.pubkey()).0, false),
+            AccountMeta::new(order_book_pda(&sol, &token_mint).0, false),
+        ],
+        data: borsh::to_vec(&SwapInstruction::RejectOffer {
+            close_account: true,
+        })
+        .unwrap(),
+    };
+
+    // Without the strategy's accounts the escrow would be left behind, so it can't.
+    let err = send(&mut context, std::slice::from_ref(&reject_ix), &[&taker])
+        .await
+        .unwrap_err();
+    assert_eq!(err, custom_error(SwapError::MissingRequiredAccount));
+
+    // With them, the maker gets the escrow, its yield and the rent back.
+    reject_ix.accounts.extend([
+        AccountMeta::new_readonly(mock_yield_id(), false),
+        AccountMeta::new(yield_vault, false),
+    ]);
+    let offer_lamports = get_sol_balance(&mut context, &offer_account).await;
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    send(&mut context, &[reject_ix], &[&taker]).await.unwrap();
+    assert_eq!(
+        get_sol_balance(&mut context, &yield_vault).await,
+        vault_rent
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + offer_lamports + 5_000_000_000 + 1_000_000
+    );
+    assert!(
+        context
+            .0
+            .get_account(offer_account)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
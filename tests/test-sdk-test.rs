@@ -0,0 +1,97 @@
+// What a downstream project's test looks like with the `test-sdk` feature: everything comes
+// from `soffer::test_sdk` and `soffer::instruction_builder`, nothing is copied in.
+use soffer::instruction_builder::{self, Amounts};
+use soffer::test_sdk::{
+    create_mint, fund_account, get_sol_balance, get_token_balance, mint_to, start,
+};
+use soffer::{Offer, OfferStatus};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::{
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use spl_associated_token_account_client::{
+    address::get_associated_token_address, instruction::create_associated_token_account,
+};
+use std::str::FromStr;
+
+#[tokio::test]
+async fn test_sdk_create_and_accept_offer() {
+    let program_id = Pubkey::from_str("HpddKoiN2TNaJ8ZdWRVNbgLuAKop4JzYuEGPAM45agk8").unwrap();
+    let mut context = start(program_id).await;
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    let mint_authority = Keypair::new();
+    fund_account(&mut context, &maker.pubkey(), 1_000_000_000).await;
+    fund_account(&mut context, &taker.pubkey(), 1_000_000_000).await;
+    let token_mint = create_mint(&mut context, &mint_authority, None, 0).await;
+    let sol = spl_token::native_mint::id();
+
+    // The builder works with associated token accounts.
+    let create_atas: Vec<_> = [&maker, &taker]
+        .iter()
+        .map(|owner| {
+            create_associated_token_account(
+                &context.1.pubkey(),
+                &owner.pubkey(),
+                &token_mint,
+                &spl_token::id(),
+            )
+        })
+        .collect();
+    let mut transaction = Transaction::new_with_payer(&create_atas, Some(&context.1.pubkey()));
+    transaction.sign(&[&context.1], context.2);
+    context.0.process_transaction(transaction).await.unwrap();
+    let maker_token_account = get_associated_token_address(&maker.pubkey(), &token_mint);
+    let taker_token_account = get_associated_token_address(&taker.pubkey(), &token_mint);
+    mint_to(
+        &mut context,
+        &token_mint,
+        &maker_token_account,
+        &mint_authority,
+        5,
+    )
+    .await;
+
+    // 5 tokens for 0.5 SOL.
+    let create_ix = instruction_builder::create_offer(
+        &program_id,
+        &maker.pubkey(),
+        &token_mint,
+        &sol,
+        Amounts {
+            offer_token_amount: 5,
+            receive_token_amount: 500_000_000,
+        },
+        None,
+    );
+    let mut transaction = Transaction::new_with_payer(&[create_ix], Some(&context.1.pubkey()));
+    transaction.sign(&[&context.1, &maker], context.2);
+    context.0.process_transaction(transaction).await.unwrap();
+
+    let (offer_account, _) =
+        instruction_builder::offer_address(&program_id, &maker.pubkey(), &token_mint, &sol, 0);
+    let account = context.0.get_account(offer_account).await.unwrap().unwrap();
+    let offer = Offer::load(&account.data).unwrap();
+
+    let maker_balance_before = get_sol_balance(&mut context, &maker.pubkey()).await;
+    let accept_ix = instruction_builder::accept_offer(&program_id, &offer, &taker.pubkey(), None);
+    let mut transaction = Transaction::new_with_payer(&[accept_ix], Some(&context.1.pubkey()));
+    transaction.sign(&[&context.1, &taker, &maker], context.2);
+    context.0.process_transaction(transaction).await.unwrap();
+
+    assert_eq!(
+        get_token_balance(&mut context, &taker_token_account).await,
+        5
+    );
+    assert_eq!(
+        get_sol_balance(&mut context, &maker.pubkey()).await,
+        maker_balance_before + 500_000_000
+    );
+    let account = context.0.get_account(offer_account).await.unwrap().unwrap();
+    assert_eq!(
+        Offer::load(&account.data).unwrap().status,
+        OfferStatus::Accepted
+    );
+}